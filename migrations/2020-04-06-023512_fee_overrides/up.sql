@@ -0,0 +1,14 @@
+-- Your SQL goes here
+CREATE TABLE fee_overrides (
+    id TEXT PRIMARY KEY NOT NULL,
+    created_at TIMESTAMP NOT NULL,
+    -- targets: a product, a category, or a store + category pair
+    product_id TEXT,
+    category_id TEXT,
+    store_id TEXT,
+    -- platform fee rate applied instead of the seller's PayoutSplit
+    platform_fee_rate DOUBLE PRECISION,
+    -- upper limit on the platform fee (in cents) for a single order item
+    max_platform_fee INT,
+    expires_at TIMESTAMP
+);
@@ -0,0 +1,13 @@
+-- Your SQL goes here
+CREATE TABLE revenue_shares (
+    id TEXT PRIMARY KEY NOT NULL,
+    created_at TIMESTAMP NOT NULL,
+    store_id TEXT NOT NULL,
+    -- recipient of this share, paid out with their own payout method
+    payee_id TEXT NOT NULL,
+    -- either a percentage of the seller's earnings, or a fixed amount (in cents)
+    percentage DOUBLE PRECISION,
+    fixed_amount INT
+);
+
+CREATE INDEX revenue_shares_store_id_idx ON revenue_shares (store_id);
@@ -0,0 +1,8 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX payout_splits_store_or_user_id_idx;
+
+ALTER TABLE payout_splits
+DROP COLUMN effective_from,
+DROP COLUMN effective_to,
+DROP COLUMN changed_by,
+DROP COLUMN reason;
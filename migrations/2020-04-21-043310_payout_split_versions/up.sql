@@ -0,0 +1,30 @@
+-- Your SQL goes here
+ALTER TABLE payout_splits
+ADD COLUMN effective_from TIMESTAMP,
+ADD COLUMN effective_to TIMESTAMP,
+ADD COLUMN changed_by TEXT,
+ADD COLUMN reason TEXT;
+
+-- existing PayoutSplits have been in effect since they were created
+UPDATE payout_splits SET effective_from = created_at;
+
+-- and were superseded by the next PayoutSplit of the same kind.
+-- SELLER and REFERRED_SELLER PayoutSplits replace each other.
+UPDATE payout_splits
+SET effective_to = next_versions.next_effective_from
+FROM (
+    SELECT
+        id,
+        lead(created_at) OVER (
+            PARTITION BY
+                store_or_user_id,
+                CASE WHEN deal_type = 'REFERRED_SELLER' THEN 'SELLER' ELSE deal_type END
+            ORDER BY created_at ASC
+        ) AS next_effective_from
+    FROM payout_splits
+) next_versions
+WHERE payout_splits.id = next_versions.id;
+
+ALTER TABLE payout_splits ALTER COLUMN effective_from SET NOT NULL;
+
+CREATE INDEX payout_splits_store_or_user_id_idx ON payout_splits (store_or_user_id);
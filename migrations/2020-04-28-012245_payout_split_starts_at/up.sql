@@ -0,0 +1,3 @@
+-- Your SQL goes here
+ALTER TABLE payout_splits
+ADD COLUMN starts_at TIMESTAMP;
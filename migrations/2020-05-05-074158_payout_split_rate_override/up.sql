@@ -0,0 +1,3 @@
+-- Your SQL goes here
+ALTER TABLE payout_splits
+ADD COLUMN rate_override BOOLEAN NOT NULL DEFAULT false;
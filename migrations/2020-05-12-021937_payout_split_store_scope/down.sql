@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX payout_splits_store_id_idx;
+
+ALTER TABLE payout_splits
+DROP COLUMN store_id;
@@ -0,0 +1,5 @@
+-- Your SQL goes here
+ALTER TABLE payout_splits
+ADD COLUMN store_id TEXT;
+
+CREATE INDEX payout_splits_store_id_idx ON payout_splits (store_id);
@@ -0,0 +1,32 @@
+-- Your SQL goes here
+CREATE TABLE payout_calculations (
+    id TEXT PRIMARY KEY NOT NULL,
+    created_at TIMESTAMP NOT NULL,
+    order_item_id TEXT NOT NULL,
+    txn_id TEXT NOT NULL,
+    currency TEXT NOT NULL,
+    -- orderItem price after discounts, and the seller's payment processing fee
+    subtotal INT NOT NULL,
+    payment_processing_fee INT NOT NULL,
+    -- payout splits and fee override that were looked up
+    seller_payout_split_id TEXT,
+    buyer_affiliate_payout_split_id TEXT,
+    seller_affiliate_payout_split_id TEXT,
+    upline_affiliate_payout_split_ids TEXT[] NOT NULL,
+    fee_override_id TEXT,
+    -- rates after expiry checks and fee overrides
+    seller_rate DOUBLE PRECISION NOT NULL,
+    buyer_affiliate_rate DOUBLE PRECISION NOT NULL,
+    seller_affiliate_rate DOUBLE PRECISION NOT NULL,
+    upline_affiliate_rates DOUBLE PRECISION[] NOT NULL,
+    -- fee schedule at the time
+    payment_fee_percentage DOUBLE PRECISION NOT NULL,
+    payment_fee_fixed INT NOT NULL,
+    platform_fee_percentage DOUBLE PRECISION NOT NULL,
+    -- discounts, by who pays for them
+    platform_funded_discount INT NOT NULL,
+    seller_funded_discount INT NOT NULL
+);
+
+CREATE INDEX payout_calculations_txn_id_idx ON payout_calculations (txn_id);
+CREATE INDEX payout_calculations_order_item_id_idx ON payout_calculations (order_item_id);
@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE payout_calculations
+DROP COLUMN store_id,
+DROP COLUMN product_id,
+DROP COLUMN category_id;
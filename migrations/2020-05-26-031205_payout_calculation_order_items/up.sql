@@ -0,0 +1,6 @@
+-- Your SQL goes here
+-- orderItem details needed to recompute a split later
+ALTER TABLE payout_calculations
+ADD COLUMN store_id TEXT,
+ADD COLUMN product_id TEXT,
+ADD COLUMN category_id TEXT;
@@ -0,0 +1,16 @@
+-- Your SQL goes here
+CREATE TABLE audit_logs (
+    id TEXT PRIMARY KEY NOT NULL,
+    created_at TIMESTAMP NOT NULL,
+    -- e.g. RECALCULATE_EARNINGS
+    action TEXT NOT NULL,
+    -- userId of the admin who did it
+    actor_id TEXT NOT NULL,
+    -- what it was done to, e.g. an orderId
+    target_id TEXT NOT NULL,
+    reason TEXT,
+    -- JSON describing the change
+    details TEXT
+);
+
+CREATE INDEX audit_logs_target_id_idx ON audit_logs (target_id);
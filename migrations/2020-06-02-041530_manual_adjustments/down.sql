@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE manual_adjustments;
+
+DELETE FROM payout_items WHERE order_item_id IS NULL OR txn_id IS NULL;
+ALTER TABLE payout_items ALTER COLUMN order_item_id SET NOT NULL;
+ALTER TABLE payout_items ALTER COLUMN txn_id SET NOT NULL;
@@ -0,0 +1,19 @@
+-- Your SQL goes here
+-- manual adjustments are not tied to an orderItem or transaction
+ALTER TABLE payout_items ALTER COLUMN order_item_id DROP NOT NULL;
+ALTER TABLE payout_items ALTER COLUMN txn_id DROP NOT NULL;
+
+CREATE TABLE manual_adjustments (
+    id TEXT PRIMARY KEY NOT NULL,
+    payout_item_id TEXT NOT NULL REFERENCES payout_items(id) ON DELETE CASCADE,
+    created_at TIMESTAMP NOT NULL,
+    -- BONUS, PENALTY or GOODWILL
+    category TEXT NOT NULL,
+    reason TEXT NOT NULL,
+    -- userId of the admin who approved it
+    approved_by TEXT NOT NULL,
+    -- userId of whoever asked for it, if not the approver
+    requested_by TEXT
+);
+
+CREATE INDEX manual_adjustments_payout_item_id_idx ON manual_adjustments (payout_item_id);
@@ -0,0 +1,24 @@
+-- Your SQL goes here
+-- every webhook event received, written before it is processed
+CREATE TABLE webhook_events (
+    id TEXT PRIMARY KEY NOT NULL,
+    -- STRIPE or PAYPAL
+    provider TEXT NOT NULL,
+    -- the provider's event id, e.g. evt_xxx or WH-xxx
+    event_id TEXT NOT NULL,
+    event_type TEXT NOT NULL,
+    -- the raw request body
+    payload TEXT NOT NULL,
+    received_at TIMESTAMP NOT NULL,
+    -- RECEIVED, PROCESSED, FAILED or ABANDONED
+    processing_status TEXT NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    last_error TEXT,
+    next_attempt_at TIMESTAMP,
+    processed_at TIMESTAMP,
+    -- providers deliver events at least once
+    UNIQUE (provider, event_id)
+);
+
+CREATE INDEX webhook_events_received_at_idx ON webhook_events (received_at);
+CREATE INDEX webhook_events_next_attempt_at_idx ON webhook_events (next_attempt_at);
@@ -0,0 +1,24 @@
+-- Your SQL goes here
+CREATE TABLE disputes (
+    -- Stripe dispute id, dp_xxx
+    id TEXT PRIMARY KEY NOT NULL,
+    -- the disputed payment
+    transaction_id TEXT NOT NULL,
+    order_id TEXT,
+    charge_id TEXT NOT NULL,
+    amount INTEGER NOT NULL,
+    currency TEXT NOT NULL,
+    reason TEXT NOT NULL,
+    -- Stripe dispute status, e.g. needs_response, won, lost
+    status TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL,
+    updated_at TIMESTAMP NOT NULL,
+    evidence_due_by TIMESTAMP,
+    evidence_submitted_at TIMESTAMP,
+    closed_at TIMESTAMP,
+    -- dispute fee charged by Stripe, booked if the dispute is lost
+    dispute_fee INTEGER NOT NULL DEFAULT 0
+);
+
+CREATE INDEX disputes_transaction_id_idx ON disputes (transaction_id);
+CREATE INDEX disputes_order_id_idx ON disputes (order_id);
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE refunds DROP COLUMN updated_at;
+ALTER TABLE refunds DROP COLUMN failure_reason;
+ALTER TABLE refunds DROP COLUMN status;
@@ -0,0 +1,4 @@
+-- Your SQL goes here
+ALTER TABLE refunds ADD COLUMN status TEXT NOT NULL DEFAULT 'SUCCEEDED';
+ALTER TABLE refunds ADD COLUMN failure_reason TEXT;
+ALTER TABLE refunds ADD COLUMN updated_at TIMESTAMP;
@@ -0,0 +1,19 @@
+-- Your SQL goes here
+CREATE TABLE refund_requests (
+    id TEXT PRIMARY KEY,
+    order_id TEXT NOT NULL,
+    payment_processor TEXT NOT NULL,
+    refund_total INT NOT NULL,
+    refund_order JSONB NOT NULL,
+    approval_reasons TEXT[] NOT NULL,
+    status TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL,
+    reviewed_by TEXT,
+    reviewed_at TIMESTAMP,
+    review_notes TEXT,
+    refund_id TEXT,
+    last_error TEXT
+);
+
+CREATE INDEX refund_requests_status_idx ON refund_requests (status);
+CREATE INDEX refund_requests_order_id_idx ON refund_requests (order_id);
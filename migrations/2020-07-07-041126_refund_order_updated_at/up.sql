@@ -0,0 +1,4 @@
+-- Your SQL goes here
+ALTER TABLE refunds ADD COLUMN order_updated_at TIMESTAMP;
+-- refunds recorded so far updated their orders when they were written
+UPDATE refunds SET order_updated_at = created_at;
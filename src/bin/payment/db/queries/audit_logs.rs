@@ -0,0 +1,30 @@
+use diesel::prelude::*;
+use diesel::PgConnection;
+use gm::db;
+
+use crate::models::{
+    AuditLog,
+    ErrJson,
+    DbError,
+};
+
+
+////////////////////////
+/// Audit Logs
+////////////////////////
+
+
+/// Audit logs for an order, payout or other target, newest first
+pub fn read_audit_logs_by_target_id(
+    conn: &PgConnection,
+    target_id: &str,
+) -> Result<Vec<AuditLog>, DbError> {
+
+    use db::schema::audit_logs;
+
+    audit_logs::table
+        .filter(audit_logs::target_id.eq(target_id))
+        .order_by(audit_logs::created_at.desc())
+        .load::<AuditLog>(conn)
+        .map_err(|e| DbError::AuditLogReadError(errJson!(e)))
+}
@@ -0,0 +1,161 @@
+use diesel::prelude::*;
+use diesel::PgConnection;
+use gm::db;
+
+use crate::models::{
+    Dispute,
+    PayoutItem,
+    PayoutStatus,
+    Transaction,
+    ErrJson,
+    DbError,
+};
+
+
+////////////////////////
+/// Disputes
+////////////////////////
+
+
+pub fn read_dispute(
+    conn: &PgConnection,
+    dispute_id: &str,
+) -> Result<Option<Dispute>, DbError> {
+
+    use db::schema::disputes;
+
+    disputes::table
+        .filter(disputes::id.eq(dispute_id))
+        .first::<Dispute>(conn)
+        .optional()
+        .map_err(|e| DbError::DisputeReadError(errJson!(e)))
+}
+
+
+pub fn read_disputes_by_order_id(
+    conn: &PgConnection,
+    order_id: &str,
+) -> Result<Vec<Dispute>, DbError> {
+
+    use db::schema::disputes;
+
+    disputes::table
+        .filter(disputes::order_id.eq(order_id))
+        .order(disputes::created_at.desc())
+        .load::<Dispute>(conn)
+        .map_err(|e| DbError::DisputeReadError(errJson!(e)))
+}
+
+
+/// Writes a new dispute, holds its unpaid payout items out of payouts,
+/// and writes clawbacks for the shares which were already paid out.
+pub fn write_dispute_and_hold_payout_items(
+    conn: &PgConnection,
+    dispute: &Dispute,
+    hold_payout_item_ids: &Vec<String>,
+    clawback_items: &Vec<PayoutItem>,
+) -> Result<Dispute, DbError> {
+
+    use db::schema::disputes;
+    use db::schema::payout_items;
+
+    conn.transaction::<Dispute, diesel::result::Error, _>(|| {
+
+        let dispute = diesel::insert_into(disputes::table)
+            .values(dispute)
+            .get_result::<Dispute>(conn)?;
+
+        diesel::update(
+            payout_items::table
+                .filter(payout_items::id.eq_any(hold_payout_item_ids))
+        )
+        .set(payout_items::payout_status.eq(PayoutStatus::DISPUTED.as_string()))
+        .execute(conn)?;
+
+        diesel::insert_into(payout_items::table)
+            .values(clawback_items)
+            .execute(conn)?;
+
+        Ok(dispute)
+
+    }).map_err(|e| DbError::DisputeWriteError(errJson!(e)))
+}
+
+
+/// Updates a dispute's status, evidence and outcome fields
+pub fn update_dispute(
+    conn: &PgConnection,
+    dispute: &Dispute,
+) -> Result<Dispute, DbError> {
+
+    use db::schema::disputes;
+
+    diesel::update(
+        disputes::table
+            .filter(disputes::id.eq(&dispute.id))
+    )
+    .set((
+        disputes::status.eq(&dispute.status),
+        disputes::updated_at.eq(&dispute.updated_at),
+        disputes::evidence_due_by.eq(&dispute.evidence_due_by),
+        disputes::evidence_submitted_at.eq(&dispute.evidence_submitted_at),
+        disputes::closed_at.eq(&dispute.closed_at),
+        disputes::dispute_fee.eq(&dispute.dispute_fee),
+    ))
+    .get_result::<Dispute>(conn)
+    .map_err(|e| DbError::DisputeWriteError(errJson!(e)))
+}
+
+
+/// Closes a dispute: releases its held payout items back to UNPAID, and
+/// writes the outcome's payout items (clawback reversals if won;
+/// refunds of the held items and dispute fees if lost, with the lost
+/// funds' transaction).
+pub fn write_dispute_outcome(
+    conn: &PgConnection,
+    dispute: &Dispute,
+    order_item_ids: &Vec<String>,
+    outcome_items: &Vec<PayoutItem>,
+    lost_tx: Option<&Transaction>,
+) -> Result<Dispute, DbError> {
+
+    use db::schema::disputes;
+    use db::schema::payout_items;
+    use db::schema::transactions;
+
+    conn.transaction::<Dispute, diesel::result::Error, _>(|| {
+
+        let dispute = diesel::update(
+            disputes::table
+                .filter(disputes::id.eq(&dispute.id))
+        )
+        .set((
+            disputes::status.eq(&dispute.status),
+            disputes::updated_at.eq(&dispute.updated_at),
+            disputes::closed_at.eq(&dispute.closed_at),
+            disputes::dispute_fee.eq(&dispute.dispute_fee),
+        ))
+        .get_result::<Dispute>(conn)?;
+
+        diesel::update(
+            payout_items::table
+                .filter(payout_items::order_item_id.eq_any(order_item_ids))
+                .filter(payout_items::payout_status.eq(PayoutStatus::DISPUTED.as_string()))
+        )
+        .set(payout_items::payout_status.eq(PayoutStatus::UNPAID.as_string()))
+        .execute(conn)?;
+
+        diesel::insert_into(payout_items::table)
+            .values(outcome_items)
+            .execute(conn)?;
+
+        if let Some(tx) = lost_tx {
+            diesel::insert_into(transactions::table)
+                .values(tx)
+                .execute(conn)?;
+        }
+
+        Ok(dispute)
+
+    }).map_err(|e| DbError::DisputeWriteError(errJson!(e)))
+}
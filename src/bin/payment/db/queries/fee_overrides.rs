@@ -0,0 +1,84 @@
+use diesel::prelude::*;
+use diesel::PgConnection;
+use gm::db;
+
+use crate::models::{
+    FeeOverride,
+    OrderItemRpc,
+    ErrJson,
+    DbError,
+    pick_most_specific_fee_override,
+};
+
+
+////////////////////////
+/// Fee Overrides
+////////////////////////
+
+
+pub fn write_fee_override(
+    conn: &PgConnection,
+    fee_override: FeeOverride,
+) -> Result<FeeOverride, DbError> {
+
+    use db::schema::fee_overrides;
+
+    diesel::insert_into(fee_overrides::table)
+        .values(fee_override)
+        .get_result::<FeeOverride>(conn)
+        .map_err(|e| DbError::FeeOverrideWriteError(errJson!(e)))
+}
+
+pub fn read_fee_overrides_by_ids(
+    conn: &PgConnection,
+    fee_override_ids: Vec<String>,
+) -> Result<Vec<FeeOverride>, DbError> {
+
+    use db::schema::fee_overrides;
+
+    fee_overrides::table
+        .filter(fee_overrides::id.eq_any(fee_override_ids))
+        .load::<FeeOverride>(conn)
+        .map_err(|e| DbError::FeeOverrideReadError(errJson!(e)))
+}
+
+pub fn delete_fee_override(
+    conn: &PgConnection,
+    fee_override_id: String,
+) -> Result<FeeOverride, DbError> {
+
+    use db::schema::fee_overrides;
+
+    diesel::delete(fee_overrides::table)
+        .filter(fee_overrides::id.eq(fee_override_id))
+        .get_result::<FeeOverride>(conn)
+        .map_err(|e| DbError::FeeOverrideWriteError(errJson!(e)))
+}
+
+/// Reads every override that could target the orderItem, then picks
+/// the most specific one: product > store + category > category
+pub fn read_fee_override_for_order_item(
+    conn: &PgConnection,
+    oitem: &OrderItemRpc,
+    created_at: &chrono::NaiveDateTime,
+) -> Result<Option<FeeOverride>, DbError> {
+
+    use db::schema::fee_overrides;
+
+    if oitem.product_id.is_none() && oitem.category_id.is_none() {
+        return Ok(None)
+    }
+
+    let product_id = oitem.product_id.clone().unwrap_or(String::from(""));
+    let category_id = oitem.category_id.clone().unwrap_or(String::from(""));
+
+    let candidates = fee_overrides::table
+        .filter(
+            fee_overrides::product_id.eq(product_id)
+            .or(fee_overrides::category_id.eq(category_id))
+        )
+        .load::<FeeOverride>(conn)
+        .map_err(|e| DbError::FeeOverrideReadError(errJson!(e)))?;
+
+    Ok(pick_most_specific_fee_override(candidates, oitem, created_at))
+}
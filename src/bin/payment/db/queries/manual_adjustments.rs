@@ -0,0 +1,88 @@
+use diesel::prelude::*;
+use diesel::PgConnection;
+use gm::db;
+
+use crate::models::{
+    PayoutItem,
+    ManualAdjustment,
+    ManualAdjustmentItem,
+    ErrJson,
+    DbError,
+};
+
+
+////////////////////////
+/// Manual Adjustments
+////////////////////////
+
+
+/// Writes a manual adjustment's PayoutItem together with its reason,
+/// so there is never an adjustment without an approver.
+pub fn write_manual_adjustment(
+    conn: &PgConnection,
+    payout_item: &PayoutItem,
+    manual_adjustment: &ManualAdjustment,
+) -> Result<ManualAdjustmentItem, DbError> {
+
+    use db::schema::payout_items;
+    use db::schema::manual_adjustments;
+
+    conn.transaction::<ManualAdjustmentItem, diesel::result::Error, _>(|| {
+
+        let payout_item = diesel::insert_into(payout_items::table)
+            .values(payout_item)
+            .get_result::<PayoutItem>(conn)?;
+
+        let adjustment = diesel::insert_into(manual_adjustments::table)
+            .values(manual_adjustment)
+            .get_result::<ManualAdjustment>(conn)?;
+
+        Ok(ManualAdjustmentItem {
+            payout_item: payout_item,
+            adjustment: adjustment,
+        })
+
+    }).map_err(|e| DbError::ManualAdjustmentWriteError(errJson!(e)))
+}
+
+
+pub fn read_manual_adjustments_by_payout_item_ids(
+    conn: &PgConnection,
+    payout_item_ids: &Vec<String>,
+) -> Result<Vec<ManualAdjustment>, DbError> {
+
+    use db::schema::manual_adjustments;
+
+    manual_adjustments::table
+        .filter(manual_adjustments::payout_item_id.eq_any(payout_item_ids))
+        .load::<ManualAdjustment>(conn)
+        .map_err(|e| DbError::ManualAdjustmentReadError(errJson!(e)))
+}
+
+
+/// A payee's manual adjustments, newest first
+pub fn read_manual_adjustments_by_payee_id(
+    conn: &PgConnection,
+    payee_id: &str,
+) -> Result<Vec<ManualAdjustmentItem>, DbError> {
+
+    use db::schema::payout_items;
+    use db::schema::manual_adjustments;
+
+    payout_items::table
+        .inner_join(manual_adjustments::table.on(
+            manual_adjustments::payout_item_id.eq(payout_items::id)
+        ))
+        .filter(payout_items::payee_id.eq(payee_id))
+        .order_by(payout_items::created_at.desc())
+        .load::<(PayoutItem, ManualAdjustment)>(conn)
+        .map(|rows| {
+            rows.into_iter()
+                .map(|(payout_item, adjustment)| ManualAdjustmentItem {
+                    payout_item: payout_item,
+                    adjustment: adjustment,
+                })
+                .collect::<Vec<ManualAdjustmentItem>>()
+        })
+        .map_err(|e| DbError::ManualAdjustmentReadError(errJson!(e)))
+}
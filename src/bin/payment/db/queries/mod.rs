@@ -1,15 +1,31 @@
+pub mod audit_logs;
+pub mod disputes;
+pub mod fee_overrides;
+pub mod manual_adjustments;
 pub mod payment_methods;
 pub mod payout_methods;
 pub mod payouts;
+pub mod payout_calculations;
 pub mod payout_items;
 pub mod payout_splits;
 pub mod refunds;
+pub mod refund_requests;
+pub mod revenue_shares;
 pub mod transactions;
+pub mod webhook_events;
 
+pub use audit_logs::*;
+pub use disputes::*;
+pub use fee_overrides::*;
+pub use manual_adjustments::*;
 pub use payment_methods::*;
 pub use payout_methods::*;
 pub use payouts::*;
+pub use payout_calculations::*;
 pub use payout_items::*;
 pub use payout_splits::*;
 pub use refunds::*;
-pub use transactions::*;
\ No newline at end of file
+pub use refund_requests::*;
+pub use revenue_shares::*;
+pub use transactions::*;
+pub use webhook_events::*;
\ No newline at end of file
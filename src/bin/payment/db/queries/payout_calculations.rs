@@ -0,0 +1,70 @@
+use diesel::prelude::*;
+use diesel::PgConnection;
+use gm::db;
+
+use crate::models::{
+    PayoutCalculation,
+    PayoutItem,
+    AuditLog,
+    ErrJson,
+    DbError,
+};
+
+
+////////////////////////
+/// Payout Calculations
+////////////////////////
+
+
+/// PayoutCalculations for the payment transaction(s) of an order
+pub fn read_payout_calculations_by_order_id(
+    conn: &PgConnection,
+    order_id: &str,
+) -> Result<Vec<PayoutCalculation>, DbError> {
+
+    use db::schema::payout_calculations;
+    use db::schema::transactions;
+
+    let txn_ids = transactions::table
+        .filter(transactions::order_id.eq(order_id))
+        .select(transactions::id);
+
+    payout_calculations::table
+        .filter(payout_calculations::txn_id.eq_any(txn_ids))
+        .order_by(payout_calculations::order_item_id.asc())
+        .load::<PayoutCalculation>(conn)
+        .map_err(|e| DbError::PayoutCalculationReadError(errJson!(e)))
+}
+
+
+/// Writes adjustment items from a recalculation, with the PayoutCalculations
+/// that produced them and the audit log. All succeed or none do.
+pub fn write_payout_adjustments(
+    conn: &PgConnection,
+    adjustment_items: &Vec<PayoutItem>,
+    payout_calculations: &Vec<PayoutCalculation>,
+    audit_log: &AuditLog,
+) -> Result<Vec<PayoutItem>, DbError> {
+
+    use db::schema::payout_items;
+    use db::schema::payout_calculations;
+    use db::schema::audit_logs;
+
+    conn.transaction::<Vec<PayoutItem>, diesel::result::Error, _>(|| {
+
+        let adjustments = diesel::insert_into(payout_items::table)
+            .values(adjustment_items)
+            .load::<PayoutItem>(conn)?;
+
+        diesel::insert_into(payout_calculations::table)
+            .values(payout_calculations)
+            .execute(conn)?;
+
+        diesel::insert_into(audit_logs::table)
+            .values(audit_log)
+            .execute(conn)?;
+
+        Ok(adjustments)
+
+    }).map_err(|e| DbError::PayoutCalculationWriteError(errJson!(e)))
+}
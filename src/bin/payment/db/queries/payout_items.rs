@@ -105,6 +105,19 @@ pub fn read_payout_items_by_ids(
 }
 
 
+pub fn read_payout_items_by_txn_id(
+    conn: &PgConnection,
+    txn_id: &str,
+) -> Result<Vec<PayoutItem>, DbError> {
+    use db::schema::payout_items;
+
+    payout_items::table
+        .filter(payout_items::txn_id.eq(txn_id))
+        .load::<PayoutItem>(conn)
+        .map_err(|e| DbError::PayoutItemReadError(errJson!(e)))
+}
+
+
 pub fn read_payout_items_by_order_item_ids(
     conn: &PgConnection,
     order_item_ids: &Vec<String>,
@@ -119,6 +132,49 @@ pub fn read_payout_items_by_order_item_ids(
 }
 
 
+/// UNPAID and REFUNDING items from before a payout period, of payees whose
+/// earnings didn't cover a clawback. They are carried forward until the payee
+/// earns enough to pay the clawback back.
+pub fn read_payout_items_carried_forward(
+    conn: &PgConnection,
+    start_date: chrono::NaiveDateTime,
+) -> Result<Vec<PayoutItem>, DbError> {
+
+    use db::schema::payout_items;
+    use diesel::dsl::*;
+
+    let unpaid_statuses = vec![
+        PayoutStatus::UNPAID,
+        PayoutStatus::MISSING_PAYOUT_METHOD,
+    ];
+
+    let payees_owing_clawbacks = payout_items::table
+        .select(payout_items::payee_id)
+        .filter(payout_items::id.like("citem_%"))
+        .filter(payout_items::payout_status.eq_any(unpaid_statuses))
+        .filter(payout_items::created_at.le(start_date))
+        .distinct()
+        .load::<String>(conn)
+        .map_err(|e| DbError::PayoutItemReadError(errJson!(e)))?;
+
+    if payees_owing_clawbacks.len() == 0 {
+        return Ok(vec![])
+    }
+
+    let carried_forward_statuses = vec![
+        PayoutStatus::UNPAID,
+        PayoutStatus::MISSING_PAYOUT_METHOD,
+        PayoutStatus::REFUNDING,
+    ];
+
+    payout_items::table
+        .filter(payout_items::created_at.le(start_date))
+        .filter(payout_items::payout_status.eq_any(carried_forward_statuses))
+        .filter(payout_items::payee_id.eq_any(payees_owing_clawbacks))
+        .load::<PayoutItem>(conn)
+        .map_err(|e| DbError::PayoutItemReadError(errJson!(e)))
+}
+
 pub fn read_payout_items_in_period(
     conn: &PgConnection,
     start_date: chrono::NaiveDateTime,
@@ -475,6 +531,8 @@ pub fn read_payout_item_history_summaries(
     // NOTE:
     // count is calculated as: SUM(CASE WHEN amount > 0 THEN 1 ELSE 0 END) OVER ()
     // because there are refund items which need to be skipped.
+    // Manual adjustments have no orderItem, they are summed separately
+    // in adjustments (and included in amount_total) but not counted.
     // May want to write logic to subtract the order associated with refund.
 
     let agg = diesel::sql_query(format!(r#"
@@ -483,7 +541,8 @@ pub fn read_payout_item_history_summaries(
             SELECT
                 (SUM(amount) OVER ()) AS amount_total,
                 (SUM(CASE WHEN payout_status = 'UNPAID' OR payout_status = 'MISSING_PAYOUT_METHOD' THEN amount ELSE 0 END) OVER ()) as unpaid,
-                (SUM(CASE WHEN amount > 0 THEN 1 ELSE 0 END) OVER ()) as count
+                (SUM(CASE WHEN amount > 0 AND order_item_id IS NOT NULL THEN 1 ELSE 0 END) OVER ()) as count,
+                (SUM(CASE WHEN order_item_id IS NULL THEN amount ELSE 0 END) OVER ()) as adjustments
             FROM payout_items
             WHERE created_at > (current_timestamp - interval '1 day')
                 AND payout_items.payee_id = $1
@@ -497,7 +556,8 @@ pub fn read_payout_item_history_summaries(
             SELECT
                 (SUM(amount) OVER ()) AS amount_total,
                 (SUM(CASE WHEN payout_status = 'UNPAID' OR payout_status = 'MISSING_PAYOUT_METHOD' THEN amount ELSE 0 END) OVER ()) as unpaid,
-                (SUM(CASE WHEN amount > 0 THEN 1 ELSE 0 END) OVER ()) as count
+                (SUM(CASE WHEN amount > 0 AND order_item_id IS NOT NULL THEN 1 ELSE 0 END) OVER ()) as count,
+                (SUM(CASE WHEN order_item_id IS NULL THEN amount ELSE 0 END) OVER ()) as adjustments
             FROM payout_items
             WHERE created_at > current_timestamp - interval '7 day'
                 AND payout_items.payee_id = $1
@@ -510,7 +570,8 @@ pub fn read_payout_item_history_summaries(
             SELECT
                 (SUM(amount) OVER ()) AS amount_total,
                 (SUM(CASE WHEN payout_status = 'UNPAID' OR payout_status = 'MISSING_PAYOUT_METHOD' THEN amount ELSE 0 END) OVER ()) as unpaid,
-                (SUM(CASE WHEN amount > 0 THEN 1 ELSE 0 END) OVER ()) as count
+                (SUM(CASE WHEN amount > 0 AND order_item_id IS NOT NULL THEN 1 ELSE 0 END) OVER ()) as count,
+                (SUM(CASE WHEN order_item_id IS NULL THEN amount ELSE 0 END) OVER ()) as adjustments
             FROM payout_items
             WHERE created_at > current_timestamp - interval '30 day'
                 AND payout_items.payee_id = $1
@@ -523,7 +584,8 @@ pub fn read_payout_item_history_summaries(
             SELECT
                 (SUM(amount) OVER ()) AS amount_total,
                 (SUM(CASE WHEN payout_status = 'UNPAID' OR payout_status = 'MISSING_PAYOUT_METHOD' THEN amount ELSE 0 END) OVER ()) as unpaid,
-                (SUM(CASE WHEN amount > 0 THEN 1 ELSE 0 END) OVER ()) as count
+                (SUM(CASE WHEN amount > 0 AND order_item_id IS NOT NULL THEN 1 ELSE 0 END) OVER ()) as count,
+                (SUM(CASE WHEN order_item_id IS NULL THEN amount ELSE 0 END) OVER ()) as adjustments
             FROM payout_items
             WHERE created_at > $2 AND created_at < $3
                 AND payout_items.payee_id = $1
@@ -536,7 +598,8 @@ pub fn read_payout_item_history_summaries(
             SELECT
                 (SUM(amount) OVER ()) AS amount_total,
                 (SUM(CASE WHEN payout_status = 'UNPAID' OR payout_status = 'MISSING_PAYOUT_METHOD' THEN amount ELSE 0 END) OVER ()) as unpaid,
-                (SUM(CASE WHEN amount > 0 THEN 1 ELSE 0 END) OVER ()) as count
+                (SUM(CASE WHEN amount > 0 AND order_item_id IS NOT NULL THEN 1 ELSE 0 END) OVER ()) as count,
+                (SUM(CASE WHEN order_item_id IS NULL THEN amount ELSE 0 END) OVER ()) as adjustments
             FROM payout_items
             WHERE created_at > $4 AND created_at < $5
                 AND payout_items.payee_id = $1
@@ -549,7 +612,8 @@ pub fn read_payout_item_history_summaries(
             SELECT
                 (SUM(amount) OVER ()) AS amount_total,
                 (SUM(CASE WHEN payout_status = 'UNPAID' OR payout_status = 'MISSING_PAYOUT_METHOD' THEN amount ELSE 0 END) OVER ()) as unpaid,
-                (SUM(CASE WHEN amount > 0 THEN 1 ELSE 0 END) OVER ()) as count
+                (SUM(CASE WHEN amount > 0 AND order_item_id IS NOT NULL THEN 1 ELSE 0 END) OVER ()) as count,
+                (SUM(CASE WHEN order_item_id IS NULL THEN amount ELSE 0 END) OVER ()) as adjustments
             FROM payout_items
             WHERE payout_items.payee_id = $1
                 AND payout_items.payee_type = ANY($6)
@@ -573,6 +637,7 @@ pub fn read_payout_item_history_summaries(
         amount_total: 0,
         unpaid: 0,
         count: 0,
+        adjustments: 0,
     });
 
     match agg {
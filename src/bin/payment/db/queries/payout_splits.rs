@@ -8,6 +8,7 @@ use crate::models::{
     PayoutSplit,
     ErrJson,
     DbError,
+    walk_referrer_chain,
 };
 use crate::models::{
     Payout,
@@ -18,7 +19,10 @@ use crate::models::{
 };
 use itertools::zip;
 use std::collections::HashMap;
-use crate::pricing::BUYER_AFFILIATE_FEE_PERCENTAGE;
+use crate::pricing::{
+    BUYER_AFFILIATE_FEE_PERCENTAGE,
+    UPLINE_REFERRAL_FEE_PERCENTAGES,
+};
 
 
 ////////////////////////
@@ -33,10 +37,27 @@ pub fn write_payout_split(
 
     use db::schema::payout_splits;
 
-    diesel::insert_into(payout_splits::table)
-        .values(payout_split)
-        .get_result::<PayoutSplit>(conn)
-        .map_err(|e| DbError::PayoutSplitWriteError(errJson!(e)))
+    let mut overlap_error: Option<DbError> = None;
+
+    // checking for overlaps, closing the previous version and appending
+    // the new one all succeed or none do
+    conn.transaction::<PayoutSplit, diesel::result::Error, _>(|| {
+
+        if let Err(e) = check_payout_split_overlaps(conn, &payout_split)? {
+            overlap_error = Some(e);
+            return Err(diesel::result::Error::RollbackTransaction)
+        }
+
+        close_superseded_payout_splits(conn, &payout_split)?;
+
+        diesel::insert_into(payout_splits::table)
+            .values(payout_split)
+            .get_result::<PayoutSplit>(conn)
+
+    }).map_err(|e| match overlap_error.take() {
+        Some(overlap_error) => overlap_error,
+        None => DbError::PayoutSplitWriteError(errJson!(e)),
+    })
 }
 
 pub fn write_two_payout_splits(
@@ -49,6 +70,9 @@ pub fn write_two_payout_splits(
     // both succeed or none do
     conn.transaction::<(PayoutSplit, PayoutSplit), diesel::result::Error, _>(|| {
 
+        close_superseded_payout_splits(conn, &referred_seller_payout_split)?;
+        close_superseded_payout_splits(conn, &seller_affiliate_payout_split)?;
+
         let referred_seller_ps = diesel::insert_into(payout_splits::table)
             .values(referred_seller_payout_split)
             .get_result::<PayoutSplit>(conn)?;
@@ -63,6 +87,159 @@ pub fn write_two_payout_splits(
     }).map_err(|e| DbError::PayoutSplitWriteError(errJson!(e)))
 }
 
+/// PayoutSplits are append-only. Writing a new version sets effective_to
+/// on the version(s) it supersedes, instead of overwriting them.
+///
+/// Scheduled PayoutSplits (with a starts_at) sit alongside the regular
+/// PayoutSplit, so they do not supersede anything.
+fn close_superseded_payout_splits(
+    conn: &PgConnection,
+    new_payout_split: &PayoutSplit,
+) -> Result<usize, diesel::result::Error> {
+
+    use db::schema::payout_splits;
+
+    if new_payout_split.starts_at.is_some() {
+        return Ok(0)
+    }
+
+    diesel::update(payout_splits::table
+        .filter(payout_splits::store_or_user_id.eq(&new_payout_split.store_or_user_id))
+        .filter(payout_splits::deal_type.eq_any(new_payout_split.deal_type.versioned_with()))
+        .filter(payout_splits::starts_at.is_null())
+        .filter(payout_splits::effective_to.is_null())
+        .filter(same_store_scope(&new_payout_split.store_id)))
+        .set(payout_splits::effective_to.eq(new_payout_split.effective_from))
+        .execute(conn)
+}
+
+/// Writes PayoutSplits from a bulk import, all or none.
+/// Each one supersedes the current version for its deal, like write_payout_split.
+pub fn write_many_payout_splits(
+    conn: &PgConnection,
+    payout_splits: Vec<PayoutSplit>,
+) -> Result<Vec<PayoutSplit>, DbError> {
+
+    use db::schema::payout_splits;
+
+    conn.transaction::<Vec<PayoutSplit>, diesel::result::Error, _>(|| {
+
+        let mut written: Vec<PayoutSplit> = vec![];
+
+        for payout_split in payout_splits.into_iter() {
+            close_superseded_payout_splits(conn, &payout_split)?;
+            let ps = diesel::insert_into(payout_splits::table)
+                .values(payout_split)
+                .get_result::<PayoutSplit>(conn)?;
+            written.push(ps);
+        }
+
+        Ok(written)
+
+    }).map_err(|e| DbError::PayoutSplitWriteError(errJson!(e)))
+}
+
+/// Store-scoped PayoutSplits are versioned separately from the
+/// affiliate's global PayoutSplit, and from other stores' deals
+fn same_store_scope(
+    store_id: &Option<String>,
+) -> Box<dyn BoxableExpression<db::schema::payout_splits::table, diesel::pg::Pg, SqlType = diesel::sql_types::Bool>> {
+
+    use db::schema::payout_splits;
+
+    match store_id {
+        None => Box::new(payout_splits::store_id.is_null()),
+        Some(sid) => Box::new(payout_splits::store_id.eq(sid.clone())),
+    }
+}
+
+/// Rejects a scheduled PayoutSplit if its window overlaps another
+/// scheduled PayoutSplit for the same store_or_user_id and deal.
+///
+/// Runs in the transaction writing the PayoutSplit, and holds a lock on
+/// the store_or_user_id until it ends, so two scheduled PayoutSplits
+/// written at the same time can't both pass the check.
+fn check_payout_split_overlaps(
+    conn: &PgConnection,
+    new_payout_split: &PayoutSplit,
+) -> Result<Result<(), DbError>, diesel::result::Error> {
+
+    use db::schema::payout_splits;
+
+    if new_payout_split.starts_at.is_none() {
+        return Ok(Ok(()))
+    }
+
+    diesel::sql_query("SELECT pg_advisory_xact_lock(hashtext($1))")
+        .bind::<Text, _>(&new_payout_split.store_or_user_id)
+        .execute(conn)?;
+
+    let scheduled_psplits = payout_splits::table
+        .filter(payout_splits::store_or_user_id.eq(&new_payout_split.store_or_user_id))
+        .filter(payout_splits::deal_type.eq_any(new_payout_split.deal_type.versioned_with()))
+        .filter(payout_splits::starts_at.is_not_null())
+        .filter(payout_splits::effective_to.is_null())
+        .filter(same_store_scope(&new_payout_split.store_id))
+        .load::<PayoutSplit>(conn)?;
+
+    Ok(match scheduled_psplits.iter().find(|ps| ps.overlaps(new_payout_split)) {
+        None => Ok(()),
+        Some(ps) => Err(DbError::PayoutSplitOverlapError(errJson!(format!(
+            "PayoutSplit from {:?} to {:?} overlaps existing PayoutSplit {} from {:?} to {:?}",
+            new_payout_split.starts_at,
+            new_payout_split.expires_at,
+            ps.id,
+            ps.starts_at,
+            ps.expires_at,
+        ))))
+    })
+}
+
+/// Ends a PayoutSplit without removing it, so it still shows
+/// in the history of rates that applied to past orders.
+pub fn retire_payout_split(
+    conn: &PgConnection,
+    payout_split_id: &str,
+    changed_by: Option<String>,
+    reason: Option<String>,
+) -> Result<PayoutSplit, DbError> {
+
+    use db::schema::payout_splits;
+
+    let now = chrono::NaiveDateTime::from_timestamp(chrono::Utc::now().timestamp(), 0);
+
+    diesel::update(payout_splits::table
+        .filter(payout_splits::id.eq(payout_split_id))
+        .filter(payout_splits::effective_to.is_null()))
+        .set((
+            payout_splits::effective_to.eq(now),
+            payout_splits::changed_by.eq(changed_by),
+            payout_splits::reason.eq(reason),
+        ))
+        .get_result::<PayoutSplit>(conn)
+        .map_err(|e| DbError::PayoutSplitWriteError(errJson!(e)))
+}
+
+/// Every version of a PayoutSplit, oldest first
+pub fn read_payout_split_history(
+    conn: &PgConnection,
+    payout_split_id: String,
+) -> Result<Vec<PayoutSplit>, DbError> {
+
+    use db::schema::payout_splits;
+
+    let payout_split = read_payout_split(conn, payout_split_id)?;
+
+    payout_splits::table
+        .filter(payout_splits::store_or_user_id.eq(payout_split.store_or_user_id))
+        .filter(payout_splits::deal_type.eq_any(payout_split.deal_type.versioned_with()))
+        .filter(same_store_scope(&payout_split.store_id))
+        .order_by(payout_splits::effective_from.asc())
+        .then_order_by(payout_splits::created_at.asc())
+        .load::<PayoutSplit>(conn)
+        .map_err(|e| DbError::PayoutSplitReadError(errJson!(e)))
+}
+
 pub fn read_payout_split(
     conn: &PgConnection,
     payout_split_id: String,
@@ -121,10 +298,40 @@ pub fn read_payout_splits_of_user_id(
 }
 
 
+/// PayoutSplit versions which have not been superseded or retired,
+/// including scheduled PayoutSplits which have not started yet.
+/// Reads all of them if store_or_user_ids is None.
+pub fn read_open_payout_splits(
+    conn: &PgConnection,
+    store_or_user_ids: Option<Vec<String>>,
+) -> Result<Vec<PayoutSplit>, DbError> {
+
+    use db::schema::payout_splits;
+
+    let query = payout_splits::table
+        .filter(payout_splits::effective_to.is_null())
+        .order_by(payout_splits::store_or_user_id.asc())
+        .then_order_by(payout_splits::deal_type.asc())
+        .then_order_by(payout_splits::starts_at.asc())
+        .into_boxed();
+
+    let query = match store_or_user_ids {
+        None => query,
+        Some(ids) => query.filter(payout_splits::store_or_user_id.eq_any(ids)),
+    };
+
+    query
+        .load::<PayoutSplit>(conn)
+        .map_err(|e| DbError::PayoutSplitReadError(errJson!(e)))
+}
+
+
+/// Reads the PayoutSplit versions in effect at valid_at (default: now)
 pub fn read_current_payout_splits_by_store_or_user_ids(
     conn: &PgConnection,
     store_or_user_ids: &Vec<String>,
     payout_deal_types: Option<Vec<PayoutDealType>>,
+    valid_at: Option<chrono::NaiveDateTime>,
 ) -> Result<Vec<PayoutSplit>, DbError> {
 
     use db::schema::payout_splits;
@@ -139,21 +346,34 @@ pub fn read_current_payout_splits_by_store_or_user_ids(
         ]
     };
 
+    let valid_at_arg = valid_at.unwrap_or(
+        chrono::NaiveDateTime::from_timestamp(chrono::Utc::now().timestamp(), 0)
+    );
+
     let result: Result<Vec<PayoutSplit>, DbError> = diesel::sql_query(format!(r#"
         SELECT rank_payout_splits_by_created_at_filter.* FROM (
             SELECT payout_splits.*,
             rank() OVER (
                 PARTITION BY store_or_user_id
-                ORDER BY created_at DESC
+                ORDER BY starts_at DESC NULLS LAST, effective_from DESC, created_at DESC
             )
             FROM payout_splits
             WHERE deal_type = ANY($2)
+            AND effective_from <= $3
+            AND (effective_to IS NULL OR effective_to > $3)
+            /* store-scoped deals are read with read_store_buyer_affiliate_payout_split */
+            AND store_id IS NULL
+            /* scheduled PayoutSplits only count within their window */
+            AND (starts_at IS NULL OR (
+                starts_at <= $3 AND (expires_at IS NULL OR expires_at > $3)
+            ))
         ) rank_payout_splits_by_created_at_filter
         WHERE RANK = 1
         AND store_or_user_id = ANY($1)
     "#))
     .bind::<Array<Text>, _>(store_or_user_ids)
     .bind::<Array<Text>, _>(payout_deal_types_arg)
+    .bind::<Timestamp, _>(valid_at_arg)
     .load(conn)
     .map_err(|e| DbError::PayoutSplitReadError(errJson!(e)));
     // cannot do .load::<Vec<PayoutSplit>>(), it will
@@ -163,29 +383,52 @@ pub fn read_current_payout_splits_by_store_or_user_ids(
 }
 
 
-pub fn try_read_buyer_psplit_create_on_null(
+/// Reads the buyer affiliate's BUYER_AFFILIATE PayoutSplit in effect at valid_at.
+/// Orders dated before the affiliate's first PayoutSplit use the current version.
+/// Writes nothing, unlike try_read_buyer_psplit_create_on_null.
+pub fn read_buyer_psplit(
     conn: &PgConnection,
-    buyer_affiliate_user_id: String,
-) -> Option<PayoutSplit> {
+    buyer_affiliate_user_id: &str,
+    valid_at: Option<chrono::NaiveDateTime>,
+) -> Result<Option<PayoutSplit>, DbError> {
 
-    let maybe_read_psplit = read_current_payout_splits_by_store_or_user_ids(
+    let psplit = read_current_payout_splits_by_store_or_user_ids(
         conn,
-        &vec![buyer_affiliate_user_id.clone()],
+        &vec![buyer_affiliate_user_id.to_string()],
         Some(vec![PayoutDealType::BUYER_AFFILIATE]),
-    ).ok();
+        valid_at,
+    )?.into_iter().next();
+
+    match (psplit, valid_at) {
+        (Some(psplit), _) => Ok(Some(psplit)),
+        (None, None) => Ok(None),
+        (None, Some(_)) => Ok(read_current_payout_splits_by_store_or_user_ids(
+            conn,
+            &vec![buyer_affiliate_user_id.to_string()],
+            Some(vec![PayoutDealType::BUYER_AFFILIATE]),
+            None,
+        )?.into_iter().next()),
+    }
+}
+
+
+pub fn try_read_buyer_psplit_create_on_null(
+    conn: &PgConnection,
+    buyer_affiliate_user_id: String,
+    valid_at: Option<chrono::NaiveDateTime>,
+) -> Option<PayoutSplit> {
 
     // if BUYER_AFFILIATE PayoutSplit exists, return early with it
-    if let Some(vpsplit) = maybe_read_psplit {
-        if let Some(psplit) = vpsplit.into_iter().next() {
-            return Some(psplit)
-        }
-    };
+    if let Ok(Some(psplit)) = read_buyer_psplit(conn, &buyer_affiliate_user_id, valid_at) {
+        return Some(psplit)
+    }
 
     // otherwise write a BUYER_AFFILIATE PayoutSplit for this userId
     // and return it
     let new_ba_payout_split = PayoutSplit::new(
         buyer_affiliate_user_id,
         PayoutDealType::BUYER_AFFILIATE,
+        None,
         None, // Buyer affiliate deal never expires
         BUYER_AFFILIATE_FEE_PERCENTAGE, // Default rate for Buyer Affiliates
         None,
@@ -195,9 +438,47 @@ pub fn try_read_buyer_psplit_create_on_null(
 }
 
 
+/// The BUYER_AFFILIATE deal an affiliate has with a store at valid_at (default: now)
+pub fn read_store_buyer_affiliate_payout_split(
+    conn: &PgConnection,
+    buyer_affiliate_user_id: &str,
+    store_id: &str,
+    valid_at: Option<chrono::NaiveDateTime>,
+) -> Result<Option<PayoutSplit>, DbError> {
+
+    use db::schema::payout_splits;
+
+    let valid_at = valid_at.unwrap_or(
+        chrono::NaiveDateTime::from_timestamp(chrono::Utc::now().timestamp(), 0)
+    );
+
+    payout_splits::table
+        .filter(payout_splits::store_or_user_id.eq(buyer_affiliate_user_id))
+        .filter(payout_splits::store_id.eq(store_id))
+        .filter(payout_splits::deal_type.eq(PayoutDealType::BUYER_AFFILIATE))
+        .filter(payout_splits::effective_from.le(valid_at))
+        .filter(payout_splits::effective_to.is_null()
+            .or(payout_splits::effective_to.gt(valid_at)))
+        .filter(payout_splits::starts_at.is_null()
+            .or(payout_splits::starts_at.le(valid_at)))
+        .filter(payout_splits::expires_at.is_null()
+            .or(payout_splits::expires_at.gt(valid_at)))
+        // scheduled deals first, then the latest version
+        .order_by(payout_splits::starts_at.desc().nulls_last())
+        .then_order_by(payout_splits::effective_from.desc())
+        .then_order_by(payout_splits::created_at.desc())
+        .first::<PayoutSplit>(conn)
+        .optional()
+        .map_err(|e| DbError::PayoutSplitReadError(errJson!(e)))
+}
+
+
+/// Reads the seller's PayoutSplit and their referrer's SELLER_AFFILIATE PayoutSplit,
+/// both as they were at valid_at (default: now)
 pub fn read_current_seller_referrer_payout_splits_by_store_id(
     conn: &PgConnection,
     store_or_user_id: String,
+    valid_at: Option<chrono::NaiveDateTime>,
 ) -> Option<PayoutSplitSellerAndAffiliate> {
 
     use db::schema::payout_splits;
@@ -206,6 +487,10 @@ pub fn read_current_seller_referrer_payout_splits_by_store_id(
         &store_or_user_id
     );
 
+    let valid_at_arg = valid_at.unwrap_or(
+        chrono::NaiveDateTime::from_timestamp(chrono::Utc::now().timestamp(), 0)
+    );
+
     let result: Result<PayoutSplitSellerAndAffiliate, DbError> =
     diesel::sql_query(format!(r#"
 
@@ -215,20 +500,42 @@ pub fn read_current_seller_referrer_payout_splits_by_store_id(
                     payout_splits.*,
                     rank() OVER (
                         PARTITION BY store_or_user_id
-                        ORDER BY created_at DESC
+                        ORDER BY starts_at DESC NULLS LAST, effective_from DESC, created_at DESC
                     )
                 FROM payout_splits
-                WHERE payout_splits.deal_type = 'REFERRED_SELLER'
-                    OR payout_splits.deal_type = 'SELLER'
+                WHERE (payout_splits.deal_type = 'REFERRED_SELLER'
+                    OR payout_splits.deal_type = 'SELLER')
+                AND payout_splits.effective_from <= $2
+                AND (payout_splits.effective_to IS NULL OR payout_splits.effective_to > $2)
+                AND (payout_splits.starts_at IS NULL OR (
+                    payout_splits.starts_at <= $2
+                    AND (payout_splits.expires_at IS NULL OR payout_splits.expires_at > $2)
+                ))
             ) rank_payout_splits_by_created_at_filter
             WHERE RANK = 1
             AND store_or_user_id = $1
         ),
 
+        /* referrer_id points to the version of the referrer's PayoutSplit
+        at the time of referral, lookup the version in effect at $2 instead */
         current_aff_psplits AS (
-            SELECT *
-            FROM payout_splits
-            WHERE payout_splits.deal_type = 'SELLER_AFFILIATE'
+            SELECT rank_payout_splits_by_created_at_filter.* FROM (
+                SELECT
+                    payout_splits.*,
+                    rank() OVER (
+                        PARTITION BY store_or_user_id
+                        ORDER BY starts_at DESC NULLS LAST, effective_from DESC, created_at DESC
+                    )
+                FROM payout_splits
+                WHERE payout_splits.deal_type = 'SELLER_AFFILIATE'
+                AND payout_splits.effective_from <= $2
+                AND (payout_splits.effective_to IS NULL OR payout_splits.effective_to > $2)
+                AND (payout_splits.starts_at IS NULL OR (
+                    payout_splits.starts_at <= $2
+                    AND (payout_splits.expires_at IS NULL OR payout_splits.expires_at > $2)
+                ))
+            ) rank_payout_splits_by_created_at_filter
+            WHERE RANK = 1
         )
 
         SELECT
@@ -239,7 +546,14 @@ pub fn read_current_seller_referrer_payout_splits_by_store_id(
                 'dealType', current_psplits.deal_type,
                 'expiresAt', current_psplits.expires_at,
                 'rate', current_psplits.rate,
-                'referrerId', current_psplits.referrer_id
+                'referrerId', current_psplits.referrer_id,
+                'effectiveFrom', current_psplits.effective_from,
+                'effectiveTo', current_psplits.effective_to,
+                'changedBy', current_psplits.changed_by,
+                'reason', current_psplits.reason,
+                'startsAt', current_psplits.starts_at,
+                'rateOverride', current_psplits.rate_override,
+                'storeId', current_psplits.store_id
                 /* 'rank', current_psplits.rank */
             ) as referred_seller,
 
@@ -253,18 +567,28 @@ pub fn read_current_seller_referrer_payout_splits_by_store_id(
 		                'dealType', payout_splits.deal_type,
 		                'expiresAt', payout_splits.expires_at,
 		                'rate', payout_splits.rate,
-		                'referrerId', payout_splits.referrer_id
+		                'referrerId', payout_splits.referrer_id,
+		                'effectiveFrom', payout_splits.effective_from,
+		                'effectiveTo', payout_splits.effective_to,
+		                'changedBy', payout_splits.changed_by,
+		                'reason', payout_splits.reason,
+		                'startsAt', payout_splits.starts_at,
+		                'rateOverride', payout_splits.rate_override,
+		                'storeId', payout_splits.store_id
 		                /* 'rank', payout_splits.rank */
 		            )
 				ELSE null
 			END as seller_affiliate
 
         FROM current_psplits
+        LEFT JOIN payout_splits referrer_psplits
+            ON referrer_psplits.id = current_psplits.referrer_id
         LEFT JOIN current_aff_psplits payout_splits
-            ON payout_splits.id = current_psplits.referrer_id
+            ON payout_splits.store_or_user_id = referrer_psplits.store_or_user_id
 
     "#))
     .bind::<Text, _>(&store_or_user_id)
+    .bind::<Timestamp, _>(valid_at_arg)
     .get_result(conn)
     .map_err(|e| DbError::PayoutSplitReadError(errJson!(e)));
     // cannot do .load::<Vec<PayoutSplit>>(), it will
@@ -283,6 +607,39 @@ pub fn read_current_seller_referrer_payout_splits_by_store_id(
 }
 
 
+/// Reads the referrers above a seller affiliate, nearest first,
+/// as deep as there are UPLINE_REFERRAL_FEE_PERCENTAGES levels.
+///
+/// referrer_id may point to an old version of a referrer's PayoutSplit,
+/// so each referrer's version in effect at valid_at (default: now) is used.
+/// Retired referrers keep their last version, which is no longer active.
+pub fn read_upline_payout_splits(
+    conn: &PgConnection,
+    seller_aff_payout_split: &PayoutSplit,
+    valid_at: Option<chrono::NaiveDateTime>,
+) -> Result<Vec<PayoutSplit>, DbError> {
+    walk_referrer_chain(
+        seller_aff_payout_split,
+        UPLINE_REFERRAL_FEE_PERCENTAGES.len(),
+        |psplit_id: &str| {
+            let referrer_psplit = match read_payout_splits_by_ids(conn, vec![psplit_id.to_string()])?
+                .into_iter()
+                .next() {
+                    None => return Ok(None),
+                    Some(ps) => ps,
+                };
+            let current_psplit = read_current_payout_splits_by_store_or_user_ids(
+                conn,
+                &vec![referrer_psplit.store_or_user_id.clone()],
+                Some(vec![PayoutDealType::SELLER_AFFILIATE]),
+                valid_at,
+            )?.into_iter().next();
+            Ok(Some(current_psplit.unwrap_or(referrer_psplit)))
+        },
+    )
+}
+
+
 // pub fn read_current_seller_referrer_payout_splits_by_store_ids(
 //     conn: &PgConnection,
 //     store_or_user_ids: Vec<String>,
@@ -379,31 +736,27 @@ pub fn read_current_seller_referrer_payout_splits_by_store_id(
 //     }
 // }
 
-/// Only used for testing. In production you never delete payoutSplits
-/// because it would just revert to the previous dated payoutSplits
-pub fn delete_payout_split(
-    conn: &PgConnection,
-    payout_split_id: &str,
-) -> Result<PayoutSplit, DbError> {
-
-    use db::schema::payout_splits;
-
-    diesel::delete(payout_splits::table
-        .filter(payout_splits::id.eq(payout_split_id)))
-        .get_result::<PayoutSplit>(conn)
-        .map_err(|e| DbError::PayoutSplitWriteError(errJson!(e)))
-
-}
-
-pub fn delete_all_payout_splits_for_user_id(
+/// Retires every PayoutSplit of a user, keeping them in the history of
+/// rates that applied to past orders, like retire_payout_split.
+pub fn retire_all_payout_splits_for_user_id(
     conn: &PgConnection,
     user_id: &str,
-) -> Result<PayoutSplit, DbError> {
+    changed_by: Option<String>,
+    reason: Option<String>,
+) -> Result<Vec<PayoutSplit>, DbError> {
 
     use db::schema::payout_splits;
 
-    diesel::delete(payout_splits::table
-        .filter(payout_splits::store_or_user_id.eq(user_id)))
-        .get_result::<PayoutSplit>(conn)
+    let now = chrono::NaiveDateTime::from_timestamp(chrono::Utc::now().timestamp(), 0);
+
+    diesel::update(payout_splits::table
+        .filter(payout_splits::store_or_user_id.eq(user_id))
+        .filter(payout_splits::effective_to.is_null()))
+        .set((
+            payout_splits::effective_to.eq(now),
+            payout_splits::changed_by.eq(changed_by),
+            payout_splits::reason.eq(reason),
+        ))
+        .get_results::<PayoutSplit>(conn)
         .map_err(|e| DbError::PayoutSplitWriteError(errJson!(e)))
 }
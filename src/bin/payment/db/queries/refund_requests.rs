@@ -0,0 +1,133 @@
+use diesel::prelude::*;
+use diesel::PgConnection;
+use gm::db;
+
+use crate::models::{
+    RefundRequest,
+    RefundRequestStatus,
+    ErrJson,
+    DbError,
+};
+
+
+////////////////////////
+/// Refund Requests
+////////////////////////
+
+
+pub fn write_refund_request(
+    conn: &PgConnection,
+    refund_request: &RefundRequest,
+) -> Result<RefundRequest, DbError> {
+
+    use db::schema::refund_requests;
+
+    diesel::insert_into(refund_requests::table)
+        .values(refund_request)
+        .get_result::<RefundRequest>(conn)
+        .map_err(|e| DbError::RefundRequestWriteError(errJson!(e)))
+}
+
+
+pub fn read_refund_request(
+    conn: &PgConnection,
+    refund_request_id: &str,
+) -> Result<Option<RefundRequest>, DbError> {
+
+    use db::schema::refund_requests;
+
+    refund_requests::table
+        .filter(refund_requests::id.eq(refund_request_id))
+        .first::<RefundRequest>(conn)
+        .optional()
+        .map_err(|e| DbError::RefundRequestReadError(errJson!(e)))
+}
+
+
+pub fn read_refund_requests_by_status(
+    conn: &PgConnection,
+    status: RefundRequestStatus,
+) -> Result<Vec<RefundRequest>, DbError> {
+
+    use db::schema::refund_requests;
+
+    refund_requests::table
+        .filter(refund_requests::status.eq(status))
+        .order(refund_requests::created_at.asc())
+        .load::<RefundRequest>(conn)
+        .map_err(|e| DbError::RefundRequestReadError(errJson!(e)))
+}
+
+
+/// Refund requests of an order that are waiting on, or being refunded after, approval.
+pub fn read_open_refund_requests_of_order(
+    conn: &PgConnection,
+    order_id: &str,
+) -> Result<Vec<RefundRequest>, DbError> {
+
+    use db::schema::refund_requests;
+
+    refund_requests::table
+        .filter(refund_requests::order_id.eq(order_id))
+        .filter(refund_requests::status.eq_any(vec![
+            RefundRequestStatus::PENDING_APPROVAL,
+            RefundRequestStatus::APPROVED,
+        ]))
+        .order(refund_requests::created_at.asc())
+        .load::<RefundRequest>(conn)
+        .map_err(|e| DbError::RefundRequestReadError(errJson!(e)))
+}
+
+
+/// Moves a refund request out of PENDING_APPROVAL, if it still is.
+/// Returns None if another admin approved or rejected it first,
+/// so an approved refund is only ever made once.
+pub fn review_refund_request(
+    conn: &PgConnection,
+    refund_request_id: &str,
+    status: RefundRequestStatus,
+    reviewed_by: &str,
+    reviewed_at: chrono::NaiveDateTime,
+    review_notes: Option<String>,
+) -> Result<Option<RefundRequest>, DbError> {
+
+    use db::schema::refund_requests;
+
+    diesel::update(
+        refund_requests::table
+            .filter(refund_requests::id.eq(refund_request_id))
+            .filter(refund_requests::status.eq(RefundRequestStatus::PENDING_APPROVAL))
+    )
+    .set((
+        refund_requests::status.eq(status),
+        refund_requests::reviewed_by.eq(reviewed_by),
+        refund_requests::reviewed_at.eq(reviewed_at),
+        refund_requests::review_notes.eq(review_notes),
+    ))
+    .get_result::<RefundRequest>(conn)
+    .optional()
+    .map_err(|e| DbError::RefundRequestWriteError(errJson!(e)))
+}
+
+
+/// Records the outcome of an approved refund: REFUNDED with its refund,
+/// or back to PENDING_APPROVAL with the error, to be approved again.
+pub fn update_refund_request_outcome(
+    conn: &PgConnection,
+    refund_request: &RefundRequest,
+) -> Result<RefundRequest, DbError> {
+
+    use db::schema::refund_requests;
+
+    diesel::update(
+        refund_requests::table
+            .filter(refund_requests::id.eq(&refund_request.id))
+    )
+    .set((
+        refund_requests::status.eq(&refund_request.status),
+        refund_requests::refund_id.eq(&refund_request.refund_id),
+        refund_requests::last_error.eq(&refund_request.last_error),
+    ))
+    .get_result::<RefundRequest>(conn)
+    .map_err(|e| DbError::RefundRequestWriteError(errJson!(e)))
+}
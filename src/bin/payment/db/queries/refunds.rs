@@ -13,6 +13,10 @@ use crate::models::{
     PaymentMethodDb,
     PaymentMethodAddress,
     Refund,
+    RefundStatus,
+    RefundAggregate,
+    RefundAnalyticsGroupBy,
+    RefundPeriod,
     PayoutItem,
     Payout,
     ConnectionQuery,
@@ -64,4 +68,275 @@ pub fn read_many_refunds(
         .filter(refunds::id.eq_any(refund_ids))
         .load::<Refund>(conn)
         .map_err(|e| DbError::RefundReadError(errJson!(e)))
-}
\ No newline at end of file
+}
+
+
+pub fn read_refunds_by_status(
+    conn: &PgConnection,
+    status: RefundStatus,
+    limit_count: i64,
+) -> Result<Vec<Refund>, DbError> {
+
+    use db::schema::refunds;
+
+    refunds::table
+        .filter(refunds::status.eq(status))
+        .order(refunds::created_at.asc())
+        .limit(limit_count)
+        .load::<Refund>(conn)
+        .map_err(|e| DbError::RefundReadError(errJson!(e)))
+}
+
+/// Total refunded on an order so far, from the transactions of its refunds.
+/// Failed and canceled refunds have been reversed and are left out.
+pub fn read_refunded_total_of_order(
+    conn: &PgConnection,
+    order_id: &str,
+) -> Result<i32, DbError> {
+
+    use db::schema::refunds;
+    use db::schema::transactions;
+
+    let refund_ids = refunds::table
+        .filter(refunds::order_id.eq(order_id))
+        .filter(refunds::status.ne_all(vec![
+            RefundStatus::FAILED,
+            RefundStatus::CANCELED,
+        ]))
+        .select(refunds::id)
+        .load::<String>(conn)
+        .map_err(|e| DbError::RefundReadError(errJson!(e)))?;
+
+    // refund transactions are negative
+    transactions::table
+        .filter(transactions::id.eq_any(refund_ids))
+        .load::<Transaction>(conn)
+        .map(|txs| txs.iter().map(|tx| -(tx.subtotal + tx.taxes)).sum())
+        .map_err(|e| DbError::TransactionReadError(errJson!(e)))
+}
+
+/// Updates a refund's status, and its balance transaction once Stripe has one
+pub fn update_refund_status(
+    conn: &PgConnection,
+    refund: &Refund,
+) -> Result<Refund, DbError> {
+
+    use db::schema::refunds;
+
+    diesel::update(
+        refunds::table
+            .filter(refunds::id.eq(&refund.id))
+    )
+    .set((
+        refunds::transaction_id.eq(&refund.transaction_id),
+        refunds::status.eq(&refund.status),
+        refunds::failure_reason.eq(&refund.failure_reason),
+        refunds::updated_at.eq(&refund.updated_at),
+    ))
+    .get_result::<Refund>(conn)
+    .map_err(|e| DbError::RefundWriteError(errJson!(e)))
+}
+
+/// Refunds whose order update in gm-shopping failed, oldest first
+pub fn read_refunds_pending_order_update(
+    conn: &PgConnection,
+    limit_count: i64,
+) -> Result<Vec<Refund>, DbError> {
+
+    use db::schema::refunds;
+
+    refunds::table
+        .filter(refunds::order_updated_at.is_null())
+        .order(refunds::created_at.asc())
+        .limit(limit_count)
+        .load::<Refund>(conn)
+        .map_err(|e| DbError::RefundReadError(errJson!(e)))
+}
+
+pub fn update_refund_order_updated_at(
+    conn: &PgConnection,
+    refund_id: &str,
+    order_updated_at: Option<chrono::NaiveDateTime>,
+) -> Result<Refund, DbError> {
+
+    use db::schema::refunds;
+
+    diesel::update(
+        refunds::table
+            .filter(refunds::id.eq(refund_id))
+    )
+    .set(refunds::order_updated_at.eq(order_updated_at))
+    .get_result::<Refund>(conn)
+    .map_err(|e| DbError::RefundWriteError(errJson!(e)))
+}
+
+/// Marks a refund failed (or canceled), and writes the transaction and
+/// payout items which reverse it, all at once. The order is marked as
+/// needing an update, so gm-shopping reverts the order's refund.
+pub fn write_refund_reversal(
+    conn: &PgConnection,
+    refund: &Refund,
+    reversal_tx: Option<&Transaction>,
+    reversal_items: &Vec<PayoutItem>,
+) -> Result<Refund, DbError> {
+
+    use db::schema::refunds;
+    use db::schema::transactions;
+    use db::schema::payout_items;
+
+    conn.transaction::<Refund, diesel::result::Error, _>(|| {
+
+        let refund = diesel::update(
+            refunds::table
+                .filter(refunds::id.eq(&refund.id))
+        )
+        .set((
+            refunds::transaction_id.eq(&refund.transaction_id),
+            refunds::status.eq(&refund.status),
+            refunds::failure_reason.eq(&refund.failure_reason),
+            refunds::updated_at.eq(&refund.updated_at),
+            refunds::order_updated_at.eq(None::<chrono::NaiveDateTime>),
+        ))
+        .get_result::<Refund>(conn)?;
+
+        if let Some(tx) = reversal_tx {
+            diesel::insert_into(transactions::table)
+                .values(tx)
+                .execute(conn)?;
+        }
+
+        diesel::insert_into(payout_items::table)
+            .values(reversal_items)
+            .execute(conn)?;
+
+        Ok(refund)
+
+    }).map_err(|e| DbError::RefundWriteError(errJson!(e)))
+}
+
+
+/// Refund counts, amounts and rates from start to end, grouped by store,
+/// refund reason or period. Failed and canceled refunds are left out.
+///
+/// Refunds are read from refunds joined to their transactions, except for
+/// stores: a store's refunds are its share of each refund's payout items.
+pub fn read_refund_aggregates(
+    conn: &PgConnection,
+    start_date: chrono::NaiveDateTime,
+    end_date: chrono::NaiveDateTime,
+    group_by: &RefundAnalyticsGroupBy,
+    period: &RefundPeriod,
+) -> Result<Vec<RefundAggregate>, DbError> {
+
+    use diesel::sql_types::Timestamp;
+
+    let refunded_statuses = format!(
+        "r.status NOT IN ('{}', '{}')",
+        RefundStatus::FAILED.as_string(),
+        RefundStatus::CANCELED.as_string(),
+    );
+
+    // (refunded, sold, how sales join refunds)
+    let (refunded, sold, join_on) = match group_by {
+        RefundAnalyticsGroupBy::STORE => (
+            format!(r#"
+                SELECT
+                    p.payee_id AS group_key,
+                    COUNT(DISTINCT r.id) AS refund_count,
+                    -COALESCE(SUM(p.amount + p.payment_processing_fee), 0) AS refund_amount
+                FROM refunds r
+                JOIN transactions t ON t.id = r.id
+                JOIN payout_items p ON p.txn_id = r.id
+                WHERE {}
+                    AND p.payee_type = 'STORE'
+                    AND (p.id LIKE 'ritem_%' OR p.id LIKE 'citem_%')
+                    AND t.created_at >= $1 AND t.created_at < $2
+                GROUP BY p.payee_id
+            "#, refunded_statuses),
+            String::from(r#"
+                SELECT
+                    payee_id AS group_key,
+                    COUNT(DISTINCT txn_id) AS sales_count,
+                    COALESCE(SUM(amount + payment_processing_fee), 0) AS sales_amount
+                FROM payout_items
+                WHERE payee_type = 'STORE'
+                    AND id LIKE 'pitem_%'
+                    AND created_at >= $1 AND created_at < $2
+                GROUP BY payee_id
+            "#),
+            "s.group_key = r.group_key",
+        ),
+        RefundAnalyticsGroupBy::REASON => (
+            format!(r#"
+                SELECT
+                    COALESCE(r.reason, 'unknown') AS group_key,
+                    COUNT(*) AS refund_count,
+                    -COALESCE(SUM(t.subtotal + t.taxes), 0) AS refund_amount
+                FROM refunds r
+                JOIN transactions t ON t.id = r.id
+                WHERE {}
+                    AND t.created_at >= $1 AND t.created_at < $2
+                GROUP BY COALESCE(r.reason, 'unknown')
+            "#, refunded_statuses),
+            String::from(r#"
+                SELECT
+                    COUNT(*) AS sales_count,
+                    COALESCE(SUM(subtotal + taxes), 0) AS sales_amount
+                FROM transactions
+                WHERE refund_id IS NULL
+                    AND created_at >= $1 AND created_at < $2
+            "#),
+            "TRUE",
+        ),
+        RefundAnalyticsGroupBy::PERIOD => (
+            format!(r#"
+                SELECT
+                    to_char(date_trunc('{period}', t.created_at), 'YYYY-MM-DD') AS group_key,
+                    COUNT(*) AS refund_count,
+                    -COALESCE(SUM(t.subtotal + t.taxes), 0) AS refund_amount
+                FROM refunds r
+                JOIN transactions t ON t.id = r.id
+                WHERE {statuses}
+                    AND t.created_at >= $1 AND t.created_at < $2
+                GROUP BY 1
+            "#, period = period.as_date_trunc(), statuses = refunded_statuses),
+            format!(r#"
+                SELECT
+                    to_char(date_trunc('{period}', created_at), 'YYYY-MM-DD') AS group_key,
+                    COUNT(*) AS sales_count,
+                    COALESCE(SUM(subtotal + taxes), 0) AS sales_amount
+                FROM transactions
+                WHERE refund_id IS NULL
+                    AND created_at >= $1 AND created_at < $2
+                GROUP BY 1
+            "#, period = period.as_date_trunc()),
+            "s.group_key = r.group_key",
+        ),
+    };
+
+    let order_by = match group_by {
+        RefundAnalyticsGroupBy::PERIOD => "r.group_key ASC",
+        _ => "refund_amount DESC",
+    };
+
+    diesel::sql_query(format!(r#"
+        WITH refunded AS ({}), sold AS ({})
+        SELECT
+            r.group_key,
+            r.refund_count,
+            r.refund_amount,
+            COALESCE(s.sales_count, 0) AS sales_count,
+            COALESCE(s.sales_amount, 0) AS sales_amount,
+            CASE WHEN COALESCE(s.sales_count, 0) > 0
+                THEN r.refund_count::float8 / s.sales_count
+                ELSE 0
+            END AS refund_rate
+        FROM refunded r
+        LEFT JOIN sold s ON {}
+        ORDER BY {}
+    "#, refunded, sold, join_on, order_by))
+    .bind::<Timestamp, _>(start_date)
+    .bind::<Timestamp, _>(end_date)
+    .load::<RefundAggregate>(conn)
+    .map_err(|e| DbError::RefundReadError(errJson!(e)))
+}
@@ -0,0 +1,65 @@
+use diesel::prelude::*;
+use diesel::PgConnection;
+use gm::db;
+
+use crate::models::{
+    RevenueShare,
+    ErrJson,
+    DbError,
+};
+
+
+////////////////////////
+/// Revenue Shares
+////////////////////////
+
+
+/// Replaces a store's whole revenue-share table.
+/// Both succeed or none do.
+pub fn write_revenue_shares_for_store(
+    conn: &PgConnection,
+    store_id: &str,
+    revenue_shares: Vec<RevenueShare>,
+) -> Result<Vec<RevenueShare>, DbError> {
+
+    use db::schema::revenue_shares;
+
+    conn.transaction::<Vec<RevenueShare>, diesel::result::Error, _>(|| {
+
+        diesel::delete(revenue_shares::table)
+            .filter(revenue_shares::store_id.eq(store_id))
+            .execute(conn)?;
+
+        diesel::insert_into(revenue_shares::table)
+            .values(&revenue_shares)
+            .get_results::<RevenueShare>(conn)
+
+    }).map_err(|e| DbError::RevenueShareWriteError(errJson!(e)))
+}
+
+pub fn read_revenue_shares_by_store_id(
+    conn: &PgConnection,
+    store_id: &str,
+) -> Result<Vec<RevenueShare>, DbError> {
+
+    use db::schema::revenue_shares;
+
+    revenue_shares::table
+        .filter(revenue_shares::store_id.eq(store_id))
+        .order_by(revenue_shares::created_at.asc())
+        .load::<RevenueShare>(conn)
+        .map_err(|e| DbError::RevenueShareReadError(errJson!(e)))
+}
+
+pub fn delete_revenue_shares_for_store(
+    conn: &PgConnection,
+    store_id: &str,
+) -> Result<Vec<RevenueShare>, DbError> {
+
+    use db::schema::revenue_shares;
+
+    diesel::delete(revenue_shares::table)
+        .filter(revenue_shares::store_id.eq(store_id))
+        .get_results::<RevenueShare>(conn)
+        .map_err(|e| DbError::RevenueShareWriteError(errJson!(e)))
+}
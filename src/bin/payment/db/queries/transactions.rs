@@ -15,6 +15,7 @@ use crate::models::{
     PaymentMethodAddress,
     Refund,
     PayoutItem,
+    PayoutCalculation,
     Payout,
     ConnectionQuery,
 };
@@ -176,6 +177,68 @@ pub fn read_many_transactions_by_ids(
         .map_err(|e| DbError::TransactionReadError(errJson!(e)))
 }
 
+/// The payment (not refund) transaction of a Stripe charge.
+/// Stripe payments record the PaymentIntent id as the charge_id.
+pub fn read_stripe_payment_transaction(
+    conn: &PgConnection,
+    payment_intent_id: Option<String>,
+    charge_id: &str,
+) -> Result<Option<Transaction>, DbError> {
+
+    use db::schema::transactions;
+
+    let charge_ids = match payment_intent_id.clone() {
+        Some(pi) => vec![charge_id.to_string(), pi],
+        None => vec![charge_id.to_string()],
+    };
+
+    transactions::table
+        .filter(transactions::refund_id.is_null())
+        .filter(
+            transactions::charge_id.eq_any(charge_ids)
+            .or(transactions::payment_intent_id.eq(payment_intent_id))
+        )
+        .first::<Transaction>(conn)
+        .optional()
+        .map_err(|e| DbError::TransactionReadError(errJson!(e)))
+}
+
+/// The payment (not refund) transaction of a Paypal sale.
+/// Paypal payments record the sale (capture) id as the charge_id.
+pub fn read_paypal_payment_transaction(
+    conn: &PgConnection,
+    sale_id: &str,
+) -> Result<Option<Transaction>, DbError> {
+
+    use db::schema::transactions;
+
+    transactions::table
+        .filter(transactions::refund_id.is_null())
+        .filter(transactions::charge_id.eq(sale_id))
+        .first::<Transaction>(conn)
+        .optional()
+        .map_err(|e| DbError::TransactionReadError(errJson!(e)))
+}
+
+
+/// The payment (not refund) transaction of an order.
+pub fn read_payment_transaction_of_order(
+    conn: &PgConnection,
+    order_id: &str,
+) -> Result<Option<Transaction>, DbError> {
+
+    use db::schema::transactions;
+
+    transactions::table
+        .filter(transactions::refund_id.is_null())
+        .filter(transactions::order_id.eq(order_id))
+        .order(transactions::created_at.asc())
+        .first::<Transaction>(conn)
+        .optional()
+        .map_err(|e| DbError::TransactionReadError(errJson!(e)))
+}
+
+
 pub fn read_recent_transactions(
     conn: &PgConnection,
     limit_count: i64,
@@ -232,10 +295,12 @@ pub fn write_transaction_and_payout_items(
     conn: &PgConnection,
     tx: &Transaction,
     payout_items: &Vec<PayoutItem>,
+    payout_calculations: &Vec<PayoutCalculation>,
 ) -> Result<(Transaction, Vec<PayoutItem>), DbError> {
 
     use db::schema::transactions;
     use db::schema::payout_items;
+    use db::schema::payout_calculations;
 
     conn.transaction::<(Transaction, Vec<PayoutItem>), diesel::result::Error, _>(|| {
 
@@ -247,10 +312,16 @@ pub fn write_transaction_and_payout_items(
             .values(payout_items)
             .load::<PayoutItem>(conn);
 
-        match (tx_result, pitem_result) {
-            (Ok(t), Ok(p)) => Ok((t, p)),
-            (Err(e1), _) => Err(e1),
-            (_, Err(e2)) => Err(e2),
+        // inputs of the split, written with the payout_items they produced
+        let pcalc_result = diesel::insert_into(payout_calculations::table)
+            .values(payout_calculations)
+            .execute(conn);
+
+        match (tx_result, pitem_result, pcalc_result) {
+            (Ok(t), Ok(p), Ok(_)) => Ok((t, p)),
+            (Err(e1), _, _) => Err(e1),
+            (_, Err(e2), _) => Err(e2),
+            (_, _, Err(e3)) => Err(e3),
         }
 
     }).map_err(|e| DbError::PayoutItemWriteError(errJson!(e)))
@@ -0,0 +1,149 @@
+use diesel::prelude::*;
+use diesel::PgConnection;
+use gm::db;
+
+use crate::models::{
+    WebhookEvent,
+    WebhookEventStatus,
+    ErrJson,
+    DbError,
+};
+
+
+////////////////////////
+/// Webhook Events
+////////////////////////
+
+
+/// Writes a webhook event before it is processed.
+/// Returns None if the provider already delivered an event with the same id.
+pub fn write_webhook_event(
+    conn: &PgConnection,
+    webhook_event: &WebhookEvent,
+) -> Result<Option<WebhookEvent>, DbError> {
+
+    use db::schema::webhook_events;
+
+    diesel::insert_into(webhook_events::table)
+        .values(webhook_event)
+        .on_conflict((webhook_events::provider, webhook_events::event_id))
+        .do_nothing()
+        .get_result::<WebhookEvent>(conn)
+        .optional()
+        .map_err(|e| DbError::WebhookEventWriteError(errJson!(e)))
+}
+
+
+/// Records the outcome of processing a webhook event
+pub fn update_webhook_event_status(
+    conn: &PgConnection,
+    webhook_event: &WebhookEvent,
+) -> Result<WebhookEvent, DbError> {
+
+    use db::schema::webhook_events;
+
+    diesel::update(
+        webhook_events::table
+            .filter(webhook_events::id.eq(&webhook_event.id))
+    )
+    .set((
+        webhook_events::processing_status.eq(webhook_event.processing_status.as_string()),
+        webhook_events::attempts.eq(webhook_event.attempts),
+        webhook_events::last_error.eq(&webhook_event.last_error),
+        webhook_events::next_attempt_at.eq(&webhook_event.next_attempt_at),
+        webhook_events::processed_at.eq(&webhook_event.processed_at),
+    ))
+    .get_result::<WebhookEvent>(conn)
+    .map_err(|e| DbError::WebhookEventWriteError(errJson!(e)))
+}
+
+
+/// Marks a webhook event PROCESSING until claimed_until, only if it still has
+/// the status it was read with. A PROCESSING event can only be claimed again
+/// once its claim went stale. Returns None if it was claimed elsewhere.
+pub fn claim_webhook_event(
+    conn: &PgConnection,
+    webhook_event: &WebhookEvent,
+    now: chrono::NaiveDateTime,
+    claimed_until: chrono::NaiveDateTime,
+) -> Result<Option<WebhookEvent>, DbError> {
+
+    use db::schema::webhook_events;
+
+    diesel::update(
+        webhook_events::table
+            .filter(webhook_events::id.eq(&webhook_event.id))
+            .filter(webhook_events::processing_status.eq(webhook_event.processing_status.as_string()))
+            .filter(
+                webhook_events::processing_status.ne(WebhookEventStatus::PROCESSING.as_string())
+                .or(webhook_events::next_attempt_at.le(now))
+            )
+    )
+    .set((
+        webhook_events::processing_status.eq(WebhookEventStatus::PROCESSING.as_string()),
+        webhook_events::next_attempt_at.eq(Some(claimed_until)),
+    ))
+    .get_result::<WebhookEvent>(conn)
+    .optional()
+    .map_err(|e| DbError::WebhookEventWriteError(errJson!(e)))
+}
+
+
+pub fn read_webhook_event(
+    conn: &PgConnection,
+    webhook_event_id: &str,
+) -> Result<WebhookEvent, DbError> {
+
+    use db::schema::webhook_events;
+
+    webhook_events::table
+        .filter(webhook_events::id.eq(webhook_event_id))
+        .first::<WebhookEvent>(conn)
+        .map_err(|e| DbError::WebhookEventReadError(errJson!(e)))
+}
+
+
+/// Webhook events received from start to end (exclusive), oldest first
+pub fn read_webhook_events_by_received_at(
+    conn: &PgConnection,
+    start: chrono::NaiveDateTime,
+    end: chrono::NaiveDateTime,
+) -> Result<Vec<WebhookEvent>, DbError> {
+
+    use db::schema::webhook_events;
+
+    webhook_events::table
+        .filter(webhook_events::received_at.ge(start))
+        .filter(webhook_events::received_at.lt(end))
+        .order(webhook_events::received_at.asc())
+        .load::<WebhookEvent>(conn)
+        .map_err(|e| DbError::WebhookEventReadError(errJson!(e)))
+}
+
+
+/// Failed webhook events whose backoff has passed, events received
+/// before stale_before which were never processed, and events whose
+/// PROCESSING claim went stale, oldest first
+pub fn read_webhook_events_due_for_retry(
+    conn: &PgConnection,
+    now: chrono::NaiveDateTime,
+    stale_before: chrono::NaiveDateTime,
+    limit_count: i64,
+) -> Result<Vec<WebhookEvent>, DbError> {
+
+    use db::schema::webhook_events;
+
+    webhook_events::table
+        .filter(
+            webhook_events::processing_status.eq(WebhookEventStatus::FAILED.as_string())
+                .and(webhook_events::next_attempt_at.le(now))
+            .or(webhook_events::processing_status.eq(WebhookEventStatus::RECEIVED.as_string())
+                .and(webhook_events::received_at.le(stale_before)))
+            .or(webhook_events::processing_status.eq(WebhookEventStatus::PROCESSING.as_string())
+                .and(webhook_events::next_attempt_at.le(now)))
+        )
+        .order(webhook_events::received_at.asc())
+        .limit(limit_count)
+        .load::<WebhookEvent>(conn)
+        .map_err(|e| DbError::WebhookEventReadError(errJson!(e)))
+}
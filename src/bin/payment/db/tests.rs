@@ -276,6 +276,7 @@ fn write_test_payout_splits(conn: &PgConnection) -> Vec<String> {
         String::from("store_1"),
         PayoutDealType::SELLER_AFFILIATE,
         None,
+        None,
         0.05,
         None,
     );
@@ -283,6 +284,7 @@ fn write_test_payout_splits(conn: &PgConnection) -> Vec<String> {
         String::from("store_2"),
         PayoutDealType::REFERRED_SELLER,
         None,
+        None,
         0.05,
         Some(ps1.id.clone()),
     );
@@ -290,6 +292,7 @@ fn write_test_payout_splits(conn: &PgConnection) -> Vec<String> {
         String::from("store_3"),
         PayoutDealType::SELLER_AFFILIATE,
         None,
+        None,
         0.05,
         None,
     );
@@ -297,6 +300,7 @@ fn write_test_payout_splits(conn: &PgConnection) -> Vec<String> {
         String::from("store_4"),
         PayoutDealType::REFERRED_SELLER,
         None,
+        None,
         0.05,
         Some(ps3.id.clone()),
     );
@@ -1261,6 +1265,19 @@ fn reads_payouts_pending_refund() {
     });
 }
 
+/// PayoutSplits are never deleted outside of tests, only retired
+fn delete_test_payout_split(
+    conn: &PgConnection,
+    payout_split_id: &str,
+) -> Result<PayoutSplit, diesel::result::Error> {
+
+    use gm::db::schema::payout_splits;
+
+    diesel::delete(payout_splits::table
+        .filter(payout_splits::id.eq(payout_split_id)))
+        .get_result::<PayoutSplit>(conn)
+}
+
 #[test]
 fn writes_and_reads_payout_splits() {
 
@@ -1287,10 +1304,10 @@ fn writes_and_reads_payout_splits() {
         // assert_eq!(results[2].deal_type, PayoutDealType::REFERRED_SELLER);
         // assert_eq!(results[3].deal_type, PayoutDealType::SELLER_AFFILIATE);
 
-        let _ = db::delete_payout_split(&conn, &ps_ids[0]);
-        let _ = db::delete_payout_split(&conn, &ps_ids[1]);
-        let _ = db::delete_payout_split(&conn, &ps_ids[2]);
-        let _ = db::delete_payout_split(&conn, &ps_ids[3]);
+        let _ = delete_test_payout_split(&conn, &ps_ids[0]);
+        let _ = delete_test_payout_split(&conn, &ps_ids[1]);
+        let _ = delete_test_payout_split(&conn, &ps_ids[2]);
+        let _ = delete_test_payout_split(&conn, &ps_ids[3]);
 
         Ok(())
     });
@@ -1319,7 +1336,8 @@ fn writes_and_reads_payout_splits_by_store_ids() {
                 PayoutDealType::SELLER_AFFILIATE,
                 PayoutDealType::SELLER,
                 PayoutDealType::REFERRED_SELLER,
-            ])
+            ]),
+            None,
         );
 
         println!("\nreads payout splits................\n{:?}\n--------", res);
@@ -1329,10 +1347,10 @@ fn writes_and_reads_payout_splits_by_store_ids() {
         assert_eq!(results.len(), 4);
         assert_eq!(results[0].store_or_user_id, String::from("store_1"));
 
-        let _ = db::delete_payout_split(&conn, &ps_ids[0]);
-        let _ = db::delete_payout_split(&conn, &ps_ids[1]);
-        let _ = db::delete_payout_split(&conn, &ps_ids[2]);
-        let _ = db::delete_payout_split(&conn, &ps_ids[3]);
+        let _ = delete_test_payout_split(&conn, &ps_ids[0]);
+        let _ = delete_test_payout_split(&conn, &ps_ids[1]);
+        let _ = delete_test_payout_split(&conn, &ps_ids[2]);
+        let _ = delete_test_payout_split(&conn, &ps_ids[3]);
 
         Ok(())
     });
@@ -1355,10 +1373,12 @@ fn writes_and_reads_payout_splits_for_seller_affiliates() {
         let res1 = db::read_current_seller_referrer_payout_splits_by_store_id(
             &conn,
             String::from("store_2"),
+            None,
         );
         let res2 = db::read_current_seller_referrer_payout_splits_by_store_id(
             &conn,
             String::from("store_4"),
+            None,
         );
 
         let results1 = res1;
@@ -1372,16 +1392,141 @@ fn writes_and_reads_payout_splits_for_seller_affiliates() {
         println!("\nreads affiliate payout splits........\n{:?}\n------", results2);
 
         // assert_eq!(results.len(), 2);
-        let _ = db::delete_payout_split(&conn, &ps_ids[0]);
-        let _ = db::delete_payout_split(&conn, &ps_ids[1]);
-        let _ = db::delete_payout_split(&conn, &ps_ids[2]);
-        let _ = db::delete_payout_split(&conn, &ps_ids[3]);
+        let _ = delete_test_payout_split(&conn, &ps_ids[0]);
+        let _ = delete_test_payout_split(&conn, &ps_ids[1]);
+        let _ = delete_test_payout_split(&conn, &ps_ids[2]);
+        let _ = delete_test_payout_split(&conn, &ps_ids[3]);
 
         Ok(())
     });
 }
 
 
+#[test]
+fn writes_payout_split_versions_and_reads_history() {
+
+    let conn = establish_connection_pg("DATABASE_URL");
+
+    let _ = conn.transaction::<(), diesel::result::Error, _>(|| {
+
+        let now = chrono::NaiveDateTime::from_timestamp(chrono::Utc::now().timestamp(), 0);
+        let ten_days_ago = now - chrono::Duration::days(10);
+        let five_days_ago = now - chrono::Duration::days(5);
+
+        let mut ps_v1 = PayoutSplit::new(
+            String::from("store_versioned"),
+            PayoutDealType::SELLER,
+            None,
+            None,
+            0.85,
+            None,
+        );
+        ps_v1.effective_from = ten_days_ago;
+
+        let ps_v2 = PayoutSplit::new(
+            String::from("store_versioned"),
+            PayoutDealType::SELLER,
+            None,
+            None,
+            0.9,
+            None,
+        ).update_changed_by(
+            Some(String::from("user_admin")),
+            Some(String::from("promotional rate")),
+        );
+
+        let _ = db::write_payout_split(&conn, ps_v1.clone());
+        let _ = db::write_payout_split(&conn, ps_v2.clone());
+
+        let past = db::read_current_payout_splits_by_store_or_user_ids(
+            &conn,
+            &vec![String::from("store_versioned")],
+            Some(vec![PayoutDealType::SELLER]),
+            Some(five_days_ago),
+        ).unwrap();
+        assert_eq!(past[0].id, ps_v1.id);
+        assert_eq!(past[0].effective_to, Some(ps_v2.effective_from));
+
+        let current = db::read_current_payout_splits_by_store_or_user_ids(
+            &conn,
+            &vec![String::from("store_versioned")],
+            Some(vec![PayoutDealType::SELLER]),
+            None,
+        ).unwrap();
+        assert_eq!(current[0].id, ps_v2.id);
+
+        let history = db::read_payout_split_history(&conn, ps_v2.id.clone()).unwrap();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[1].changed_by, Some(String::from("user_admin")));
+
+        let _ = delete_test_payout_split(&conn, &ps_v1.id);
+        let _ = delete_test_payout_split(&conn, &ps_v2.id);
+
+        Ok(())
+    });
+}
+
+
+#[test]
+fn prefers_store_scoped_buyer_affiliate_payout_split() {
+
+    let conn = establish_connection_pg("DATABASE_URL");
+
+    let _ = conn.transaction::<(), diesel::result::Error, _>(|| {
+
+        let global_ps = PayoutSplit::new(
+            String::from("user_store_scoped_aff"),
+            PayoutDealType::BUYER_AFFILIATE,
+            None,
+            None,
+            0.25,
+            None,
+        );
+        let store_ps = PayoutSplit::new(
+            String::from("user_store_scoped_aff"),
+            PayoutDealType::BUYER_AFFILIATE,
+            None,
+            None,
+            0.4,
+            None,
+        ).update_store_id(Some(String::from("store_own_programme")));
+
+        let _ = db::write_payout_split(&conn, global_ps.clone());
+        // does not supersede the global deal
+        let _ = db::write_payout_split(&conn, store_ps.clone());
+
+        let global = db::read_current_payout_splits_by_store_or_user_ids(
+            &conn,
+            &vec![String::from("user_store_scoped_aff")],
+            Some(vec![PayoutDealType::BUYER_AFFILIATE]),
+            None,
+        ).unwrap();
+        assert_eq!(global.len(), 1);
+        assert_eq!(global[0].id, global_ps.id);
+
+        let scoped = db::read_store_buyer_affiliate_payout_split(
+            &conn,
+            "user_store_scoped_aff",
+            "store_own_programme",
+            None,
+        ).unwrap();
+        assert_eq!(scoped.map(|ps| ps.id), Some(store_ps.id.clone()));
+
+        let other_store = db::read_store_buyer_affiliate_payout_split(
+            &conn,
+            "user_store_scoped_aff",
+            "store_other",
+            None,
+        ).unwrap();
+        assert_eq!(other_store.is_none(), true);
+
+        let _ = delete_test_payout_split(&conn, &global_ps.id);
+        let _ = delete_test_payout_split(&conn, &store_ps.id);
+
+        Ok(())
+    });
+}
+
 #[test]
 fn reads_payouts_aggregates() {
 
@@ -76,6 +76,7 @@ use payment_clients::{
 use webhooks::{
     handle_stripe_refund_webhook,
     handle_paypal_refund_webhook,
+    WebhookRetryScheduler,
 };
 
 //// Constants
@@ -114,6 +115,9 @@ async fn main() -> std::io::Result<()> {
         )
     );
 
+    // Retry failed and unprocessed webhook events in the background
+    WebhookRetryScheduler::new(database_actor.clone()).start();
+
     // Start the http server
     HttpServer::new(move || {
         // Start the actors, set AppState
@@ -200,6 +204,18 @@ async fn main() -> std::io::Result<()> {
                 .route(web::post().to(rest::refund_endpoint)))
             .service(web::resource("/read/many")
                 .route(web::post().to(rest::read_refunds_by_ids)))
+            .service(web::resource("/reconcile")
+                .route(web::post().to(rest::reconcile_refunds)))
+            .service(web::resource("/analytics")
+                .route(web::get().to(rest::read_refund_analytics)))
+            .service(web::scope("/requests")
+                .service(web::resource("/read")
+                    .route(web::get().to(rest::read_refund_requests)))
+                .service(web::resource("/approve")
+                    .route(web::post().to(rest::approve_refund_request)))
+                .service(web::resource("/reject")
+                    .route(web::post().to(rest::reject_refund_request)))
+            )
         )
         .service(web::scope("/tx")
             .service(web::resource("/read/many")
@@ -238,6 +254,8 @@ async fn main() -> std::io::Result<()> {
                 .route(web::post().to(rest::read_payouts_connection)))
             .service(web::resource("/read/many")
                 .route(web::post().to(rest::read_payouts_by_ids)))
+            .service(web::resource("/read/statement")
+                .route(web::get().to(rest::read_payout_statement)))
             .service(web::resource("/read/store")
                 .route(web::post().to(rest::read_payouts_by_store_id_connection)))
             .service(web::resource("/read/store/in/period")
@@ -262,6 +280,12 @@ async fn main() -> std::io::Result<()> {
                 .route(web::get().to(rest::read_payout_split)))
             .service(web::resource("/read/many")
                 .route(web::post().to(rest::read_many_payout_splits)))
+            .service(web::resource("/read/history")
+                .route(web::get().to(rest::read_payout_split_history)))
+            .service(web::resource("/export")
+                .route(web::get().to(rest::export_payout_splits_csv)))
+            .service(web::resource("/import")
+                .route(web::post().to(rest::import_payout_splits_csv)))
             .service(web::resource("/read/many/current")
                 .route(web::post().to(rest::read_current_payout_splits_by_ids)))
             .service(web::resource("/read/many/of/user")
@@ -277,6 +301,50 @@ async fn main() -> std::io::Result<()> {
             .service(web::resource("/delete")
                 .route(web::delete().to(rest::delete_payout_split)))
         )
+        .service(web::scope("/feeOverride")
+            .service(web::resource("/read/many")
+                .route(web::post().to(rest::read_many_fee_overrides)))
+            .service(web::resource("/write")
+                .route(web::post().to(rest::write_fee_override)))
+            .service(web::resource("/delete")
+                .route(web::delete().to(rest::delete_fee_override)))
+        )
+        .service(web::scope("/revenueShare")
+            .service(web::resource("/read")
+                .route(web::get().to(rest::read_revenue_shares)))
+            .service(web::resource("/write")
+                .route(web::post().to(rest::write_revenue_shares)))
+            .service(web::resource("/delete")
+                .route(web::delete().to(rest::delete_revenue_shares)))
+        )
+        .service(web::scope("/payoutCalculation")
+            .service(web::resource("/read")
+                .route(web::get().to(rest::read_payout_calculations)))
+            .service(web::resource("/verify")
+                .route(web::get().to(rest::verify_payout_calculations)))
+            .service(web::resource("/recalculate")
+                .route(web::post().to(rest::recalculate_order_earnings)))
+        )
+        .service(web::scope("/manualAdjustment")
+            .service(web::resource("/read")
+                .route(web::get().to(rest::read_manual_adjustments)))
+            .service(web::resource("/write")
+                .route(web::post().to(rest::write_manual_adjustment)))
+        )
+        .service(web::scope("/auditLog")
+            .service(web::resource("/read")
+                .route(web::get().to(rest::read_audit_logs)))
+        )
+        .service(web::scope("/disputes")
+            .service(web::resource("/read")
+                .route(web::get().to(rest::read_disputes)))
+            .service(web::resource("/evidence")
+                .route(web::post().to(rest::submit_dispute_evidence)))
+        )
+        .service(web::scope("/feeQuote")
+            .service(web::resource("")
+                .route(web::post().to(rest::quote_fees)))
+        )
         .service(web::scope("/webhooks")
             .service(web::scope("/refund")
                 .service(web::resource("/stripe")
@@ -284,6 +352,14 @@ async fn main() -> std::io::Result<()> {
                 .service(web::resource("/paypal")
                     .route(web::post().to(handle_paypal_refund_webhook)))
             )
+            .service(web::scope("/events")
+                .service(web::resource("/read")
+                    .route(web::get().to(rest::read_webhook_events)))
+                .service(web::resource("/replay")
+                    .route(web::post().to(rest::replay_webhook_events)))
+                .service(web::resource("/retry")
+                    .route(web::post().to(rest::retry_webhook_events)))
+            )
         )
         .service(web::scope("/test")
             .service(web::resource("")
@@ -0,0 +1,56 @@
+use diesel::prelude::*;
+use gm::db::schema::audit_logs;
+
+
+/// Record of an admin operation on payouts, written in the same
+/// DB transaction as the change it describes.
+#[serde(rename_all = "camelCase")]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Queryable, Insertable)]
+#[table_name = "audit_logs"]
+pub struct AuditLog {
+    pub id: String,
+    pub created_at: chrono::NaiveDateTime,
+    pub action: String,
+    /// userId of the admin
+    pub actor_id: String,
+    /// What the operation was done to, e.g. an orderId
+    pub target_id: String,
+    pub reason: Option<String>,
+    /// JSON describing the change
+    pub details: Option<String>,
+}
+
+impl AuditLog {
+    pub fn new(
+        action: AuditAction,
+        actor_id: String,
+        target_id: String,
+        reason: Option<String>,
+        details: Option<serde_json::Value>,
+    ) -> Self {
+        Self {
+            id: format!("audit_{}", uuid::Uuid::new_v4().to_string()),
+            created_at: chrono::NaiveDateTime::from_timestamp(
+                chrono::Utc::now().timestamp(), 0
+            ),
+            action: action.as_string(),
+            actor_id: actor_id,
+            target_id: target_id,
+            reason: reason,
+            details: details.map(|d| d.to_string()),
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum AuditAction {
+    /// Earnings of an order recomputed, with adjustment payout items
+    RECALCULATE_EARNINGS,
+}
+impl AuditAction {
+    pub fn as_string(&self) -> String {
+        String::from(format!("{:?}", &self))
+    }
+}
@@ -0,0 +1,201 @@
+use crate::models::{
+    PayoutItem,
+    PayoutStatus,
+    PayeeType,
+};
+
+
+/// Who pays back a payee's share of a refund, if the share was already paid out
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ClawbackAbsorber {
+    /// Deducted from the payee's future payouts
+    PAYEE,
+    /// The platform covers it
+    PLATFORM,
+    /// The orderItem's seller covers it
+    SELLER,
+}
+impl Default for ClawbackAbsorber {
+    fn default() -> Self {
+        ClawbackAbsorber::PAYEE
+    }
+}
+
+
+/// Set by admins on a refund. By default every payee pays back their own share.
+#[serde(rename_all = "camelCase")]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ClawbackPolicy {
+    #[serde(default)]
+    pub store: ClawbackAbsorber,
+    #[serde(default)]
+    pub platform: ClawbackAbsorber,
+    #[serde(default)]
+    pub buyer_affiliate: ClawbackAbsorber,
+    #[serde(default)]
+    pub seller_affiliate: ClawbackAbsorber,
+}
+impl ClawbackPolicy {
+    pub fn absorber(&self, payee_type: &PayeeType) -> &ClawbackAbsorber {
+        match payee_type {
+            PayeeType::STORE => &self.store,
+            PayeeType::PLATFORM => &self.platform,
+            PayeeType::BUYER_AFFILIATE => &self.buyer_affiliate,
+            PayeeType::SELLER_AFFILIATE => &self.seller_affiliate,
+        }
+    }
+
+    pub fn is_default(&self) -> bool {
+        vec![
+            &self.store,
+            &self.platform,
+            &self.buyer_affiliate,
+            &self.seller_affiliate,
+        ].into_iter().all(|a| *a == ClawbackAbsorber::PAYEE)
+    }
+}
+
+
+/// Whether a payout item is in a payout, or has been paid out
+pub fn is_paid_out_status(payout_status: &PayoutStatus) -> bool {
+    match payout_status {
+        PayoutStatus::PENDING_APPROVAL |
+        PayoutStatus::PROCESSING |
+        PayoutStatus::PAID => true,
+        _ => false,
+    }
+}
+
+
+/// Turns refund items for shares which were already paid out into clawback
+/// items. Clawbacks are UNPAID negative payout items, so create_payout nets
+/// them against the absorbing payee's future earnings, instead of
+/// cancelling out earnings which were never going to be paid.
+///
+/// Refund items for shares which have not been paid out are returned as is.
+pub fn create_clawback_payout_items(
+    payout_items: &Vec<PayoutItem>,
+    refund_items: Vec<PayoutItem>,
+    clawback_policy: &ClawbackPolicy,
+) -> Vec<PayoutItem> {
+
+    refund_items
+        .into_iter()
+        .map(|ritem: PayoutItem| {
+
+            let paid_out = payout_items.iter().any(|p| {
+                p.order_item_id == ritem.order_item_id
+                && p.payee_id == ritem.payee_id
+                && p.payee_type == ritem.payee_type
+                && !p.id.starts_with("ritem_")
+                && !p.id.starts_with("citem_")
+                && is_paid_out_status(&p.payout_status)
+            });
+            if !paid_out {
+                return ritem
+            }
+
+            let seller = payout_items.iter().find(|p| {
+                p.order_item_id == ritem.order_item_id
+                && p.payee_type == PayeeType::STORE
+            });
+
+            let (payee_id, payee_type) = match (
+                clawback_policy.absorber(&ritem.payee_type),
+                seller,
+            ) {
+                (ClawbackAbsorber::PLATFORM, _) => {
+                    (String::from("gm-platform"), PayeeType::PLATFORM)
+                },
+                (ClawbackAbsorber::SELLER, Some(s)) => {
+                    (s.payee_id.clone(), PayeeType::STORE)
+                },
+                _ => (ritem.payee_id.clone(), ritem.payee_type.clone()),
+            };
+
+            // the payment processing fee is only the seller's to carry,
+            // anyone else absorbing the share pays it as part of the amount
+            let (amount, payment_processing_fee) =
+                match payee_id == ritem.payee_id && payee_type == ritem.payee_type {
+                    true => (ritem.amount, ritem.payment_processing_fee),
+                    false => (ritem.amount + ritem.payment_processing_fee, 0),
+                };
+
+            PayoutItem {
+                id: format!("citem_{}", uuid::Uuid::new_v4().to_string()),
+                payee_id: payee_id,
+                payee_type: payee_type,
+                amount: amount,
+                payment_processing_fee: payment_processing_fee,
+                payout_status: PayoutStatus::UNPAID,
+                payout_id: None,
+                ..ritem
+            }
+        })
+        .collect::<Vec<PayoutItem>>()
+}
+
+
+
+#[test]
+fn creates_clawbacks_only_for_paid_out_shares() {
+
+    use crate::models::create_partial_refund_payout_items;
+
+    let created_at = chrono::NaiveDateTime::from_timestamp(1_500_000_000, 0);
+    let pitem = |payee_id: &str, payee_type: PayeeType, amount: i32, fee: i32| PayoutItem::new(
+        String::from("oitem_123"),
+        String::from(payee_id),
+        Some(payee_type),
+        amount,
+        fee,
+        created_at,
+        String::from("USD"),
+        String::from("txn_123"),
+    );
+
+    // seller and platform were paid last month, the affiliate has no payout method yet
+    let payout_items = vec![
+        pitem("store_123", PayeeType::STORE, 8110, 390).set_payout_status(PayoutStatus::PAID),
+        pitem("gm-platform", PayeeType::PLATFORM, 1000, 0).set_payout_status(PayoutStatus::PAID),
+        pitem("user_123", PayeeType::BUYER_AFFILIATE, 500, 0)
+            .set_payout_status(PayoutStatus::MISSING_PAYOUT_METHOD),
+    ];
+    let refund_items = create_partial_refund_payout_items(
+        &payout_items,
+        None,
+        &created_at,
+        "txn_refund",
+    ).expect("refund items");
+
+    let summary = |items: Vec<PayoutItem>| items.into_iter()
+        .map(|p| (p.is_clawback(), p.payee_id, p.amount, p.payment_processing_fee, p.payout_status))
+        .collect::<Vec<(bool, String, i32, i32, PayoutStatus)>>();
+
+    assert_eq!(
+        summary(create_clawback_payout_items(
+            &payout_items,
+            refund_items.clone(),
+            &ClawbackPolicy::default(),
+        )),
+        vec![
+            (true, String::from("gm-platform"), -1000, 0, PayoutStatus::UNPAID),
+            (true, String::from("store_123"), -8110, -390, PayoutStatus::UNPAID),
+            (false, String::from("user_123"), -500, 0, PayoutStatus::REFUNDING),
+        ]
+    );
+
+    // platform covers the seller's share, as a goodwill gesture
+    let policy = ClawbackPolicy {
+        store: ClawbackAbsorber::PLATFORM,
+        ..ClawbackPolicy::default()
+    };
+    assert_eq!(
+        summary(create_clawback_payout_items(&payout_items, refund_items, &policy)),
+        vec![
+            (true, String::from("gm-platform"), -1000, 0, PayoutStatus::UNPAID),
+            (true, String::from("gm-platform"), -8500, 0, PayoutStatus::UNPAID),
+            (false, String::from("user_123"), -500, 0, PayoutStatus::REFUNDING),
+        ]
+    );
+}
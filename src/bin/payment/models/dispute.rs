@@ -0,0 +1,352 @@
+use diesel::prelude::*;
+use gm::db::schema::disputes;
+use gm::models::stripe;
+
+use crate::models::{
+    PayoutItem,
+    PayoutStatus,
+    PayeeType,
+    Transaction,
+    ClawbackPolicy,
+    create_partial_refund_payout_items,
+    create_clawback_payout_items,
+    allocate_refund_amount,
+    remaining_refundable_items,
+    refundable_total,
+    is_refund_status,
+};
+
+
+/// A chargeback on a payment. While it is open, the payment's unpaid
+/// payout items are held (DISPUTED), and paid out shares are clawed back.
+#[serde(rename_all = "camelCase")]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Queryable, Insertable)]
+#[table_name = "disputes"]
+pub struct Dispute {
+    /// Stripe dispute id, dp_xxx
+    pub id: String,
+    /// The disputed payment's transaction
+    pub transaction_id: String,
+    pub order_id: Option<String>,
+    pub charge_id: String,
+    pub amount: i32,
+    pub currency: String,
+    pub reason: String,
+    /// Stripe dispute status, e.g. needs_response, won, lost
+    pub status: String,
+    pub created_at: chrono::NaiveDateTime,
+    pub updated_at: chrono::NaiveDateTime,
+    pub evidence_due_by: Option<chrono::NaiveDateTime>,
+    pub evidence_submitted_at: Option<chrono::NaiveDateTime>,
+    pub closed_at: Option<chrono::NaiveDateTime>,
+    /// Charged by Stripe, booked against sellers if the dispute is lost
+    pub dispute_fee: i32,
+}
+
+impl Dispute {
+    pub fn new(
+        stripe_dispute: &stripe::Dispute,
+        payment_tx: &Transaction,
+        created_at: chrono::NaiveDateTime,
+    ) -> Self {
+        Self {
+            id: stripe_dispute.id.clone(),
+            transaction_id: payment_tx.id.clone(),
+            order_id: payment_tx.order_id.clone(),
+            charge_id: stripe_dispute.charge.id().to_string(),
+            amount: stripe_dispute.amount as i32,
+            currency: stripe_dispute.currency.to_string(),
+            reason: stripe_dispute.reason.clone(),
+            status: stripe_dispute.status.as_str().to_string(),
+            created_at: created_at,
+            updated_at: created_at,
+            evidence_due_by: stripe_dispute.evidence_details.due_by
+                .map(|t| chrono::NaiveDateTime::from_timestamp(t, 0)),
+            evidence_submitted_at: None,
+            closed_at: None,
+            dispute_fee: 0,
+        }
+    }
+
+    /// Takes the latest status and evidence due date from Stripe
+    pub fn update_from_stripe(
+        self,
+        stripe_dispute: &stripe::Dispute,
+        updated_at: chrono::NaiveDateTime,
+    ) -> Self {
+        Self {
+            status: stripe_dispute.status.as_str().to_string(),
+            evidence_due_by: stripe_dispute.evidence_details.due_by
+                .map(|t| chrono::NaiveDateTime::from_timestamp(t, 0)),
+            updated_at: updated_at,
+            ..self
+        }
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed_at.is_some()
+    }
+}
+
+
+/// How a dispute ended, for the payout items held while it was open
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum DisputeOutcome {
+    /// Held items are released, clawbacks reversed
+    WON,
+    /// Held items are refunded and the dispute fee is booked
+    LOST,
+}
+
+/// The outcome of a dispute with a final Stripe status, None while it is open.
+/// A dispute on a refunded charge is closed like a won dispute,
+/// the refund itself is recorded by the refund webhook.
+pub fn dispute_outcome(status: &stripe::DisputeStatus) -> Option<DisputeOutcome> {
+    match status {
+        stripe::DisputeStatus::Won |
+        stripe::DisputeStatus::WarningClosed |
+        stripe::DisputeStatus::ChargeRefunded => Some(DisputeOutcome::WON),
+        stripe::DisputeStatus::Lost => Some(DisputeOutcome::LOST),
+        _ => None,
+    }
+}
+
+/// Dispute fees, from the dispute's balance transactions
+pub fn stripe_dispute_fee(stripe_dispute: &stripe::Dispute) -> i32 {
+    stripe_dispute.balance_transactions
+        .iter()
+        .map(|bt| bt.fee)
+        .sum::<i64>() as i32
+}
+
+
+/// Sale items which haven't been paid out yet, to hold while disputed
+pub fn dispute_hold_item_ids(payout_items: &Vec<PayoutItem>) -> Vec<String> {
+    payout_items
+        .iter()
+        .filter(|p| !p.id.starts_with("ritem_") && !p.is_clawback() && !p.is_dispute_fee())
+        .filter(|p| !is_refund_status(&p.payout_status))
+        .filter(|p| {
+            p.payout_status == PayoutStatus::UNPAID ||
+            p.payout_status == PayoutStatus::MISSING_PAYOUT_METHOD
+        })
+        .map(|p| p.id.clone())
+        .collect::<Vec<String>>()
+}
+
+/// Clawbacks of every share of the disputed payment which was already paid
+/// out, taken from the payees' future payouts until the dispute is won.
+pub fn create_dispute_clawback_items(
+    payout_items: &Vec<PayoutItem>,
+    dispute_id: &str,
+    created_at: &chrono::NaiveDateTime,
+) -> Vec<PayoutItem> {
+    match create_partial_refund_payout_items(payout_items, None, created_at, dispute_id) {
+        Err(_) => vec![],
+        Ok(refund_items) => create_clawback_payout_items(
+                payout_items,
+                refund_items,
+                &ClawbackPolicy::default(),
+            )
+            .into_iter()
+            .filter(|p| p.is_clawback())
+            .collect(),
+    }
+}
+
+/// Gives back clawbacks taken when a dispute was opened, once it's won
+pub fn reverse_dispute_clawback_items(
+    payout_items: &Vec<PayoutItem>,
+    dispute_id: &str,
+    created_at: &chrono::NaiveDateTime,
+) -> Vec<PayoutItem> {
+    payout_items
+        .iter()
+        .filter(|p| p.is_clawback() && p.txn_id.as_ref().map(|t| t.as_str()) == Some(dispute_id))
+        .map(|citem| PayoutItem {
+            id: format!("citem_{}", uuid::Uuid::new_v4().to_string()),
+            amount: -citem.amount,
+            payment_processing_fee: -citem.payment_processing_fee,
+            created_at: created_at.clone(),
+            payout_status: PayoutStatus::UNPAID,
+            payout_id: None,
+            ..citem.clone()
+        })
+        .collect::<Vec<PayoutItem>>()
+}
+
+/// Refund items for a lost dispute, once its held items are released,
+/// so payout_items should be UNPAID again. dispute_amount is the disputed
+/// payout share, without taxes, as booked by the dispute's transaction.
+///
+/// If all of the payment was disputed, paid out shares were already clawed
+/// back when the dispute opened, and net to nothing. Otherwise those
+/// clawbacks are given back, and dispute_amount is pro-rated across every
+/// share like a partial refund, clawing back shares which were paid out.
+pub fn create_dispute_loss_items(
+    payout_items: &Vec<PayoutItem>,
+    dispute_amount: i32,
+    dispute_id: &str,
+    created_at: &chrono::NaiveDateTime,
+) -> Vec<PayoutItem> {
+
+    let is_dispute_clawback = |p: &PayoutItem| {
+        p.is_clawback() && p.txn_id.as_ref().map(|t| t.as_str()) == Some(dispute_id)
+    };
+    // the payment's items, as they were before the dispute opened
+    let sale_items = payout_items
+        .iter()
+        .filter(|p| !is_dispute_clawback(p))
+        .cloned()
+        .collect::<Vec<PayoutItem>>();
+
+    if dispute_amount >= refundable_total(&remaining_refundable_items(&sale_items)) {
+        return create_partial_refund_payout_items(payout_items, None, created_at, dispute_id)
+            .unwrap_or(vec![])
+    }
+
+    match create_partial_refund_payout_items(
+        &sale_items,
+        Some(dispute_amount),
+        created_at,
+        dispute_id,
+    ) {
+        Err(e) => {
+            warn!("could not allocate {} lost in dispute {}: {}", dispute_amount, dispute_id, e);
+            vec![]
+        },
+        Ok(refund_items) => reverse_dispute_clawback_items(payout_items, dispute_id, created_at)
+            .into_iter()
+            .chain(create_clawback_payout_items(
+                &sale_items,
+                refund_items,
+                &ClawbackPolicy::default(),
+            ).into_iter())
+            .collect(),
+    }
+}
+
+/// Books a lost dispute's fee against the sellers of the disputed payment,
+/// in proportion to their sales. Without sellers, the platform carries it.
+pub fn create_dispute_fee_items(
+    payout_items: &Vec<PayoutItem>,
+    dispute_fee: i32,
+    dispute_id: &str,
+    created_at: &chrono::NaiveDateTime,
+) -> Vec<PayoutItem> {
+
+    if dispute_fee <= 0 {
+        return vec![]
+    }
+
+    let sale_items = payout_items
+        .iter()
+        .filter(|p| p.id.starts_with("pitem_") && p.payee_type == PayeeType::STORE)
+        .cloned()
+        .collect::<Vec<PayoutItem>>();
+
+    let fee_item = |pitem: &PayoutItem, amount: i32| PayoutItem {
+        id: format!("ditem_{}", uuid::Uuid::new_v4().to_string()),
+        amount: -amount,
+        payment_processing_fee: 0,
+        created_at: created_at.clone(),
+        payout_status: PayoutStatus::UNPAID,
+        txn_id: Some(dispute_id.to_string()),
+        payout_id: None,
+        ..pitem.clone()
+    };
+
+    match allocate_refund_amount(&sale_items, dispute_fee) {
+        Ok(shares) => sale_items
+            .iter()
+            .zip(shares.into_iter())
+            .filter(|(_, (amount, fee))| amount + fee != 0)
+            .map(|(pitem, (amount, fee))| fee_item(pitem, amount + fee))
+            .collect(),
+        Err(_) => match payout_items.first() {
+            None => vec![],
+            Some(pitem) => vec![fee_item(
+                &PayoutItem {
+                    payee_id: String::from("gm-platform"),
+                    payee_type: PayeeType::PLATFORM,
+                    ..pitem.clone()
+                },
+                dispute_fee,
+            )],
+        },
+    }
+}
+
+
+
+#[test]
+fn holds_and_settles_disputed_payout_items() {
+
+    use crate::models::refundable_total;
+
+    let created_at = chrono::NaiveDateTime::from_timestamp(1_500_000_000, 0);
+    let pitem = |payee_id: &str, payee_type: PayeeType, amount: i32, fee: i32| PayoutItem::new(
+        String::from("oitem_123"),
+        String::from(payee_id),
+        Some(payee_type),
+        amount,
+        fee,
+        created_at,
+        String::from("USD"),
+        String::from("txn_123"),
+    );
+
+    // the platform was paid last month, the seller and affiliate weren't
+    let payout_items = vec![
+        pitem("store_123", PayeeType::STORE, 8110, 390),
+        pitem("gm-platform", PayeeType::PLATFORM, 1000, 0).set_payout_status(PayoutStatus::PAID),
+        pitem("user_123", PayeeType::BUYER_AFFILIATE, 500, 0),
+    ];
+
+    // dispute opened: unpaid items are held, paid shares clawed back
+    let held_ids = dispute_hold_item_ids(&payout_items);
+    assert_eq!(held_ids, vec![payout_items[0].id.clone(), payout_items[2].id.clone()]);
+
+    let clawbacks = create_dispute_clawback_items(&payout_items, "dp_123", &created_at);
+    assert_eq!(
+        clawbacks.iter()
+            .map(|p| (p.payee_id.clone(), p.amount, p.payout_status.clone()))
+            .collect::<Vec<(String, i32, PayoutStatus)>>(),
+        vec![(String::from("gm-platform"), -1000, PayoutStatus::UNPAID)]
+    );
+
+    let disputed = payout_items.iter().cloned()
+        .chain(clawbacks.into_iter())
+        .collect::<Vec<PayoutItem>>();
+
+    // won: clawbacks are given back
+    let reversals = reverse_dispute_clawback_items(&disputed, "dp_123", &created_at);
+    assert_eq!(refundable_total(&reversals), 1000);
+    assert_eq!(reverse_dispute_clawback_items(&disputed, "dp_456", &created_at).len(), 0);
+
+    // lost: held shares are refunded, the seller pays the fee
+    let losses = create_dispute_loss_items(&disputed, 10000, "dp_123", &created_at);
+    assert_eq!(refundable_total(&losses), -9000);
+    assert_eq!(losses.iter().all(|p| !p.is_clawback()), true);
+
+    // partly lost: every share pays its part of what was lost,
+    // the platform's clawback is replaced by a smaller one
+    let losses = create_dispute_loss_items(&disputed, 2000, "dp_123", &created_at);
+    assert_eq!(refundable_total(&losses), -2000 + 1000);
+    assert_eq!(
+        losses.iter()
+            .filter(|p| p.payee_id == "gm-platform")
+            .map(|p| (p.is_clawback(), p.amount))
+            .collect::<Vec<(bool, i32)>>(),
+        vec![(true, 1000), (true, -200)]
+    );
+
+    let fees = create_dispute_fee_items(&disputed, 1500, "dp_123", &created_at);
+    assert_eq!(
+        fees.iter()
+            .map(|p| (p.is_dispute_fee(), p.payee_id.clone(), p.amount))
+            .collect::<Vec<(bool, String, i32)>>(),
+        vec![(true, String::from("store_123"), -1500)]
+    );
+}
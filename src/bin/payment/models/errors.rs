@@ -254,6 +254,38 @@ pub enum DbError {
     #[fail(display = "{}", _0)]
     PayoutSplitReadError(ErrJson),
     #[fail(display = "{}", _0)]
+    FeeOverrideWriteError(ErrJson),
+    #[fail(display = "{}", _0)]
+    FeeOverrideReadError(ErrJson),
+    #[fail(display = "{}", _0)]
+    RevenueShareWriteError(ErrJson),
+    #[fail(display = "{}", _0)]
+    RevenueShareReadError(ErrJson),
+    #[fail(display = "{}", _0)]
+    PayoutSplitOverlapError(ErrJson),
+    #[fail(display = "{}", _0)]
+    PayoutCalculationWriteError(ErrJson),
+    #[fail(display = "{}", _0)]
+    PayoutCalculationReadError(ErrJson),
+    #[fail(display = "{}", _0)]
+    AuditLogReadError(ErrJson),
+    #[fail(display = "{}", _0)]
+    ManualAdjustmentWriteError(ErrJson),
+    #[fail(display = "{}", _0)]
+    ManualAdjustmentReadError(ErrJson),
+    #[fail(display = "{}", _0)]
+    WebhookEventWriteError(ErrJson),
+    #[fail(display = "{}", _0)]
+    WebhookEventReadError(ErrJson),
+    #[fail(display = "{}", _0)]
+    DisputeWriteError(ErrJson),
+    #[fail(display = "{}", _0)]
+    DisputeReadError(ErrJson),
+    #[fail(display = "{}", _0)]
+    RefundRequestWriteError(ErrJson),
+    #[fail(display = "{}", _0)]
+    RefundRequestReadError(ErrJson),
+    #[fail(display = "{}", _0)]
     PoolError(ErrJson),
 }
 
@@ -362,6 +394,102 @@ impl ResponseError for DbError {
                     .content_type("application/json")
                     .json(json!({ "file": ejson.file, "message": ejson.message }))
             },
+            DbError::FeeOverrideWriteError(ejson) => {
+                warn!("{}: {}", ejson.file, ejson.message);
+                HttpResponse::build(StatusCode::INTERNAL_SERVER_ERROR)
+                    .content_type("application/json")
+                    .json(json!({ "file": ejson.file, "message": ejson.message }))
+            },
+            DbError::FeeOverrideReadError(ejson) => {
+                warn!("{}: {}", ejson.file, ejson.message);
+                HttpResponse::build(StatusCode::INTERNAL_SERVER_ERROR)
+                    .content_type("application/json")
+                    .json(json!({ "file": ejson.file, "message": ejson.message }))
+            },
+            DbError::RevenueShareWriteError(ejson) => {
+                warn!("{}: {}", ejson.file, ejson.message);
+                HttpResponse::build(StatusCode::INTERNAL_SERVER_ERROR)
+                    .content_type("application/json")
+                    .json(json!({ "file": ejson.file, "message": ejson.message }))
+            },
+            DbError::RevenueShareReadError(ejson) => {
+                warn!("{}: {}", ejson.file, ejson.message);
+                HttpResponse::build(StatusCode::INTERNAL_SERVER_ERROR)
+                    .content_type("application/json")
+                    .json(json!({ "file": ejson.file, "message": ejson.message }))
+            },
+            DbError::PayoutSplitOverlapError(ejson) => {
+                warn!("{}: {}", ejson.file, ejson.message);
+                HttpResponse::build(StatusCode::BAD_REQUEST)
+                    .content_type("application/json")
+                    .json(json!({ "file": ejson.file, "message": ejson.message }))
+            },
+            DbError::PayoutCalculationWriteError(ejson) => {
+                warn!("{}: {}", ejson.file, ejson.message);
+                HttpResponse::build(StatusCode::INTERNAL_SERVER_ERROR)
+                    .content_type("application/json")
+                    .json(json!({ "file": ejson.file, "message": ejson.message }))
+            },
+            DbError::PayoutCalculationReadError(ejson) => {
+                warn!("{}: {}", ejson.file, ejson.message);
+                HttpResponse::build(StatusCode::INTERNAL_SERVER_ERROR)
+                    .content_type("application/json")
+                    .json(json!({ "file": ejson.file, "message": ejson.message }))
+            },
+            DbError::AuditLogReadError(ejson) => {
+                warn!("{}: {}", ejson.file, ejson.message);
+                HttpResponse::build(StatusCode::INTERNAL_SERVER_ERROR)
+                    .content_type("application/json")
+                    .json(json!({ "file": ejson.file, "message": ejson.message }))
+            },
+            DbError::ManualAdjustmentWriteError(ejson) => {
+                warn!("{}: {}", ejson.file, ejson.message);
+                HttpResponse::build(StatusCode::INTERNAL_SERVER_ERROR)
+                    .content_type("application/json")
+                    .json(json!({ "file": ejson.file, "message": ejson.message }))
+            },
+            DbError::ManualAdjustmentReadError(ejson) => {
+                warn!("{}: {}", ejson.file, ejson.message);
+                HttpResponse::build(StatusCode::INTERNAL_SERVER_ERROR)
+                    .content_type("application/json")
+                    .json(json!({ "file": ejson.file, "message": ejson.message }))
+            },
+            DbError::WebhookEventWriteError(ejson) => {
+                warn!("{}: {}", ejson.file, ejson.message);
+                HttpResponse::build(StatusCode::INTERNAL_SERVER_ERROR)
+                    .content_type("application/json")
+                    .json(json!({ "file": ejson.file, "message": ejson.message }))
+            },
+            DbError::WebhookEventReadError(ejson) => {
+                warn!("{}: {}", ejson.file, ejson.message);
+                HttpResponse::build(StatusCode::INTERNAL_SERVER_ERROR)
+                    .content_type("application/json")
+                    .json(json!({ "file": ejson.file, "message": ejson.message }))
+            },
+            DbError::DisputeWriteError(ejson) => {
+                warn!("{}: {}", ejson.file, ejson.message);
+                HttpResponse::build(StatusCode::INTERNAL_SERVER_ERROR)
+                    .content_type("application/json")
+                    .json(json!({ "file": ejson.file, "message": ejson.message }))
+            },
+            DbError::DisputeReadError(ejson) => {
+                warn!("{}: {}", ejson.file, ejson.message);
+                HttpResponse::build(StatusCode::INTERNAL_SERVER_ERROR)
+                    .content_type("application/json")
+                    .json(json!({ "file": ejson.file, "message": ejson.message }))
+            },
+            DbError::RefundRequestWriteError(ejson) => {
+                warn!("{}: {}", ejson.file, ejson.message);
+                HttpResponse::build(StatusCode::INTERNAL_SERVER_ERROR)
+                    .content_type("application/json")
+                    .json(json!({ "file": ejson.file, "message": ejson.message }))
+            },
+            DbError::RefundRequestReadError(ejson) => {
+                warn!("{}: {}", ejson.file, ejson.message);
+                HttpResponse::build(StatusCode::INTERNAL_SERVER_ERROR)
+                    .content_type("application/json")
+                    .json(json!({ "file": ejson.file, "message": ejson.message }))
+            },
             DbError::PoolError(ejson) => {
                 warn!("{}: {}", ejson.file, ejson.message);
                 HttpResponse::build(StatusCode::INTERNAL_SERVER_ERROR)
@@ -507,6 +635,8 @@ impl ResponseError for MonthError {
 pub enum AffiliateError {
     #[fail(display = "{}", _0)]
     CreateSellerAffiliateManually(ErrJson),
+    #[fail(display = "{}", _0)]
+    StoreScopedDealType(ErrJson),
 }
 
 impl ResponseError for AffiliateError {
@@ -517,6 +647,299 @@ impl ResponseError for AffiliateError {
                 HttpResponse::build(StatusCode::BAD_REQUEST)
                     .json(json!({ "file": ejson.file, "message": ejson.message }))
             },
+            AffiliateError::StoreScopedDealType(ejson) => {
+                warn!("{}: {}", ejson.file, ejson.message);
+                HttpResponse::build(StatusCode::BAD_REQUEST)
+                    .json(json!({ "file": ejson.file, "message": ejson.message }))
+            },
        }
     }
-}
\ No newline at end of file
+}
+
+
+#[derive(Debug, Serialize, Deserialize, Fail)]
+pub enum RevenueShareError {
+    #[fail(display = "{}", _0)]
+    InvalidRevenueShares(ErrJson),
+}
+
+impl ResponseError for RevenueShareError {
+    fn error_response(&self) -> HttpResponse {
+       match self {
+            RevenueShareError::InvalidRevenueShares(ejson) => {
+                warn!("{}: {}", ejson.file, ejson.message);
+                HttpResponse::build(StatusCode::BAD_REQUEST)
+                    .json(json!({ "file": ejson.file, "message": ejson.message }))
+            },
+       }
+    }
+}
+
+
+#[derive(Debug, Serialize, Deserialize, Fail)]
+pub enum FeeOverrideError {
+    /// Missing a target or a fee, or a fee outside its range
+    #[fail(display = "{}", _0)]
+    InvalidFeeOverride(ErrJson),
+}
+
+impl ResponseError for FeeOverrideError {
+    fn error_response(&self) -> HttpResponse {
+       match self {
+            FeeOverrideError::InvalidFeeOverride(ejson) => {
+                warn!("{}: {}", ejson.file, ejson.message);
+                HttpResponse::build(StatusCode::BAD_REQUEST)
+                    .json(json!({ "file": ejson.file, "message": ejson.message }))
+            },
+       }
+    }
+}
+
+
+#[derive(Debug, Serialize, Deserialize, Fail)]
+pub enum PayoutSplitRateError {
+    /// Each rule the rate broke, so admins can see which limit to fix
+    #[fail(display = "{:?}", _0)]
+    InvalidRates(Vec<crate::models::RateViolation>),
+}
+
+impl ResponseError for PayoutSplitRateError {
+    fn error_response(&self) -> HttpResponse {
+       match self {
+            PayoutSplitRateError::InvalidRates(violations) => {
+                warn!("Invalid PayoutSplit rates: {:?}", violations);
+                HttpResponse::build(StatusCode::BAD_REQUEST)
+                    .json(json!({
+                        "message": "PayoutSplit rates are outside the allowed limits",
+                        "violations": violations,
+                    }))
+            },
+       }
+    }
+}
+
+
+#[derive(Debug, Serialize, Deserialize, Fail)]
+pub enum PayoutCalculationError {
+    /// Orders paid before PayoutCalculations were recorded can't be recomputed
+    #[fail(display = "{}", _0)]
+    NotRecorded(ErrJson),
+}
+
+impl ResponseError for PayoutCalculationError {
+    fn error_response(&self) -> HttpResponse {
+       match self {
+            PayoutCalculationError::NotRecorded(ejson) => {
+                warn!("{}: {}", ejson.file, ejson.message);
+                HttpResponse::build(StatusCode::BAD_REQUEST)
+                    .json(json!({ "file": ejson.file, "message": ejson.message }))
+            },
+       }
+    }
+}
+
+
+#[derive(Debug, Serialize, Deserialize, Fail)]
+pub enum ManualAdjustmentError {
+    #[fail(display = "{}", _0)]
+    InvalidAdjustment(ErrJson),
+}
+
+impl ResponseError for ManualAdjustmentError {
+    fn error_response(&self) -> HttpResponse {
+       match self {
+            ManualAdjustmentError::InvalidAdjustment(ejson) => {
+                warn!("{}: {}", ejson.file, ejson.message);
+                HttpResponse::build(StatusCode::BAD_REQUEST)
+                    .json(json!({ "file": ejson.file, "message": ejson.message }))
+            },
+       }
+    }
+}
+
+
+#[derive(Debug, Serialize, Deserialize, Fail)]
+pub enum RefundError {
+    /// Refund amount is not positive, or more than is left to refund
+    #[fail(display = "{}", _0)]
+    InvalidAmount(ErrJson),
+    /// Refund request doesn't exist, or was already approved or rejected
+    #[fail(display = "{}", _0)]
+    InvalidRefundRequest(ErrJson),
+    /// Other refund reason without reason details
+    #[fail(display = "{}", _0)]
+    InvalidReason(ErrJson),
+}
+
+impl ResponseError for RefundError {
+    fn error_response(&self) -> HttpResponse {
+       match self {
+            RefundError::InvalidAmount(ejson) => {
+                warn!("{}: {}", ejson.file, ejson.message);
+                HttpResponse::build(StatusCode::BAD_REQUEST)
+                    .json(json!({ "file": ejson.file, "message": ejson.message }))
+            },
+            RefundError::InvalidRefundRequest(ejson) => {
+                warn!("{}: {}", ejson.file, ejson.message);
+                HttpResponse::build(StatusCode::BAD_REQUEST)
+                    .json(json!({ "file": ejson.file, "message": ejson.message }))
+            },
+            RefundError::InvalidReason(ejson) => {
+                warn!("{}: {}", ejson.file, ejson.message);
+                HttpResponse::build(StatusCode::BAD_REQUEST)
+                    .json(json!({ "file": ejson.file, "message": ejson.message }))
+            },
+       }
+    }
+}
+
+
+/// A PaymentIntent which doesn't match the payment being confirmed
+#[derive(Debug, Serialize, Deserialize, Fail)]
+pub enum PaymentVerificationError {
+    /// The PaymentIntent hasn't succeeded
+    #[fail(display = "{}", _0)]
+    NotSucceeded(ErrJson),
+    #[fail(display = "{}", _0)]
+    AmountMismatch(ErrJson),
+    #[fail(display = "{}", _0)]
+    CurrencyMismatch(ErrJson),
+    /// The PaymentIntent was created for another order
+    #[fail(display = "{}", _0)]
+    OrderMismatch(ErrJson),
+}
+
+impl ResponseError for PaymentVerificationError {
+    fn error_response(&self) -> HttpResponse {
+       match self {
+            PaymentVerificationError::NotSucceeded(ejson) => {
+                warn!("{}: {}", ejson.file, ejson.message);
+                HttpResponse::build(StatusCode::BAD_REQUEST)
+                    .json(json!({ "file": ejson.file, "message": ejson.message }))
+            },
+            PaymentVerificationError::AmountMismatch(ejson) => {
+                warn!("{}: {}", ejson.file, ejson.message);
+                HttpResponse::build(StatusCode::BAD_REQUEST)
+                    .json(json!({ "file": ejson.file, "message": ejson.message }))
+            },
+            PaymentVerificationError::CurrencyMismatch(ejson) => {
+                warn!("{}: {}", ejson.file, ejson.message);
+                HttpResponse::build(StatusCode::BAD_REQUEST)
+                    .json(json!({ "file": ejson.file, "message": ejson.message }))
+            },
+            PaymentVerificationError::OrderMismatch(ejson) => {
+                warn!("{}: {}", ejson.file, ejson.message);
+                HttpResponse::build(StatusCode::BAD_REQUEST)
+                    .json(json!({ "file": ejson.file, "message": ejson.message }))
+            },
+       }
+    }
+}
+
+
+/// Payment amounts which don't match the cart in gm-shopping
+#[derive(Debug, Serialize, Deserialize, Fail)]
+pub enum CartValidationError {
+    /// orderItems' prices don't add up to the subtotal,
+    /// or the submitted subtotal isn't the cart's subtotal
+    #[fail(display = "{}", _0)]
+    SubtotalMismatch(ErrJson),
+    /// The submitted taxes aren't the cart's taxes
+    #[fail(display = "{}", _0)]
+    TaxesMismatch(ErrJson),
+    /// The submitted total isn't the cart's total
+    #[fail(display = "{}", _0)]
+    TotalMismatch(ErrJson),
+}
+
+impl ResponseError for CartValidationError {
+    fn error_response(&self) -> HttpResponse {
+       match self {
+            CartValidationError::SubtotalMismatch(ejson) => {
+                warn!("{}: {}", ejson.file, ejson.message);
+                HttpResponse::build(StatusCode::BAD_REQUEST)
+                    .json(json!({ "file": ejson.file, "message": ejson.message }))
+            },
+            CartValidationError::TaxesMismatch(ejson) => {
+                warn!("{}: {}", ejson.file, ejson.message);
+                HttpResponse::build(StatusCode::BAD_REQUEST)
+                    .json(json!({ "file": ejson.file, "message": ejson.message }))
+            },
+            CartValidationError::TotalMismatch(ejson) => {
+                warn!("{}: {}", ejson.file, ejson.message);
+                HttpResponse::build(StatusCode::BAD_REQUEST)
+                    .json(json!({ "file": ejson.file, "message": ejson.message }))
+            },
+       }
+    }
+}
+
+
+#[derive(Debug, Serialize, Deserialize, Fail)]
+pub enum DisputeError {
+    #[fail(display = "{}", _0)]
+    NotFound(ErrJson),
+    /// Won or lost disputes can't be responded to anymore
+    #[fail(display = "{}", _0)]
+    AlreadyClosed(ErrJson),
+}
+
+impl ResponseError for DisputeError {
+    fn error_response(&self) -> HttpResponse {
+       match self {
+            DisputeError::NotFound(ejson) => {
+                warn!("{}: {}", ejson.file, ejson.message);
+                HttpResponse::build(StatusCode::NOT_FOUND)
+                    .json(json!({ "file": ejson.file, "message": ejson.message }))
+            },
+            DisputeError::AlreadyClosed(ejson) => {
+                warn!("{}: {}", ejson.file, ejson.message);
+                HttpResponse::build(StatusCode::CONFLICT)
+                    .json(json!({ "file": ejson.file, "message": ejson.message }))
+            },
+       }
+    }
+}
+
+
+#[derive(Debug, Serialize, Deserialize, Fail)]
+pub enum WebhookError {
+    /// Missing, malformed, expired or wrong webhook signature
+    #[fail(display = "{}", _0)]
+    InvalidSignature(ErrJson),
+    /// Signing secrets or webhook id missing from .env
+    #[fail(display = "{}", _0)]
+    NotConfigured(ErrJson),
+    /// Paypal signing certificate could not be fetched or parsed
+    #[fail(display = "{}", _0)]
+    Certificate(ErrJson),
+    #[fail(display = "{}", _0)]
+    InvalidPayload(ErrJson),
+}
+
+impl ResponseError for WebhookError {
+    fn error_response(&self) -> HttpResponse {
+       match self {
+            WebhookError::InvalidSignature(ejson) => {
+                warn!("{}: {}", ejson.file, ejson.message);
+                HttpResponse::build(StatusCode::BAD_REQUEST)
+                    .json(json!({ "file": ejson.file, "message": ejson.message }))
+            },
+            WebhookError::NotConfigured(ejson) => {
+                error!("{}: {}", ejson.file, ejson.message);
+                HttpResponse::build(StatusCode::INTERNAL_SERVER_ERROR)
+                    .json(json!({ "file": ejson.file, "message": ejson.message }))
+            },
+            WebhookError::Certificate(ejson) => {
+                warn!("{}: {}", ejson.file, ejson.message);
+                HttpResponse::build(StatusCode::INTERNAL_SERVER_ERROR)
+                    .json(json!({ "file": ejson.file, "message": ejson.message }))
+            },
+            WebhookError::InvalidPayload(ejson) => {
+                warn!("{}: {}", ejson.file, ejson.message);
+                HttpResponse::build(StatusCode::BAD_REQUEST)
+                    .json(json!({ "file": ejson.file, "message": ejson.message }))
+            },
+       }
+    }
+}
@@ -0,0 +1,213 @@
+use diesel::prelude::*;
+use gm::db::schema::fee_overrides;
+
+use crate::models::OrderItemRpc;
+
+
+#[serde(rename_all = "camelCase")]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Queryable, Insertable)]
+#[table_name = "fee_overrides"]
+pub struct FeeOverride {
+    pub id: String,
+    pub created_at: chrono::NaiveDateTime,
+    /// Targets a single product. Most specific, wins over everything else.
+    pub product_id: Option<String>,
+    /// Targets a product category, e.g. firearm accessories.
+    /// If store_id is also set, only applies to that store's category.
+    pub category_id: Option<String>,
+    pub store_id: Option<String>,
+    /// Platform fee rate applied instead of the seller's PayoutSplit,
+    /// e.g. 0.10 for a 10% platform fee.
+    pub platform_fee_rate: Option<f64>,
+    /// Upper limit on the platform fee (in cents) for a single order item.
+    pub max_platform_fee: Option<i32>,
+    pub expires_at: Option<chrono::NaiveDateTime>,
+}
+
+impl FeeOverride {
+    pub fn new(
+        product_id: Option<String>,
+        category_id: Option<String>,
+        store_id: Option<String>,
+        platform_fee_rate: Option<f64>,
+        max_platform_fee: Option<i32>,
+        expires_at: Option<chrono::NaiveDateTime>,
+    ) -> Self {
+        Self {
+            id: format!("fee_override_{}", uuid::Uuid::new_v4().to_string()),
+            created_at: chrono::NaiveDateTime::from_timestamp(
+                chrono::Utc::now().timestamp(), 0
+            ),
+            product_id: product_id,
+            category_id: category_id,
+            store_id: store_id,
+            platform_fee_rate: platform_fee_rate,
+            max_platform_fee: max_platform_fee,
+            expires_at: expires_at,
+        }
+    }
+
+    /// Higher is more specific:
+    /// product > store + category > category
+    pub fn specificity(&self) -> i32 {
+        match (&self.product_id, &self.store_id, &self.category_id) {
+            (Some(_), _, _) => 3,
+            (None, Some(_), Some(_)) => 2,
+            (None, None, Some(_)) => 1,
+            _ => 0,
+        }
+    }
+
+    /// Whether this override targets the given orderItem
+    pub fn applies_to(&self, oitem: &OrderItemRpc) -> bool {
+        match (&self.product_id, &self.store_id, &self.category_id) {
+            (Some(product_id), _, _) => {
+                oitem.product_id.as_ref() == Some(product_id)
+            },
+            (None, Some(store_id), Some(category_id)) => {
+                &oitem.store_id == store_id &&
+                oitem.category_id.as_ref() == Some(category_id)
+            },
+            (None, None, Some(category_id)) => {
+                oitem.category_id.as_ref() == Some(category_id)
+            },
+            _ => false,
+        }
+    }
+
+    pub fn is_expired(&self, now: &chrono::NaiveDateTime) -> bool {
+        match self.expires_at {
+            None => false,
+            Some(exp) => exp <= *now,
+        }
+    }
+}
+
+
+/// Picks the most specific FeeOverride for an orderItem.
+/// Ties are broken by the most recently created override.
+pub fn pick_most_specific_fee_override(
+    fee_overrides: Vec<FeeOverride>,
+    oitem: &OrderItemRpc,
+    created_at: &chrono::NaiveDateTime,
+) -> Option<FeeOverride> {
+    fee_overrides
+        .into_iter()
+        .filter(|f: &FeeOverride| f.applies_to(oitem) && !f.is_expired(created_at))
+        .max_by_key(|f: &FeeOverride| (f.specificity(), f.created_at))
+}
+
+
+
+#[test]
+fn picks_most_specific_fee_override() {
+
+    let oitem = OrderItemRpc {
+        id: String::from("oitem_123"),
+        actual_price: 10000,
+        created_at: chrono::NaiveDateTime::from_timestamp(1_500_000_000, 0),
+        currency: String::from("USD"),
+        payment_processing_fee: None,
+        store_id: String::from("store_123"),
+        product_id: Some(String::from("product_123")),
+        category_id: Some(String::from("category_accessories")),
+        discounts: None,
+    };
+    let created_at = chrono::NaiveDateTime::from_timestamp(1_500_000_000, 0);
+
+    let category_fo = FeeOverride::new(
+        None,
+        Some(String::from("category_accessories")),
+        None,
+        Some(0.10),
+        None,
+        None,
+    );
+    let store_category_fo = FeeOverride::new(
+        None,
+        Some(String::from("category_accessories")),
+        Some(String::from("store_123")),
+        Some(0.12),
+        None,
+        None,
+    );
+    let other_store_fo = FeeOverride::new(
+        None,
+        Some(String::from("category_accessories")),
+        Some(String::from("store_456")),
+        Some(0.05),
+        None,
+        None,
+    );
+    let product_fo = FeeOverride::new(
+        Some(String::from("product_123")),
+        None,
+        None,
+        None,
+        Some(500),
+        None,
+    );
+
+    let res = pick_most_specific_fee_override(
+        vec![ category_fo.clone(), store_category_fo.clone(), other_store_fo.clone() ],
+        &oitem,
+        &created_at,
+    ).expect("store + category override");
+    assert_eq!(res.id, store_category_fo.id);
+
+    let res = pick_most_specific_fee_override(
+        vec![ category_fo.clone(), product_fo.clone(), store_category_fo.clone() ],
+        &oitem,
+        &created_at,
+    ).expect("product override");
+    assert_eq!(res.id, product_fo.id);
+
+    let res = pick_most_specific_fee_override(
+        vec![ other_store_fo ],
+        &oitem,
+        &created_at,
+    );
+    assert_eq!(res.is_none(), true);
+}
+
+#[test]
+fn skips_expired_fee_override() {
+
+    let oitem = OrderItemRpc {
+        id: String::from("oitem_123"),
+        actual_price: 10000,
+        created_at: chrono::NaiveDateTime::from_timestamp(1_500_000_000, 0),
+        currency: String::from("USD"),
+        payment_processing_fee: None,
+        store_id: String::from("store_123"),
+        product_id: None,
+        category_id: Some(String::from("category_accessories")),
+        discounts: None,
+    };
+    let created_at = chrono::NaiveDateTime::from_timestamp(1_500_000_000, 0);
+
+    let expired_fo = FeeOverride::new(
+        None,
+        Some(String::from("category_accessories")),
+        Some(String::from("store_123")),
+        Some(0.10),
+        None,
+        Some(chrono::NaiveDateTime::from_timestamp(1_400_000_000, 0)),
+    );
+    let category_fo = FeeOverride::new(
+        None,
+        Some(String::from("category_accessories")),
+        None,
+        Some(0.12),
+        None,
+        None,
+    );
+
+    let res = pick_most_specific_fee_override(
+        vec![ expired_fo, category_fo.clone() ],
+        &oitem,
+        &created_at,
+    ).expect("category override");
+    assert_eq!(res.id, category_fo.id);
+}
@@ -0,0 +1,236 @@
+use diesel::prelude::*;
+use gm::db::schema::manual_adjustments;
+
+use diesel::deserialize::FromSql;
+use diesel::pg::Pg;
+use diesel::serialize::{Output, ToSql};
+use diesel::sql_types::Text;
+use std::str::FromStr;
+
+use crate::models::{
+    PayoutItem,
+    PayeeType,
+};
+
+
+/// Why an admin added or removed money from a payee's earnings,
+/// outside of any order. The money itself is a PayoutItem, paid out
+/// (or deducted) by create_payout like any other UNPAID item.
+#[serde(rename_all = "camelCase")]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Queryable, Insertable)]
+#[table_name = "manual_adjustments"]
+pub struct ManualAdjustment {
+    pub id: String,
+    pub payout_item_id: String,
+    pub created_at: chrono::NaiveDateTime,
+    pub category: ManualAdjustmentCategory,
+    pub reason: String,
+    /// userId of the admin who approved it
+    pub approved_by: String,
+    /// userId of whoever asked for it, if not the approver
+    pub requested_by: Option<String>,
+}
+
+impl ManualAdjustment {
+    pub fn new(
+        payout_item_id: String,
+        category: ManualAdjustmentCategory,
+        reason: String,
+        approved_by: String,
+        requested_by: Option<String>,
+        created_at: chrono::NaiveDateTime,
+    ) -> Self {
+        Self {
+            id: format!("madj_{}", uuid::Uuid::new_v4().to_string()),
+            payout_item_id: payout_item_id,
+            created_at: created_at,
+            category: category,
+            reason: reason,
+            approved_by: approved_by,
+            requested_by: requested_by,
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(AsExpression, FromSqlRow)]
+#[sql_type = "Text"]
+pub enum ManualAdjustmentCategory {
+    /// Paid to the payee
+    BONUS,
+    /// Deducted from the payee's next payout
+    PENALTY,
+    /// Paid to the payee, e.g. to make up for a platform outage
+    GOODWILL,
+}
+impl ManualAdjustmentCategory {
+    pub fn as_string(&self) -> String {
+        String::from(format!("{:?}", &self))
+    }
+
+    /// Penalties are deducted, everything else is paid out
+    pub fn signed_amount(&self, amount: i32) -> i32 {
+        match self {
+            ManualAdjustmentCategory::PENALTY => -amount,
+            _ => amount,
+        }
+    }
+}
+impl ToSql<Text, Pg> for ManualAdjustmentCategory {
+    fn to_sql<W: std::io::Write>(&self, out: &mut Output<W, Pg>) -> ::diesel::serialize::Result {
+        let category = self.as_string();
+        ToSql::<Text, Pg>::to_sql(&category, out)
+    }
+}
+impl FromSql<Text, Pg> for ManualAdjustmentCategory {
+    fn from_sql(maybe_bytes: Option<&[u8]>) -> diesel::deserialize::Result<Self> {
+        let category = <String as FromSql<Text, Pg>>::from_sql(maybe_bytes)?;
+        ManualAdjustmentCategory::from_str(&category).map_err(|e| e.into())
+    }
+}
+impl FromStr for ManualAdjustmentCategory {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "BONUS" => Ok(ManualAdjustmentCategory::BONUS),
+            "PENALTY" => Ok(ManualAdjustmentCategory::PENALTY),
+            "GOODWILL" => Ok(ManualAdjustmentCategory::GOODWILL),
+            _ => Err(format!("Invalid ManualAdjustmentCategory: {}", s)),
+        }
+    }
+}
+
+
+/// A manual adjustment's PayoutItem, with the reason it was made.
+#[serde(rename_all = "camelCase")]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ManualAdjustmentItem {
+    pub payout_item: PayoutItem,
+    pub adjustment: ManualAdjustment,
+}
+
+
+/// A Payout broken down into order earnings, refunds (and clawbacks of
+/// refunds on earlier payouts, and lost disputes) and manual adjustments,
+/// so payees can see why their payout differs from their sales.
+#[serde(rename_all = "camelCase")]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PayoutStatement {
+    pub payout_id: String,
+    pub payee_id: String,
+    pub payee_type: PayeeType,
+    pub currency: String,
+    pub amount: i32,
+    pub earnings_total: i32,
+    pub refunds_total: i32,
+    pub adjustments_total: i32,
+    /// Items from orders, including recalculation adjustments
+    pub earnings: Vec<PayoutItem>,
+    pub refunds: Vec<PayoutItem>,
+    pub adjustments: Vec<ManualAdjustmentItem>,
+}
+
+impl PayoutStatement {
+    pub fn new(
+        payout_id: String,
+        payee_id: String,
+        payee_type: PayeeType,
+        currency: String,
+        payout_items: Vec<PayoutItem>,
+        manual_adjustments: Vec<ManualAdjustment>,
+    ) -> Self {
+
+        let mut earnings: Vec<PayoutItem> = vec![];
+        let mut refunds: Vec<PayoutItem> = vec![];
+        let mut adjustments: Vec<ManualAdjustmentItem> = vec![];
+
+        for pitem in payout_items.into_iter() {
+            match manual_adjustments.iter().find(|m| m.payout_item_id == pitem.id) {
+                Some(madj) => adjustments.push(ManualAdjustmentItem {
+                    payout_item: pitem,
+                    adjustment: madj.clone(),
+                }),
+                None => match pitem.id.starts_with("ritem_")
+                    || pitem.is_clawback()
+                    || pitem.is_dispute_fee() {
+                    true => refunds.push(pitem),
+                    false => earnings.push(pitem),
+                },
+            }
+        }
+
+        let earnings_total = earnings.iter().map(|p| p.amount).sum::<i32>();
+        let refunds_total = refunds.iter().map(|p| p.amount).sum::<i32>();
+        let adjustments_total = adjustments.iter().map(|m| m.payout_item.amount).sum::<i32>();
+
+        Self {
+            payout_id: payout_id,
+            payee_id: payee_id,
+            payee_type: payee_type,
+            currency: currency,
+            amount: earnings_total + refunds_total + adjustments_total,
+            earnings_total: earnings_total,
+            refunds_total: refunds_total,
+            adjustments_total: adjustments_total,
+            earnings: earnings,
+            refunds: refunds,
+            adjustments: adjustments,
+        }
+    }
+}
+
+
+
+#[test]
+fn separates_manual_adjustments_in_payout_statements() {
+
+    let created_at = chrono::NaiveDateTime::from_timestamp(1_500_000_000, 0);
+
+    let sale = PayoutItem::new(
+        String::from("oitem_123"),
+        String::from("store_123"),
+        Some(PayeeType::STORE),
+        8110,
+        390,
+        created_at,
+        String::from("USD"),
+        String::from("txn_123"),
+    );
+    let refund = sale.to_refund(created_at, String::from("txn_456"));
+    let category = ManualAdjustmentCategory::PENALTY;
+    let penalty = PayoutItem::new_manual_adjustment(
+        String::from("store_123"),
+        PayeeType::STORE,
+        category.signed_amount(1000),
+        created_at,
+        String::from("USD"),
+    );
+    let madj = ManualAdjustment::new(
+        penalty.id.clone(),
+        category,
+        String::from("listing policy violation"),
+        String::from("admin_123"),
+        None,
+        created_at,
+    );
+
+    let statement = PayoutStatement::new(
+        String::from("payout_123"),
+        String::from("store_123"),
+        PayeeType::STORE,
+        String::from("USD"),
+        vec![sale.clone(), sale, refund, penalty],
+        vec![madj],
+    );
+
+    assert_eq!(statement.earnings.len(), 2);
+    assert_eq!(statement.refunds.len(), 1);
+    assert_eq!(statement.adjustments.len(), 1);
+    assert_eq!(statement.earnings_total, 16220);
+    assert_eq!(statement.refunds_total, -8110);
+    assert_eq!(statement.adjustments_total, -1000);
+    assert_eq!(statement.amount, 7110);
+    assert_eq!(statement.adjustments[0].payout_item.order_item_id, None);
+}
@@ -5,49 +5,77 @@ use gm::db::{establish_connection_pg};
 use gm::db;
 
 pub mod affiliate;
+pub mod audit_log;
 pub mod auth_info;
 pub mod cart;
+pub mod clawback;
 pub mod connection;
 pub mod currency;
+pub mod dispute;
 #[macro_use]
 pub mod errors;
+pub mod fee_override;
+pub mod manual_adjustment;
 pub mod order;
 pub mod paginate_page;
 pub mod paginate_cursor;
 pub mod payment_method;
 pub mod paypal;
 pub mod payouts;
+pub mod payout_adjustment;
+pub mod payout_calculation;
 pub mod payout_items;
 pub mod payout_period;
 pub mod payout_methods;
 pub mod payout_signatures;
 pub mod payout_split;
+pub mod payout_split_csv;
+pub mod payout_split_rules;
 pub mod transaction;
 pub mod to_payout_items;
 pub mod refund;
+pub mod refund_allocation;
+pub mod refund_analytics;
+pub mod refund_request;
+pub mod revenue_share;
 pub mod user;
+pub mod webhook_event;
 
 pub mod tests;
 
 pub use affiliate::*;
+pub use audit_log::*;
 pub use auth_info::*;
 pub use cart::*;
+pub use clawback::*;
 pub use connection::*;
 pub use currency::*;
+pub use dispute::*;
 pub use errors::*;
+pub use fee_override::*;
+pub use manual_adjustment::*;
 pub use order::*;
 pub use paginate_page::*;
 pub use paginate_cursor::*;
 pub use payment_method::*;
 pub use paypal::*;
 pub use payouts::*;
+pub use payout_adjustment::*;
+pub use payout_calculation::*;
 pub use payout_items::*;
 pub use payout_period::*;
 pub use payout_methods::*;
 pub use payout_signatures::*;
 pub use payout_split::*;
+pub use payout_split_csv::*;
+pub use payout_split_rules::*;
 pub use transaction::*;
 pub use to_payout_items::*;
 pub use refund::*;
+pub use refund_allocation::*;
+pub use refund_analytics::*;
+pub use refund_request::*;
+pub use revenue_share::*;
 pub use user::*;
+pub use webhook_event::*;
 
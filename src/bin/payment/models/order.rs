@@ -63,6 +63,33 @@ pub struct OrderItemRpc {
     pub payment_processing_fee: Option<i32>,
     /// entities
     pub store_id: String,
+    /// used to look up product and category fee overrides
+    pub product_id: Option<String>,
+    pub category_id: Option<String>,
+    /// Discounts already taken off actual_price, and who pays for them
+    pub discounts: Option<Vec<OrderItemDiscount>>,
+}
+
+
+#[serde(rename_all = "camelCase")]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct OrderItemDiscount {
+    pub promo_code_id: Option<String>,
+    /// Discount in cents
+    pub amount: i32,
+    pub funded_by: DiscountFunding,
+    /// Fraction of a SPLIT discount the platform pays for, defaults to half
+    pub platform_share: Option<f64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum DiscountFunding {
+    /// Platform promotion. Seller is paid as if there was no discount
+    PLATFORM,
+    /// Seller's own promotion, seller pays for all of it
+    SELLER,
+    /// Both pay, by OrderItemDiscount.platform_share
+    SPLIT,
 }
 
 
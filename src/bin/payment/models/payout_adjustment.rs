@@ -0,0 +1,131 @@
+use std::collections::BTreeMap;
+
+use crate::models::{
+    PayoutItem,
+    PayoutStatus,
+    PayoutCalculation,
+    AuditLog,
+};
+
+
+/// Result of recomputing an order's earnings.
+/// In preview mode nothing is written, and there is no audit_log.
+#[serde(rename_all = "camelCase")]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PayoutRecalculation {
+    pub order_id: String,
+    pub preview: bool,
+    /// PayoutSplits and fee overrides in effect at this date were used
+    pub valid_at: chrono::NaiveDateTime,
+    pub adjustments: Vec<PayoutItem>,
+    pub payout_calculations: Vec<PayoutCalculation>,
+    /// Refunded orderItems, or ones without a recorded store, are left alone
+    pub skipped_order_item_ids: Vec<String>,
+    pub audit_log: Option<AuditLog>,
+}
+
+
+/// Signed adjustment items for the difference between an orderItem's
+/// existing payout items (including earlier adjustments) and recomputed ones,
+/// per orderItem, payee and payee type. Existing items are never changed,
+/// so items which were already paid out stay as they were.
+pub fn payout_adjustment_items(
+    existing_payout_items: &Vec<PayoutItem>,
+    recomputed_payout_items: &Vec<PayoutItem>,
+    created_at: chrono::NaiveDateTime,
+) -> Vec<PayoutItem> {
+
+    // (order_item_id, payee_id, payee_type) => (amount, fee, item)
+    let mut diffs: BTreeMap<(Option<String>, String, String), (i32, i32, PayoutItem)> = BTreeMap::new();
+
+    let signed_items = existing_payout_items.iter()
+        .map(|p| (-1, p))
+        .chain(recomputed_payout_items.iter().map(|p| (1, p)));
+
+    for (sign, pitem) in signed_items {
+        let key = (
+            pitem.order_item_id.clone(),
+            pitem.payee_id.clone(),
+            pitem.payee_type.as_string(),
+        );
+        let entry = diffs.entry(key).or_insert((0, 0, pitem.clone()));
+        entry.0 += sign * pitem.amount;
+        entry.1 += sign * pitem.payment_processing_fee;
+    }
+
+    diffs.into_iter()
+        .filter(|(_, (amount, fee, _))| *amount != 0 || *fee != 0)
+        .map(|(_, (amount, fee, pitem))| {
+            PayoutItem {
+                amount: amount,
+                payment_processing_fee: fee,
+                created_at: created_at,
+                payout_status: PayoutStatus::UNPAID,
+                payout_id: None,
+                ..pitem
+            }.as_adjustment()
+        })
+        .collect::<Vec<PayoutItem>>()
+}
+
+
+/// Whether a payout item has been refunded, or is being refunded
+pub fn is_refund_status(payout_status: &PayoutStatus) -> bool {
+    match payout_status {
+        PayoutStatus::REFUNDING |
+        PayoutStatus::PENDING_REFUND |
+        PayoutStatus::REFUNDED => true,
+        _ => false,
+    }
+}
+
+
+
+#[test]
+fn creates_signed_adjustment_items_for_differences() {
+
+    use crate::models::PayeeType;
+
+    let created_at = chrono::NaiveDateTime::from_timestamp(1_500_000_000, 0);
+    let pitem = |payee_id: &str, payee_type: PayeeType, amount: i32, fee: i32| PayoutItem::new(
+        String::from("oitem_123"),
+        String::from(payee_id),
+        Some(payee_type),
+        amount,
+        fee,
+        created_at,
+        String::from("USD"),
+        String::from("txn_123"),
+    );
+
+    // seller was charged the default 15% instead of their 10% deal,
+    // and the store item has already been paid out
+    let existing = vec![
+        pitem("store_123", PayeeType::STORE, 8110, 390).set_payout_status(PayoutStatus::PAID),
+        pitem("gm-platform", PayeeType::PLATFORM, 1500, 0),
+    ];
+    let recomputed = vec![
+        pitem("store_123", PayeeType::STORE, 8610, 390),
+        pitem("gm-platform", PayeeType::PLATFORM, 1000, 0),
+    ];
+
+    let adjustments = payout_adjustment_items(&existing, &recomputed, created_at);
+    assert_eq!(adjustments.len(), 2);
+    assert_eq!(adjustments.iter().all(|a| a.is_adjustment()), true);
+    assert_eq!(
+        adjustments.iter()
+            .map(|a| (a.payee_id.clone(), a.amount, a.payout_status.clone()))
+            .collect::<Vec<(String, i32, PayoutStatus)>>(),
+        vec![
+            (String::from("gm-platform"), -500, PayoutStatus::UNPAID),
+            (String::from("store_123"), 500, PayoutStatus::UNPAID),
+        ]
+    );
+
+    // running it again with the adjustments included changes nothing
+    let existing_and_adjustments = existing.into_iter()
+        .chain(adjustments.into_iter())
+        .collect::<Vec<PayoutItem>>();
+    let adjustments = payout_adjustment_items(&existing_and_adjustments, &recomputed, created_at);
+    assert_eq!(adjustments.len(), 0);
+}
@@ -0,0 +1,348 @@
+use diesel::prelude::*;
+use gm::db::schema::payout_calculations;
+
+use crate::models::{
+    OrderItemRpc,
+    OrderItemDiscount,
+    DiscountFunding,
+    PayoutItem,
+    PayoutSplit,
+    PayeeType,
+    FeeOverride,
+};
+use crate::pricing::{
+    AppliedRates,
+    CalculatedEarnings,
+    FundedDiscounts,
+    PaymentFees,
+    generate_earnings_with_discounts,
+};
+
+
+/// Everything that went into splitting one orderItem into PayoutItems,
+/// so the split can be explained, and recomputed later.
+#[serde(rename_all = "camelCase")]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Queryable, Insertable)]
+#[table_name = "payout_calculations"]
+pub struct PayoutCalculation {
+    pub id: String,
+    pub created_at: chrono::NaiveDateTime,
+    pub order_item_id: String,
+    pub txn_id: String,
+    pub currency: String,
+    /// orderItem.actual_price, after discounts
+    pub subtotal: i32,
+    /// Payment processing fee paid by the seller
+    pub payment_processing_fee: i32,
+    pub seller_payout_split_id: Option<String>,
+    pub buyer_affiliate_payout_split_id: Option<String>,
+    pub seller_affiliate_payout_split_id: Option<String>,
+    /// Referrers of the seller affiliate, nearest first
+    pub upline_affiliate_payout_split_ids: Vec<String>,
+    pub fee_override_id: Option<String>,
+    /// Rates after expiry checks and fee overrides
+    pub seller_rate: f64,
+    pub buyer_affiliate_rate: f64,
+    pub seller_affiliate_rate: f64,
+    pub upline_affiliate_rates: Vec<f64>,
+    /// Fee schedule at the time
+    pub payment_fee_percentage: f64,
+    pub payment_fee_fixed: i32,
+    pub platform_fee_percentage: f64,
+    pub platform_funded_discount: i32,
+    pub seller_funded_discount: i32,
+    /// orderItem details, so the split can be recomputed under new PayoutSplits
+    pub store_id: Option<String>,
+    pub product_id: Option<String>,
+    pub category_id: Option<String>,
+}
+
+impl PayoutCalculation {
+    pub fn new(
+        order_item_id: String,
+        txn_id: String,
+        currency: String,
+        subtotal: i32,
+        payment_processing_fee: i32,
+        created_at: chrono::NaiveDateTime,
+    ) -> Self {
+        let fees = PaymentFees::new();
+        Self {
+            id: format!("pcalc_{}", uuid::Uuid::new_v4().to_string()),
+            created_at: created_at,
+            order_item_id: order_item_id,
+            txn_id: txn_id,
+            currency: currency,
+            subtotal: subtotal,
+            payment_processing_fee: payment_processing_fee,
+            seller_payout_split_id: None,
+            buyer_affiliate_payout_split_id: None,
+            seller_affiliate_payout_split_id: None,
+            upline_affiliate_payout_split_ids: vec![],
+            fee_override_id: None,
+            seller_rate: 0.0,
+            buyer_affiliate_rate: 0.0,
+            seller_affiliate_rate: 0.0,
+            upline_affiliate_rates: vec![],
+            payment_fee_percentage: fees.payment_fee_percentage,
+            payment_fee_fixed: fees.payment_fee_fixed,
+            platform_fee_percentage: fees.platform_fee_percentage,
+            platform_funded_discount: 0,
+            seller_funded_discount: 0,
+            store_id: None,
+            product_id: None,
+            category_id: None,
+        }
+    }
+
+    pub fn update_order_item(mut self, oitem: &OrderItemRpc) -> Self {
+        self.store_id = Some(oitem.store_id.clone());
+        self.product_id = oitem.product_id.clone();
+        self.category_id = oitem.category_id.clone();
+        self
+    }
+
+    /// Rebuilds the orderItem this split was calculated for.
+    /// Discounts are rebuilt as one PLATFORM and one SELLER funded discount.
+    /// None if the store was not recorded.
+    pub fn to_order_item_rpc(&self) -> Option<OrderItemRpc> {
+
+        let discount = |amount: i32, funded_by: DiscountFunding| OrderItemDiscount {
+            promo_code_id: None,
+            amount: amount,
+            funded_by: funded_by,
+            platform_share: None,
+        };
+        let discounts = vec![
+            discount(self.platform_funded_discount, DiscountFunding::PLATFORM),
+            discount(self.seller_funded_discount, DiscountFunding::SELLER),
+        ].into_iter()
+        .filter(|d| d.amount != 0)
+        .collect::<Vec<OrderItemDiscount>>();
+
+        self.store_id.as_ref().map(|store_id| OrderItemRpc {
+            id: self.order_item_id.clone(),
+            actual_price: self.subtotal,
+            created_at: self.created_at,
+            currency: self.currency.clone(),
+            payment_processing_fee: None,
+            store_id: store_id.clone(),
+            product_id: self.product_id.clone(),
+            category_id: self.category_id.clone(),
+            discounts: Some(discounts),
+        })
+    }
+
+    pub fn update_payout_splits(
+        mut self,
+        seller_payout_split: &Option<PayoutSplit>,
+        buyer_aff_payout_split: &Option<PayoutSplit>,
+        seller_aff_payout_split: &Option<PayoutSplit>,
+        upline_aff_payout_splits: &Vec<PayoutSplit>,
+        fee_override: &Option<FeeOverride>,
+    ) -> Self {
+        self.seller_payout_split_id = seller_payout_split.as_ref().map(|ps| ps.id.clone());
+        self.buyer_affiliate_payout_split_id = buyer_aff_payout_split.as_ref().map(|ps| ps.id.clone());
+        self.seller_affiliate_payout_split_id = seller_aff_payout_split.as_ref().map(|ps| ps.id.clone());
+        self.upline_affiliate_payout_split_ids = upline_aff_payout_splits
+            .iter()
+            .map(|ps| ps.id.clone())
+            .collect();
+        self.fee_override_id = fee_override.as_ref().map(|fo| fo.id.clone());
+        self
+    }
+
+    pub fn update_applied_rates(mut self, applied_rates: AppliedRates) -> Self {
+        self.seller_rate = applied_rates.seller_rate;
+        self.buyer_affiliate_rate = applied_rates.buyer_aff_rate;
+        self.seller_affiliate_rate = applied_rates.seller_aff_rate;
+        self.upline_affiliate_rates = applied_rates.upline_aff_rates;
+        self
+    }
+
+    pub fn update_funded_discounts(mut self, funded_discounts: FundedDiscounts) -> Self {
+        self.platform_funded_discount = funded_discounts.platform_funded;
+        self.seller_funded_discount = funded_discounts.seller_funded;
+        self
+    }
+
+    /// Splits the subtotal again with the stored rates and discounts
+    pub fn recompute(&self) -> CalculatedEarnings {
+        generate_earnings_with_discounts(
+            self.subtotal,
+            self.payment_processing_fee,
+            AppliedRates {
+                seller_rate: self.seller_rate,
+                buyer_aff_rate: self.buyer_affiliate_rate,
+                seller_aff_rate: self.seller_affiliate_rate,
+                upline_aff_rates: self.upline_affiliate_rates.clone(),
+            },
+            FundedDiscounts {
+                platform_funded: self.platform_funded_discount,
+                seller_funded: self.seller_funded_discount,
+            },
+        )
+    }
+
+    /// Whether the fee schedule has changed since this calculation
+    pub fn fee_schedule_changed(&self) -> bool {
+        let fees = PaymentFees::new();
+        self.payment_fee_percentage != fees.payment_fee_percentage ||
+        self.payment_fee_fixed != fees.payment_fee_fixed ||
+        self.platform_fee_percentage != fees.platform_fee_percentage
+    }
+}
+
+
+/// An orderItem's split may be recalculated, keeps the most recent
+/// PayoutCalculation for each orderItem
+pub fn latest_payout_calculations(
+    payout_calculations: Vec<PayoutCalculation>,
+) -> Vec<PayoutCalculation> {
+    let mut latest: Vec<PayoutCalculation> = vec![];
+    for pcalc in payout_calculations.into_iter() {
+        match latest.iter_mut().find(|p| p.order_item_id == pcalc.order_item_id) {
+            Some(p) => if pcalc.created_at > p.created_at { *p = pcalc },
+            None => latest.push(pcalc),
+        }
+    }
+    latest
+}
+
+
+#[serde(rename_all = "camelCase")]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PayoutMismatch {
+    pub payee_type: PayeeType,
+    /// amount, or paymentProcessingFee
+    pub field: String,
+    pub expected: i32,
+    pub actual: i32,
+}
+
+#[serde(rename_all = "camelCase")]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PayoutCalculationCheck {
+    pub payout_calculation: PayoutCalculation,
+    pub recomputed: CalculatedEarnings,
+    pub mismatches: Vec<PayoutMismatch>,
+    pub fee_schedule_changed: bool,
+}
+
+
+/// Recomputes a PayoutCalculation and compares it against the stored
+/// PayoutItems for the same orderItem and transaction, totalled by payee type
+/// (a store's revenue-share recipients are all STORE payout items).
+pub fn verify_payout_calculation(
+    payout_calculation: PayoutCalculation,
+    payout_items: &Vec<PayoutItem>,
+) -> PayoutCalculationCheck {
+
+    let recomputed = payout_calculation.recompute();
+
+    let pitems = payout_items
+        .iter()
+        .filter(|p| p.order_item_id.as_ref() == Some(&payout_calculation.order_item_id))
+        .filter(|p| p.txn_id.as_ref() == Some(&payout_calculation.txn_id))
+        .collect::<Vec<&PayoutItem>>();
+
+    let total = |payee_type: PayeeType| -> (i32, i32) {
+        pitems.iter()
+            .filter(|p| p.payee_type == payee_type)
+            .fold((0, 0), |(amount, fee), p| {
+                (amount + p.amount, fee + p.payment_processing_fee)
+            })
+    };
+
+    let (store_amount, store_fee) = total(PayeeType::STORE);
+    let (platform_amount, _) = total(PayeeType::PLATFORM);
+    let (buyer_aff_amount, _) = total(PayeeType::BUYER_AFFILIATE);
+    let (seller_aff_amount, _) = total(PayeeType::SELLER_AFFILIATE);
+
+    let expected_actual = vec![
+        (PayeeType::STORE, "amount", recomputed.seller_earnings_less_payment_fee, store_amount),
+        (PayeeType::STORE, "paymentProcessingFee", recomputed.payment_processing_fee, store_fee),
+        (PayeeType::PLATFORM, "amount", recomputed.gm_earnings, platform_amount),
+        (PayeeType::BUYER_AFFILIATE, "amount", recomputed.buyer_affiliate_earnings, buyer_aff_amount),
+        (
+            PayeeType::SELLER_AFFILIATE,
+            "amount",
+            recomputed.seller_affiliate_earnings
+                + recomputed.upline_affiliate_earnings.iter().sum::<i32>(),
+            seller_aff_amount,
+        ),
+    ];
+
+    let mismatches = expected_actual
+        .into_iter()
+        .filter(|(_, _, expected, actual)| expected != actual)
+        .map(|(payee_type, field, expected, actual)| PayoutMismatch {
+            payee_type: payee_type,
+            field: String::from(field),
+            expected: expected,
+            actual: actual,
+        })
+        .collect::<Vec<PayoutMismatch>>();
+
+    PayoutCalculationCheck {
+        fee_schedule_changed: payout_calculation.fee_schedule_changed(),
+        payout_calculation: payout_calculation,
+        recomputed: recomputed,
+        mismatches: mismatches,
+    }
+}
+
+
+
+#[test]
+fn verifies_payout_calculation_against_payout_items() {
+
+    let created_at = chrono::NaiveDateTime::from_timestamp(1_500_000_000, 0);
+    let pcalc = PayoutCalculation::new(
+        String::from("oitem_123"),
+        String::from("txn_123"),
+        String::from("USD"),
+        10000,
+        390,
+        created_at,
+    ).update_applied_rates(AppliedRates {
+        seller_rate: 0.85,
+        buyer_aff_rate: 0.0,
+        seller_aff_rate: 0.0,
+        upline_aff_rates: vec![],
+    });
+
+    let pitem = |payee_type: PayeeType, amount: i32, fee: i32| PayoutItem::new(
+        String::from("oitem_123"),
+        String::from("payee_123"),
+        Some(payee_type),
+        amount,
+        fee,
+        created_at,
+        String::from("USD"),
+        String::from("txn_123"),
+    );
+
+    let pitems = vec![
+        pitem(PayeeType::STORE, 8110, 390),
+        pitem(PayeeType::PLATFORM, 1500, 0),
+    ];
+    let check = verify_payout_calculation(pcalc.clone(), &pitems);
+    assert_eq!(check.mismatches, vec![]);
+
+    // a store payout item that was edited by hand
+    let pitems = vec![
+        pitem(PayeeType::STORE, 8000, 390),
+        pitem(PayeeType::PLATFORM, 1500, 0),
+    ];
+    let check = verify_payout_calculation(pcalc, &pitems);
+    assert_eq!(check.mismatches, vec![
+        PayoutMismatch {
+            payee_type: PayeeType::STORE,
+            field: String::from("amount"),
+            expected: 8110,
+            actual: 8000,
+        }
+    ]);
+}
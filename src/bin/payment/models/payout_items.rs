@@ -49,8 +49,9 @@ pub struct PayoutItem {
     pub created_at: chrono::NaiveDateTime,
     pub payout_status: PayoutStatus,
     pub currency: String,
-    pub order_item_id: String,
-    pub txn_id: String,
+    /// None for manual adjustments, which don't come from an order
+    pub order_item_id: Option<String>,
+    pub txn_id: Option<String>,
     pub payout_id: Option<String>,
 }
 impl PayoutItem {
@@ -74,8 +75,8 @@ impl PayoutItem {
             created_at: created_at,
             payout_status: PayoutStatus::UNPAID,
             currency: currency,
-            order_item_id: order_item_id,
-            txn_id: txn_id,
+            order_item_id: Some(order_item_id),
+            txn_id: Some(txn_id),
             payout_id: None,
         }
     }
@@ -96,11 +97,63 @@ impl PayoutItem {
             payout_status: PayoutStatus::REFUNDING,
             currency: self.currency.clone(),
             order_item_id: self.order_item_id.clone(),
-            txn_id: txn_id,
+            txn_id: Some(txn_id),
             payout_id: None,
         }
     }
 
+    /// Adjustment items correct the earnings of an orderItem after it was
+    /// split, e.g. when a PayoutSplit was set up wrongly. They are signed,
+    /// and are paid out (or deducted) in the next payout like any other item.
+    pub fn as_adjustment(mut self) -> Self {
+        self.id = format!("aitem_{}", uuid::Uuid::new_v4().to_string());
+        self
+    }
+
+    pub fn is_adjustment(&self) -> bool {
+        self.id.starts_with("aitem_")
+    }
+
+    /// Manual adjustments are bonuses, penalties or goodwill credits given
+    /// by an admin. They have no orderItem, and their reason, category and
+    /// approver are kept in a ManualAdjustment.
+    pub fn new_manual_adjustment(
+        payee_id: String,
+        payee_type: PayeeType,
+        amount: i32,
+        created_at: chrono::NaiveDateTime,
+        currency: String,
+    ) -> Self {
+        Self {
+            id: format!("mitem_{}", uuid::Uuid::new_v4().to_string()),
+            payee_id: payee_id,
+            payee_type: payee_type,
+            amount: amount,
+            payment_processing_fee: 0,
+            created_at: created_at,
+            payout_status: PayoutStatus::UNPAID,
+            currency: currency,
+            order_item_id: None,
+            txn_id: None,
+            payout_id: None,
+        }
+    }
+
+    pub fn is_manual_adjustment(&self) -> bool {
+        self.id.starts_with("mitem_")
+    }
+
+    /// Clawbacks take back a refunded share which was already paid out,
+    /// from the payee's (or whoever absorbs it) future payouts.
+    pub fn is_clawback(&self) -> bool {
+        self.id.starts_with("citem_")
+    }
+
+    /// Dispute fees Stripe charges for a lost dispute, booked against sellers
+    pub fn is_dispute_fee(&self) -> bool {
+        self.id.starts_with("ditem_")
+    }
+
     pub fn set_payout_status(mut self, payout_status: PayoutStatus) -> Self {
         self.payout_status = payout_status;
         self
@@ -120,8 +173,8 @@ impl std::default::Default for PayoutItem {
             ),
             payout_status: PayoutStatus::UNPAID,
             currency: Currency::USD.as_string(),
-            order_item_id: String::from(""),
-            txn_id: String::from(""),
+            order_item_id: None,
+            txn_id: None,
             payout_id: None,
         }
     }
@@ -210,6 +263,10 @@ pub struct SummaryStatistics {
     pub unpaid: i64,
     #[sql_type = "BigInt"]
     pub count: i64,
+    /// Sum of manual adjustments (bonuses, penalties, goodwill credits)
+    #[sql_type = "BigInt"]
+    #[serde(default)]
+    pub adjustments: i64,
 }
 impl FromSql<Json, Pg> for SummaryStatistics {
     fn from_sql(maybe_bytes: Option<&[u8]>) -> diesel::deserialize::Result<Self> {
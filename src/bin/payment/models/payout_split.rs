@@ -1,5 +1,5 @@
 use diesel::prelude::*;
-use diesel::sql_types::{Double, Float8, Jsonb, Json, Text, BigInt, Timestamp, Nullable};
+use diesel::sql_types::{Double, Float8, Jsonb, Json, Text, BigInt, Timestamp, Nullable, Bool};
 use diesel::serialize::{Output, ToSql};
 use diesel::pg::Pg;
 use diesel::deserialize::FromSql;
@@ -31,32 +31,119 @@ pub struct PayoutSplit {
     /// another REFERRED_SELLER.
     #[sql_type = "Nullable<Text>"]
     pub referrer_id: Option<String>,
+    /// PayoutSplits are never overwritten. Each change appends a new version,
+    /// and closes the previous version by setting its effective_to.
+    #[sql_type = "Timestamp"]
+    pub effective_from: chrono::NaiveDateTime,
+    /// None while this version is still in effect
+    #[sql_type = "Nullable<Timestamp>"]
+    pub effective_to: Option<chrono::NaiveDateTime>,
+    /// userId of whoever made this version, and why
+    #[sql_type = "Nullable<Text>"]
+    pub changed_by: Option<String>,
+    #[sql_type = "Nullable<Text>"]
+    pub reason: Option<String>,
+    /// Scheduled PayoutSplits only apply from starts_at until expires_at,
+    /// and take precedence over the regular PayoutSplit (without a starts_at).
+    /// Windows of scheduled PayoutSplits for the same deal may not overlap.
+    #[sql_type = "Nullable<Timestamp>"]
+    pub starts_at: Option<chrono::NaiveDateTime>,
+    /// Set when an admin wrote this version past the rate limits,
+    /// see check_payout_split_rate
+    #[sql_type = "Bool"]
+    #[serde(default)]
+    pub rate_override: bool,
+    /// BUYER_AFFILIATE deals can be scoped to a single store, for stores
+    /// which run their own affiliate programme. For orderItems from that store,
+    /// this PayoutSplit is used instead of the affiliate's global PayoutSplit.
+    #[sql_type = "Nullable<Text>"]
+    pub store_id: Option<String>,
 }
 
 impl PayoutSplit {
     pub fn new(
         store_or_user_id: String,
         deal_type: PayoutDealType,
+        starts_at: Option<chrono::NaiveDateTime>,
         expires_at: Option<chrono::NaiveDateTime>,
         rate: f64,
         referrer_id: Option<String>,
     ) -> Self {
 
-        let created_at = chrono::Utc::now();
+        let created_at = chrono::NaiveDateTime::from_timestamp(
+            chrono::Utc::now().timestamp(), 0
+        );
 
         PayoutSplit {
             id: format!("psplit_{}", uuid::Uuid::new_v4().to_string()),
-            created_at: chrono::NaiveDateTime::from_timestamp(
-                created_at.timestamp(), 0
-            ),
+            created_at: created_at,
             store_or_user_id: store_or_user_id,
             deal_type: deal_type,
             expires_at: expires_at,
             rate: rate,
             referrer_id: referrer_id,
+            effective_from: created_at,
+            effective_to: None,
+            changed_by: None,
+            reason: None,
+            starts_at: starts_at,
+            rate_override: false,
+            store_id: None,
         }
     }
 
+    /// Whether the deal window [starts_at, expires_at) includes the date,
+    /// and the version had not been superseded or retired by then
+    pub fn is_active_at(&self, date: &chrono::NaiveDateTime) -> bool {
+        let started = match self.starts_at {
+            None => true,
+            Some(start) => start <= *date,
+        };
+        let not_expired = match self.expires_at {
+            None => true,
+            Some(exp) => exp > *date,
+        };
+        let not_retired = match self.effective_to {
+            None => true,
+            Some(to) => to > *date,
+        };
+        started && not_expired && not_retired
+    }
+
+    /// Whether the deal windows of two PayoutSplits overlap.
+    /// A missing starts_at or expires_at is an open-ended window.
+    pub fn overlaps(&self, other: &PayoutSplit) -> bool {
+        let starts_before_other_ends = match (self.starts_at, other.expires_at) {
+            (Some(start), Some(other_end)) => start < other_end,
+            _ => true,
+        };
+        let other_starts_before_end = match (other.starts_at, self.expires_at) {
+            (Some(other_start), Some(end)) => other_start < end,
+            _ => true,
+        };
+        starts_before_other_ends && other_starts_before_end
+    }
+
+    pub fn update_changed_by(
+        mut self,
+        changed_by: Option<String>,
+        reason: Option<String>,
+    ) -> Self {
+        self.changed_by = changed_by;
+        self.reason = reason;
+        self
+    }
+
+    pub fn update_rate_override(mut self, rate_override: bool) -> Self {
+        self.rate_override = rate_override;
+        self
+    }
+
+    pub fn update_store_id(mut self, store_id: Option<String>) -> Self {
+        self.store_id = store_id;
+        self
+    }
+
     pub fn update_referrer_id(mut self, referrer_id: String) -> Self {
         self.referrer_id = Some(referrer_id);
         self
@@ -92,6 +179,18 @@ impl PayoutDealType {
     pub fn as_string(&self) -> String {
         String::from(format!("{:?}", &self))
     }
+
+    /// Deal types which supersede each other when a new version is written.
+    /// A seller has either a SELLER or a REFERRED_SELLER PayoutSplit, never both.
+    pub fn versioned_with(&self) -> Vec<PayoutDealType> {
+        match self {
+            PayoutDealType::SELLER | PayoutDealType::REFERRED_SELLER => vec![
+                PayoutDealType::SELLER,
+                PayoutDealType::REFERRED_SELLER,
+            ],
+            deal_type => vec![deal_type.clone()],
+        }
+    }
 }
 impl Default for PayoutDealType {
     fn default() -> Self {
@@ -126,3 +225,136 @@ impl FromStr for PayoutDealType {
     }
 }
 
+
+
+/// Walks up the referrer_id chain starting from a seller affiliate's PayoutSplit,
+/// returning their referrers' SELLER_AFFILIATE PayoutSplits, nearest first.
+/// Stops after max_levels, at a missing referrer, or when a PayoutSplit
+/// is seen twice (a referral cycle). Stops with the error if a read fails.
+pub fn walk_referrer_chain<F, E>(
+    seller_aff_payout_split: &PayoutSplit,
+    max_levels: usize,
+    read_payout_split: F,
+) -> Result<Vec<PayoutSplit>, E>
+    where F: Fn(&str) -> Result<Option<PayoutSplit>, E>
+{
+    let mut visited: Vec<String> = vec![seller_aff_payout_split.id.clone()];
+    let mut upline: Vec<PayoutSplit> = vec![];
+    let mut next_id = seller_aff_payout_split.referrer_id.clone();
+
+    while let Some(referrer_id) = next_id {
+        if upline.len() >= max_levels {
+            break
+        }
+        if visited.contains(&referrer_id) {
+            warn!("Referral cycle detected at PayoutSplit: {:?}", referrer_id);
+            break
+        }
+        let psplit = match read_payout_split(&referrer_id)? {
+            Some(ps) if ps.deal_type == PayoutDealType::SELLER_AFFILIATE => ps,
+            _ => break,
+        };
+        visited.push(psplit.id.clone());
+        next_id = psplit.referrer_id.clone();
+        upline.push(psplit);
+    }
+
+    Ok(upline)
+}
+
+
+
+#[test]
+fn detects_overlapping_payout_split_windows() {
+
+    let date = |m: u32| chrono::NaiveDateTime::new(
+        chrono::NaiveDate::from_ymd(2020, m, 1),
+        chrono::NaiveTime::from_hms(0, 0, 0),
+    );
+
+    // 0% platform fee from 1 July for three months
+    let promo = PayoutSplit::new(
+        String::from("store_123"),
+        PayoutDealType::SELLER,
+        Some(date(7)),
+        Some(date(10)),
+        1.0,
+        None,
+    );
+    let overlapping = PayoutSplit::new(
+        String::from("store_123"),
+        PayoutDealType::SELLER,
+        Some(date(9)),
+        None,
+        0.9,
+        None,
+    );
+    let after = PayoutSplit::new(
+        String::from("store_123"),
+        PayoutDealType::SELLER,
+        Some(date(10)),
+        Some(date(12)),
+        0.9,
+        None,
+    );
+
+    assert_eq!(promo.overlaps(&overlapping), true);
+    assert_eq!(overlapping.overlaps(&promo), true);
+    assert_eq!(promo.overlaps(&after), false);
+    assert_eq!(after.overlaps(&promo), false);
+
+    assert_eq!(promo.is_active_at(&date(6)), false);
+    assert_eq!(promo.is_active_at(&date(7)), true);
+    assert_eq!(promo.is_active_at(&date(10)), false);
+
+    let mut retired = promo.clone();
+    retired.effective_to = Some(date(8));
+    assert_eq!(retired.is_active_at(&date(7)), true);
+    assert_eq!(retired.is_active_at(&date(8)), false);
+}
+
+#[test]
+fn walks_referrer_chain_and_stops_at_cycles() {
+
+    let mut psplit_a = PayoutSplit::new(
+        String::from("user_a"),
+        PayoutDealType::SELLER_AFFILIATE,
+        None,
+        None,
+        0.05,
+        None,
+    );
+    let psplit_b = PayoutSplit::new(
+        String::from("user_b"),
+        PayoutDealType::SELLER_AFFILIATE,
+        None,
+        None,
+        0.05,
+        Some(psplit_a.id.clone()),
+    );
+    let psplit_c = PayoutSplit::new(
+        String::from("user_c"),
+        PayoutDealType::SELLER_AFFILIATE,
+        None,
+        None,
+        0.05,
+        Some(psplit_b.id.clone()),
+    );
+    // a -> c -> b -> a
+    psplit_a.referrer_id = Some(psplit_c.id.clone());
+
+    let psplits = vec![psplit_a.clone(), psplit_b.clone(), psplit_c.clone()];
+    let read = |id: &str| Ok::<_, ()>(psplits.iter().find(|ps| ps.id == id).cloned());
+
+    let upline = walk_referrer_chain(&psplit_c, 5, read).unwrap();
+    assert_eq!(
+        upline.iter().map(|ps| ps.store_or_user_id.clone()).collect::<Vec<String>>(),
+        vec![String::from("user_b"), String::from("user_a")]
+    );
+
+    let upline = walk_referrer_chain(&psplit_c, 1, read).unwrap();
+    assert_eq!(upline.len(), 1);
+
+    let failing_read = |_id: &str| Err::<Option<PayoutSplit>, _>("read failed");
+    assert_eq!(walk_referrer_chain(&psplit_c, 5, failing_read).err(), Some("read failed"));
+}
@@ -0,0 +1,454 @@
+use gm::utils::dates::pick_datetime_format;
+
+use crate::models::{
+    PayoutSplit,
+    PayoutDealType,
+};
+
+
+/// Columns of a PayoutSplit CSV, in the order they are exported.
+/// On import, the header row decides the column order, and unknown
+/// columns are ignored.
+pub const PAYOUT_SPLIT_CSV_COLUMNS: [&str; 7] = [
+    "store_or_user_id",
+    "deal_type",
+    "rate",
+    "starts_at",
+    "expires_at",
+    "referrer",
+    "store_id",
+];
+
+
+#[serde(rename_all = "camelCase")]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PayoutSplitCsvRow {
+    /// Line number in the CSV, header is line 1
+    pub line: usize,
+    pub payout_split: PayoutSplit,
+}
+
+#[serde(rename_all = "camelCase")]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CsvRowError {
+    pub line: usize,
+    pub store_or_user_id: Option<String>,
+    pub message: String,
+}
+
+#[serde(rename_all = "camelCase")]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PayoutSplitImportReport {
+    /// Empty if there are any errors, nothing is written in that case
+    pub imported: Vec<PayoutSplit>,
+    pub errors: Vec<CsvRowError>,
+}
+
+
+/// Parses CSV rows into new PayoutSplits.
+/// Rows which fail to parse are returned as errors, the rest as PayoutSplits.
+pub fn parse_payout_split_csv(
+    csv: &str,
+    changed_by: Option<String>,
+    reason: Option<String>,
+) -> (Vec<PayoutSplitCsvRow>, Vec<CsvRowError>) {
+
+    let mut rows: Vec<PayoutSplitCsvRow> = vec![];
+    let mut errors: Vec<CsvRowError> = vec![];
+
+    let mut lines = csv.lines()
+        .enumerate()
+        .map(|(i, l)| (i + 1, l))
+        .filter(|(_, l)| !l.trim().is_empty());
+
+    let header: Vec<String> = match lines.next() {
+        Some((_, l)) => split_csv_line(l).iter()
+            .map(|h| h.trim().to_lowercase())
+            .collect(),
+        None => {
+            errors.push(CsvRowError {
+                line: 1,
+                store_or_user_id: None,
+                message: String::from("CSV is empty"),
+            });
+            return (rows, errors)
+        }
+    };
+
+    let column = |name: &str| header.iter().position(|h| h == name);
+    let missing_columns = ["store_or_user_id", "deal_type", "rate"]
+        .iter()
+        .filter(|c| column(c).is_none())
+        .map(|c| c.to_string())
+        .collect::<Vec<String>>();
+
+    if missing_columns.len() > 0 {
+        errors.push(CsvRowError {
+            line: 1,
+            store_or_user_id: None,
+            message: format!("missing columns: {}", missing_columns.join(", ")),
+        });
+        return (rows, errors)
+    }
+
+    for (line, l) in lines {
+
+        let fields = split_csv_line(l);
+        let field = |name: &str| -> Option<String> {
+            column(name)
+                .and_then(|i| fields.get(i))
+                .map(|f| f.trim().to_string())
+                .filter(|f| !f.is_empty())
+        };
+
+        let store_or_user_id = field("store_or_user_id");
+        let row_error = |message: String| CsvRowError {
+            line: line,
+            store_or_user_id: store_or_user_id.clone(),
+            message: message,
+        };
+
+        let parsed = parse_payout_split_fields(
+            store_or_user_id.clone(),
+            field("deal_type"),
+            field("rate"),
+            field("starts_at"),
+            field("expires_at"),
+            field("referrer"),
+            field("store_id"),
+        );
+
+        match parsed {
+            Err(message) => errors.push(row_error(message)),
+            Ok(payout_split) => rows.push(PayoutSplitCsvRow {
+                line: line,
+                payout_split: payout_split.update_changed_by(
+                    changed_by.clone(),
+                    reason.clone(),
+                ),
+            }),
+        }
+    }
+
+    (rows, errors)
+}
+
+fn parse_payout_split_fields(
+    store_or_user_id: Option<String>,
+    deal_type: Option<String>,
+    rate: Option<String>,
+    starts_at: Option<String>,
+    expires_at: Option<String>,
+    referrer_id: Option<String>,
+    store_id: Option<String>,
+) -> Result<PayoutSplit, String> {
+
+    let store_or_user_id = store_or_user_id
+        .ok_or(String::from("store_or_user_id is required"))?;
+
+    let deal_type = match deal_type.as_ref().map(|d| d.to_uppercase()) {
+        Some(ref d) if d == "SELLER" => PayoutDealType::SELLER,
+        Some(ref d) if d == "SELLER_AFFILIATE" => PayoutDealType::SELLER_AFFILIATE,
+        Some(ref d) if d == "REFERRED_SELLER" => PayoutDealType::REFERRED_SELLER,
+        Some(ref d) if d == "BUYER_AFFILIATE" => PayoutDealType::BUYER_AFFILIATE,
+        Some(d) => return Err(format!("unknown deal_type: {}", d)),
+        None => return Err(String::from("deal_type is required")),
+    };
+
+    let rate = rate
+        .ok_or(String::from("rate is required"))?
+        .parse::<f64>()
+        .map_err(|e| format!("rate is not a number: {}", e))?;
+
+    if rate < 0.0 || rate > 1.0 {
+        return Err(format!("rate must be between 0 and 1, got {}", rate))
+    }
+
+    let starts_at = parse_csv_date(starts_at)
+        .map_err(|e| format!("starts_at: {}", e))?;
+    let expires_at = parse_csv_date(expires_at)
+        .map_err(|e| format!("expires_at: {}", e))?;
+
+    if let (Some(start), Some(exp)) = (starts_at, expires_at) {
+        if exp <= start {
+            return Err(String::from("expires_at must be after starts_at"))
+        }
+    }
+
+    if deal_type == PayoutDealType::REFERRED_SELLER && referrer_id.is_none() {
+        return Err(String::from("REFERRED_SELLER requires a referrer"))
+    }
+
+    if store_id.is_some() && deal_type != PayoutDealType::BUYER_AFFILIATE {
+        return Err(format!("{:?} cannot be scoped to a store", deal_type))
+    }
+
+    Ok(PayoutSplit::new(
+        store_or_user_id,
+        deal_type,
+        starts_at,
+        expires_at,
+        rate,
+        referrer_id,
+    ).update_store_id(store_id))
+}
+
+/// Accepts dates (2020-07-01) or datetimes (2020-07-01T00:00:00Z)
+fn parse_csv_date(
+    date: Option<String>
+) -> Result<Option<chrono::NaiveDateTime>, String> {
+    match date {
+        None => Ok(None),
+        Some(d) => {
+            if d.len() == 10 {
+                chrono::NaiveDate::parse_from_str(&d, "%Y-%m-%d")
+                    .map(|nd| Some(nd.and_hms(0, 0, 0)))
+                    .map_err(|e| format!("invalid date {}: {}", d, e))
+            } else {
+                chrono::NaiveDateTime::parse_from_str(&d, pick_datetime_format(&d))
+                    .map(Some)
+                    .map_err(|e| format!("invalid date {}: {}", d, e))
+            }
+        }
+    }
+}
+
+
+/// Checks parsed rows against each other, and against existing PayoutSplits:
+/// - a store_or_user_id may only have one regular PayoutSplit per deal
+///   (store-scoped deals count separately for each store),
+/// - scheduled PayoutSplits for the same deal may not overlap,
+/// - referrers must be existing SELLER_AFFILIATE PayoutSplits.
+pub fn check_payout_split_rows(
+    rows: &Vec<PayoutSplitCsvRow>,
+    referrer_payout_splits: &Vec<PayoutSplit>,
+    open_payout_splits: &Vec<PayoutSplit>,
+) -> Vec<CsvRowError> {
+
+    let mut errors: Vec<CsvRowError> = vec![];
+
+    for (i, row) in rows.iter().enumerate() {
+
+        let ps = &row.payout_split;
+        let row_error = |message: String| CsvRowError {
+            line: row.line,
+            store_or_user_id: Some(ps.store_or_user_id.clone()),
+            message: message,
+        };
+
+        let same_deal = |other: &PayoutSplit| {
+            other.store_or_user_id == ps.store_or_user_id &&
+            other.store_id == ps.store_id &&
+            ps.deal_type.versioned_with().contains(&other.deal_type)
+        };
+
+        // earlier rows for the same deal
+        let clashing_row = rows[..i].iter()
+            .find(|prev| {
+                let other = &prev.payout_split;
+                same_deal(other) && match (ps.starts_at, other.starts_at) {
+                    (None, None) => true,
+                    (Some(_), Some(_)) => ps.overlaps(other),
+                    _ => false,
+                }
+            });
+
+        if let Some(prev) = clashing_row {
+            errors.push(row_error(format!(
+                "conflicts with line {} for the same {:?} deal",
+                prev.line,
+                ps.deal_type,
+            )));
+            continue
+        }
+
+        if ps.starts_at.is_some() {
+            let overlapping = open_payout_splits.iter()
+                .find(|other| {
+                    same_deal(other) &&
+                    other.starts_at.is_some() &&
+                    ps.overlaps(other)
+                });
+            if let Some(other) = overlapping {
+                errors.push(row_error(format!(
+                    "overlaps existing PayoutSplit {} from {:?} to {:?}",
+                    other.id,
+                    other.starts_at,
+                    other.expires_at,
+                )));
+                continue
+            }
+        }
+
+        if let Some(referrer_id) = &ps.referrer_id {
+            let referrer = referrer_payout_splits.iter()
+                .find(|r| &r.id == referrer_id);
+            match referrer {
+                None => errors.push(row_error(format!(
+                    "referrer PayoutSplit {} does not exist",
+                    referrer_id
+                ))),
+                Some(r) if r.deal_type != PayoutDealType::SELLER_AFFILIATE => {
+                    errors.push(row_error(format!(
+                        "referrer PayoutSplit {} is not a SELLER_AFFILIATE deal",
+                        referrer_id
+                    )))
+                },
+                Some(r) if r.store_or_user_id == ps.store_or_user_id => {
+                    errors.push(row_error(String::from(
+                        "a PayoutSplit cannot refer itself"
+                    )))
+                },
+                Some(_) => {},
+            }
+        }
+    }
+
+    errors.sort_by_key(|e| e.line);
+    errors
+}
+
+
+/// Writes PayoutSplits as CSV, in a format parse_payout_split_csv reads back
+pub fn payout_splits_to_csv(payout_splits: &Vec<PayoutSplit>) -> String {
+
+    let fmt_date = |d: Option<chrono::NaiveDateTime>| {
+        d.map(|d| d.format("%Y-%m-%dT%H:%M:%SZ").to_string())
+            .unwrap_or(String::from(""))
+    };
+
+    let mut csv = PAYOUT_SPLIT_CSV_COLUMNS.join(",");
+    csv.push_str("\n");
+
+    for ps in payout_splits.iter() {
+        let fields = vec![
+            escape_csv_field(&ps.store_or_user_id),
+            ps.deal_type.as_string(),
+            ps.rate.to_string(),
+            fmt_date(ps.starts_at),
+            fmt_date(ps.expires_at),
+            escape_csv_field(&ps.referrer_id.clone().unwrap_or(String::from(""))),
+            escape_csv_field(&ps.store_id.clone().unwrap_or(String::from(""))),
+        ];
+        csv.push_str(&fields.join(","));
+        csv.push_str("\n");
+    }
+
+    csv
+}
+
+/// Splits a CSV line on commas, except inside double quotes.
+/// A doubled quote ("") inside a quoted field is a literal quote.
+fn split_csv_line(line: &str) -> Vec<String> {
+
+    let mut fields: Vec<String> = vec![];
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut chars = line.trim_end_matches('\r').chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match (c, in_quotes) {
+            ('"', true) => {
+                if chars.peek() == Some(&'"') {
+                    field.push('"');
+                    chars.next();
+                } else {
+                    in_quotes = false;
+                }
+            },
+            ('"', false) => in_quotes = true,
+            (',', false) => {
+                fields.push(field);
+                field = String::new();
+            },
+            (c, _) => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+fn escape_csv_field(field: &str) -> String {
+    if field.contains(',') || field.contains('"') || field.contains('\n') {
+        format!("\"{}\"", field.replace("\"", "\"\""))
+    } else {
+        String::from(field)
+    }
+}
+
+
+
+#[test]
+fn parses_payout_split_csv_with_row_errors() {
+
+    let csv = "store_or_user_id,deal_type,rate,expires_at,referrer\n\
+        store_123,SELLER,0.9,2030-01-01,\n\
+        store_456,seller,1.5,,\n\
+        store_789,REFERRED_SELLER,0.88,,\n\
+        \"user_1,2\",BUYER_AFFILIATE,0.05,2030-01-01T00:00:00Z,\n\
+        store_000,VIP,0.9,,\n";
+
+    let (rows, errors) = parse_payout_split_csv(csv, None, None);
+
+    assert_eq!(rows.len(), 2);
+    assert_eq!(rows[0].line, 2);
+    assert_eq!(rows[0].payout_split.deal_type, PayoutDealType::SELLER);
+    assert_eq!(rows[1].payout_split.store_or_user_id, String::from("user_1,2"));
+    assert_eq!(
+        errors.iter().map(|e| e.line).collect::<Vec<usize>>(),
+        vec![3, 4, 6]
+    );
+
+    // exported CSV reads back the same
+    let psplits = rows.iter()
+        .map(|r| r.payout_split.clone())
+        .collect::<Vec<PayoutSplit>>();
+    let (rows2, errors2) = parse_payout_split_csv(
+        &payout_splits_to_csv(&psplits), None, None
+    );
+    assert_eq!(errors2.len(), 0);
+    assert_eq!(rows2[1].payout_split.store_or_user_id, String::from("user_1,2"));
+    assert_eq!(rows2[1].payout_split.expires_at, psplits[1].expires_at);
+}
+
+#[test]
+fn checks_payout_split_rows_against_each_other_and_existing() {
+
+    let csv = "store_or_user_id,deal_type,rate,starts_at,expires_at,referrer\n\
+        store_123,SELLER,0.9,,,\n\
+        store_123,REFERRED_SELLER,0.88,,,psplit_aff\n\
+        store_456,SELLER,1.0,2030-07-01,2030-10-01,\n\
+        store_789,REFERRED_SELLER,0.88,,,psplit_missing\n";
+
+    let (rows, errors) = parse_payout_split_csv(csv, None, None);
+    assert_eq!(errors.len(), 0);
+
+    let mut referrer = PayoutSplit::new(
+        String::from("user_aff"),
+        PayoutDealType::SELLER_AFFILIATE,
+        None,
+        None,
+        0.02,
+        None,
+    );
+    referrer.id = String::from("psplit_aff");
+
+    let scheduled = PayoutSplit::new(
+        String::from("store_456"),
+        PayoutDealType::SELLER,
+        Some(chrono::NaiveDate::from_ymd(2030, 9, 1).and_hms(0, 0, 0)),
+        None,
+        0.95,
+        None,
+    );
+
+    let errors = check_payout_split_rows(
+        &rows,
+        &vec![referrer],
+        &vec![scheduled],
+    );
+    assert_eq!(
+        errors.iter().map(|e| e.line).collect::<Vec<usize>>(),
+        vec![3, 4, 5]
+    );
+}
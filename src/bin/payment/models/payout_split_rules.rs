@@ -0,0 +1,311 @@
+use crate::models::{
+    PayoutSplit,
+    PayoutDealType,
+};
+use crate::pricing::{
+    PAYMENT_FEE_PERCENTAGE,
+    SELLER_FEE_PERCENTAGE,
+    MAX_BUYER_AFFILIATE_FEE_PERCENTAGE,
+    MAX_REFERRAL_FEE_PERCENTAGE,
+    UPLINE_REFERRAL_FEE_PERCENTAGES,
+};
+
+
+/// Allowed range of PayoutSplit.rate for a deal type, inclusive
+#[serde(rename_all = "camelCase")]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RateBounds {
+    pub min: f64,
+    pub max: f64,
+}
+
+impl RateBounds {
+    /// Reads bounds from env vars <PREFIX>_RATE_MIN and <PREFIX>_RATE_MAX,
+    /// falling back to the defaults given
+    fn from_env(prefix: &str, default_min: f64, default_max: f64) -> Self {
+        let read_env = |suffix: &str, default: f64| {
+            std::env::var(format!("{}_{}", prefix, suffix))
+                .ok()
+                .and_then(|v| v.parse::<f64>().ok())
+                .unwrap_or(default)
+        };
+        Self {
+            min: read_env("RATE_MIN", default_min),
+            max: read_env("RATE_MAX", default_max),
+        }
+    }
+}
+
+
+#[serde(rename_all = "camelCase")]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PayoutSplitRateRules {
+    pub seller: RateBounds,
+    pub referred_seller: RateBounds,
+    pub seller_affiliate: RateBounds,
+    pub buyer_affiliate: RateBounds,
+}
+
+impl PayoutSplitRateRules {
+    /// Fallback bounds, used if not defined in docker-compose
+    pub fn from_env() -> Self {
+        // the seller affiliate and their upline share the referral cap
+        let max_seller_aff_rate = MAX_REFERRAL_FEE_PERCENTAGE
+            - UPLINE_REFERRAL_FEE_PERCENTAGES.iter().sum::<f64>();
+        Self {
+            seller: RateBounds::from_env("SELLER", 0.5, 1.0),
+            referred_seller: RateBounds::from_env("REFERRED_SELLER", 0.5, 1.0),
+            seller_affiliate: RateBounds::from_env(
+                "SELLER_AFFILIATE", 0.0, max_seller_aff_rate
+            ),
+            buyer_affiliate: RateBounds::from_env(
+                "BUYER_AFFILIATE", 0.0, MAX_BUYER_AFFILIATE_FEE_PERCENTAGE
+            ),
+        }
+    }
+
+    pub fn bounds(&self, deal_type: &PayoutDealType) -> &RateBounds {
+        match deal_type {
+            PayoutDealType::SELLER => &self.seller,
+            PayoutDealType::REFERRED_SELLER => &self.referred_seller,
+            PayoutDealType::SELLER_AFFILIATE => &self.seller_affiliate,
+            PayoutDealType::BUYER_AFFILIATE => &self.buyer_affiliate,
+        }
+    }
+}
+
+lazy_static! {
+    pub static ref PAYOUT_SPLIT_RATE_RULES: PayoutSplitRateRules = PayoutSplitRateRules::from_env();
+}
+
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum RateRule {
+    /// Below the configured minimum for the deal type
+    MIN_RATE,
+    /// Above the configured maximum for the deal type
+    MAX_RATE,
+    /// Seller would not have enough left to pay the payment processing fee
+    /// after the largest possible buyer affiliate cut
+    PAYMENT_FEE_COVERAGE,
+    /// Buyer affiliate rate above MAX_BUYER_AFFILIATE_FEE_PERCENTAGE
+    MAX_BUYER_AFFILIATE_RATE,
+    /// Seller affiliate and upline rates above MAX_REFERRAL_FEE_PERCENTAGE
+    MAX_REFERRAL_RATE,
+    /// NaN or infinite
+    NOT_A_RATE,
+}
+
+#[serde(rename_all = "camelCase")]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RateViolation {
+    pub store_or_user_id: String,
+    pub deal_type: PayoutDealType,
+    pub rate: f64,
+    pub rule: RateRule,
+    /// The bound which was crossed
+    pub limit: f64,
+    pub message: String,
+}
+
+
+/// Checks a PayoutSplit's rate against the bounds for its deal type,
+/// and against the platform-wide limits in pricing.
+pub fn validate_payout_split_rate(
+    payout_split: &PayoutSplit,
+    rules: &PayoutSplitRateRules,
+) -> Vec<RateViolation> {
+
+    let rate = payout_split.rate;
+    let deal_type = &payout_split.deal_type;
+    let violation = |rule: RateRule, limit: f64, message: String| RateViolation {
+        store_or_user_id: payout_split.store_or_user_id.clone(),
+        deal_type: deal_type.clone(),
+        rate: rate,
+        rule: rule,
+        limit: limit,
+        message: message,
+    };
+
+    if !rate.is_finite() {
+        return vec![
+            violation(RateRule::NOT_A_RATE, 0.0, format!("rate {} is not a number", rate))
+        ]
+    }
+
+    let mut violations: Vec<RateViolation> = vec![];
+    let bounds = rules.bounds(deal_type);
+
+    if rate < bounds.min {
+        violations.push(violation(
+            RateRule::MIN_RATE,
+            bounds.min,
+            format!("{:?} rate {} is below the minimum {}", deal_type, rate, bounds.min),
+        ));
+    }
+    if rate > bounds.max {
+        violations.push(violation(
+            RateRule::MAX_RATE,
+            bounds.max,
+            format!("{:?} rate {} is above the maximum {}", deal_type, rate, bounds.max),
+        ));
+    }
+
+    match deal_type {
+        PayoutDealType::SELLER | PayoutDealType::REFERRED_SELLER => {
+            // The seller pays the payment processing fee out of what remains
+            // after the buyer affiliate's cut, see generate_earnings_from_payout_splits
+            let min_seller_rate = PAYMENT_FEE_PERCENTAGE
+                / (1.0 - MAX_BUYER_AFFILIATE_FEE_PERCENTAGE / SELLER_FEE_PERCENTAGE);
+            if rate < min_seller_rate {
+                violations.push(violation(
+                    RateRule::PAYMENT_FEE_COVERAGE,
+                    min_seller_rate,
+                    format!(
+                        "{:?} rate {} cannot cover the {} payment processing fee after a {} buyer affiliate cut",
+                        deal_type, rate, PAYMENT_FEE_PERCENTAGE, MAX_BUYER_AFFILIATE_FEE_PERCENTAGE
+                    ),
+                ));
+            }
+        },
+        PayoutDealType::BUYER_AFFILIATE => {
+            if rate > MAX_BUYER_AFFILIATE_FEE_PERCENTAGE {
+                violations.push(violation(
+                    RateRule::MAX_BUYER_AFFILIATE_RATE,
+                    MAX_BUYER_AFFILIATE_FEE_PERCENTAGE,
+                    format!(
+                        "BUYER_AFFILIATE rate {} is above MAX_BUYER_AFFILIATE_FEE_PERCENTAGE {}",
+                        rate, MAX_BUYER_AFFILIATE_FEE_PERCENTAGE
+                    ),
+                ));
+            }
+        },
+        PayoutDealType::SELLER_AFFILIATE => {
+            let upline_rate = UPLINE_REFERRAL_FEE_PERCENTAGES.iter().sum::<f64>();
+            if rate + upline_rate > MAX_REFERRAL_FEE_PERCENTAGE {
+                violations.push(violation(
+                    RateRule::MAX_REFERRAL_RATE,
+                    MAX_REFERRAL_FEE_PERCENTAGE - upline_rate,
+                    format!(
+                        "SELLER_AFFILIATE rate {} plus upline rates {} is above MAX_REFERRAL_FEE_PERCENTAGE {}",
+                        rate, upline_rate, MAX_REFERRAL_FEE_PERCENTAGE
+                    ),
+                ));
+            }
+        },
+    }
+
+    violations
+}
+
+
+/// Validates a PayoutSplit's rate before it is written.
+///
+/// Admins may override the limits, but only with a changed_by and a reason.
+/// Callers must check the admin's role; changed_by is always set from their auth info.
+/// Overridden PayoutSplits are flagged with rate_override, so the versions in
+/// the PayoutSplit history show who broke the limits and why.
+pub fn check_payout_split_rate(
+    payout_split: PayoutSplit,
+    override_rate_limits: bool,
+) -> Result<PayoutSplit, Vec<RateViolation>> {
+
+    let violations = validate_payout_split_rate(&payout_split, &PAYOUT_SPLIT_RATE_RULES);
+
+    if violations.len() == 0 {
+        return Ok(payout_split)
+    }
+
+    let has_audit_info = payout_split.changed_by.is_some() && payout_split.reason.is_some();
+    // NaN rates can not be overridden
+    let can_override = violations.iter().all(|v| v.rule != RateRule::NOT_A_RATE);
+
+    if override_rate_limits && has_audit_info && can_override {
+        warn!(
+            "PayoutSplit rate limits overridden by {:?} ({:?}): {:?}",
+            payout_split.changed_by,
+            payout_split.reason,
+            violations,
+        );
+        Ok(payout_split.update_rate_override(true))
+    } else {
+        Err(violations)
+    }
+}
+
+
+
+#[test]
+fn validates_payout_split_rates_per_deal_type() {
+
+    let rules = PayoutSplitRateRules {
+        seller: RateBounds { min: 0.5, max: 1.0 },
+        referred_seller: RateBounds { min: 0.5, max: 1.0 },
+        seller_affiliate: RateBounds { min: 0.0, max: 0.135 },
+        buyer_affiliate: RateBounds { min: 0.0, max: 0.5 },
+    };
+    let psplit = |deal_type: PayoutDealType, rate: f64| PayoutSplit::new(
+        String::from("store_123"),
+        deal_type,
+        None,
+        None,
+        rate,
+        None,
+    );
+
+    let rules_broken = |ps: PayoutSplit| validate_payout_split_rate(&ps, &rules)
+        .into_iter()
+        .map(|v| v.rule)
+        .collect::<Vec<RateRule>>();
+
+    assert_eq!(rules_broken(psplit(PayoutDealType::SELLER, 0.85)), vec![]);
+    assert_eq!(
+        rules_broken(psplit(PayoutDealType::SELLER, 1.5)),
+        vec![RateRule::MAX_RATE]
+    );
+    assert_eq!(
+        rules_broken(psplit(PayoutDealType::REFERRED_SELLER, 0.05)),
+        vec![RateRule::MIN_RATE, RateRule::PAYMENT_FEE_COVERAGE]
+    );
+    assert_eq!(
+        rules_broken(psplit(PayoutDealType::BUYER_AFFILIATE, -0.1)),
+        vec![RateRule::MIN_RATE]
+    );
+    assert_eq!(
+        rules_broken(psplit(PayoutDealType::BUYER_AFFILIATE, 0.6)),
+        vec![RateRule::MAX_RATE, RateRule::MAX_BUYER_AFFILIATE_RATE]
+    );
+    assert_eq!(
+        rules_broken(psplit(PayoutDealType::SELLER_AFFILIATE, 0.14)),
+        vec![RateRule::MAX_RATE, RateRule::MAX_REFERRAL_RATE]
+    );
+    assert_eq!(
+        rules_broken(psplit(PayoutDealType::SELLER, std::f64::NAN)),
+        vec![RateRule::NOT_A_RATE]
+    );
+}
+
+#[test]
+fn overrides_rate_limits_only_with_audit_info() {
+
+    let psplit = PayoutSplit::new(
+        String::from("store_123"),
+        PayoutDealType::SELLER,
+        None,
+        None,
+        0.3,
+        None,
+    );
+
+    assert_eq!(check_payout_split_rate(psplit.clone(), false).is_err(), true);
+    assert_eq!(check_payout_split_rate(psplit.clone(), true).is_err(), true);
+
+    let overridden = check_payout_split_rate(
+        psplit.update_changed_by(
+            Some(String::from("admin_123")),
+            Some(String::from("clearance sale, agreed with finance")),
+        ),
+        true,
+    ).expect("override with changed_by and reason");
+    assert_eq!(overridden.rate_override, true);
+}
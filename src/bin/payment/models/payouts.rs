@@ -258,6 +258,48 @@ pub fn aggregate_payout_totals_by_payee_id(
 
 
 
+/// Clawbacks bigger than a payee's earnings can't be paid out.
+/// Splits off those payees, whose items are left as they are and
+/// carried forward to the next period.
+pub fn partition_carried_forward_payouts(
+    payout_hashmap: HashMap<PayeeId, Payout>,
+) -> (HashMap<PayeeId, Payout>, Vec<PayeeId>) {
+
+    let (carried_forward, payable): (HashMap<PayeeId, Payout>, HashMap<PayeeId, Payout>) =
+        payout_hashmap
+            .into_iter()
+            .partition(|(_, payout)| {
+                payout.amount < 0 && payout.payout_item_ids
+                    .iter()
+                    .any(|id| id.starts_with("citem_"))
+            });
+
+    let carried_forward_payee_ids = carried_forward
+        .into_iter()
+        .map(|(payee_id, payout)| {
+            info!("carrying forward clawback of {} for payee: {}", payout.amount, payee_id);
+            payee_id
+        })
+        .collect::<Vec<PayeeId>>();
+
+    (payable, carried_forward_payee_ids)
+}
+
+/// REFUNDING items to settle as PENDING_REFUND. Those of payees carried
+/// forward stay REFUNDING, to be deducted along with their clawbacks.
+pub fn refund_item_ids_to_settle(
+    payout_items_refunding: &Vec<PayoutItem>,
+    carried_forward_payee_ids: &Vec<PayeeId>,
+) -> Vec<String> {
+    payout_items_refunding
+        .iter()
+        .filter(|pitem| !carried_forward_payee_ids.contains(&pitem.payee_id))
+        .map(|pitem| pitem.id.clone())
+        .collect::<Vec<String>>()
+}
+
+
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[derive(AsExpression, FromSqlRow)]
 #[sql_type = "Text"]
@@ -269,6 +311,8 @@ pub enum PayoutStatus {
     PROCESSING,
     PAID,
     RETAINED,
+    /// Held out of payouts while the payment is disputed
+    DISPUTED,
     // refund states
     REFUNDING,
     PENDING_REFUND,
@@ -308,6 +352,7 @@ impl FromStr for PayoutStatus {
             "PROCESSING" => PayoutStatus::PROCESSING,
             "RETAINED" => PayoutStatus::RETAINED,
             "PAID" => PayoutStatus::PAID,
+            "DISPUTED" => PayoutStatus::DISPUTED,
             "REFUNDING" => PayoutStatus::REFUNDING,
             "PENDING_REFUND" => PayoutStatus::PENDING_REFUND,
             "REFUNDED" => PayoutStatus::REFUNDED,
@@ -405,3 +450,58 @@ impl std::default::Default for PayoutAggregates {
 
 
 
+#[test]
+fn carries_forward_refunds_of_payees_owing_clawbacks() {
+
+    let created_at = chrono::NaiveDateTime::from_timestamp(1_500_000_000, 0);
+    let payout_period = PayoutPeriod::new(2020, 6).expect("payout period");
+    let pitem = |payee_id: &str, amount: i32, status: PayoutStatus| PayoutItem::new(
+        String::from("oitem_123"),
+        String::from(payee_id),
+        Some(PayeeType::STORE),
+        amount,
+        0,
+        created_at,
+        String::from("USD"),
+        String::from("txn_123"),
+    ).set_payout_status(status);
+
+    let clawback = {
+        let mut c = pitem("store_123", -5000, PayoutStatus::UNPAID);
+        c.id = String::from("citem_123");
+        c
+    };
+    let payout_items = vec![
+        // store_123 owes more than it earned this period
+        pitem("store_123", 1000, PayoutStatus::UNPAID),
+        clawback,
+        pitem("store_123", -200, PayoutStatus::REFUNDING),
+        // store_456 is paid out as usual
+        pitem("store_456", 3000, PayoutStatus::UNPAID),
+        pitem("store_456", -300, PayoutStatus::REFUNDING),
+    ];
+    let payout_items_refunding = payout_items.iter()
+        .filter(|p| p.payout_status == PayoutStatus::REFUNDING)
+        .cloned()
+        .collect::<Vec<PayoutItem>>();
+
+    let (payable, carried_forward) = partition_carried_forward_payouts(
+        aggregate_payout_totals_by_payee_id(
+            payout_period,
+            payout_items,
+            HashMap::new(),
+            HashMap::new(),
+            String::from("user_admin"),
+        )
+    );
+
+    assert_eq!(payable.keys().collect::<Vec<&PayeeId>>(), vec!["store_456"]);
+    assert_eq!(payable["store_456"].amount, 2700);
+    assert_eq!(carried_forward, vec![String::from("store_123")]);
+
+    // store_123's refund stays REFUNDING, to be carried forward
+    assert_eq!(
+        refund_item_ids_to_settle(&payout_items_refunding, &carried_forward),
+        vec![payout_items_refunding[1].id.clone()]
+    );
+}
@@ -2,6 +2,18 @@
 use diesel::prelude::*;
 use gm::db::schema::refunds;
 
+use diesel::deserialize::FromSql;
+use diesel::pg::Pg;
+use diesel::serialize::{Output, ToSql};
+use diesel::sql_types::Text;
+use std::str::FromStr;
+
+use crate::models::{
+    PayoutItem,
+    PayoutStatus,
+    Transaction,
+};
+
 
 #[serde(rename_all = "camelCase")]
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -15,6 +27,14 @@ pub struct Refund {
     pub created_at: chrono::NaiveDateTime,
     pub reason: Option<String>,
     pub reason_details: Option<String>,
+    /// Refunds can be pending for days, and fail or be canceled after
+    /// they were recorded. Failed refunds are reversed.
+    pub status: RefundStatus,
+    pub failure_reason: Option<String>,
+    pub updated_at: Option<chrono::NaiveDateTime>,
+    /// When gm-shopping's order was updated for the refund.
+    /// Refunds without one have their order update retried.
+    pub order_updated_at: Option<chrono::NaiveDateTime>,
 }
 
 impl Refund {
@@ -26,6 +46,7 @@ impl Refund {
         created_at: chrono::NaiveDateTime,
         reason: Option<String>,
         reason_details: Option<String>,
+        status: RefundStatus,
     ) -> Self {
         Self {
             id: id,
@@ -35,6 +56,10 @@ impl Refund {
             created_at: created_at,
             reason: reason,
             reason_details: reason_details,
+            status: status,
+            failure_reason: None,
+            updated_at: None,
+            order_updated_at: None,
         }
     }
 
@@ -57,31 +82,299 @@ impl Refund {
         self.reason_details = Some(reason_details);
         self
     }
+
+    /// Takes a refund's latest status from Stripe or Paypal
+    pub fn update_status(
+        self,
+        status: RefundStatus,
+        failure_reason: Option<String>,
+        updated_at: chrono::NaiveDateTime,
+    ) -> Self {
+        Self {
+            status: status,
+            failure_reason: failure_reason.or(self.failure_reason),
+            updated_at: Some(updated_at),
+            ..self
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(AsExpression, FromSqlRow)]
+#[sql_type = "Text"]
+pub enum RefundStatus {
+    /// Recorded, but the money hasn't reached the customer yet
+    PENDING,
+    SUCCEEDED,
+    /// The refund's payout items and transaction have been reversed
+    FAILED,
+    /// Canceled before it was processed, reversed like failed refunds
+    CANCELED,
+}
+impl RefundStatus {
+    pub fn as_string(&self) -> String {
+        String::from(format!("{:?}", &self))
+    }
+
+    /// Stripe refunds are `pending`, `succeeded`, `failed` or `canceled`
+    pub fn from_stripe(status: Option<&str>) -> Self {
+        match status {
+            Some("pending") => RefundStatus::PENDING,
+            Some("failed") => RefundStatus::FAILED,
+            Some("canceled") => RefundStatus::CANCELED,
+            _ => RefundStatus::SUCCEEDED,
+        }
+    }
+
+    /// Paypal refunds are `PENDING`, `COMPLETED`, `FAILED` or `CANCELLED`
+    pub fn from_paypal(status: Option<&str>) -> Self {
+        match status {
+            Some("PENDING") => RefundStatus::PENDING,
+            Some("FAILED") => RefundStatus::FAILED,
+            Some("CANCELLED") => RefundStatus::CANCELED,
+            _ => RefundStatus::SUCCEEDED,
+        }
+    }
+
+    /// Failed and canceled refunds never return money to the customer
+    pub fn is_reversed(&self) -> bool {
+        match self {
+            RefundStatus::FAILED | RefundStatus::CANCELED => true,
+            _ => false,
+        }
+    }
+}
+impl Default for RefundStatus {
+    fn default() -> Self {
+        RefundStatus::SUCCEEDED
+    }
+}
+impl ToSql<Text, Pg> for RefundStatus {
+    fn to_sql<W: std::io::Write>(&self, out: &mut Output<W, Pg>) -> ::diesel::serialize::Result {
+        let status = self.as_string();
+        ToSql::<Text, Pg>::to_sql(&status, out)
+    }
+}
+impl FromSql<Text, Pg> for RefundStatus {
+    fn from_sql(maybe_bytes: Option<&[u8]>) -> diesel::deserialize::Result<Self> {
+        let status = <String as FromSql<Text, Pg>>::from_sql(maybe_bytes)?;
+        RefundStatus::from_str(&status).map_err(|e| e.into())
+    }
+}
+impl FromStr for RefundStatus {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "PENDING" => Ok(RefundStatus::PENDING),
+            "SUCCEEDED" => Ok(RefundStatus::SUCCEEDED),
+            "FAILED" => Ok(RefundStatus::FAILED),
+            "CANCELED" => Ok(RefundStatus::CANCELED),
+            _ => Err(format!("Invalid RefundStatus: {}", s)),
+        }
+    }
+}
+
+
+/// Id of the transaction which reverses a failed refund's transaction
+pub fn refund_reversal_id(refund_id: &str) -> String {
+    format!("{}_reversal", refund_id)
+}
+
+/// Offsets a failed refund's transaction, so the payment counts in full again
+pub fn create_refund_reversal_transaction(
+    refund_tx: &Transaction,
+    created_at: chrono::NaiveDateTime,
+) -> Transaction {
+    Transaction {
+        id: refund_reversal_id(&refund_tx.id),
+        subtotal: -refund_tx.subtotal,
+        taxes: -refund_tx.taxes,
+        payment_processing_fee: -refund_tx.payment_processing_fee,
+        created_at: created_at,
+        details: Some(format!("Reversal of failed refund {}", refund_tx.id)),
+        ..refund_tx.clone()
+    }
+}
+
+/// Offsets a failed refund's refund and clawback items, giving each payee
+/// their share back in their next payout, even if the refund items were
+/// already netted in a payout. Reversals keep the prefix of the item they
+/// offset, and net out in remaining_refundable_items, so the orderItems can
+/// be refunded again.
+pub fn reverse_refund_payout_items(
+    refund_items: &Vec<PayoutItem>,
+    refund_id: &str,
+    created_at: &chrono::NaiveDateTime,
+) -> Vec<PayoutItem> {
+    let reversal_id = refund_reversal_id(refund_id);
+    refund_items
+        .iter()
+        .filter(|p| p.txn_id.as_ref().map(|t| t.as_str()) == Some(refund_id))
+        .map(|ritem| PayoutItem {
+            id: format!(
+                "{}_{}",
+                ritem.id.split('_').next().unwrap_or("ritem"),
+                uuid::Uuid::new_v4().to_string()
+            ),
+            amount: -ritem.amount,
+            payment_processing_fee: -ritem.payment_processing_fee,
+            created_at: created_at.clone(),
+            payout_status: match ritem.id.starts_with("ritem_") {
+                true => PayoutStatus::REFUNDING,
+                false => PayoutStatus::UNPAID,
+            },
+            txn_id: Some(reversal_id.clone()),
+            payout_id: None,
+            ..ritem.clone()
+        })
+        .collect::<Vec<PayoutItem>>()
 }
 
+/// Why an order was refunded, stored in Refund.reason for refund analytics.
+/// Other needs reason_details.
 #[derive(Copy, Clone, Debug, Deserialize, Serialize, Eq, PartialEq)]
 #[serde(rename_all = "snake_case")]
 pub enum RefundReason {
-    Duplicate,
+    /// Arrived damaged or defective
+    Damaged,
+    NotAsDescribed,
+    NotReceived,
+    WrongItem,
+    #[serde(alias = "fraud")]
     Fraudulent,
-    RequestedByCustomer,
+    /// Charged twice for the same order
+    Duplicate,
+    /// The buyer changed their mind
+    #[serde(alias = "requested_by_customer")]
+    BuyerRemorse,
+    /// The seller couldn't fulfil the order
+    SellerCancelled,
+    Other,
 }
 
 impl RefundReason {
     pub fn as_str(&self) -> &'static str {
         match self {
-            RefundReason::Duplicate => "duplicate",
+            RefundReason::Damaged => "damaged",
+            RefundReason::NotAsDescribed => "not_as_described",
+            RefundReason::NotReceived => "not_received",
+            RefundReason::WrongItem => "wrong_item",
             RefundReason::Fraudulent => "fraudulent",
-            RefundReason::RequestedByCustomer => "requested_by_customer",
+            RefundReason::Duplicate => "duplicate",
+            RefundReason::BuyerRemorse => "buyer_remorse",
+            RefundReason::SellerCancelled => "seller_cancelled",
+            RefundReason::Other => "other",
         }
     }
 
+    /// Also reads Stripe's refund reasons, for refunds made from the
+    /// Stripe dashboard. Unknown reasons are Other.
     pub fn from_str(s: &str) -> Self {
         match s {
-            "duplicate" => RefundReason::Duplicate,
+            "damaged" => RefundReason::Damaged,
+            "not_as_described" => RefundReason::NotAsDescribed,
+            "not_received" => RefundReason::NotReceived,
+            "wrong_item" => RefundReason::WrongItem,
             "fraudulent" | "fraud" => RefundReason::Fraudulent,
-            "requested_by_customer" => RefundReason::RequestedByCustomer,
-            _ => RefundReason::RequestedByCustomer,
+            "duplicate" => RefundReason::Duplicate,
+            "buyer_remorse" | "requested_by_customer" => RefundReason::BuyerRemorse,
+            "seller_cancelled" => RefundReason::SellerCancelled,
+            _ => RefundReason::Other,
+        }
+    }
+
+    /// Stripe only takes `duplicate`, `fraudulent` or `requested_by_customer`
+    pub fn stripe_reason(&self) -> &'static str {
+        match self {
+            RefundReason::Duplicate => "duplicate",
+            RefundReason::Fraudulent => "fraudulent",
+            _ => "requested_by_customer",
         }
     }
 }
+
+
+
+#[test]
+fn reverses_failed_refund_payout_items() {
+
+    use crate::models::{
+        PayeeType,
+        create_partial_refund_payout_items,
+        remaining_refundable_items,
+        refundable_total,
+    };
+
+    let created_at = chrono::NaiveDateTime::from_timestamp(1_500_000_000, 0);
+    let payout_items = vec![
+        PayoutItem::new(
+            String::from("oitem_123"),
+            String::from("store_123"),
+            Some(PayeeType::STORE),
+            8110,
+            390,
+            created_at,
+            String::from("USD"),
+            String::from("txn_123"),
+        ),
+        PayoutItem::new(
+            String::from("oitem_123"),
+            String::from("gm-platform"),
+            Some(PayeeType::PLATFORM),
+            1500,
+            0,
+            created_at,
+            String::from("USD"),
+            String::from("txn_123"),
+        ),
+    ];
+
+    let refund_items = create_partial_refund_payout_items(
+        &payout_items,
+        Some(5000),
+        &created_at,
+        "re_123",
+    ).unwrap();
+
+    let refunded = payout_items.iter().cloned()
+        .chain(refund_items.into_iter())
+        .collect::<Vec<PayoutItem>>();
+    assert_eq!(refundable_total(&remaining_refundable_items(&refunded)), 5000);
+
+    let reversals = reverse_refund_payout_items(&refunded, "re_123", &created_at);
+    assert_eq!(refundable_total(&reversals), 5000);
+    assert_eq!(reversals.iter().all(|p| p.id.starts_with("ritem_")), true);
+    assert_eq!(
+        reversals.iter().all(|p| p.txn_id == Some(String::from("re_123_reversal"))),
+        true
+    );
+    assert_eq!(reversals.iter().all(|p| p.payout_status == PayoutStatus::REFUNDING), true);
+
+    // the orderItem can be refunded in full again
+    let reversed = refunded.into_iter()
+        .chain(reversals.into_iter())
+        .collect::<Vec<PayoutItem>>();
+    assert_eq!(refundable_total(&remaining_refundable_items(&reversed)), 10000);
+
+    assert_eq!(RefundStatus::from_stripe(Some("pending")), RefundStatus::PENDING);
+    assert_eq!(RefundStatus::from_paypal(Some("CANCELLED")).is_reversed(), true);
+    assert_eq!(RefundStatus::from_stripe(None).is_reversed(), false);
+}
+
+
+#[test]
+fn reads_refund_reasons() {
+
+    let reason = serde_json::from_str::<RefundReason>(r#""not_as_described""#).unwrap();
+    assert_eq!(reason, RefundReason::NotAsDescribed);
+    assert_eq!(reason.stripe_reason(), "requested_by_customer");
+
+    // Stripe's reasons, from before the reason taxonomy
+    let reason = serde_json::from_str::<RefundReason>(r#""requested_by_customer""#).unwrap();
+    assert_eq!(reason, RefundReason::BuyerRemorse);
+    assert_eq!(RefundReason::from_str("fraudulent").stripe_reason(), "fraudulent");
+
+    assert_eq!(serde_json::from_str::<RefundReason>(r#""changed_my_mind""#).is_err(), true);
+    assert_eq!(RefundReason::from_str("changed_my_mind"), RefundReason::Other);
+}
@@ -0,0 +1,276 @@
+use std::collections::BTreeMap;
+
+use crate::models::{
+    PayoutItem,
+    PayoutStatus,
+};
+
+
+/// What is left to refund of each payee's share of an orderItem:
+/// the orderItem's payout items, less earlier (partial) refund items.
+/// Payout items are netted per (orderItem, payee, payee type).
+pub fn remaining_refundable_items(
+    payout_items: &Vec<PayoutItem>,
+) -> Vec<PayoutItem> {
+
+    let mut remaining: BTreeMap<(Option<String>, String, String), PayoutItem> = BTreeMap::new();
+
+    for pitem in payout_items.iter() {
+        let key = (
+            pitem.order_item_id.clone(),
+            pitem.payee_id.clone(),
+            pitem.payee_type.as_string(),
+        );
+        match remaining.get_mut(&key) {
+            Some(r) => {
+                r.amount += pitem.amount;
+                r.payment_processing_fee += pitem.payment_processing_fee;
+            },
+            None => {
+                remaining.insert(key, pitem.clone());
+            },
+        }
+    }
+
+    remaining.into_iter()
+        .map(|(_, pitem)| pitem)
+        .filter(|p| p.amount != 0 || p.payment_processing_fee != 0)
+        .collect::<Vec<PayoutItem>>()
+}
+
+
+/// The value the customer paid for payout items: every payee's amount,
+/// plus the payment processing fee deducted from the seller.
+pub fn refundable_total(payout_items: &Vec<PayoutItem>) -> i32 {
+    payout_items
+        .iter()
+        .map(|p| p.amount + p.payment_processing_fee)
+        .sum::<i32>()
+}
+
+
+/// Splits refund_amount across the remaining payout items in proportion
+/// to their value, to the exact cent (largest remainder method).
+/// Returns (amount, payment_processing_fee) to refund for each item,
+/// in the same order as remaining_items.
+pub fn allocate_refund_amount(
+    remaining_items: &Vec<PayoutItem>,
+    refund_amount: i32,
+) -> Result<Vec<(i32, i32)>, String> {
+
+    // amounts and fees are allocated as separate parts, so a seller's
+    // payment processing fee is refunded pro-rata as well
+    let parts: Vec<i64> = remaining_items
+        .iter()
+        .flat_map(|p| vec![p.amount as i64, p.payment_processing_fee as i64])
+        .map(|v| if v > 0 { v } else { 0 })
+        .collect();
+
+    let total: i64 = parts.iter().sum();
+    let refund_amount = refund_amount as i64;
+
+    // negative parts (e.g. a promo the platform paid for) are not refunded,
+    // so the shares of the other parts must stay within the net total
+    let max_refund = std::cmp::min(total, refundable_total(remaining_items) as i64);
+
+    if refund_amount <= 0 {
+        return Err(format!("refund amount must be positive, got: {}", refund_amount))
+    }
+    if refund_amount > max_refund {
+        return Err(format!(
+            "refund amount {} is more than the {} left to refund", refund_amount, max_refund
+        ))
+    }
+
+    let mut shares: Vec<i64> = parts.iter().map(|v| refund_amount * v / total).collect();
+    let allocated: i64 = shares.iter().sum();
+
+    // hand out the leftover cents to the largest remainders,
+    // earlier parts first on ties
+    let mut by_remainder: Vec<(usize, i64)> = parts
+        .iter()
+        .enumerate()
+        .map(|(i, v)| (i, refund_amount * v % total))
+        .collect();
+    by_remainder.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+    for (i, _) in by_remainder.into_iter().take((refund_amount - allocated) as usize) {
+        shares[i] += 1;
+    }
+
+    Ok(shares
+        .chunks(2)
+        .map(|c| (c[0] as i32, c[1] as i32))
+        .collect())
+}
+
+
+/// Refund items for refund_amount of the orderItems' payout items,
+/// or for everything left to refund if refund_amount is None.
+/// Earlier partial refunds are taken into account, so an orderItem can be
+/// refunded several times, but never for more than it was sold for.
+pub fn create_partial_refund_payout_items(
+    payout_items: &Vec<PayoutItem>,
+    refund_amount: Option<i32>,
+    created_at: &chrono::NaiveDateTime,
+    tx_id: &str,
+) -> Result<Vec<PayoutItem>, String> {
+
+    let remaining_items = remaining_refundable_items(payout_items);
+    let remaining_total = refundable_total(&remaining_items);
+
+    if remaining_total <= 0 {
+        return Err(String::from("order items have already been fully refunded"))
+    }
+
+    let refunds: Vec<(i32, i32)> = match refund_amount {
+        None => remaining_items
+            .iter()
+            .map(|p| (p.amount, p.payment_processing_fee))
+            .collect(),
+        Some(amount) => allocate_refund_amount(&remaining_items, amount)?,
+    };
+
+    Ok(remaining_items
+        .into_iter()
+        .zip(refunds.into_iter())
+        .filter(|(_, (amount, fee))| *amount != 0 || *fee != 0)
+        .map(|(pitem, (amount, fee))| {
+            PayoutItem {
+                amount: amount,
+                payment_processing_fee: fee,
+                payout_status: PayoutStatus::UNPAID,
+                payout_id: None,
+                ..pitem
+            }.to_refund(created_at.clone(), tx_id.to_string())
+        })
+        .collect::<Vec<PayoutItem>>())
+}
+
+
+
+/// Splits a refund made outside the service (e.g. from the Stripe dashboard)
+/// into the part refunding payout items and the part refunding taxes,
+/// in the same proportions as the payment's subtotal and taxes.
+/// Returns (payout_refund, taxes_refund).
+pub fn split_refund_taxes(
+    refund_amount: i32,
+    subtotal: i32,
+    taxes: i32,
+) -> (i32, i32) {
+    let total = (subtotal + taxes) as i64;
+    if total <= 0 || taxes <= 0 {
+        return (refund_amount, 0)
+    }
+    let taxes_refund = (refund_amount as i64 * taxes as i64 / total) as i32;
+    (refund_amount - taxes_refund, taxes_refund)
+}
+
+
+#[test]
+fn allocates_partial_refunds_to_the_exact_cent() {
+
+    use crate::models::PayeeType;
+
+    let created_at = chrono::NaiveDateTime::from_timestamp(1_500_000_000, 0);
+    let pitem = |payee_id: &str, payee_type: PayeeType, amount: i32, fee: i32| PayoutItem::new(
+        String::from("oitem_123"),
+        String::from(payee_id),
+        Some(payee_type),
+        amount,
+        fee,
+        created_at,
+        String::from("USD"),
+        String::from("txn_123"),
+    );
+
+    // a $200 item
+    let payout_items = vec![
+        pitem("store_123", PayeeType::STORE, 16293, 610),
+        pitem("gm-platform", PayeeType::PLATFORM, 2097, 0),
+        pitem("user_123", PayeeType::BUYER_AFFILIATE, 1000, 0),
+    ];
+
+    // $10 of shipping damage
+    let refund_items = create_partial_refund_payout_items(
+        &payout_items,
+        Some(1000),
+        &created_at,
+        "txn_refund_1",
+    ).expect("partial refund");
+
+    assert_eq!(refund_items.len(), 3);
+    assert_eq!(refundable_total(&refund_items), -1000);
+    assert_eq!(
+        refund_items.iter()
+            .map(|p| (p.payee_id.clone(), p.amount, p.payment_processing_fee))
+            .collect::<Vec<(String, i32, i32)>>(),
+        vec![
+            (String::from("gm-platform"), -105, 0),
+            (String::from("store_123"), -815, -30),
+            (String::from("user_123"), -50, 0),
+        ]
+    );
+    assert_eq!(refund_items.iter().all(|p| p.payout_status == PayoutStatus::REFUNDING), true);
+
+    // refund the rest, then nothing is left
+    let refunded_once = payout_items.iter().cloned()
+        .chain(refund_items.into_iter())
+        .collect::<Vec<PayoutItem>>();
+
+    assert_eq!(
+        create_partial_refund_payout_items(&refunded_once, Some(19001), &created_at, "txn_refund_2")
+            .is_err(),
+        true
+    );
+    let rest = create_partial_refund_payout_items(&refunded_once, None, &created_at, "txn_refund_2")
+        .expect("refund the rest");
+    assert_eq!(refundable_total(&rest), -19000);
+
+    let refunded_twice = refunded_once.into_iter()
+        .chain(rest.into_iter())
+        .collect::<Vec<PayoutItem>>();
+    assert_eq!(
+        create_partial_refund_payout_items(&refunded_twice, Some(1), &created_at, "txn_refund_3")
+            .is_err(),
+        true
+    );
+}
+
+#[test]
+fn allocates_partial_refunds_within_the_net_total_with_negative_items() {
+
+    use crate::models::PayeeType;
+
+    let created_at = chrono::NaiveDateTime::from_timestamp(1_500_000_000, 0);
+    let pitem = |payee_id: &str, payee_type: PayeeType, amount: i32, fee: i32| PayoutItem::new(
+        String::from("oitem_123"),
+        String::from(payee_id),
+        Some(payee_type),
+        amount,
+        fee,
+        created_at,
+        String::from("USD"),
+        String::from("txn_123"),
+    );
+
+    // the platform paid for a promo, so its share is negative
+    let payout_items = vec![
+        pitem("store_123", PayeeType::STORE, 900, 100),
+        pitem("gm-platform", PayeeType::PLATFORM, -300, 0),
+    ];
+    assert_eq!(refundable_total(&payout_items), 700);
+
+    assert_eq!(allocate_refund_amount(&payout_items, 701).is_err(), true);
+
+    let shares = allocate_refund_amount(&payout_items, 700).expect("allocate 700");
+    assert_eq!(shares, vec![(630, 70), (0, 0)]);
+    assert_eq!(shares.iter().map(|(a, f)| a + f).sum::<i32>(), 700);
+}
+
+#[test]
+fn splits_refund_taxes_pro_rata() {
+    assert_eq!(split_refund_taxes(11000, 10000, 1000), (10000, 1000));
+    assert_eq!(split_refund_taxes(1000, 10000, 1000), (910, 90));
+    assert_eq!(split_refund_taxes(1000, 10000, 0), (1000, 0));
+}
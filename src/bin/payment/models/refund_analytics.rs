@@ -0,0 +1,57 @@
+use diesel::sql_types::{BigInt, Double, Text};
+
+
+/// What refund aggregates are grouped by
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum RefundAnalyticsGroupBy {
+    /// Each store's share of refunds, against the store's sales
+    STORE,
+    /// Refund reasons, against all sales
+    REASON,
+    /// Refunds and sales per day, week or month
+    PERIOD,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum RefundPeriod {
+    DAY,
+    WEEK,
+    MONTH,
+}
+impl RefundPeriod {
+    /// Postgres date_trunc field
+    pub fn as_date_trunc(&self) -> &'static str {
+        match self {
+            RefundPeriod::DAY => "day",
+            RefundPeriod::WEEK => "week",
+            RefundPeriod::MONTH => "month",
+        }
+    }
+}
+impl Default for RefundPeriod {
+    fn default() -> Self {
+        RefundPeriod::MONTH
+    }
+}
+
+
+/// Refunds which succeeded (or are pending) in a group, and the sales they
+/// are refunds of. Amounts are in cents, refunds are positive.
+#[serde(rename_all = "camelCase")]
+#[derive(Clone, Debug, Serialize, Deserialize, QueryableByName)]
+pub struct RefundAggregate {
+    /// storeId, refund reason, or the period's start date
+    #[sql_type = "Text"]
+    pub group_key: String,
+    #[sql_type = "BigInt"]
+    pub refund_count: i64,
+    #[sql_type = "BigInt"]
+    pub refund_amount: i64,
+    #[sql_type = "BigInt"]
+    pub sales_count: i64,
+    #[sql_type = "BigInt"]
+    pub sales_amount: i64,
+    /// refund_count / sales_count
+    #[sql_type = "Double"]
+    pub refund_rate: f64,
+}
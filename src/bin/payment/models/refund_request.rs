@@ -0,0 +1,213 @@
+use diesel::prelude::*;
+use gm::db::schema::refund_requests;
+
+use diesel::deserialize::FromSql;
+use diesel::pg::Pg;
+use diesel::serialize::{Output, ToSql};
+use diesel::sql_types::Text;
+use std::str::FromStr;
+
+
+/// Refunds over this many cents (including taxes) need an admin's approval
+const REFUND_APPROVAL_THRESHOLD: &str = "REFUND_APPROVAL_THRESHOLD";
+const DEFAULT_REFUND_APPROVAL_THRESHOLD: i32 = 50000;
+/// Refunds more than this many days after the payment need an admin's approval
+const REFUND_WINDOW_DAYS: &str = "REFUND_WINDOW_DAYS";
+const DEFAULT_REFUND_WINDOW_DAYS: i64 = 30;
+
+pub fn refund_approval_threshold() -> i32 {
+    dotenv::dotenv().ok();
+    std::env::var(REFUND_APPROVAL_THRESHOLD)
+        .ok()
+        .and_then(|s| s.parse::<i32>().ok())
+        .unwrap_or(DEFAULT_REFUND_APPROVAL_THRESHOLD)
+}
+
+pub fn refund_window_days() -> i64 {
+    dotenv::dotenv().ok();
+    std::env::var(REFUND_WINDOW_DAYS)
+        .ok()
+        .and_then(|s| s.parse::<i64>().ok())
+        .unwrap_or(DEFAULT_REFUND_WINDOW_DAYS)
+}
+
+
+/// A refund which waits for an admin's approval before Stripe or Paypal
+/// is asked to refund anything. Holds the original refund request, which
+/// is executed as is once approved.
+#[serde(rename_all = "camelCase")]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Queryable, Insertable)]
+#[table_name = "refund_requests"]
+pub struct RefundRequest {
+    /// rreq_xxxx
+    pub id: String,
+    pub order_id: String,
+    pub payment_processor: String,
+    /// Amount to refund in cents, including taxes
+    pub refund_total: i32,
+    /// The RefundOrderBody to execute once approved
+    pub refund_order: serde_json::Value,
+    /// Why the refund needs approval, RefundApprovalReasons
+    pub approval_reasons: Vec<String>,
+    pub status: RefundRequestStatus,
+    pub created_at: chrono::NaiveDateTime,
+    /// userId of the admin who approved or rejected the refund
+    pub reviewed_by: Option<String>,
+    pub reviewed_at: Option<chrono::NaiveDateTime>,
+    pub review_notes: Option<String>,
+    /// Set once the approved refund has been made
+    pub refund_id: Option<String>,
+    /// Why an approved refund could not be made, it can be approved again
+    pub last_error: Option<String>,
+}
+
+impl RefundRequest {
+    pub fn new(
+        order_id: String,
+        payment_processor: String,
+        refund_total: i32,
+        refund_order: serde_json::Value,
+        approval_reasons: Vec<RefundApprovalReason>,
+        created_at: chrono::NaiveDateTime,
+    ) -> Self {
+        Self {
+            id: format!("rreq_{}", uuid::Uuid::new_v4().to_string()),
+            order_id: order_id,
+            payment_processor: payment_processor,
+            refund_total: refund_total,
+            refund_order: refund_order,
+            approval_reasons: approval_reasons
+                .iter()
+                .map(|r| r.as_string())
+                .collect(),
+            status: RefundRequestStatus::PENDING_APPROVAL,
+            created_at: created_at,
+            reviewed_by: None,
+            reviewed_at: None,
+            review_notes: None,
+            refund_id: None,
+            last_error: None,
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum RefundApprovalReason {
+    /// More than REFUND_APPROVAL_THRESHOLD refunded on the order
+    AMOUNT_OVER_THRESHOLD,
+    /// More than REFUND_WINDOW_DAYS after the payment
+    OUTSIDE_REFUND_WINDOW,
+}
+impl RefundApprovalReason {
+    pub fn as_string(&self) -> String {
+        String::from(format!("{:?}", &self))
+    }
+}
+
+/// Why a refund needs an admin's approval, empty if it can be made straight away.
+/// paid_at is when the order was paid for. The threshold applies to the order's
+/// refunds as a whole: refunded_total is what has been refunded on the order already,
+/// or is held by refund requests still waiting on approval.
+pub fn refund_approval_reasons(
+    refund_total: i32,
+    refunded_total: i32,
+    paid_at: Option<chrono::NaiveDateTime>,
+    now: chrono::NaiveDateTime,
+    threshold: i32,
+    window_days: i64,
+) -> Vec<RefundApprovalReason> {
+
+    let mut reasons = vec![];
+
+    if refunded_total + refund_total > threshold {
+        reasons.push(RefundApprovalReason::AMOUNT_OVER_THRESHOLD);
+    }
+    if let Some(paid_at) = paid_at {
+        if now - paid_at > chrono::Duration::days(window_days) {
+            reasons.push(RefundApprovalReason::OUTSIDE_REFUND_WINDOW);
+        }
+    }
+
+    reasons
+}
+
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(AsExpression, FromSqlRow)]
+#[sql_type = "Text"]
+pub enum RefundRequestStatus {
+    PENDING_APPROVAL,
+    /// Approved, the refund is being made
+    APPROVED,
+    REJECTED,
+    /// Approved, and refunded
+    REFUNDED,
+}
+impl RefundRequestStatus {
+    pub fn as_string(&self) -> String {
+        String::from(format!("{:?}", &self))
+    }
+}
+impl ToSql<Text, Pg> for RefundRequestStatus {
+    fn to_sql<W: std::io::Write>(&self, out: &mut Output<W, Pg>) -> ::diesel::serialize::Result {
+        let status = self.as_string();
+        ToSql::<Text, Pg>::to_sql(&status, out)
+    }
+}
+impl FromSql<Text, Pg> for RefundRequestStatus {
+    fn from_sql(maybe_bytes: Option<&[u8]>) -> diesel::deserialize::Result<Self> {
+        let status = <String as FromSql<Text, Pg>>::from_sql(maybe_bytes)?;
+        RefundRequestStatus::from_str(&status).map_err(|e| e.into())
+    }
+}
+impl FromStr for RefundRequestStatus {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "PENDING_APPROVAL" => Ok(RefundRequestStatus::PENDING_APPROVAL),
+            "APPROVED" => Ok(RefundRequestStatus::APPROVED),
+            "REJECTED" => Ok(RefundRequestStatus::REJECTED),
+            "REFUNDED" => Ok(RefundRequestStatus::REFUNDED),
+            _ => Err(format!("Invalid RefundRequestStatus: {}", s)),
+        }
+    }
+}
+
+
+
+#[test]
+fn refunds_over_threshold_or_outside_window_need_approval() {
+
+    let paid_at = chrono::NaiveDateTime::from_timestamp(1_500_000_000, 0);
+    let now = paid_at + chrono::Duration::days(10);
+
+    assert_eq!(
+        refund_approval_reasons(20000, 0, Some(paid_at), now, 50000, 30),
+        vec![]
+    );
+    assert_eq!(
+        refund_approval_reasons(50001, 0, Some(paid_at), now, 50000, 30),
+        vec![RefundApprovalReason::AMOUNT_OVER_THRESHOLD]
+    );
+
+    let later = paid_at + chrono::Duration::days(31);
+    assert_eq!(
+        refund_approval_reasons(50001, 0, Some(paid_at), later, 50000, 30),
+        vec![
+            RefundApprovalReason::AMOUNT_OVER_THRESHOLD,
+            RefundApprovalReason::OUTSIDE_REFUND_WINDOW,
+        ]
+    );
+    assert_eq!(
+        refund_approval_reasons(20000, 0, None, later, 50000, 30),
+        vec![]
+    );
+
+    // partial refunds just under the threshold add up
+    assert_eq!(
+        refund_approval_reasons(30000, 30000, Some(paid_at), now, 50000, 30),
+        vec![RefundApprovalReason::AMOUNT_OVER_THRESHOLD]
+    );
+}
@@ -0,0 +1,197 @@
+use diesel::prelude::*;
+use gm::db::schema::revenue_shares;
+
+
+#[serde(rename_all = "camelCase")]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Queryable, Insertable)]
+#[table_name = "revenue_shares"]
+pub struct RevenueShare {
+    pub id: String,
+    pub created_at: chrono::NaiveDateTime,
+    pub store_id: String,
+    /// Recipient of this share, paid out with their own PayoutMethod
+    pub payee_id: String,
+    /// Percentage of the seller's earnings, e.g. 0.4 for 40%
+    pub percentage: Option<f64>,
+    /// Or a fixed amount (in cents) per orderItem, taken before percentages
+    pub fixed_amount: Option<i32>,
+}
+
+impl RevenueShare {
+    pub fn new(
+        store_id: String,
+        payee_id: String,
+        percentage: Option<f64>,
+        fixed_amount: Option<i32>,
+    ) -> Self {
+        Self {
+            id: format!("rshare_{}", uuid::Uuid::new_v4().to_string()),
+            created_at: chrono::NaiveDateTime::from_timestamp(
+                chrono::Utc::now().timestamp(), 0
+            ),
+            store_id: store_id,
+            payee_id: payee_id,
+            percentage: percentage,
+            fixed_amount: fixed_amount,
+        }
+    }
+}
+
+
+/// Checks a store's revenue-share table before it is written
+pub fn validate_revenue_shares(
+    revenue_shares: &Vec<RevenueShare>,
+) -> Result<(), String> {
+
+    for rs in revenue_shares.iter() {
+        match (rs.percentage, rs.fixed_amount) {
+            (Some(p), None) => {
+                if p <= 0.0 || p > 1.0 {
+                    return Err(format!(
+                        "percentage for payee {} must be between 0 and 1, got {}",
+                        rs.payee_id, p
+                    ))
+                }
+            },
+            (None, Some(f)) => {
+                if f <= 0 {
+                    return Err(format!(
+                        "fixedAmount for payee {} must be greater than 0, got {}",
+                        rs.payee_id, f
+                    ))
+                }
+            },
+            _ => {
+                return Err(format!(
+                    "payee {} needs either a percentage or a fixedAmount, not both",
+                    rs.payee_id
+                ))
+            }
+        }
+    }
+
+    let total_percentage = revenue_shares.iter()
+        .filter_map(|rs| rs.percentage)
+        .sum::<f64>();
+
+    if total_percentage > 1.0 {
+        return Err(format!(
+            "percentages add up to more than 100%: {}",
+            total_percentage
+        ))
+    }
+
+    Ok(())
+}
+
+
+/// Partitions the seller's earnings for one orderItem between the store's
+/// revenue-share recipients. Fixed amounts come off first, then percentages
+/// of what is left. Whatever remains (unallocated share, rounding cents) goes
+/// to the store itself, so the parts always add up to seller_earnings.
+///
+/// Returns (payee_id, amount) pairs, one per payee, store first.
+pub fn split_store_earnings(
+    store_id: &str,
+    seller_earnings: i32,
+    revenue_shares: &Vec<RevenueShare>,
+) -> Vec<(String, i32)> {
+
+    if seller_earnings <= 0 || revenue_shares.is_empty() {
+        return vec![(store_id.to_string(), seller_earnings)]
+    }
+
+    let mut remaining = seller_earnings;
+    let mut shares: Vec<(String, i32)> = vec![];
+
+    // 1. fixed amounts, never more than what is left
+    for rs in revenue_shares.iter() {
+        if let Some(f) = rs.fixed_amount {
+            let amount = f.min(remaining);
+            remaining -= amount;
+            shares.push((rs.payee_id.clone(), amount));
+        }
+    }
+
+    // 2. percentages of the earnings left after fixed amounts.
+    // Round down, so rounding cents stay with the store.
+    let after_fixed = remaining;
+    for rs in revenue_shares.iter() {
+        if let Some(p) = rs.percentage {
+            let amount = ((after_fixed as f64) * p).floor() as i32;
+            let amount = amount.min(remaining);
+            remaining -= amount;
+            shares.push((rs.payee_id.clone(), amount));
+        }
+    }
+
+    // 3. remainder to the store, then merge payees listed more than once
+    std::iter::once((store_id.to_string(), remaining))
+        .chain(shares.into_iter())
+        .fold(vec![], |mut acc: Vec<(String, i32)>, (payee_id, amount)| {
+            match acc.iter_mut().find(|(pid, _)| pid == &payee_id) {
+                Some((_, total)) => *total += amount,
+                None => acc.push((payee_id, amount)),
+            };
+            acc
+        })
+}
+
+/// Index of the split which pays the payment processing fee: the first one
+/// with earnings, as payout items for 0 are dropped and the fee with them.
+pub fn payment_fee_payer_index(splits: &Vec<(String, i32)>) -> usize {
+    splits
+        .iter()
+        .position(|(_, amount)| *amount != 0)
+        .unwrap_or(0)
+}
+
+
+
+#[test]
+fn splits_store_earnings_and_reconciles() {
+
+    let shares = vec![
+        RevenueShare::new(String::from("store_123"), String::from("user_a"), Some(0.5), None),
+        RevenueShare::new(String::from("store_123"), String::from("user_b"), Some(0.3), None),
+        RevenueShare::new(String::from("store_123"), String::from("user_c"), None, Some(1000)),
+    ];
+    assert_eq!(validate_revenue_shares(&shares), Ok(()));
+
+    // 1000 fixed, then 50% and 30% of 8999
+    let split = split_store_earnings("store_123", 9999, &shares);
+    assert_eq!(split, vec![
+        (String::from("store_123"), 1801),
+        (String::from("user_c"), 1000),
+        (String::from("user_a"), 4499),
+        (String::from("user_b"), 2699),
+    ]);
+    assert_eq!(split.iter().map(|(_, a)| a).sum::<i32>(), 9999);
+
+    // fixed amounts can't exceed the seller's earnings
+    let split = split_store_earnings("store_123", 500, &shares);
+    assert_eq!(split, vec![
+        (String::from("store_123"), 0),
+        (String::from("user_c"), 500),
+        (String::from("user_a"), 0),
+        (String::from("user_b"), 0),
+    ]);
+    // the store's item rounds to 0, so user_c pays the payment processing fee
+    assert_eq!(payment_fee_payer_index(&split), 1);
+}
+
+#[test]
+fn rejects_invalid_revenue_shares() {
+
+    let shares = vec![
+        RevenueShare::new(String::from("store_123"), String::from("user_a"), Some(0.7), None),
+        RevenueShare::new(String::from("store_123"), String::from("user_b"), Some(0.4), None),
+    ];
+    assert_eq!(validate_revenue_shares(&shares).is_err(), true);
+
+    let shares = vec![
+        RevenueShare::new(String::from("store_123"), String::from("user_a"), Some(0.5), Some(100)),
+    ];
+    assert_eq!(validate_revenue_shares(&shares).is_err(), true);
+}
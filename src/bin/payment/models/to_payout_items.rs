@@ -16,6 +16,8 @@ use chrono::offset::TimeZone;
 use crate::db;
 use crate::pricing::{
     calculate_platform_fees,
+    resolve_applied_rates,
+    FundedDiscounts,
     PaymentFees,
     CalculatedEarnings,
 };
@@ -25,6 +27,10 @@ use crate::models::{
     PayoutDealType,
     PayoutSplit,
     PayeeType,
+    PayoutCalculation,
+    DbError,
+    split_store_earnings,
+    payment_fee_payer_index,
 };
 
 
@@ -36,16 +42,42 @@ pub fn to_payout_items(
     // supplied by OrderItems, or set by Mock tests
     created_at: &chrono::NaiveDateTime,
     buyer_affiliate_user_id: Option<String>,
-) -> Vec<PayoutItem> {
+) -> Result<(Vec<PayoutItem>, Vec<PayoutCalculation>), DbError> {
 
     debug!("\n\n============= to_payout_items(...) =================\n");
     // 1. lookup the most current PayoutSplit for buyer_affiliate
-    let buyer_aff_psplit: Option<PayoutSplit> = match buyer_affiliate_user_id {
+    let buyer_aff_psplit: Option<PayoutSplit> = match &buyer_affiliate_user_id {
         None => None,
         Some(ba_user_id) => {
-            db::try_read_buyer_psplit_create_on_null(conn, ba_user_id)
+            db::try_read_buyer_psplit_create_on_null(
+                conn,
+                ba_user_id.clone(),
+                Some(created_at.clone()),
+            )
         }
     };
+
+    to_payout_items_with_buyer_aff_psplit(
+        conn,
+        order_items_rpc,
+        tx_id,
+        created_at,
+        buyer_affiliate_user_id,
+        buyer_aff_psplit,
+    )
+}
+
+/// Like to_payout_items, with the buyer affiliate's PayoutSplit already looked up.
+/// Only reads PayoutSplits, so previews can use it without writing any.
+pub fn to_payout_items_with_buyer_aff_psplit(
+    conn: &diesel::PgConnection,
+    order_items_rpc: Vec<OrderItemRpc>,
+    tx_id: &str,
+    created_at: &chrono::NaiveDateTime,
+    buyer_affiliate_user_id: Option<String>,
+    buyer_aff_psplit: Option<PayoutSplit>,
+) -> Result<(Vec<PayoutItem>, Vec<PayoutCalculation>), DbError> {
+
     debug!("Buyer Affiliate PayoutSplit: {:?}", &buyer_aff_psplit);
     // do this outside order_items_rpc.iter(), as buyer_affiliate applies to all
     // orderItems, instead of each orderItem having it's own buyer-affiliate
@@ -67,9 +99,10 @@ pub fn to_payout_items(
     // debug!("HashMap<storeOrUserId, PayoutSplit>: {:?}", &seller_aff_psplit_hmap);
 
 
+    let (vec_pitems, payout_calculations): (Vec<Vec<PayoutItem>>, Vec<PayoutCalculation>) =
     order_items_rpc.clone()
     .iter()
-    .map(|oitem: &OrderItemRpc| {
+    .map(|oitem: &OrderItemRpc| -> Result<(Vec<PayoutItem>, PayoutCalculation), DbError> {
 
         // seller pays payment_processing_fees
         let seller_payment_proc_fee = PaymentFees::new()
@@ -86,7 +119,8 @@ pub fn to_payout_items(
         ): (Option<PayoutSplit>, Option<PayoutSplit>) =
         match db::read_current_seller_referrer_payout_splits_by_store_id(
             &conn,
-            oitem.store_id.clone()
+            oitem.store_id.clone(),
+            Some(created_at.clone()),
         ) {
             None => (None, None),
             Some(psplits) => {
@@ -97,6 +131,64 @@ pub fn to_payout_items(
         debug!("Seller PayoutSplit: {:?}", &seller_psplit);
         debug!("Seller Affiliate PayoutSplit: {:?}", &seller_aff_psplit);
 
+        // the seller affiliate's own referrers, walking up the referrer_id chain
+        let upline_aff_psplits: Vec<PayoutSplit> = match &seller_aff_psplit {
+            None => vec![],
+            Some(s) => db::read_upline_payout_splits(&conn, s, Some(created_at.clone()))?,
+        };
+        debug!("Upline Affiliate PayoutSplits: {:?}", &upline_aff_psplits);
+
+        // a store's own affiliate deal wins over the affiliate's global deal
+        let buyer_aff_psplit: Option<PayoutSplit> = match &buyer_affiliate_user_id {
+            None => None,
+            Some(ba_user_id) => db::read_store_buyer_affiliate_payout_split(
+                &conn,
+                ba_user_id,
+                &oitem.store_id,
+                Some(created_at.clone()),
+            )?.or(buyer_aff_psplit.clone()),
+        };
+        debug!("Buyer Affiliate PayoutSplit for store: {:?}", &buyer_aff_psplit);
+
+        // 3. lookup product or category fee overrides for this orderItem
+        let fee_override = db::read_fee_override_for_order_item(
+            &conn,
+            &oitem,
+            &created_at,
+        )?;
+        debug!("FeeOverride: {:?}", &fee_override);
+
+        let funded_discounts = FundedDiscounts::from_discounts(
+            &oitem.discounts.clone().unwrap_or(vec![])
+        );
+
+        // record what went into this split, so it can be recomputed later
+        let payout_calculation = PayoutCalculation::new(
+            oitem.id.clone(),
+            tx_id.to_string(),
+            oitem.currency.clone(),
+            oitem.actual_price,
+            seller_payment_proc_fee,
+            created_at.clone(),
+        ).update_order_item(&oitem)
+        .update_payout_splits(
+            &seller_psplit,
+            &buyer_aff_psplit,
+            &seller_aff_psplit,
+            &upline_aff_psplits,
+            &fee_override,
+        ).update_applied_rates(resolve_applied_rates(
+            oitem.actual_price,
+            &funded_discounts,
+            seller_psplit.clone(),
+            buyer_aff_psplit.clone(),
+            seller_aff_psplit.clone(),
+            upline_aff_psplits.clone(),
+            fee_override.clone(),
+            Some(created_at.clone()),
+        )).update_funded_discounts(funded_discounts.clone());
+        debug!("PayoutCalculation: {:?}", &payout_calculation);
+
         debug!("\n====================================");
         debug!("Calculating Earnings from PayoutSplits");
         let CalculatedEarnings {
@@ -105,31 +197,56 @@ pub fn to_payout_items(
             gm_earnings,
             buyer_affiliate_earnings,
             seller_affiliate_earnings,
+            upline_affiliate_earnings,
+            platform_promo_cost,
         } = calculate_platform_fees(
             oitem.actual_price,
             seller_payment_proc_fee,
             seller_psplit, // PayputSplit for Seller goes here
             buyer_aff_psplit.clone(), // PayoutSplit goes here
             seller_aff_psplit.clone(), // PayoutSplit goes here
+            upline_aff_psplits.clone(),
+            fee_override,
+            funded_discounts,
             Some(created_at.clone())
         );
         debug!("seller_earnings_less_payment_fee: {:?}", &seller_earnings_less_payment_fee);
         debug!("gm_earnings: {:?}", &gm_earnings);
+        debug!("platform_promo_cost: {:?}", &platform_promo_cost);
         debug!("buyer_affiliate_earnings: {:?}", &buyer_affiliate_earnings);
         debug!("seller_affiliate_earnings: {:?}", &seller_affiliate_earnings);
 
-        let mut pitems = vec![
-            // STORE
+        // STORE, split between the store's revenue-share recipients if it has any.
+        // The first item with earnings records the payment processing fee.
+        let revenue_shares = db::read_revenue_shares_by_store_id(&conn, &oitem.store_id)?;
+        debug!("RevenueShares: {:?}", &revenue_shares);
+
+        let store_splits = split_store_earnings(
+            &oitem.store_id,
+            seller_earnings_less_payment_fee,
+            &revenue_shares,
+        );
+        let fee_payer_index = payment_fee_payer_index(&store_splits);
+
+        let mut pitems = store_splits
+        .into_iter()
+        .enumerate()
+        .map(|(i, (payee_id, amount))| {
             PayoutItem::new(
                 oitem.id.clone(),
-                oitem.store_id.clone(),
+                payee_id,
                 Some(PayeeType::STORE),
-                seller_earnings_less_payment_fee,
-                payment_processing_fee, // seller pays payment processing fee
+                amount,
+                // seller pays payment processing fee
+                if i == fee_payer_index { payment_processing_fee } else { 0 },
                 created_at.clone(),
                 oitem.currency.clone(),
                 tx_id.to_string(),
-            ),
+            )
+        })
+        .collect::<Vec<PayoutItem>>();
+
+        pitems.append(&mut vec![
             // PLATFORM
             PayoutItem::new(
                 oitem.id.clone(),
@@ -141,7 +258,7 @@ pub fn to_payout_items(
                 oitem.currency.clone(),
                 tx_id.to_string(),
             ),
-        ];
+        ]);
 
         if let Some(b) = buyer_aff_psplit.clone() {
             pitems.append(&mut vec![
@@ -160,27 +277,75 @@ pub fn to_payout_items(
         };
 
         if let Some(s) = seller_aff_psplit {
-            pitems.append(&mut vec![
-                // SELLER AFFILIATE, filtered out if 0
-                PayoutItem::new(
-                    oitem.id.clone(),
-                    s.store_or_user_id.clone(),
-                    Some(PayeeType::SELLER_AFFILIATE),
-                    seller_affiliate_earnings,
-                    0, // payment_processing_fee paid by seller affiliate
-                    created_at.clone(),
-                    oitem.currency.clone(),
-                    tx_id.to_string(),
+            // SELLER AFFILIATE and their upline referrers.
+            // One payout item per beneficiary, in case someone
+            // appears at more than one level in the chain.
+            let referral_earnings = std::iter::once((s.store_or_user_id.clone(), seller_affiliate_earnings))
+                .chain(
+                    upline_aff_psplits.iter()
+                        .map(|ps| ps.store_or_user_id.clone())
+                        .zip(upline_affiliate_earnings.into_iter())
                 )
-            ])
+                .fold(vec![], |mut acc: Vec<(String, i32)>, (user_id, earnings)| {
+                    match acc.iter_mut().find(|(uid, _)| uid == &user_id) {
+                        Some((_, total)) => *total += earnings,
+                        None => acc.push((user_id, earnings)),
+                    };
+                    acc
+                });
+
+            pitems.append(&mut referral_earnings
+                .into_iter()
+                .map(|(user_id, earnings)| {
+                    // filtered out if 0
+                    PayoutItem::new(
+                        oitem.id.clone(),
+                        user_id,
+                        Some(PayeeType::SELLER_AFFILIATE),
+                        earnings,
+                        0, // payment_processing_fee paid by seller affiliate
+                        created_at.clone(),
+                        oitem.currency.clone(),
+                        tx_id.to_string(),
+                    )
+                })
+                .collect::<Vec<PayoutItem>>()
+            )
         };
 
         // return newly generated payout_items
-        pitems
+        Ok((pitems, payout_calculation))
     })
+    .collect::<Result<Vec<(Vec<PayoutItem>, PayoutCalculation)>, DbError>>()?
+    .into_iter()
+    .unzip();
+
+    let payout_items = vec_pitems
+    .into_iter()
     .flatten()
-    .filter(|pItem: &PayoutItem| pItem.amount > 0)
-    .collect::<Vec<PayoutItem>>()
+    .filter(is_booked_payout_item)
+    .collect::<Vec<PayoutItem>>();
+
+    Ok((payout_items, payout_calculations))
+}
+
+
+/// Drops payout items for 0. Negative PLATFORM items book the cost of platform
+/// promotions, and negative STORE items the seller-funded discounts which cost
+/// more than the seller earned, so both are kept and netted in the payout.
+pub fn is_booked_payout_item(pitem: &PayoutItem) -> bool {
+    match (pitem.amount, &pitem.payee_type) {
+        (a, _) if a > 0 => true,
+        (a, PayeeType::PLATFORM) if a < 0 => true,
+        (a, PayeeType::STORE) if a < 0 => {
+            warn!(
+                "seller-funded discounts exceed earnings for orderItem {:?}, store {} owes {}",
+                pitem.order_item_id, pitem.payee_id, -a
+            );
+            true
+        },
+        _ => false,
+    }
 }
 
 
@@ -200,3 +365,28 @@ impl PayoutSplitSellerAndAffiliate {
     }
 }
 
+
+
+
+#[test]
+fn keeps_negative_store_and_platform_payout_items() {
+
+    let created_at = chrono::NaiveDateTime::from_timestamp(1_584_424_582, 0);
+    let pitem = |payee_type: PayeeType, amount: i32| PayoutItem::new(
+        String::from("oitem_123"),
+        String::from("payee_123"),
+        Some(payee_type),
+        amount,
+        0,
+        created_at,
+        String::from("USD"),
+        String::from("txn_123"),
+    );
+
+    assert_eq!(is_booked_payout_item(&pitem(PayeeType::STORE, 100)), true);
+    // seller-funded discount larger than the seller's earnings
+    assert_eq!(is_booked_payout_item(&pitem(PayeeType::STORE, -100)), true);
+    assert_eq!(is_booked_payout_item(&pitem(PayeeType::PLATFORM, -100)), true);
+    assert_eq!(is_booked_payout_item(&pitem(PayeeType::BUYER_AFFILIATE, -100)), false);
+    assert_eq!(is_booked_payout_item(&pitem(PayeeType::STORE, 0)), false);
+}
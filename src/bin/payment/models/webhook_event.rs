@@ -0,0 +1,229 @@
+use diesel::prelude::*;
+use gm::db::schema::webhook_events;
+
+use diesel::deserialize::FromSql;
+use diesel::pg::Pg;
+use diesel::serialize::{Output, ToSql};
+use diesel::sql_types::Text;
+use std::str::FromStr;
+
+
+/// Failed events are retried up to this many attempts, then abandoned
+/// until an admin replays them.
+pub const WEBHOOK_EVENT_MAX_ATTEMPTS: i32 = 8;
+/// Wait before the first retry, doubled after every failed attempt
+const WEBHOOK_EVENT_RETRY_BASE_SECS: i64 = 60;
+const WEBHOOK_EVENT_RETRY_MAX_SECS: i64 = 6 * 60 * 60;
+/// Events still RECEIVED this long after they arrived, or still PROCESSING
+/// this long after they were claimed, were never finished, e.g. the service
+/// stopped mid-request, so they are retried too
+pub const WEBHOOK_EVENT_STALE_SECS: i64 = 10 * 60;
+
+
+/// A webhook event as received from Stripe or Paypal, written before it is
+/// processed, so failed events can be retried and any event replayed.
+#[serde(rename_all = "camelCase")]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Queryable, Insertable)]
+#[table_name = "webhook_events"]
+pub struct WebhookEvent {
+    pub id: String,
+    pub provider: WebhookProvider,
+    /// The provider's event id, unique per provider
+    pub event_id: String,
+    pub event_type: String,
+    /// Raw request body, as signed by the provider
+    pub payload: String,
+    pub received_at: chrono::NaiveDateTime,
+    pub processing_status: WebhookEventStatus,
+    pub attempts: i32,
+    pub last_error: Option<String>,
+    pub next_attempt_at: Option<chrono::NaiveDateTime>,
+    pub processed_at: Option<chrono::NaiveDateTime>,
+}
+
+impl WebhookEvent {
+    pub fn new(
+        provider: WebhookProvider,
+        event_id: String,
+        event_type: String,
+        payload: String,
+        received_at: chrono::NaiveDateTime,
+    ) -> Self {
+        Self {
+            id: format!("whevt_{}", uuid::Uuid::new_v4().to_string()),
+            provider: provider,
+            event_id: event_id,
+            event_type: event_type,
+            payload: payload,
+            received_at: received_at,
+            processing_status: WebhookEventStatus::RECEIVED,
+            attempts: 0,
+            last_error: None,
+            next_attempt_at: None,
+            processed_at: None,
+        }
+    }
+
+    pub fn set_processed(self, processed_at: chrono::NaiveDateTime) -> Self {
+        Self {
+            processing_status: WebhookEventStatus::PROCESSED,
+            attempts: self.attempts + 1,
+            last_error: None,
+            next_attempt_at: None,
+            processed_at: Some(processed_at),
+            ..self
+        }
+    }
+
+    /// Schedules the next retry with exponential backoff,
+    /// or abandons the event after WEBHOOK_EVENT_MAX_ATTEMPTS.
+    pub fn set_failed(self, error: String, failed_at: chrono::NaiveDateTime) -> Self {
+        let attempts = self.attempts + 1;
+        match attempts >= WEBHOOK_EVENT_MAX_ATTEMPTS {
+            true => Self {
+                processing_status: WebhookEventStatus::ABANDONED,
+                attempts: attempts,
+                last_error: Some(error),
+                next_attempt_at: None,
+                ..self
+            },
+            false => Self {
+                processing_status: WebhookEventStatus::FAILED,
+                attempts: attempts,
+                last_error: Some(error),
+                next_attempt_at: Some(
+                    failed_at + chrono::Duration::seconds(webhook_retry_backoff_secs(attempts))
+                ),
+                ..self
+            },
+        }
+    }
+}
+
+/// Seconds to wait before retrying an event which failed `attempts` times
+pub fn webhook_retry_backoff_secs(attempts: i32) -> i64 {
+    let exponent = std::cmp::max(attempts - 1, 0) as u32;
+    std::cmp::min(
+        WEBHOOK_EVENT_RETRY_BASE_SECS.saturating_mul(2i64.saturating_pow(exponent)),
+        WEBHOOK_EVENT_RETRY_MAX_SECS,
+    )
+}
+
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(AsExpression, FromSqlRow)]
+#[sql_type = "Text"]
+pub enum WebhookProvider {
+    STRIPE,
+    PAYPAL,
+}
+impl WebhookProvider {
+    pub fn as_string(&self) -> String {
+        String::from(format!("{:?}", &self))
+    }
+}
+impl ToSql<Text, Pg> for WebhookProvider {
+    fn to_sql<W: std::io::Write>(&self, out: &mut Output<W, Pg>) -> ::diesel::serialize::Result {
+        let provider = self.as_string();
+        ToSql::<Text, Pg>::to_sql(&provider, out)
+    }
+}
+impl FromSql<Text, Pg> for WebhookProvider {
+    fn from_sql(maybe_bytes: Option<&[u8]>) -> diesel::deserialize::Result<Self> {
+        let provider = <String as FromSql<Text, Pg>>::from_sql(maybe_bytes)?;
+        WebhookProvider::from_str(&provider).map_err(|e| e.into())
+    }
+}
+impl FromStr for WebhookProvider {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "STRIPE" => Ok(WebhookProvider::STRIPE),
+            "PAYPAL" => Ok(WebhookProvider::PAYPAL),
+            _ => Err(format!("Invalid WebhookProvider: {}", s)),
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(AsExpression, FromSqlRow)]
+#[sql_type = "Text"]
+pub enum WebhookEventStatus {
+    /// Written, not processed yet. Retried after WEBHOOK_EVENT_STALE_SECS
+    RECEIVED,
+    /// Claimed by a request or the retry scheduler, next_attempt_at is when
+    /// the claim goes stale
+    PROCESSING,
+    PROCESSED,
+    /// Failed, will be retried at next_attempt_at
+    FAILED,
+    /// Failed WEBHOOK_EVENT_MAX_ATTEMPTS times, only replayed by admins
+    ABANDONED,
+}
+impl WebhookEventStatus {
+    pub fn as_string(&self) -> String {
+        String::from(format!("{:?}", &self))
+    }
+}
+impl ToSql<Text, Pg> for WebhookEventStatus {
+    fn to_sql<W: std::io::Write>(&self, out: &mut Output<W, Pg>) -> ::diesel::serialize::Result {
+        let status = self.as_string();
+        ToSql::<Text, Pg>::to_sql(&status, out)
+    }
+}
+impl FromSql<Text, Pg> for WebhookEventStatus {
+    fn from_sql(maybe_bytes: Option<&[u8]>) -> diesel::deserialize::Result<Self> {
+        let status = <String as FromSql<Text, Pg>>::from_sql(maybe_bytes)?;
+        WebhookEventStatus::from_str(&status).map_err(|e| e.into())
+    }
+}
+impl FromStr for WebhookEventStatus {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "RECEIVED" => Ok(WebhookEventStatus::RECEIVED),
+            "PROCESSING" => Ok(WebhookEventStatus::PROCESSING),
+            "PROCESSED" => Ok(WebhookEventStatus::PROCESSED),
+            "FAILED" => Ok(WebhookEventStatus::FAILED),
+            "ABANDONED" => Ok(WebhookEventStatus::ABANDONED),
+            _ => Err(format!("Invalid WebhookEventStatus: {}", s)),
+        }
+    }
+}
+
+
+
+#[test]
+fn retries_failed_webhook_events_with_backoff() {
+
+    let received_at = chrono::NaiveDateTime::from_timestamp(1_500_000_000, 0);
+    let event = WebhookEvent::new(
+        WebhookProvider::STRIPE,
+        String::from("evt_123"),
+        String::from("charge.refunded"),
+        String::from("{}"),
+        received_at,
+    );
+
+    let failed = event.set_failed(String::from("db down"), received_at);
+    assert_eq!(failed.processing_status, WebhookEventStatus::FAILED);
+    assert_eq!(failed.attempts, 1);
+    assert_eq!(failed.next_attempt_at, Some(received_at + chrono::Duration::seconds(60)));
+
+    let failed = failed.set_failed(String::from("db down"), received_at);
+    assert_eq!(failed.next_attempt_at, Some(received_at + chrono::Duration::seconds(120)));
+
+    assert_eq!(webhook_retry_backoff_secs(20), 6 * 60 * 60);
+
+    let abandoned = (2..WEBHOOK_EVENT_MAX_ATTEMPTS)
+        .fold(failed, |e, _| e.set_failed(String::from("db down"), received_at));
+    assert_eq!(abandoned.processing_status, WebhookEventStatus::ABANDONED);
+    assert_eq!(abandoned.attempts, WEBHOOK_EVENT_MAX_ATTEMPTS);
+    assert_eq!(abandoned.next_attempt_at, None);
+
+    let processed = abandoned.set_processed(received_at);
+    assert_eq!(processed.processing_status, WebhookEventStatus::PROCESSED);
+    assert_eq!(processed.last_error, None);
+}
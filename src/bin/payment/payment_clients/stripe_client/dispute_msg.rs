@@ -0,0 +1,84 @@
+use super::awc_handlers::{
+    awc_get,
+    awc_post,
+    awc_post_body,
+};
+use super::actor::{
+    STRIPE_ENDPOINT_URL,
+    StripeClient,
+    StripeResponse,
+};
+
+///////// Actor Implementation /////////
+use actix::{Addr, Handler, Context, Message};
+use actix::prelude::{ ResponseActFuture, WrapFuture };
+use actix_web::{ Error };
+use std::sync::Arc;
+
+use std::boxed::Box;
+use std::pin::Pin;
+use serde_derive::{Deserialize, Serialize};
+use serde::de::DeserializeOwned;
+use crate::models::{ StripeError, ErrJson };
+
+
+use gm::models::stripe::{
+    DisputeUpdateParams,
+    Dispute,
+};
+
+type DisputeId = String;
+
+/// https://stripe.com/docs/api/disputes
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum DisputeMsg {
+    /// Retrieves the dispute with the given ID.
+    /// For more details see https://stripe.com/docs/api/disputes/retrieve.
+    Retrieve(DisputeId),
+    /// Submits or stages evidence to respond to a dispute.
+    /// For more details see https://stripe.com/docs/api/disputes/update.
+    Update(DisputeId, DisputeUpdateParams),
+    /// Accepts the dispute as lost, without responding.
+    /// For more details see https://stripe.com/docs/api/disputes/close.
+    Close(DisputeId),
+}
+
+
+impl Message for DisputeMsg {
+    type Result = StripeResponse<Dispute>;
+}
+
+impl Handler<DisputeMsg> for StripeClient {
+
+    type Result = ResponseActFuture<Self, StripeResponse<Dispute>>;
+
+    fn handle(
+        &mut self,
+        msg: DisputeMsg,
+        _ctx: &mut Context<Self>
+    ) -> Self::Result {
+
+        let http_client = Arc::clone(&self.client);
+
+        Box::pin(async move {
+            match msg {
+                DisputeMsg::Retrieve(dispute_id) => {
+                    awc_get(http_client,
+                        &format!("/disputes/{}", dispute_id),
+                    ).await
+                },
+                DisputeMsg::Update(dispute_id, body) => {
+                    awc_post_body(http_client,
+                        &format!("/disputes/{}", dispute_id),
+                        body
+                    ).await
+                },
+                DisputeMsg::Close(dispute_id) => {
+                    awc_post(http_client,
+                        &format!("/disputes/{}/close", dispute_id),
+                    ).await
+                },
+            }
+        }.into_actor(self))
+    }
+}
@@ -6,6 +6,7 @@ mod payment_intent_msg;
 mod payment_method_msg;
 mod customer_msg;
 mod refund_msg;
+mod dispute_msg;
 mod list_msg;
 mod tests;
 
@@ -16,5 +17,6 @@ pub use payment_intent_msg::PaymentIntentMsg;
 pub use payment_method_msg::PaymentMethodMsg;
 pub use customer_msg::CustomerMsg;
 pub use refund_msg::RefundMsg;
+pub use dispute_msg::DisputeMsg;
 pub use list_msg::ListMsg;
 
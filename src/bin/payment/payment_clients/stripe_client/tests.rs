@@ -48,7 +48,7 @@ fn serde_sq_encodes_payment_intent_properl() {
         confirm: Some(false), // Do not try auto-confirm. Do in 2nd step
         confirmation_method: Some("automatic".to_string()),
         description: None,
-        // metadata: None,
+        metadata: None,
         // on_behalf_of: None,
         // receipt_email: None,
         // return_url: None,
@@ -103,7 +103,7 @@ async fn serde_qs_encodes_and_calls_stripe() {
                 confirm: Some(false), // Do not try auto-confirm. Do in 2nd step
                 confirmation_method: Some("automatic".to_string()),
                 description: None,
-                // metadata: None,
+                metadata: None,
                 // on_behalf_of: None,
                 // receipt_email: None,
                 // return_url: None,
@@ -2,6 +2,9 @@ use crate::models::{
     PayoutSplit,
     PayoutItem,
     PayoutDealType,
+    FeeOverride,
+    OrderItemDiscount,
+    DiscountFunding,
 };
 
 /// Fallback Variables
@@ -26,6 +29,54 @@ pub static BUYER_AFFILIATE_FEE_PERCENTAGE: f64 = 0.25;
 pub static MAX_BUYER_AFFILIATE_FEE_PERCENTAGE: f64 = 0.5;
 // (the largest custom buyer affiliate deal we can offer)
 
+pub static DEFAULT_UPLINE_REFERRAL_FEE_PERCENTAGES: [f64; 2] = [0.01, 0.005];
+// rates for the referrers of a seller affiliate, walking up the referrer_id chain.
+// level 2 gets 1%, level 3 gets 0.5%. Set UPLINE_REFERRAL_FEE_PERCENTAGES
+// (comma separated, nearest level first) to change them or go deeper.
+// (level 1 is the seller affiliate, who gets their own PayoutSplit.rate)
+const UPLINE_REFERRAL_FEE_PERCENTAGES_ENV: &str = "UPLINE_REFERRAL_FEE_PERCENTAGES";
+pub static MAX_REFERRAL_FEE_PERCENTAGE: f64 = PLATFORM_FEE_PERCENTAGE;
+// cap on the total take of the seller affiliate and their upline,
+// which all comes out of the platform's share
+
+lazy_static! {
+    pub static ref UPLINE_REFERRAL_FEE_PERCENTAGES: Vec<f64> = upline_referral_fee_percentages();
+}
+
+/// Per-level upline rates from env, or the defaults if unset or invalid
+fn upline_referral_fee_percentages() -> Vec<f64> {
+    dotenv::dotenv().ok();
+    match std::env::var(UPLINE_REFERRAL_FEE_PERCENTAGES_ENV) {
+        Err(_) => DEFAULT_UPLINE_REFERRAL_FEE_PERCENTAGES.to_vec(),
+        Ok(s) => parse_upline_referral_fee_percentages(&s).unwrap_or_else(|e| {
+            warn!("{}, using the default upline rates", e);
+            DEFAULT_UPLINE_REFERRAL_FEE_PERCENTAGES.to_vec()
+        }),
+    }
+}
+
+/// Parses comma separated upline rates. They are paid out of the platform's
+/// share, so they must add up to less than PLATFORM_FEE_PERCENTAGE.
+pub fn parse_upline_referral_fee_percentages(s: &str) -> Result<Vec<f64>, String> {
+    let rates = s.split(",")
+        .map(|r| r.trim())
+        .filter(|r| !r.is_empty())
+        .map(|r| match r.parse::<f64>() {
+            Ok(rate) if rate.is_finite() && rate >= 0.0 => Ok(rate),
+            _ => Err(format!("Invalid {}: {}", UPLINE_REFERRAL_FEE_PERCENTAGES_ENV, r)),
+        })
+        .collect::<Result<Vec<f64>, String>>()?;
+
+    let total_rate = rates.iter().sum::<f64>();
+    if total_rate >= PLATFORM_FEE_PERCENTAGE {
+        return Err(format!(
+            "{} add up to {}, which is not less than PLATFORM_FEE_PERCENTAGE {}",
+            UPLINE_REFERRAL_FEE_PERCENTAGES_ENV, total_rate, PLATFORM_FEE_PERCENTAGE
+        ))
+    }
+    Ok(rates)
+}
+
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct PaymentFees {
@@ -61,6 +112,12 @@ pub fn calculate_platform_fees(
     seller_payout_split: Option<PayoutSplit>,
     buyer_aff_payout_split: Option<PayoutSplit>,
     seller_aff_payout_split: Option<PayoutSplit>,
+    upline_aff_payout_splits: Vec<PayoutSplit>,
+    // referrers of the seller affiliate, nearest first
+    fee_override: Option<FeeOverride>,
+    // per-product or per-category fee, takes precedence over seller's PayoutSplit
+    funded_discounts: FundedDiscounts,
+    // discounts already taken off the subtotal, and who pays for them
     created_at: Option<chrono::NaiveDateTime>,
     // set by frenzy as mock_date or
     // read from Stripe.PaymentIntent.createdAt
@@ -75,22 +132,139 @@ pub fn calculate_platform_fees(
     // then calculate the fee for seller to pay
     // 3.6% of subtotal, plus 30c per transaction
 
-    let seller_rate = check_rate_expiry_for_seller(seller_payout_split, created_at);
-    let buyer_aff_rate = check_rate_expiry_for_affiliate(buyer_aff_payout_split, created_at);
-    let seller_aff_rate = check_rate_expiry_for_affiliate(seller_aff_payout_split, created_at);
+    let AppliedRates {
+        seller_rate,
+        buyer_aff_rate,
+        seller_aff_rate,
+        upline_aff_rates,
+    } = resolve_applied_rates(
+        subtotal,
+        &funded_discounts,
+        seller_payout_split,
+        buyer_aff_payout_split,
+        seller_aff_payout_split,
+        upline_aff_payout_splits,
+        fee_override,
+        created_at,
+    );
     debug!("Seller rate: {}", seller_rate);
     debug!("Seller Affiliate rate: {}", seller_aff_rate);
+    debug!("Upline Affiliate rates: {:?}", upline_aff_rates);
     debug!("Buyer Affiliate rate: {}", buyer_aff_rate);
 
-    generate_earnings_from_payout_splits(
+    generate_earnings_with_discounts(
+        subtotal,
+        payment_processing_fee,
+        AppliedRates {
+            seller_rate,
+            buyer_aff_rate,
+            seller_aff_rate,
+            upline_aff_rates,
+        },
+        funded_discounts,
+    )
+}
+
+/// Splits the undiscounted price as usual, then charges each
+/// discount to whoever funds it.
+/// Also used to recompute a PayoutCalculation from its stored inputs.
+pub fn generate_earnings_with_discounts(
+    subtotal: i32,
+    payment_processing_fee: i32,
+    applied_rates: AppliedRates,
+    funded_discounts: FundedDiscounts,
+) -> CalculatedEarnings {
+
+    let FundedDiscounts {
+        platform_funded,
+        seller_funded,
+    } = funded_discounts;
+    debug!("Platform funded discounts: {}", platform_funded);
+    debug!("Seller funded discounts: {}", seller_funded);
+
+    let earnings = generate_earnings_from_payout_splits(
         GenerateEarningsInput {
-            subtotal: subtotal,
+            subtotal: subtotal + platform_funded + seller_funded,
             payment_processing_fee: payment_processing_fee,
-            seller_rate: seller_rate,
-            buyer_aff_rate: buyer_aff_rate,
-            seller_aff_rate: seller_aff_rate,
+            seller_rate: applied_rates.seller_rate,
+            buyer_aff_rate: applied_rates.buyer_aff_rate,
+            seller_aff_rate: applied_rates.seller_aff_rate,
+            upline_aff_rates: applied_rates.upline_aff_rates,
         }
-    )
+    );
+
+    CalculatedEarnings {
+        seller_earnings_less_payment_fee: earnings.seller_earnings_less_payment_fee - seller_funded,
+        // may be negative if a platform promotion costs more than the platform fee
+        gm_earnings: earnings.gm_earnings - platform_funded,
+        platform_promo_cost: platform_funded,
+        ..earnings
+    }
+}
+
+/// Discount totals (in cents) for one orderItem, by who pays for them
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct FundedDiscounts {
+    pub platform_funded: i32,
+    pub seller_funded: i32,
+}
+
+impl FundedDiscounts {
+    pub fn from_discounts(discounts: &Vec<OrderItemDiscount>) -> Self {
+        discounts.iter().fold(
+            FundedDiscounts::default(),
+            |acc, d| {
+                let platform_part = match d.funded_by {
+                    DiscountFunding::PLATFORM => d.amount,
+                    DiscountFunding::SELLER => 0,
+                    DiscountFunding::SPLIT => (
+                        d.amount as f64 * d.platform_share.unwrap_or(0.5)
+                    ).round() as i32,
+                };
+                FundedDiscounts {
+                    platform_funded: acc.platform_funded + platform_part,
+                    // seller part as the remainder to prevent rounding cents
+                    seller_funded: acc.seller_funded + d.amount - platform_part,
+                }
+            }
+        )
+    }
+}
+
+/// Rates actually used to split a subtotal, after expired PayoutSplits
+/// fall back to defaults and fee overrides are applied.
+#[serde(rename_all = "camelCase")]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AppliedRates {
+    pub seller_rate: f64,
+    pub buyer_aff_rate: f64,
+    pub seller_aff_rate: f64,
+    pub upline_aff_rates: Vec<f64>,
+}
+
+/// Rates are applied to the undiscounted price (see generate_earnings_with_discounts),
+/// so fee override caps are converted into rates against it too.
+pub fn resolve_applied_rates(
+    subtotal: i32,
+    funded_discounts: &FundedDiscounts,
+    seller_payout_split: Option<PayoutSplit>,
+    buyer_aff_payout_split: Option<PayoutSplit>,
+    seller_aff_payout_split: Option<PayoutSplit>,
+    upline_aff_payout_splits: Vec<PayoutSplit>,
+    fee_override: Option<FeeOverride>,
+    created_at: Option<chrono::NaiveDateTime>,
+) -> AppliedRates {
+    AppliedRates {
+        seller_rate: apply_fee_override(
+            check_rate_expiry_for_seller(seller_payout_split, created_at),
+            fee_override,
+            subtotal + funded_discounts.platform_funded + funded_discounts.seller_funded,
+            created_at,
+        ),
+        buyer_aff_rate: check_rate_expiry_for_affiliate(buyer_aff_payout_split, created_at),
+        seller_aff_rate: check_rate_expiry_for_affiliate(seller_aff_payout_split, created_at),
+        upline_aff_rates: check_rate_expiry_for_upline(upline_aff_payout_splits, created_at),
+    }
 }
 
 fn check_rate_expiry_for_seller(
@@ -107,6 +281,11 @@ fn check_rate_expiry_for_seller(
     match payout_split {
         // no payout_split, revert to default platform fee for seller
         None => 1.0 - PLATFORM_FEE_PERCENTAGE,
+        // payout_split scheduled for later, revert to default platform fee
+        Some(ref ps) if ps.starts_at.map(|start| start > now).unwrap_or(false) => {
+            debug!("PayoutSplit starts at: {:?}, not valid yet", ps.starts_at);
+            1.0 - PLATFORM_FEE_PERCENTAGE
+        },
         Some(ps) => match ps.expires_at {
             None => ps.rate, // payout_split with no expiry, use it
             Some(exp) => {
@@ -124,6 +303,49 @@ fn check_rate_expiry_for_seller(
 }
 
 
+/// Replaces the seller's rate with the FeeOverride's platform fee rate,
+/// then caps the platform fee at max_platform_fee (in cents) if set.
+/// subtotal is the price the rate is applied to, before discounts.
+/// Expired overrides are ignored.
+fn apply_fee_override(
+    seller_rate: f64,
+    fee_override: Option<FeeOverride>,
+    subtotal: i32,
+    created_at: Option<chrono::NaiveDateTime>,
+) -> f64 {
+
+    let now: chrono::NaiveDateTime = match created_at {
+        Some(date) => date,
+        None => chrono::NaiveDateTime::from_timestamp(
+                    chrono::Utc::now().timestamp(), 0)
+    };
+
+    match fee_override {
+        None => seller_rate,
+        Some(fo) => {
+            if fo.is_expired(&now) {
+                return seller_rate
+            }
+            debug!("Applying FeeOverride: {:?}", fo.id);
+
+            let overridden_rate = match fo.platform_fee_rate {
+                Some(platform_rate) => 1.0 - platform_rate,
+                None => seller_rate,
+            };
+
+            match fo.max_platform_fee {
+                Some(max_fee) if subtotal > 0 => {
+                    // a lower platform fee means a higher seller rate
+                    let min_seller_rate = 1.0 - (max_fee as f64 / subtotal as f64);
+                    overridden_rate.max(min_seller_rate)
+                },
+                _ => overridden_rate,
+            }
+        }
+    }
+}
+
+
 fn check_rate_expiry_for_affiliate(
     payout_split: Option<PayoutSplit>,
     created_at: Option<chrono::NaiveDateTime>,
@@ -138,6 +360,11 @@ fn check_rate_expiry_for_affiliate(
     match payout_split {
         // no payout_split, affiliate gets nothing by default
         None => 0.0,
+        // payout_split scheduled for later, affiliate gets nothing yet
+        Some(ref ps) if ps.starts_at.map(|start| start > now).unwrap_or(false) => {
+            debug!("PayoutSplit starts at: {:?}, not valid yet", ps.starts_at);
+            0.0
+        },
         Some(ps) => match ps.expires_at {
             None => ps.rate, // payout_split with no expiry, use it
             Some(exp) => {
@@ -154,20 +381,51 @@ fn check_rate_expiry_for_affiliate(
     }
 }
 
+/// Upline referrers get the rate for their level, not their PayoutSplit.rate.
+/// An expired, retired (or not yet started) PayoutSplit earns nothing,
+/// but the chain carries on above it.
+fn check_rate_expiry_for_upline(
+    upline_payout_splits: Vec<PayoutSplit>,
+    created_at: Option<chrono::NaiveDateTime>,
+) -> Vec<f64> {
+
+    let now: chrono::NaiveDateTime = match created_at {
+        Some(date) => date,
+        None => chrono::NaiveDateTime::from_timestamp(
+                    chrono::Utc::now().timestamp(), 0)
+    };
+
+    upline_payout_splits
+        .iter()
+        .zip(UPLINE_REFERRAL_FEE_PERCENTAGES.iter())
+        .map(|(ps, level_rate)| match ps.is_active_at(&now) {
+            true => *level_rate,
+            false => 0.0,
+        })
+        .collect::<Vec<f64>>()
+}
+
 pub struct GenerateEarningsInput {
     pub subtotal: i32,
     pub payment_processing_fee: i32,
     pub seller_rate: f64,
     pub buyer_aff_rate: f64,
     pub seller_aff_rate: f64,
+    pub upline_aff_rates: Vec<f64>,
 }
 
+#[serde(rename_all = "camelCase")]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct CalculatedEarnings {
     pub seller_earnings_less_payment_fee: i32,
     pub payment_processing_fee: i32,
     pub gm_earnings: i32,
     pub buyer_affiliate_earnings: i32,
     pub seller_affiliate_earnings: i32,
+    /// Earnings of the seller affiliate's referrers, nearest first
+    pub upline_affiliate_earnings: Vec<i32>,
+    /// Platform funded discounts, already taken out of gm_earnings
+    pub platform_promo_cost: i32,
 }
 
 
@@ -200,35 +458,40 @@ pub fn generate_earnings_from_payout_splits(
     let seller_earnings = (subtotal.clone() as f64 * seller_rate).ceil();
     let relay_share = subtotal.clone() as f64 - seller_earnings;
 
-    // 2. split relay's share into 2 for seller affiliates
+    // 2. split relay's share for the seller affiliate and their upline referrers
     // NOTE: This is just to support legacy program.
     // (usually 100% of this share stays with relay)
-    let (
-      gm_earnings,
-      seller_aff_earnings,
-    ) = match seller_aff_rate {
-        x if x <= PLATFORM_FEE_PERCENTAGE => {
-
-            // 0.05 / 0.15 = 1/3. Seller affiliate get 5% of the subtotal,
-            // which is 33% of gm-platform's 15% earnings
-            // Calculate affiliate earnings as a percentage (33%) of relay's share
-            let seller_aff_earnings = (
-                seller_aff_rate * relay_share.clone() / PLATFORM_FEE_PERCENTAGE
-            ).round() as i32;
-
-            // calculate gm-platform-fee as the remainder to prevent rounding cents
-            let gm_earnings: i32 = (relay_share.clone() as i32) - seller_aff_earnings;
-            (gm_earnings, seller_aff_earnings)
-        },
-        // assert that affiliate take rate never exceeds 100% of our share
-        _ => {
-            // if fee exceeds our share, set gm fee as 0.
-            let gm_earnings = 0 as i32;
-            let seller_aff_earnings = relay_share.clone() as i32;
-            (gm_earnings, seller_aff_earnings)
-        }
+    //
+    // If the combined referral rate exceeds the cap, scale every level down
+    // proportionally, so the cap is never exceeded.
+    let total_referral_rate = seller_aff_rate + g.upline_aff_rates.iter().sum::<f64>();
+    let referral_scale = match total_referral_rate {
+        x if x > MAX_REFERRAL_FEE_PERCENTAGE => MAX_REFERRAL_FEE_PERCENTAGE / x,
+        _ => 1.0,
     };
 
+    // 0.05 / 0.15 = 1/3. Seller affiliate get 5% of the subtotal,
+    // which is 33% of gm-platform's 15% earnings
+    // Calculate affiliate earnings as a percentage (33%) of relay's share
+    let mut relay_remaining = relay_share.clone() as i32;
+    let mut referral_earnings = std::iter::once(seller_aff_rate)
+        .chain(g.upline_aff_rates.clone().into_iter())
+        .map(|rate| {
+            let earnings = (
+                rate * referral_scale * relay_share.clone() / PLATFORM_FEE_PERCENTAGE
+            ).round() as i32;
+            // guard against rounding cents past relay's share
+            let earnings = earnings.min(relay_remaining).max(0);
+            relay_remaining -= earnings;
+            earnings
+        })
+        .collect::<Vec<i32>>();
+
+    let seller_aff_earnings = referral_earnings.remove(0);
+    let upline_aff_earnings = referral_earnings;
+    // calculate gm-platform-fee as the remainder to prevent rounding cents
+    let gm_earnings: i32 = relay_remaining;
+
     // 3. split seller's share into 2 for buyer affiliates...
 
     // Calculate the max rate a buyer affiliate can get, given that we may have custom
@@ -248,6 +511,8 @@ pub fn generate_earnings_from_payout_splits(
         gm_earnings: gm_earnings,
         buyer_affiliate_earnings: buyer_aff_earnings,
         seller_affiliate_earnings: seller_aff_earnings,
+        upline_affiliate_earnings: upline_aff_earnings,
+        platform_promo_cost: 0,
     }
 
     // OLD WAY BELOW FYI (when affiliate takings came out of platform fees)
@@ -363,8 +628,10 @@ mod tests {
             payment_processing_fee: _,
             gm_earnings,
             seller_affiliate_earnings,
-            buyer_affiliate_earnings
-        } = calculate_platform_fees(1000, 0, None, None, None, None);
+            buyer_affiliate_earnings,
+            upline_affiliate_earnings: _,
+            platform_promo_cost: _,
+        } = calculate_platform_fees(1000, 0, None, None, None, vec![], None, FundedDiscounts::default(), None);
 
         assert_eq!(seller_earnings_less_payment_fee, 784);
         assert_eq!(gm_earnings, 150);
@@ -381,8 +648,10 @@ mod tests {
             payment_processing_fee: _,
             gm_earnings,
             seller_affiliate_earnings,
-            buyer_affiliate_earnings
-        } = calculate_platform_fees(2000, 0, None, None, None, None);
+            buyer_affiliate_earnings,
+            upline_affiliate_earnings: _,
+            platform_promo_cost: _,
+        } = calculate_platform_fees(2000, 0, None, None, None, vec![], None, FundedDiscounts::default(), None);
 
         assert_eq!(seller_earnings_less_payment_fee, 1598);
         assert_eq!(gm_earnings, 300);
@@ -401,8 +670,10 @@ mod tests {
             payment_processing_fee: _,
             gm_earnings,
             seller_affiliate_earnings,
-            buyer_affiliate_earnings
-        } = calculate_platform_fees(subtotal, 0, None, None, None, None);
+            buyer_affiliate_earnings,
+            upline_affiliate_earnings: _,
+            platform_promo_cost: _,
+        } = calculate_platform_fees(subtotal, 0, None, None, None, vec![], None, FundedDiscounts::default(), None);
 
         assert_eq!(seller_earnings_less_payment_fee, 1073);
         assert_eq!(gm_earnings, 203);
@@ -421,6 +692,7 @@ mod tests {
           String::from("store_test1"),
           PayoutDealType::BUYER_AFFILIATE,
           None,
+          None,
           0.25,
           None,
         ));
@@ -429,8 +701,10 @@ mod tests {
             payment_processing_fee: _,
             gm_earnings,
             seller_affiliate_earnings,
-            buyer_affiliate_earnings
-        } = calculate_platform_fees(subtotal, 0, None, buyer_aff, None, None);
+            buyer_affiliate_earnings,
+            upline_affiliate_earnings: _,
+            platform_promo_cost: _,
+        } = calculate_platform_fees(subtotal, 0, None, buyer_aff, None, vec![], None, FundedDiscounts::default(), None);
 
         assert_eq!(gm_earnings, 351);
         assert_eq!(seller_affiliate_earnings, 0);
@@ -449,6 +723,7 @@ mod tests {
           String::from("store_test1"),
           PayoutDealType::BUYER_AFFILIATE,
           None,
+          None,
           0.25,
           None,
         ));
@@ -456,6 +731,7 @@ mod tests {
             String::from("store_test1"),
             PayoutDealType::SELLER_AFFILIATE,
             None,
+            None,
             0.05,
             None,
           ));
@@ -464,8 +740,10 @@ mod tests {
             payment_processing_fee: _,
             gm_earnings,
             seller_affiliate_earnings,
-            buyer_affiliate_earnings
-        } = calculate_platform_fees(subtotal, 0, None, buyer_aff, seller_aff, None);
+            buyer_affiliate_earnings,
+            upline_affiliate_earnings: _,
+            platform_promo_cost: _,
+        } = calculate_platform_fees(subtotal, 0, None, buyer_aff, seller_aff, vec![], None, FundedDiscounts::default(), None);
 
         assert_eq!(gm_earnings, 234);
         assert_eq!(seller_affiliate_earnings, 117);
@@ -484,6 +762,7 @@ mod tests {
           String::from("store_test1"),
           PayoutDealType::BUYER_AFFILIATE,
           None,
+          None,
           0.2,
           None,
         ));
@@ -492,8 +771,10 @@ mod tests {
             payment_processing_fee: _,
             gm_earnings,
             seller_affiliate_earnings,
-            buyer_affiliate_earnings
-        } = calculate_platform_fees(subtotal, 0, None, buyer_aff, None, None);
+            buyer_affiliate_earnings,
+            upline_affiliate_earnings: _,
+            platform_promo_cost: _,
+        } = calculate_platform_fees(subtotal, 0, None, buyer_aff, None, vec![], None, FundedDiscounts::default(), None);
 
         assert_eq!(gm_earnings, 351);
         assert_eq!(seller_affiliate_earnings, 0);
@@ -512,6 +793,7 @@ mod tests {
           String::from("store_test1"),
           PayoutDealType::BUYER_AFFILIATE,
           None,
+          None,
           0.3,
           None,
         ));
@@ -520,8 +802,10 @@ mod tests {
             payment_processing_fee: _,
             gm_earnings,
             seller_affiliate_earnings,
-            buyer_affiliate_earnings
-        } = calculate_platform_fees(subtotal, 0, None, buyer_aff, None, None);
+            buyer_affiliate_earnings,
+            upline_affiliate_earnings: _,
+            platform_promo_cost: _,
+        } = calculate_platform_fees(subtotal, 0, None, buyer_aff, None, vec![], None, FundedDiscounts::default(), None);
 
         assert_eq!(gm_earnings, 351);
         assert_eq!(seller_affiliate_earnings, 0);
@@ -540,6 +824,7 @@ mod tests {
           String::from("store_test1"),
           PayoutDealType::BUYER_AFFILIATE,
           None,
+          None,
           0.6, // clip to 0.5
           None,
         ));
@@ -548,8 +833,10 @@ mod tests {
             payment_processing_fee: _,
             gm_earnings,
             seller_affiliate_earnings,
-            buyer_affiliate_earnings
-        } = calculate_platform_fees(subtotal, 0, None, buyer_aff, None, None);
+            buyer_affiliate_earnings,
+            upline_affiliate_earnings: _,
+            platform_promo_cost: _,
+        } = calculate_platform_fees(subtotal, 0, None, buyer_aff, None, vec![], None, FundedDiscounts::default(), None);
 
         assert_eq!(gm_earnings, 351);
         assert_eq!(seller_affiliate_earnings, 0);
@@ -568,6 +855,7 @@ mod tests {
           String::from("store_test1"),
           PayoutDealType::SELLER_AFFILIATE,
           None,
+          None,
           0.3,
           None,
         ));
@@ -576,8 +864,10 @@ mod tests {
             payment_processing_fee: _,
             gm_earnings,
             seller_affiliate_earnings,
-            buyer_affiliate_earnings
-        } = calculate_platform_fees(subtotal, 0, None, None, seller_aff, None);
+            buyer_affiliate_earnings,
+            upline_affiliate_earnings: _,
+            platform_promo_cost: _,
+        } = calculate_platform_fees(subtotal, 0, None, None, seller_aff, vec![], None, FundedDiscounts::default(), None);
 
         assert_eq!(gm_earnings, 0);
         assert_eq!(seller_affiliate_earnings, 351);
@@ -586,6 +876,144 @@ mod tests {
         assert_eq!(subtotal, expectedFee + buyer_affiliate_earnings + seller_affiliate_earnings + gm_earnings + seller_earnings_less_payment_fee);
     }
 
+    #[test]
+    fn calc_splits_saff_with_upline_referrers() {
+        // seller affiliate at 5%, their referrers at 1% and 0.5%
+        // 15% platform fees
+        let subtotal = 2345; // 1994 to 351 (seller v platform portions)
+        let expectedFee = 114; // (rounded from 114.42)
+        let seller_aff = Some(PayoutSplit::new(
+            String::from("user_saff"),
+            PayoutDealType::SELLER_AFFILIATE,
+            None,
+            None,
+            0.05,
+            None,
+        ));
+        let upline = vec![
+            PayoutSplit::new(String::from("user_level2"), PayoutDealType::SELLER_AFFILIATE, None, None, 0.05, None),
+            PayoutSplit::new(String::from("user_level3"), PayoutDealType::SELLER_AFFILIATE, None, None, 0.05, None),
+        ];
+        let CalculatedEarnings {
+            seller_earnings_less_payment_fee,
+            payment_processing_fee: _,
+            gm_earnings,
+            seller_affiliate_earnings,
+            buyer_affiliate_earnings,
+            upline_affiliate_earnings,
+            platform_promo_cost: _,
+        } = calculate_platform_fees(subtotal, 0, None, None, seller_aff, upline, None, FundedDiscounts::default(), None);
+
+        assert_eq!(seller_affiliate_earnings, 117);
+        assert_eq!(upline_affiliate_earnings, vec![23, 12]);
+        assert_eq!(gm_earnings, 199);
+        assert_eq!(seller_earnings_less_payment_fee, 1880);
+        assert_eq!(subtotal, expectedFee + buyer_affiliate_earnings + seller_affiliate_earnings
+            + upline_affiliate_earnings.iter().sum::<i32>() + gm_earnings + seller_earnings_less_payment_fee);
+    }
+
+    #[test]
+    fn calc_splits_saff_with_upline_above_max_rate() {
+        // combined referral rate of 15.5% is scaled down to the 15% cap
+        let subtotal = 2345; // 1994 to 351 (seller v platform portions)
+        let expectedFee = 114; // (rounded from 114.42)
+        let seller_aff = Some(PayoutSplit::new(
+            String::from("user_saff"),
+            PayoutDealType::SELLER_AFFILIATE,
+            None,
+            None,
+            0.14,
+            None,
+        ));
+        let upline = vec![
+            PayoutSplit::new(String::from("user_level2"), PayoutDealType::SELLER_AFFILIATE, None, None, 0.05, None),
+            PayoutSplit::new(String::from("user_level3"), PayoutDealType::SELLER_AFFILIATE, None, None, 0.05, None),
+        ];
+        let CalculatedEarnings {
+            seller_earnings_less_payment_fee,
+            payment_processing_fee: _,
+            gm_earnings,
+            seller_affiliate_earnings,
+            buyer_affiliate_earnings,
+            upline_affiliate_earnings,
+            platform_promo_cost: _,
+        } = calculate_platform_fees(subtotal, 0, None, None, seller_aff, upline, None, FundedDiscounts::default(), None);
+
+        assert_eq!(seller_affiliate_earnings, 317);
+        assert_eq!(upline_affiliate_earnings, vec![23, 11]);
+        assert_eq!(gm_earnings, 0);
+        assert_eq!(subtotal, expectedFee + buyer_affiliate_earnings + seller_affiliate_earnings
+            + upline_affiliate_earnings.iter().sum::<i32>() + gm_earnings + seller_earnings_less_payment_fee);
+    }
+
+    #[test]
+    fn calc_splits_platform_funded_discount() {
+        // $10 item with a $2 platform promotion, buyer pays $8
+        // seller is paid on $10, platform pays for the promotion
+        let subtotal = 800;
+        let expectedFee = 59; // 800 * 0.036 + 30 (rounded from 58.8)
+        let discounts = FundedDiscounts::from_discounts(&vec![
+            OrderItemDiscount {
+                promo_code_id: Some(String::from("promo_123")),
+                amount: 200,
+                funded_by: DiscountFunding::PLATFORM,
+                platform_share: None,
+            }
+        ]);
+        let CalculatedEarnings {
+            seller_earnings_less_payment_fee,
+            payment_processing_fee: _,
+            gm_earnings,
+            seller_affiliate_earnings,
+            buyer_affiliate_earnings,
+            upline_affiliate_earnings: _,
+            platform_promo_cost,
+        } = calculate_platform_fees(subtotal, expectedFee, None, None, None, vec![], None, discounts, None);
+
+        assert_eq!(seller_earnings_less_payment_fee, 850 - expectedFee);
+        assert_eq!(gm_earnings, 150 - 200);
+        assert_eq!(platform_promo_cost, 200);
+        assert_eq!(subtotal, expectedFee + buyer_affiliate_earnings + seller_affiliate_earnings + gm_earnings + seller_earnings_less_payment_fee);
+    }
+
+    #[test]
+    fn calc_splits_seller_and_split_funded_discounts() {
+        // $10 item, $1 seller discount and $2 split 50/50, buyer pays $7
+        // platform still takes 15% of $10, less its $1 half of the split
+        let subtotal = 700;
+        let expectedFee = 55; // 700 * 0.036 + 30 (rounded from 55.2)
+        let discounts = FundedDiscounts::from_discounts(&vec![
+            OrderItemDiscount {
+                promo_code_id: None,
+                amount: 100,
+                funded_by: DiscountFunding::SELLER,
+                platform_share: None,
+            },
+            OrderItemDiscount {
+                promo_code_id: None,
+                amount: 200,
+                funded_by: DiscountFunding::SPLIT,
+                platform_share: Some(0.5),
+            },
+        ]);
+        assert_eq!(discounts, FundedDiscounts { platform_funded: 100, seller_funded: 200 });
+
+        let CalculatedEarnings {
+            seller_earnings_less_payment_fee,
+            payment_processing_fee: _,
+            gm_earnings,
+            seller_affiliate_earnings,
+            buyer_affiliate_earnings,
+            upline_affiliate_earnings: _,
+            platform_promo_cost,
+        } = calculate_platform_fees(subtotal, expectedFee, None, None, None, vec![], None, discounts, None);
+
+        assert_eq!(seller_earnings_less_payment_fee, 850 - 200 - expectedFee);
+        assert_eq!(gm_earnings, 150 - 100);
+        assert_eq!(platform_promo_cost, 100);
+        assert_eq!(subtotal, expectedFee + buyer_affiliate_earnings + seller_affiliate_earnings + gm_earnings + seller_earnings_less_payment_fee);
+    }
+
     #[test]
     fn calc_splits_min_order_no_baff() {
         // buyer affiliate low rate
@@ -597,8 +1025,10 @@ mod tests {
             payment_processing_fee: _,
             gm_earnings,
             seller_affiliate_earnings,
-            buyer_affiliate_earnings
-        } = calculate_platform_fees(subtotal, 0, None, None, None, None);
+            buyer_affiliate_earnings,
+            upline_affiliate_earnings: _,
+            platform_promo_cost: _,
+        } = calculate_platform_fees(subtotal, 0, None, None, None, vec![], None, FundedDiscounts::default(), None);
 
         assert_eq!(gm_earnings, 15);
         assert_eq!(seller_affiliate_earnings, 0);
@@ -617,6 +1047,7 @@ mod tests {
           String::from("store_test1"),
           PayoutDealType::BUYER_AFFILIATE,
           None,
+          None,
           0.25,
           None,
         ));
@@ -625,8 +1056,10 @@ mod tests {
             payment_processing_fee: _,
             gm_earnings,
             seller_affiliate_earnings,
-            buyer_affiliate_earnings
-        } = calculate_platform_fees(subtotal, 0, None, buyer_aff, None, None);
+            buyer_affiliate_earnings,
+            upline_affiliate_earnings: _,
+            platform_promo_cost: _,
+        } = calculate_platform_fees(subtotal, 0, None, buyer_aff, None, vec![], None, FundedDiscounts::default(), None);
 
         assert_eq!(gm_earnings, 15);
         assert_eq!(seller_affiliate_earnings, 0);
@@ -645,6 +1078,7 @@ mod tests {
           String::from("store_test1"),
           PayoutDealType::BUYER_AFFILIATE,
           None,
+          None,
           0.6, // above max, should clip to 0.5
           None,
         ));
@@ -653,8 +1087,10 @@ mod tests {
             payment_processing_fee: _,
             gm_earnings,
             seller_affiliate_earnings,
-            buyer_affiliate_earnings
-        } = calculate_platform_fees(subtotal, 0, None, buyer_aff, None, None);
+            buyer_affiliate_earnings,
+            upline_affiliate_earnings: _,
+            platform_promo_cost: _,
+        } = calculate_platform_fees(subtotal, 0, None, buyer_aff, None, vec![], None, FundedDiscounts::default(), None);
 
         assert_eq!(gm_earnings, 15);
         assert_eq!(seller_affiliate_earnings, 0);
@@ -663,6 +1099,104 @@ mod tests {
         assert_eq!(subtotal, expectedFee + buyer_affiliate_earnings + seller_affiliate_earnings + gm_earnings + seller_earnings_less_payment_fee);
     }
 
+    #[test]
+    fn calc_splits_with_fee_override_rate() {
+        // 10% platform fee override instead of 15%
+        let subtotal = 1000;
+        let expectedFee = 66; // 1000 * 0.036 + 30
+        let fee_override = Some(FeeOverride::new(
+            None,
+            Some(String::from("category_test1")),
+            None,
+            Some(0.10),
+            None,
+            None,
+        ));
+        let CalculatedEarnings {
+            seller_earnings_less_payment_fee,
+            payment_processing_fee: _,
+            gm_earnings,
+            seller_affiliate_earnings,
+            buyer_affiliate_earnings,
+            upline_affiliate_earnings: _,
+            platform_promo_cost: _,
+        } = calculate_platform_fees(subtotal, 0, None, None, None, vec![], fee_override, FundedDiscounts::default(), None);
+
+        assert_eq!(gm_earnings, 100);
+        assert_eq!(seller_affiliate_earnings, 0);
+        assert_eq!(buyer_affiliate_earnings, 0);
+        assert_eq!(seller_earnings_less_payment_fee, 834);
+        assert_eq!(subtotal, expectedFee + buyer_affiliate_earnings + seller_affiliate_earnings + gm_earnings + seller_earnings_less_payment_fee);
+    }
+
+    #[test]
+    fn calc_splits_with_fee_override_max_fee() {
+        // 15% platform fee, capped at $50
+        let subtotal = 100000;
+        let expectedFee = 3630; // 100000 * 0.036 + 30
+        let fee_override = Some(FeeOverride::new(
+            Some(String::from("product_test1")),
+            None,
+            None,
+            None,
+            Some(5000),
+            None,
+        ));
+        let CalculatedEarnings {
+            seller_earnings_less_payment_fee,
+            payment_processing_fee: _,
+            gm_earnings,
+            seller_affiliate_earnings,
+            buyer_affiliate_earnings,
+            upline_affiliate_earnings: _,
+            platform_promo_cost: _,
+        } = calculate_platform_fees(subtotal, 0, None, None, None, vec![], fee_override, FundedDiscounts::default(), None);
+
+        assert_eq!(gm_earnings, 5000);
+        assert_eq!(seller_affiliate_earnings, 0);
+        assert_eq!(buyer_affiliate_earnings, 0);
+        assert_eq!(seller_earnings_less_payment_fee, 91370);
+        assert_eq!(subtotal, expectedFee + buyer_affiliate_earnings + seller_affiliate_earnings + gm_earnings + seller_earnings_less_payment_fee);
+    }
+
+    #[test]
+    fn calc_splits_with_fee_override_max_fee_and_discounts() {
+        // $100 item with a $50 platform promotion, buyer pays $50.
+        // The $5 cap is on the platform's share of the $100 the split is made on.
+        let subtotal = 5000;
+        let expectedFee = 210; // 5000 * 0.036 + 30
+        let fee_override = Some(FeeOverride::new(
+            Some(String::from("product_test1")),
+            None,
+            None,
+            None,
+            Some(500),
+            None,
+        ));
+        let discounts = FundedDiscounts::from_discounts(&vec![
+            OrderItemDiscount {
+                promo_code_id: Some(String::from("promo_123")),
+                amount: 5000,
+                funded_by: DiscountFunding::PLATFORM,
+                platform_share: None,
+            }
+        ]);
+        let CalculatedEarnings {
+            seller_earnings_less_payment_fee,
+            payment_processing_fee: _,
+            gm_earnings,
+            seller_affiliate_earnings,
+            buyer_affiliate_earnings,
+            upline_affiliate_earnings: _,
+            platform_promo_cost,
+        } = calculate_platform_fees(subtotal, 0, None, None, None, vec![], fee_override, discounts, None);
+
+        assert_eq!(gm_earnings + platform_promo_cost, 500);
+        assert_eq!(platform_promo_cost, 5000);
+        assert_eq!(seller_earnings_less_payment_fee, 9500 - expectedFee);
+        assert_eq!(subtotal, expectedFee + buyer_affiliate_earnings + seller_affiliate_earnings + gm_earnings + seller_earnings_less_payment_fee);
+    }
+
     #[test]
     fn payout_split_affiliate_expired() {
 
@@ -691,6 +1225,7 @@ mod tests {
             Some(PayoutSplit::new(
                 String::from("store_test1"),
                 PayoutDealType::BUYER_AFFILIATE,
+                None,
                 Some(expiry),
                 input_rate,
                 None,
@@ -705,6 +1240,35 @@ mod tests {
         assert_eq!(expect_rate, result_rate)
     }
 
+    #[test]
+    fn payout_split_seller_not_started() {
+
+        let now = chrono::NaiveDateTime::from_timestamp(
+            chrono::Utc::now().timestamp(),
+            0,
+        );
+        let starts_at = now + chrono::Duration::days(30);
+
+        // 0% platform fee, but only from next month
+        let payout_split = PayoutSplit::new(
+            String::from("store_test1"),
+            PayoutDealType::SELLER,
+            Some(starts_at),
+            Some(starts_at + chrono::Duration::days(90)),
+            1.0,
+            None,
+        );
+
+        let rate_now = check_rate_expiry_for_seller(Some(payout_split.clone()), None);
+        let rate_later = check_rate_expiry_for_seller(
+            Some(payout_split),
+            Some(starts_at + chrono::Duration::days(1)),
+        );
+
+        assert_eq!(rate_now, 1.0 - PLATFORM_FEE_PERCENTAGE);
+        assert_eq!(rate_later, 1.0);
+    }
+
     #[test]
     fn payout_split_affiliate_not_expired() {
 
@@ -733,6 +1297,7 @@ mod tests {
             Some(PayoutSplit::new(
                 String::from("store_test1"),
                 PayoutDealType::BUYER_AFFILIATE,
+                None,
                 Some(expiry),
                 input_rate,
                 None,
@@ -746,5 +1311,18 @@ mod tests {
 
         assert_eq!(expect_rate, result_rate)
     }
+
+    #[test]
+    fn parses_upline_referral_fee_percentages() {
+        assert_eq!(
+            parse_upline_referral_fee_percentages("0.02, 0.01,0.005"),
+            Ok(vec![0.02, 0.01, 0.005])
+        );
+        assert_eq!(parse_upline_referral_fee_percentages(""), Ok(vec![]));
+        // must leave part of the platform's share
+        assert!(parse_upline_referral_fee_percentages("0.1,0.05").is_err());
+        assert!(parse_upline_referral_fee_percentages("0.01,-0.005").is_err());
+        assert!(parse_upline_referral_fee_percentages("0.01,abc").is_err());
+    }
 }
 
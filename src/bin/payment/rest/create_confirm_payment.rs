@@ -14,6 +14,8 @@ use crate::db::GetPool;
 use crate::models::{
     PaypalResponse,
     StripeError, DbError, ErrJson,
+    PaymentVerificationError,
+    CartValidationError,
     Transaction,
     CartRpc,
     OrderDb,
@@ -171,10 +173,40 @@ impl From<PaymentProcessorData> for MockPaymentProcessorData {
     }
 }
 
+/// MOCK payments are only made when the server allows them, never because
+/// a request has the test params: they skip verifying the PaymentIntent.
+const PAYMENT_MOCK_MODE: &str = "PAYMENT_MOCK_MODE";
+
+pub fn mock_payments_enabled() -> bool {
+    dotenv::dotenv().ok();
+    std::env::var(PAYMENT_MOCK_MODE)
+        .ok()
+        .and_then(|s| s.parse::<bool>().ok())
+        .unwrap_or(false)
+}
+
+/// The MOCK payment's date, if the test params are present and mock payments are enabled.
+/// Test params sent while they are disabled are ignored, and the payment is LIVE.
+fn mock_payment_date(
+    mode: &Option<String>,
+    date: &Option<chrono::NaiveDateTime>,
+    mock_payments_enabled: bool,
+) -> Option<chrono::NaiveDateTime> {
+    match (mode, date) {
+        (Some(_), Some(date)) if mock_payments_enabled => Some(*date),
+        (Some(mode), Some(_)) => {
+            warn!("ignoring test params for mode {:?}, {} is not set", mode, PAYMENT_MOCK_MODE);
+            None
+        },
+        _ => None,
+    }
+}
+
 
 pub async fn create_payment(
     req: HttpRequest,
-    json: Json<OrderPaymentParams>
+    json: Json<OrderPaymentParams>,
+    query: Query<TxQuery>,
 ) -> Result<HttpResponse, Error> {
 
     debug!(">>>>> create_payment(...)");
@@ -189,20 +221,21 @@ pub async fn create_payment(
 
     debug!("paymentProcessorData parsed: {:?}", pay_proc_data);
 
-    match (&pay_proc_data.mode, &pay_proc_data.date) {
-        (Some(_), Some(_)) => {
+    match mock_payment_date(&pay_proc_data.mode, &pay_proc_data.date, mock_payments_enabled()) {
+        Some(_) => {
             // test params found, MOCK transaction
             create_payment_mock(
                 params,
                 MockPaymentProcessorData::from(pay_proc_data)
             ).await
         },
-        _ => {
+        None => {
             // No test params, LIVE transaction
             create_payment_live(
                 req,
                 params,
-                StripePaymentProcessorData::from(pay_proc_data)
+                StripePaymentProcessorData::from(pay_proc_data),
+                &query.cart_id,
             ).await
         }
     }
@@ -235,6 +268,7 @@ pub async fn create_payment_live(
     req: HttpRequest,
     params: OrderPaymentParams,
     payment_processor_data: StripePaymentProcessorData,
+    cart_id: &str,
 ) -> Result<HttpResponse, Error> {
 
     debug!(
@@ -242,6 +276,15 @@ pub async fn create_payment_live(
         &payment_processor_data
     );
 
+    // Check the amounts against the cart before charging anything
+    let cart = rpc_get_cart(
+        &AppState::httpClient(&req),
+        cart_id
+    ).await?;
+
+    verify_cart_amounts(&cart, &params).map_err(Error::from)?;
+    info!("cart total: {:?} === tx total: {:?}", &cart.total, &params.total);
+
     // Create payment intent data
     let stripe_params = stripe::PaymentIntentCreateParams {
         amount: params.total as u64,
@@ -254,8 +297,9 @@ pub async fn create_payment_live(
         confirm: Some(false), // Do not try auto-confirm. Do in 2nd step
         confirmation_method: Some("automatic".to_string()),
         description: None,
+        // links the PaymentIntent to its order, checked by confirm_payment
+        metadata: Some(payment_intent_metadata(&params.order_id)),
         // application_fee_amount: None,
-        // metadata: None,
         // on_behalf_of: None,
         // receipt_email: None,
         // return_url: None,
@@ -322,6 +366,16 @@ pub async fn create_payment_live(
 }
 
 
+/// Metadata key on PaymentIntents made by create_payment_live
+pub const PAYMENT_INTENT_ORDER_ID_KEY: &str = "orderId";
+
+fn payment_intent_metadata(order_id: &str) -> stripe::Metadata {
+    let mut metadata = stripe::Metadata::new();
+    metadata.insert(PAYMENT_INTENT_ORDER_ID_KEY.to_string(), order_id.to_string());
+    metadata
+}
+
+
 /////////////////////////////////////////////////////////
 /////////////////////////////////////////////////////////
 /////// CONFIRM PAYMENT
@@ -373,8 +427,8 @@ pub async fn confirm_payment(
     debug!("buyer_affiliate_user_id: {:?}", &buyer_affiliate_user_id);
 
     // check for Mock params
-    match (&pay_proc_data.mode, &pay_proc_data.date) {
-        (Some(_), Some(mock_date)) => {
+    match mock_payment_date(&pay_proc_data.mode, &pay_proc_data.date, mock_payments_enabled()) {
+        Some(mock_date) => {
             // test params found, MOCK confirmation
             // currently same as LIVE since there are no outgoing API calls
             // for the payment confirm step (done on frontend)
@@ -383,21 +437,18 @@ pub async fn confirm_payment(
                 order_params,
                 payment_intent,
                 customer,
-                mock_date, // &chrono::NaiveDateTime
+                Some(&mock_date), // &chrono::NaiveDateTime
                 buyer_affiliate_user_id,
             ).await
         },
-        _ => {
+        None => {
             // No test params, LIVE confirmation
-            let created_at = chrono::NaiveDateTime::from_timestamp(
-                payment_intent.created as i64, 0
-            );
             confirm_payment_handler(
                 req,
                 order_params,
                 payment_intent,
                 customer,
-                &created_at,
+                None,
                 buyer_affiliate_user_id,
             ).await
         }
@@ -406,15 +457,40 @@ pub async fn confirm_payment(
 
 
 
+/// Records a confirmed payment's transaction and payout items.
+///
+/// LIVE payments are only recorded from the PaymentIntent as retrieved from
+/// Stripe, never as sent by the client, after checking it succeeded
+/// for this order's total. MOCK payments (mock_date), only made when
+/// PAYMENT_MOCK_MODE is set, make no Stripe calls.
 pub async fn confirm_payment_handler(
     req: HttpRequest,
     order_params: OrderPaymentParams,
     payment_intent: PaymentIntent,
     customer: Option<String>,
-    created_at: &chrono::NaiveDateTime,
+    mock_date: Option<&chrono::NaiveDateTime>,
     buyer_affiliate_user_id: Option<String>,
 ) -> Result<HttpResponse, Error> {
 
+    let (payment_intent, customer, created_at) = match mock_date {
+        Some(mock_date) => (payment_intent, customer, *mock_date),
+        None => {
+            let pi_retrieved: PaymentIntent = AppState::stripeActor(&req)
+                .send(PaymentIntentMsg::Retrieve(payment_intent.id.to_string()))
+                .await??;
+
+            verify_payment_intent(&pi_retrieved, &order_params).map_err(Error::from)?;
+
+            let created_at = chrono::NaiveDateTime::from_timestamp(
+                pi_retrieved.created as i64, 0
+            );
+            let customer = pi_retrieved.customer.clone().or(customer);
+            (pi_retrieved, customer, created_at)
+        },
+    };
+
+    // orderItems are sent again on confirmation, check them before making payout items
+    verify_order_items_subtotal(&order_params).map_err(Error::from)?;
 
     // Execute and confirm the Stripe payment intent.
     let conn = AppState::databaseActor(&req)
@@ -424,13 +500,13 @@ pub async fn confirm_payment_handler(
     let tx_id = format!("txn_{}", payment_intent.id.clone());
 
     // Create payout_items
-    let payout_items: Vec<PayoutItem> = to_payout_items(
+    let (payout_items, payout_calculations) = to_payout_items(
         &conn,
         order_params.order_items_rpc.clone(),
         &tx_id,
         &created_at,
         buyer_affiliate_user_id
-    );
+    )?;
     debug!("created payout_items: {:?}", &payout_items);
 
     // seller pays payment_processing_fee for each orderItem:
@@ -447,7 +523,7 @@ pub async fn confirm_payment_handler(
         subtotal: order_params.subtotal,
         taxes: order_params.taxes,
         payment_processing_fee: payment_proc_fee,
-        created_at: *created_at,
+        created_at: created_at,
         currency: Currency::from_str(&payment_intent.currency.to_string()).ok(),
         charge_id: Some(payment_intent.id.clone().to_string()),
         customer_id: customer,
@@ -466,7 +542,8 @@ pub async fn confirm_payment_handler(
     let (_tx_result, _pitems_result) = db::write_transaction_and_payout_items(
         &conn,
         &tx,
-        &payout_items
+        &payout_items,
+        &payout_calculations,
     ).map_err(Error::from)?;
 
     Ok(HttpResponse::Ok()
@@ -477,6 +554,100 @@ pub async fn confirm_payment_handler(
 }
 
 
+/// Checks the orderItems' prices add up to the subtotal,
+/// and the subtotal, taxes and total are the cart's in gm-shopping.
+pub fn verify_cart_amounts(
+    cart: &CartRpc,
+    order_params: &OrderPaymentParams,
+) -> Result<(), CartValidationError> {
+
+    verify_order_items_subtotal(order_params)?;
+
+    if cart.subtotal != order_params.subtotal {
+        return Err(CartValidationError::SubtotalMismatch(errJson!(format!(
+            "subtotal {:?} does not equal submitted subtotal: {:?}",
+            cart.subtotal, order_params.subtotal
+        ))))
+    }
+
+    if cart.taxes != order_params.taxes {
+        return Err(CartValidationError::TaxesMismatch(errJson!(format!(
+            "taxes {:?} does not equal submitted taxes: {:?}",
+            cart.taxes, order_params.taxes
+        ))))
+    }
+
+    if cart.total != order_params.total {
+        return Err(CartValidationError::TotalMismatch(errJson!(format!(
+            "total {:?} does not equal submitted amount: {:?}",
+            cart.total, order_params.total
+        ))))
+    }
+
+    Ok(())
+}
+
+
+/// Checks the orderItems' prices, which payout items are made from, add up to the subtotal.
+pub fn verify_order_items_subtotal(
+    order_params: &OrderPaymentParams,
+) -> Result<(), CartValidationError> {
+
+    let items_subtotal: i32 = order_params.order_items_rpc
+        .iter()
+        .map(|o| o.actual_price)
+        .sum();
+
+    if items_subtotal != order_params.subtotal {
+        return Err(CartValidationError::SubtotalMismatch(errJson!(format!(
+            "orderItems add up to {:?}, the submitted subtotal is: {:?}",
+            items_subtotal, order_params.subtotal
+        ))))
+    }
+
+    Ok(())
+}
+
+
+/// Checks a PaymentIntent retrieved from Stripe is a succeeded payment
+/// of the order's total, in the order's currency, made for the order.
+pub fn verify_payment_intent(
+    payment_intent: &PaymentIntent,
+    order_params: &OrderPaymentParams,
+) -> Result<(), PaymentVerificationError> {
+
+    match payment_intent.status {
+        Some(stripe::PaymentIntentStatus::Succeeded) => {},
+        _ => return Err(PaymentVerificationError::NotSucceeded(errJson!(format!(
+            "PaymentIntent {} has status: {:?}", payment_intent.id, payment_intent.status
+        )))),
+    }
+
+    let amount_received = payment_intent.amount_received.unwrap_or(payment_intent.amount);
+    if payment_intent.amount != order_params.total || amount_received != order_params.total {
+        return Err(PaymentVerificationError::AmountMismatch(errJson!(format!(
+            "PaymentIntent {} is for {} (received {}), the order's total is {}",
+            payment_intent.id, payment_intent.amount, amount_received, order_params.total
+        ))))
+    }
+
+    if payment_intent.currency.to_string().to_lowercase() != order_params.currency.to_lowercase() {
+        return Err(PaymentVerificationError::CurrencyMismatch(errJson!(format!(
+            "PaymentIntent {} is in {}, the order is in {}",
+            payment_intent.id, payment_intent.currency, order_params.currency
+        ))))
+    }
+
+    match payment_intent.metadata.get(PAYMENT_INTENT_ORDER_ID_KEY) {
+        Some(order_id) if *order_id == order_params.order_id => Ok(()),
+        other => Err(PaymentVerificationError::OrderMismatch(errJson!(format!(
+            "PaymentIntent {} was made for order {:?}, not {}",
+            payment_intent.id, other, order_params.order_id
+        )))),
+    }
+}
+
+
 
 
 
@@ -618,4 +789,144 @@ fn deserializes_confirm_payment_payment_processor_data_with_datestring() {
         ),
         Err(e) => panic!(e.to_string()),
     }
-}
\ No newline at end of file
+}
+
+#[test]
+fn verifies_payment_intents_against_the_order() {
+
+    let order_params = OrderPaymentParams {
+        order_id: String::from("order_123"),
+        currency: String::from("USD"),
+        subtotal: 1400,
+        taxes: 0,
+        payment_processing_fee: 0,
+        total: 1400,
+        payment_processor_data: String::from("{}"),
+        order_items_rpc: vec![],
+    };
+
+    let created_at = chrono::NaiveDateTime::from_timestamp(1_584_424_582, 0);
+    let mut payment_intent = PaymentIntent::new_mock_data(1400, created_at);
+    payment_intent.status = Some(stripe::PaymentIntentStatus::Succeeded);
+    payment_intent.metadata = payment_intent_metadata("order_123");
+
+    assert_eq!(verify_payment_intent(&payment_intent, &order_params).is_ok(), true);
+
+    let mut unpaid = payment_intent.clone();
+    unpaid.status = Some(stripe::PaymentIntentStatus::RequiresPaymentMethod);
+    match verify_payment_intent(&unpaid, &order_params) {
+        Err(PaymentVerificationError::NotSucceeded(_)) => {},
+        other => panic!("expected NotSucceeded, got: {:?}", other),
+    }
+
+    let mut cheaper = payment_intent.clone();
+    cheaper.amount = 100;
+    match verify_payment_intent(&cheaper, &order_params) {
+        Err(PaymentVerificationError::AmountMismatch(_)) => {},
+        other => panic!("expected AmountMismatch, got: {:?}", other),
+    }
+
+    let mut other_currency = payment_intent.clone();
+    other_currency.currency = stripe::Currency::AUD;
+    match verify_payment_intent(&other_currency, &order_params) {
+        Err(PaymentVerificationError::CurrencyMismatch(_)) => {},
+        other => panic!("expected CurrencyMismatch, got: {:?}", other),
+    }
+
+    let mut other_order = payment_intent.clone();
+    other_order.metadata = payment_intent_metadata("order_456");
+    match verify_payment_intent(&other_order, &order_params) {
+        Err(PaymentVerificationError::OrderMismatch(_)) => {},
+        other => panic!("expected OrderMismatch, got: {:?}", other),
+    }
+}
+
+#[test]
+fn only_makes_mock_payments_when_enabled() {
+
+    let date = Some(chrono::NaiveDateTime::from_timestamp(1_584_424_582, 0));
+    let mode = Some(String::from("gm-frenzy"));
+
+    assert_eq!(mock_payment_date(&mode, &date, true), date);
+    // test params from the client are ignored unless mock payments are enabled
+    assert_eq!(mock_payment_date(&mode, &date, false), None);
+    assert_eq!(mock_payment_date(&None, &date, true), None);
+    assert_eq!(mock_payment_date(&mode, &None, true), None);
+}
+
+#[test]
+fn verifies_payment_amounts_against_the_cart() {
+
+    let created_at = chrono::NaiveDateTime::from_timestamp(1_584_424_582, 0);
+    let order_item = |id: &str, actual_price: i32| OrderItemRpc {
+        id: String::from(id),
+        actual_price: actual_price,
+        created_at: created_at,
+        currency: String::from("USD"),
+        payment_processing_fee: None,
+        store_id: String::from("store_123"),
+        product_id: None,
+        category_id: None,
+        discounts: None,
+    };
+
+    let order_params = OrderPaymentParams {
+        order_id: String::from("order_123"),
+        currency: String::from("USD"),
+        subtotal: 1400,
+        taxes: 100,
+        payment_processing_fee: 0,
+        total: 1500,
+        payment_processor_data: String::from("{}"),
+        order_items_rpc: vec![
+            order_item("oitem_1", 1000),
+            order_item("oitem_2", 400),
+        ],
+    };
+
+    let cart = CartRpc {
+        id: String::from("cart_123"),
+        user_id: None,
+        updated_at: None,
+        items: None,
+        applied_discount_codes: None,
+        subtotal: 1400,
+        taxes: 100,
+        payment_processing_fee: 0,
+        total: 1500,
+    };
+
+    assert_eq!(verify_cart_amounts(&cart, &order_params).is_ok(), true);
+
+    let mut underpriced = order_params.clone();
+    underpriced.order_items_rpc[1].actual_price = 1;
+    match verify_cart_amounts(&cart, &underpriced) {
+        Err(CartValidationError::SubtotalMismatch(_)) => {},
+        other => panic!("expected SubtotalMismatch, got: {:?}", other),
+    }
+
+    // moving money from taxes into orderItems keeps the total
+    let mut overpriced = order_params.clone();
+    overpriced.order_items_rpc[1].actual_price = 500;
+    overpriced.subtotal = 1500;
+    overpriced.taxes = 0;
+    match verify_cart_amounts(&cart, &overpriced) {
+        Err(CartValidationError::SubtotalMismatch(_)) => {},
+        other => panic!("expected SubtotalMismatch, got: {:?}", other),
+    }
+    assert_eq!(verify_order_items_subtotal(&overpriced).is_ok(), true);
+
+    let mut taxed = order_params.clone();
+    taxed.taxes = 0;
+    match verify_cart_amounts(&cart, &taxed) {
+        Err(CartValidationError::TaxesMismatch(_)) => {},
+        other => panic!("expected TaxesMismatch, got: {:?}", other),
+    }
+
+    let mut undercharged = order_params.clone();
+    undercharged.total = 1;
+    match verify_cart_amounts(&cart, &undercharged) {
+        Err(CartValidationError::TotalMismatch(_)) => {},
+        other => panic!("expected TotalMismatch, got: {:?}", other),
+    }
+}
@@ -0,0 +1,131 @@
+use actix_web::{
+    HttpRequest,
+    HttpResponse,
+    web::Query,
+    web::Json,
+    Error,
+};
+use gm::models::stripe::{
+    DisputeEvidence,
+    DisputeUpdateParams,
+};
+
+use crate::db;
+use crate::db::GetPool;
+use crate::models::{
+    ErrJson,
+    DisputeError,
+    AuthInfo,
+    Dispute,
+};
+use crate::payment_clients::stripe_client::DisputeMsg;
+use crate::{AppState};
+use crate::rpc;
+use crate::rest::is_worthy_enough;
+
+
+#[serde(rename_all = "camelCase")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReadDisputesQuery {
+    order_id: String,
+}
+
+pub async fn read_disputes(
+    req: HttpRequest,
+    query: Query<ReadDisputesQuery>,
+) -> Result<HttpResponse, Error> {
+
+    let auth_info: AuthInfo = rpc::rpc_get_auth_info(
+        &AppState::from(&req).http_client,
+        &req
+    ).await?;
+    is_worthy_enough(&auth_info.user_role).map_err(Error::from)?;
+
+    let conn = AppState::databaseActor(&req)
+                .send(GetPool::Postgres)
+                .await??;
+
+    let disputes = db::read_disputes_by_order_id(&conn, &query.order_id)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .json(disputes))
+}
+
+
+#[serde(rename_all = "camelCase")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SubmitDisputeEvidenceBody {
+    dispute_id: String,
+    evidence: DisputeEvidence,
+    /// Stage the evidence on Stripe without sending it to the bank yet.
+    /// Evidence can usually only be submitted once.
+    #[serde(default)]
+    stage_only: bool,
+}
+
+/// Responds to a dispute with evidence, through Stripe.
+/// The dispute's outcome is settled later by its `charge.dispute.closed` event.
+pub async fn submit_dispute_evidence(
+    req: HttpRequest,
+    json: Json<SubmitDisputeEvidenceBody>,
+) -> Result<HttpResponse, Error> {
+
+    let body = json.into_inner();
+
+    let auth_info: AuthInfo = rpc::rpc_get_auth_info(
+        &AppState::from(&req).http_client,
+        &req
+    ).await?;
+    is_worthy_enough(&auth_info.user_role).map_err(Error::from)?;
+
+    let conn = AppState::databaseActor(&req)
+                .send(GetPool::Postgres)
+                .await??;
+
+    let dispute = db::read_dispute(&conn, &body.dispute_id)?
+        .ok_or(Error::from(DisputeError::NotFound(errJson!(
+            format!("no dispute with id: {}", body.dispute_id)
+        ))))?;
+
+    if dispute.is_closed() {
+        return Err(Error::from(DisputeError::AlreadyClosed(errJson!(
+            format!("dispute {} is already closed as {}", dispute.id, dispute.status)
+        ))))
+    }
+
+    let stripe_dispute = AppState::stripeActor(&req)
+        .send(DisputeMsg::Update(
+            dispute.id.clone(),
+            DisputeUpdateParams {
+                evidence: Some(body.evidence),
+                metadata: None,
+                submit: Some(!body.stage_only),
+            }
+        ))
+        .await??;
+
+    info!("{} {} evidence for dispute {}",
+        auth_info.user_id,
+        if body.stage_only { "staged" } else { "submitted" },
+        dispute.id,
+    );
+
+    let now = chrono::NaiveDateTime::from_timestamp(chrono::Utc::now().timestamp(), 0);
+    let evidence_submitted_at = match body.stage_only {
+        true => dispute.evidence_submitted_at,
+        false => Some(now),
+    };
+
+    let dispute = db::update_dispute(
+        &conn,
+        &Dispute {
+            evidence_submitted_at: evidence_submitted_at,
+            ..dispute.update_from_stripe(&stripe_dispute, now)
+        },
+    )?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .json(dispute))
+}
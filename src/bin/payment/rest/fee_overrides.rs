@@ -0,0 +1,173 @@
+use actix_web::{
+    HttpRequest,
+    HttpResponse,
+    web::Query, web::Json,
+    Error,
+};
+
+use crate::db;
+use crate::db::GetPool;
+use crate::models::{
+    FeeOverride,
+    FeeOverrideError,
+    ErrJson,
+};
+use crate::{AppState};
+
+
+#[serde(rename_all = "camelCase")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReadManyFeeOverridesBody {
+    fee_override_ids: Vec<String>,
+}
+
+pub async fn read_many_fee_overrides(
+    req: HttpRequest,
+    json: Json<ReadManyFeeOverridesBody>,
+) -> Result<HttpResponse, Error> {
+
+    let fee_override_ids: Vec<String> = json.into_inner().fee_override_ids;
+    debug!("fee_override_ids: {:?}", &fee_override_ids);
+
+    let conn = AppState::databaseActor(&req)
+                .send(GetPool::Postgres)
+                .await??;
+
+    let fee_overrides = db::read_fee_overrides_by_ids(&conn, fee_override_ids)?;
+
+    Ok(HttpResponse::Ok()
+    .content_type("application/json")
+    .json(fee_overrides))
+}
+
+
+#[serde(rename_all = "camelCase")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WriteFeeOverrideBody {
+    product_id: Option<String>,
+    category_id: Option<String>,
+    store_id: Option<String>,
+    platform_fee_rate: Option<f64>,
+    max_platform_fee: Option<i32>,
+    expires_at: Option<chrono::NaiveDateTime>,
+}
+
+/// A FeeOverride needs a product or category, and a platform fee rate
+/// between 0 and 1 or a max platform fee of at least 0 (or both).
+/// Rates over 1 would leave sellers a negative rate.
+fn check_fee_override(params: &WriteFeeOverrideBody) -> Result<(), FeeOverrideError> {
+
+    let invalid = |message: &str| Err(FeeOverrideError::InvalidFeeOverride(errJson!(message)));
+
+    if params.product_id.is_none() && params.category_id.is_none() {
+        return invalid("FeeOverride needs a productId or a categoryId")
+    }
+    if params.platform_fee_rate.is_none() && params.max_platform_fee.is_none() {
+        return invalid("FeeOverride needs a platformFeeRate or a maxPlatformFee")
+    }
+    match params.platform_fee_rate {
+        Some(rate) if !(rate >= 0.0 && rate <= 1.0) => {
+            return invalid(&format!("platformFeeRate must be between 0 and 1, got: {}", rate))
+        },
+        _ => {},
+    }
+    match params.max_platform_fee {
+        Some(max_fee) if max_fee < 0 => {
+            return invalid(&format!("maxPlatformFee must be at least 0, got: {}", max_fee))
+        },
+        _ => {},
+    }
+    Ok(())
+}
+
+pub async fn write_fee_override(
+    req: HttpRequest,
+    json: Json<WriteFeeOverrideBody>,
+) -> Result<HttpResponse, Error> {
+
+    let params = json.into_inner();
+    debug!("json: {:?}", &params);
+
+    check_fee_override(&params)?;
+
+    let fee_override = FeeOverride::new(
+        params.product_id,
+        params.category_id,
+        params.store_id,
+        params.platform_fee_rate,
+        params.max_platform_fee,
+        params.expires_at,
+    );
+    debug!("fee_override: {:?}", &fee_override);
+
+    let conn = AppState::databaseActor(&req)
+                .send(GetPool::Postgres)
+                .await??;
+
+    let fee_override = db::write_fee_override(&conn, fee_override)?;
+
+    Ok(HttpResponse::Ok()
+    .content_type("application/json")
+    .json(fee_override))
+}
+
+
+#[serde(rename_all = "camelCase")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeleteFeeOverride {
+    fee_override_id: String,
+}
+
+pub async fn delete_fee_override(
+    req: HttpRequest,
+    query: Query<DeleteFeeOverride>,
+) -> Result<HttpResponse, Error> {
+
+    let params = query.into_inner();
+    debug!("json: {:?}", &params);
+
+    let conn = AppState::databaseActor(&req)
+                .send(GetPool::Postgres)
+                .await??;
+
+    let fee_override = db::delete_fee_override(&conn, params.fee_override_id)?;
+
+    Ok(HttpResponse::Ok()
+    .content_type("application/json")
+    .json(fee_override))
+}
+
+
+
+#[test]
+fn checks_fee_override_ranges() {
+
+    let body = WriteFeeOverrideBody {
+        product_id: Some(String::from("product_123")),
+        category_id: None,
+        store_id: None,
+        platform_fee_rate: Some(0.1),
+        max_platform_fee: Some(500),
+        expires_at: None,
+    };
+    assert_eq!(check_fee_override(&body).is_ok(), true);
+
+    let no_target = WriteFeeOverrideBody { product_id: None, ..body.clone() };
+    assert_eq!(check_fee_override(&no_target).is_err(), true);
+
+    let no_fee = WriteFeeOverrideBody {
+        platform_fee_rate: None,
+        max_platform_fee: None,
+        ..body.clone()
+    };
+    assert_eq!(check_fee_override(&no_fee).is_err(), true);
+
+    let over_one = WriteFeeOverrideBody { platform_fee_rate: Some(1.5), ..body.clone() };
+    assert_eq!(check_fee_override(&over_one).is_err(), true);
+
+    let negative_rate = WriteFeeOverrideBody { platform_fee_rate: Some(-0.1), ..body.clone() };
+    assert_eq!(check_fee_override(&negative_rate).is_err(), true);
+
+    let negative_cap = WriteFeeOverrideBody { max_platform_fee: Some(-1), ..body.clone() };
+    assert_eq!(check_fee_override(&negative_cap).is_err(), true);
+}
@@ -0,0 +1,239 @@
+use actix_web::{
+    HttpRequest,
+    HttpResponse,
+    web::Json,
+    Error,
+};
+use gm::utils::dates::from_datetimestr_to_option_naivedatetime;
+
+use crate::db;
+use crate::db::GetPool;
+use crate::models::{
+    FeeOverride,
+    OrderItemRpc,
+    OrderItemDiscount,
+    PayoutSplit,
+    PayoutDealType,
+};
+use crate::pricing::{
+    calculate_platform_fees,
+    resolve_applied_rates,
+    FundedDiscounts,
+    PaymentFees,
+    CalculatedEarnings,
+    AppliedRates,
+    BUYER_AFFILIATE_FEE_PERCENTAGE,
+};
+use crate::{AppState};
+
+
+#[serde(rename_all = "camelCase")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FeeQuoteBody {
+    subtotal: i32,
+    store_id: String,
+    buyer_affiliate_user_id: Option<String>,
+    /// Defaults to the seller's referrer if not supplied
+    seller_affiliate_user_id: Option<String>,
+    product_id: Option<String>,
+    category_id: Option<String>,
+    /// Discounts already taken off the subtotal
+    discounts: Option<Vec<OrderItemDiscount>>,
+    /// Quote as if the order was placed at this date, defaults to now
+    #[serde(default)]
+    #[serde(deserialize_with = "from_datetimestr_to_option_naivedatetime")]
+    date: Option<chrono::NaiveDateTime>,
+}
+
+#[serde(rename_all = "camelCase")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FeeQuote {
+    subtotal: i32,
+    date: chrono::NaiveDateTime,
+    earnings: CalculatedEarnings,
+    applied_rates: AppliedRates,
+    seller_payout_split: Option<PayoutSplit>,
+    buyer_affiliate_payout_split: Option<PayoutSplit>,
+    seller_affiliate_payout_split: Option<PayoutSplit>,
+    upline_affiliate_payout_splits: Vec<PayoutSplit>,
+    fee_override: Option<FeeOverride>,
+}
+
+
+/// Simulates the payout for a sale without writing anything.
+/// Uses the same PayoutSplits and fee overrides as to_payout_items()
+pub async fn quote_fees(
+    req: HttpRequest,
+    json: Json<FeeQuoteBody>,
+) -> Result<HttpResponse, Error> {
+
+    let params = json.into_inner();
+    debug!("json: {:?}", &params);
+
+    if params.subtotal <= 0 {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("application/json")
+            .json(json!({
+                "message": "subtotal must be greater than 0"
+            })))
+    }
+
+    let date = params.date.unwrap_or(
+        chrono::NaiveDateTime::from_timestamp(chrono::Utc::now().timestamp(), 0)
+    );
+
+    let conn = AppState::databaseActor(&req)
+                .send(GetPool::Postgres)
+                .await??;
+
+    // 1. seller and the seller's referrer
+    let (
+        seller_psplit,
+        referrer_psplit
+    ): (Option<PayoutSplit>, Option<PayoutSplit>) =
+    match db::read_current_seller_referrer_payout_splits_by_store_id(
+        &conn,
+        params.store_id.clone(),
+        Some(date),
+    ) {
+        None => (None, None),
+        Some(psplits) => (psplits.referred_seller, psplits.seller_affiliate),
+    };
+
+    let seller_aff_psplit = match params.seller_affiliate_user_id {
+        None => referrer_psplit,
+        Some(saff_user_id) => db::read_current_payout_splits_by_store_or_user_ids(
+            &conn,
+            &vec![saff_user_id],
+            Some(vec![PayoutDealType::SELLER_AFFILIATE]),
+            Some(date),
+        )?.into_iter().next(),
+    };
+
+    let upline_aff_psplits: Vec<PayoutSplit> = match &seller_aff_psplit {
+        None => vec![],
+        Some(s) => db::read_upline_payout_splits(&conn, s, Some(date))?,
+    };
+
+    // 2. buyer affiliate. Orders create a default PayoutSplit if the
+    // affiliate has none, so quote with an unsaved default instead.
+    let buyer_aff_psplit = match params.buyer_affiliate_user_id {
+        None => None,
+        Some(baff_user_id) => {
+            // the store's own affiliate deal wins over the global deal
+            let store_psplit = db::read_store_buyer_affiliate_payout_split(
+                &conn,
+                &baff_user_id,
+                &params.store_id,
+                Some(date),
+            )?;
+            let current = match store_psplit {
+                Some(psplit) => Some(psplit),
+                None => db::read_current_payout_splits_by_store_or_user_ids(
+                    &conn,
+                    &vec![baff_user_id.clone()],
+                    Some(vec![PayoutDealType::BUYER_AFFILIATE]),
+                    Some(date),
+                )?.into_iter().next(),
+            };
+
+            match current {
+                Some(psplit) => Some(psplit),
+                None => Some(PayoutSplit::new(
+                    baff_user_id,
+                    PayoutDealType::BUYER_AFFILIATE,
+                    None,
+                    None,
+                    BUYER_AFFILIATE_FEE_PERCENTAGE,
+                    None,
+                )),
+            }
+        }
+    };
+
+    // 3. product or category fee overrides
+    let quote_item = OrderItemRpc {
+        id: String::from("oitem_quote"),
+        actual_price: params.subtotal,
+        created_at: date,
+        currency: String::from("USD"),
+        payment_processing_fee: None,
+        store_id: params.store_id.clone(),
+        product_id: params.product_id,
+        category_id: params.category_id,
+        discounts: params.discounts.clone(),
+    };
+    let fee_override = db::read_fee_override_for_order_item(
+        &conn,
+        &quote_item,
+        &date,
+    )?;
+
+    let seller_payment_proc_fee = PaymentFees::new()
+        .calculate_payment_processing_fee(params.subtotal);
+
+    let funded_discounts = FundedDiscounts::from_discounts(
+        &params.discounts.clone().unwrap_or(vec![])
+    );
+
+    let applied_rates = resolve_applied_rates(
+        params.subtotal,
+        &funded_discounts,
+        seller_psplit.clone(),
+        buyer_aff_psplit.clone(),
+        seller_aff_psplit.clone(),
+        upline_aff_psplits.clone(),
+        fee_override.clone(),
+        Some(date),
+    );
+
+    let earnings = calculate_platform_fees(
+        params.subtotal,
+        seller_payment_proc_fee,
+        seller_psplit.clone(),
+        buyer_aff_psplit.clone(),
+        seller_aff_psplit.clone(),
+        upline_aff_psplits.clone(),
+        fee_override.clone(),
+        funded_discounts,
+        Some(date),
+    );
+
+    Ok(HttpResponse::Ok()
+    .content_type("application/json")
+    .json(FeeQuote {
+        subtotal: params.subtotal,
+        date: date,
+        earnings: earnings,
+        applied_rates: applied_rates,
+        seller_payout_split: seller_psplit,
+        buyer_affiliate_payout_split: buyer_aff_psplit,
+        seller_affiliate_payout_split: seller_aff_psplit,
+        upline_affiliate_payout_splits: upline_aff_psplits,
+        fee_override: fee_override,
+    }))
+}
+
+
+
+#[test]
+fn deserializes_fee_quote_body() {
+    let body: FeeQuoteBody = serde_json::from_str(r#"{
+        "subtotal": 10000,
+        "storeId": "store_123",
+        "buyerAffiliateUserId": "user_123"
+    }"#).unwrap();
+    assert_eq!(body.subtotal, 10000);
+    assert_eq!(body.date, None);
+
+    let body: FeeQuoteBody = serde_json::from_str(r#"{
+        "subtotal": 10000,
+        "storeId": "store_123",
+        "date": "2020-04-06T10:00:00"
+    }"#).unwrap();
+    assert_eq!(body.buyer_affiliate_user_id, None);
+    assert_eq!(
+        body.date,
+        Some(chrono::NaiveDateTime::from_timestamp(1586167200, 0))
+    );
+}
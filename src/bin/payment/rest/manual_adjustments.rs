@@ -0,0 +1,127 @@
+use actix_web::{
+    HttpRequest,
+    HttpResponse,
+    web::Query,
+    web::Json,
+    Error,
+};
+
+use crate::db;
+use crate::db::GetPool;
+use crate::models::{
+    ErrJson,
+    ManualAdjustmentError,
+    AuthInfo,
+    PayoutItem,
+    PayeeType,
+    Currency,
+    ManualAdjustment,
+    ManualAdjustmentCategory,
+};
+use crate::{AppState};
+use crate::rpc;
+use crate::rest::is_worthy_enough;
+
+
+#[serde(rename_all = "camelCase")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WriteManualAdjustmentBody {
+    payee_id: String,
+    payee_type: PayeeType,
+    category: ManualAdjustmentCategory,
+    /// In cents, always positive. PENALTY adjustments are deducted.
+    amount: i32,
+    currency: Option<String>,
+    reason: String,
+    /// userId of whoever asked for it, if not the approving admin
+    requested_by: Option<String>,
+}
+
+/// Creates a bonus, penalty or goodwill credit for any payee.
+/// The admin making the request is recorded as the approver, and the
+/// adjustment is paid out (or deducted) by the next create_payout.
+pub async fn write_manual_adjustment(
+    req: HttpRequest,
+    json: Json<WriteManualAdjustmentBody>,
+) -> Result<HttpResponse, Error> {
+
+    let body = json.into_inner();
+    debug!("writing manual adjustment: {:?}", &body);
+
+    let auth_info: AuthInfo = rpc::rpc_get_auth_info(
+        &AppState::from(&req).http_client,
+        &req
+    ).await?;
+    is_worthy_enough(&auth_info.user_role).map_err(Error::from)?;
+
+    if body.amount <= 0 {
+        return Err(Error::from(ManualAdjustmentError::InvalidAdjustment(errJson!(
+            format!("amount must be positive, got: {}. PENALTY adjustments are deducted", body.amount)
+        ))))
+    }
+    if body.reason.trim().len() == 0 {
+        return Err(Error::from(ManualAdjustmentError::InvalidAdjustment(errJson!(
+            "a reason is required for manual adjustments"
+        ))))
+    }
+
+    let conn = AppState::databaseActor(&req)
+                .send(GetPool::Postgres)
+                .await??;
+
+    let created_at = chrono::NaiveDateTime::from_timestamp(chrono::Utc::now().timestamp(), 0);
+
+    let payout_item = PayoutItem::new_manual_adjustment(
+        body.payee_id,
+        body.payee_type,
+        body.category.signed_amount(body.amount),
+        created_at,
+        body.currency.unwrap_or(Currency::USD.as_string()),
+    );
+
+    let manual_adjustment = ManualAdjustment::new(
+        payout_item.id.clone(),
+        body.category,
+        body.reason,
+        auth_info.user_id,
+        body.requested_by,
+        created_at,
+    );
+
+    let adjustment_item = db::write_manual_adjustment(
+        &conn,
+        &payout_item,
+        &manual_adjustment,
+    )?;
+    info!("wrote manual adjustment: {:?}", &adjustment_item);
+
+    Ok(HttpResponse::Ok()
+    .content_type("application/json")
+    .json(adjustment_item))
+}
+
+
+#[serde(rename_all = "camelCase")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManualAdjustmentsByPayeeBody {
+    payee_id: String,
+}
+
+pub async fn read_manual_adjustments(
+    req: HttpRequest,
+    query: Query<ManualAdjustmentsByPayeeBody>,
+) -> Result<HttpResponse, Error> {
+
+    let payee_id = query.into_inner().payee_id;
+    debug!("payee_id: {:?}", &payee_id);
+
+    let conn = AppState::databaseActor(&req)
+                .send(GetPool::Postgres)
+                .await??;
+
+    let adjustment_items = db::read_manual_adjustments_by_payee_id(&conn, &payee_id)?;
+
+    Ok(HttpResponse::Ok()
+    .content_type("application/json")
+    .json(adjustment_items))
+}
@@ -5,26 +5,44 @@ pub mod paypal;
 pub mod affiliate_commissions;
 pub mod affiliates;
 pub mod create_confirm_payment;
+pub mod disputes;
+pub mod fee_overrides;
+pub mod fee_quotes;
+pub mod manual_adjustments;
 pub mod transactions;
 pub mod refunds;
+pub mod refund_analytics;
+pub mod refund_requests;
+pub mod revenue_shares;
 pub mod payment_methods;
 pub mod payout_methods;
+pub mod payout_calculations;
 pub mod payout_items;
 pub mod payout_splits;
 pub mod payouts;
 pub mod health;
+pub mod webhook_events;
 
 pub use affiliate_commissions::*;
 pub use affiliates::*;
 pub use create_confirm_payment::*;
+pub use disputes::*;
+pub use fee_overrides::*;
+pub use fee_quotes::*;
+pub use manual_adjustments::*;
 pub use transactions::*;
 pub use refunds::*;
+pub use refund_analytics::*;
+pub use refund_requests::*;
+pub use revenue_shares::*;
 pub use payment_methods::*;
 pub use payout_methods::*;
+pub use payout_calculations::*;
 pub use payout_items::*;
 pub use payout_splits::*;
 pub use payouts::*;
 pub use health::*;
+pub use webhook_events::*;
 
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -0,0 +1,370 @@
+use actix_web::{
+    HttpRequest,
+    HttpResponse,
+    web::Query,
+    web::Json,
+    Error,
+};
+use std::collections::BTreeMap;
+
+use crate::db;
+use crate::db::GetPool;
+use crate::models::{
+    ErrJson,
+    PayoutCalculationError,
+    DbError,
+    AuthInfo,
+    PayoutCalculation,
+    PayoutCalculationCheck,
+    PayoutItem,
+    PayoutRecalculation,
+    AuditLog,
+    AuditAction,
+    OrderItemRpc,
+    verify_payout_calculation,
+    latest_payout_calculations,
+    payout_adjustment_items,
+    is_refund_status,
+    to_payout_items_with_buyer_aff_psplit,
+    PayoutSplit,
+    PayoutDealType,
+};
+use crate::pricing::BUYER_AFFILIATE_FEE_PERCENTAGE;
+use crate::{AppState};
+use crate::rpc;
+use crate::rest::is_worthy_enough;
+use gm::utils::dates::from_datetimestr_to_option_naivedatetime;
+
+
+#[serde(rename_all = "camelCase")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PayoutCalculationsByOrderBody {
+    order_id: String,
+}
+
+pub async fn read_payout_calculations(
+    req: HttpRequest,
+    query: Query<PayoutCalculationsByOrderBody>,
+) -> Result<HttpResponse, Error> {
+
+    let order_id = query.into_inner().order_id;
+    debug!("order_id: {:?}", &order_id);
+
+    let conn = AppState::databaseActor(&req)
+                .send(GetPool::Postgres)
+                .await??;
+
+    let payout_calculations = db::read_payout_calculations_by_order_id(&conn, &order_id)?;
+
+    Ok(HttpResponse::Ok()
+    .content_type("application/json")
+    .json(payout_calculations))
+}
+
+
+#[serde(rename_all = "camelCase")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PayoutCalculationsVerified {
+    order_id: String,
+    /// false if any orderItem's stored payout items differ from the recomputed split
+    verified: bool,
+    checks: Vec<PayoutCalculationCheck>,
+}
+
+/// Recomputes an order's split from the stored PayoutCalculations,
+/// and flags any payout items that don't match.
+pub async fn verify_payout_calculations(
+    req: HttpRequest,
+    query: Query<PayoutCalculationsByOrderBody>,
+) -> Result<HttpResponse, Error> {
+
+    let order_id = query.into_inner().order_id;
+    debug!("order_id: {:?}", &order_id);
+
+    let conn = AppState::databaseActor(&req)
+                .send(GetPool::Postgres)
+                .await??;
+
+    // orderItems which were recalculated are checked against their latest split
+    let payout_calculations = latest_payout_calculations(
+        db::read_payout_calculations_by_order_id(&conn, &order_id)?
+    );
+
+    let order_item_ids = payout_calculations
+        .iter()
+        .map(|pcalc: &PayoutCalculation| pcalc.order_item_id.clone())
+        .collect::<Vec<String>>();
+
+    let payout_items = db::read_payout_items_by_order_item_ids(&conn, &order_item_ids)?;
+
+    let checks = payout_calculations
+        .into_iter()
+        .map(|pcalc| verify_payout_calculation(pcalc, &payout_items))
+        .collect::<Vec<PayoutCalculationCheck>>();
+
+    let verified = checks.iter().all(|c| c.mismatches.len() == 0);
+    if !verified {
+        warn!("Payout items for order {} do not match their PayoutCalculations", order_id);
+    }
+
+    Ok(HttpResponse::Ok()
+    .content_type("application/json")
+    .json(PayoutCalculationsVerified {
+        order_id: order_id,
+        verified: verified,
+        checks: checks,
+    }))
+}
+
+
+
+#[serde(rename_all = "camelCase")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecalculateEarningsBody {
+    order_id: String,
+    reason: String,
+    /// Returns the adjustments without writing them, defaults to true
+    preview: Option<bool>,
+    /// Use the PayoutSplits and fee overrides in effect at this date,
+    /// defaults to now
+    #[serde(default)]
+    #[serde(deserialize_with = "from_datetimestr_to_option_naivedatetime")]
+    valid_at: Option<chrono::NaiveDateTime>,
+}
+
+/// Recomputes an order's earnings with corrected PayoutSplits or fee overrides.
+///
+/// The order's original payout items are left untouched (they may already be
+/// paid out). The difference is booked as signed adjustment payout items,
+/// which create_payout nets against each payee's next payout.
+/// Running it again with the same splits produces no further adjustments.
+pub async fn recalculate_order_earnings(
+    req: HttpRequest,
+    json: Json<RecalculateEarningsBody>,
+) -> Result<HttpResponse, Error> {
+
+    let body = json.into_inner();
+    let preview = body.preview.unwrap_or(true);
+    let valid_at = body.valid_at.unwrap_or(
+        chrono::NaiveDateTime::from_timestamp(chrono::Utc::now().timestamp(), 0)
+    );
+    debug!("recalculating earnings for order: {:?}", &body);
+
+    let auth_info: AuthInfo = rpc::rpc_get_auth_info(
+        &AppState::from(&req).http_client,
+        &req
+    ).await?;
+    is_worthy_enough(&auth_info.user_role).map_err(Error::from)?;
+
+    let conn = AppState::databaseActor(&req)
+                .send(GetPool::Postgres)
+                .await??;
+
+    // 1. the inputs of each orderItem's split, as last calculated
+    let payout_calculations = latest_payout_calculations(
+        db::read_payout_calculations_by_order_id(&conn, &body.order_id)?
+    );
+    if payout_calculations.len() == 0 {
+        return Err(Error::from(PayoutCalculationError::NotRecorded(errJson!(
+            format!("No PayoutCalculations recorded for order: {}", body.order_id)
+        ))))
+    }
+
+    let order_item_ids = payout_calculations
+        .iter()
+        .map(|pcalc: &PayoutCalculation| pcalc.order_item_id.clone())
+        .collect::<Vec<String>>();
+
+    let existing_payout_items = db::read_payout_items_by_order_item_ids(&conn, &order_item_ids)?
+        .into_iter()
+        .filter(|p| payout_calculations.iter().any(|pcalc| Some(&pcalc.txn_id) == p.txn_id.as_ref()))
+        .collect::<Vec<PayoutItem>>();
+
+    // 2. refunded orderItems are settled by the refund, not adjusted
+    let (
+        payout_calculations,
+        skipped_order_item_ids
+    ): (Vec<PayoutCalculation>, Vec<String>) = {
+        let mut recalculable = vec![];
+        let mut skipped = vec![];
+        for pcalc in payout_calculations.into_iter() {
+            let refunded = existing_payout_items.iter().any(|p| {
+                p.order_item_id.as_ref() == Some(&pcalc.order_item_id)
+                && is_refund_status(&p.payout_status)
+            });
+            if refunded || pcalc.store_id.is_none() {
+                skipped.push(pcalc.order_item_id.clone());
+            } else {
+                recalculable.push(pcalc);
+            }
+        }
+        (recalculable, skipped)
+    };
+
+    // 3. buyer affiliate of each transaction, from the PayoutSplit used originally
+    let buyer_aff_psplit_ids = payout_calculations
+        .iter()
+        .filter_map(|pcalc| pcalc.buyer_affiliate_payout_split_id.clone())
+        .collect::<Vec<String>>();
+
+    let buyer_aff_psplits = match buyer_aff_psplit_ids.len() {
+        0 => vec![],
+        _ => db::read_payout_splits_by_ids(&conn, buyer_aff_psplit_ids)?,
+    };
+
+    let mut oitems_by_txn: BTreeMap<String, (Vec<OrderItemRpc>, Option<String>)> = BTreeMap::new();
+    for pcalc in payout_calculations.iter() {
+        let buyer_affiliate_user_id = buyer_aff_psplits
+            .iter()
+            .find(|ps| Some(ps.id.clone()) == pcalc.buyer_affiliate_payout_split_id)
+            .map(|ps| ps.store_or_user_id.clone());
+
+        let entry = oitems_by_txn
+            .entry(pcalc.txn_id.clone())
+            .or_insert((vec![], None));
+        if let Some(oitem) = pcalc.to_order_item_rpc() {
+            entry.0.push(oitem);
+        }
+        if entry.1.is_none() {
+            entry.1 = buyer_affiliate_user_id;
+        }
+    }
+
+    // 4. recompute with the PayoutSplits and fee overrides valid now
+    let now = chrono::NaiveDateTime::from_timestamp(chrono::Utc::now().timestamp(), 0);
+    let (
+        recomputed_payout_items,
+        recomputed_payout_calculations
+    ): (Vec<PayoutItem>, Vec<PayoutCalculation>) = oitems_by_txn
+        .into_iter()
+        .try_fold((vec![], vec![]), |(mut pitems, mut pcalcs), (txn_id, (oitems, buyer_aff_id))| {
+            // previews read PayoutSplits only. An affiliate without a
+            // BUYER_AFFILIATE PayoutSplit is previewed with an unsaved default.
+            let buyer_aff_psplit = match (buyer_aff_id.clone(), preview) {
+                (None, _) => None,
+                (Some(ba_user_id), true) => match db::read_buyer_psplit(&conn, &ba_user_id, Some(valid_at))? {
+                    Some(psplit) => Some(psplit),
+                    None => Some(PayoutSplit::new(
+                        ba_user_id,
+                        PayoutDealType::BUYER_AFFILIATE,
+                        None,
+                        None,
+                        BUYER_AFFILIATE_FEE_PERCENTAGE,
+                        None,
+                    )),
+                },
+                (Some(ba_user_id), false) => db::try_read_buyer_psplit_create_on_null(
+                    &conn,
+                    ba_user_id,
+                    Some(valid_at),
+                ),
+            };
+            let (new_pitems, new_pcalcs) = to_payout_items_with_buyer_aff_psplit(
+                &conn,
+                oitems,
+                &txn_id,
+                &valid_at,
+                buyer_aff_id,
+                buyer_aff_psplit,
+            )?;
+            pitems.extend(new_pitems);
+            pcalcs.extend(new_pcalcs.into_iter().map(|mut pcalc| {
+                // so the recalculation is the latest for the orderItem
+                pcalc.created_at = now;
+                pcalc
+            }));
+            Ok::<_, DbError>((pitems, pcalcs))
+        })?;
+
+    // 5. book the differences
+    let adjustments = payout_adjustment_items(
+        &existing_payout_items,
+        &recomputed_payout_items,
+        now,
+    );
+
+    let recalculation = match preview {
+        true => PayoutRecalculation {
+            order_id: body.order_id,
+            preview: preview,
+            valid_at: valid_at,
+            adjustments: adjustments,
+            payout_calculations: recomputed_payout_calculations,
+            skipped_order_item_ids: skipped_order_item_ids,
+            audit_log: None,
+        },
+        false => {
+            let audit_log = AuditLog::new(
+                AuditAction::RECALCULATE_EARNINGS,
+                auth_info.user_id.clone(),
+                body.order_id.clone(),
+                Some(body.reason.clone()),
+                Some(json!({
+                    "validAt": valid_at,
+                    "adjustmentIds": adjustments.iter()
+                        .map(|a| a.id.clone())
+                        .collect::<Vec<String>>(),
+                    "payoutCalculationIds": recomputed_payout_calculations.iter()
+                        .map(|p| p.id.clone())
+                        .collect::<Vec<String>>(),
+                    "skippedOrderItemIds": skipped_order_item_ids,
+                })),
+            );
+            let adjustments = match adjustments.len() {
+                0 => vec![],
+                _ => db::write_payout_adjustments(
+                    &conn,
+                    &adjustments,
+                    &recomputed_payout_calculations,
+                    &audit_log,
+                )?,
+            };
+            info!(
+                "{} recalculated earnings for order {}: {} adjustments",
+                auth_info.user_id, body.order_id, adjustments.len()
+            );
+            PayoutRecalculation {
+                order_id: body.order_id,
+                preview: preview,
+                valid_at: valid_at,
+                adjustments: adjustments,
+                payout_calculations: recomputed_payout_calculations,
+                skipped_order_item_ids: skipped_order_item_ids,
+                audit_log: Some(audit_log),
+            }
+        }
+    };
+
+    Ok(HttpResponse::Ok()
+    .content_type("application/json")
+    .json(recalculation))
+}
+
+
+#[serde(rename_all = "camelCase")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditLogsByTargetBody {
+    target_id: String,
+}
+
+pub async fn read_audit_logs(
+    req: HttpRequest,
+    query: Query<AuditLogsByTargetBody>,
+) -> Result<HttpResponse, Error> {
+
+    let auth_info: AuthInfo = rpc::rpc_get_auth_info(
+        &AppState::from(&req).http_client,
+        &req
+    ).await?;
+    is_worthy_enough(&auth_info.user_role).map_err(Error::from)?;
+
+    let conn = AppState::databaseActor(&req)
+                .send(GetPool::Postgres)
+                .await??;
+
+    let audit_logs = db::read_audit_logs_by_target_id(&conn, &query.target_id)?;
+
+    Ok(HttpResponse::Ok()
+    .content_type("application/json")
+    .json(audit_logs))
+}
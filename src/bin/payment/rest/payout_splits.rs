@@ -23,6 +23,15 @@ use crate::models::{
     RpcError,
     AffiliateError,
     Affiliate,
+    PayoutSplitImportReport,
+    PayoutSplitCsvRow,
+    CsvRowError,
+    PayoutSplitRateError,
+    AuthInfo,
+    check_payout_split_rate,
+    parse_payout_split_csv,
+    check_payout_split_rows,
+    payout_splits_to_csv,
 };
 use crate::{AppState};
 use crate::pricing::{
@@ -33,6 +42,8 @@ use crate::pricing::{
 use crate::models::get_one_year_from_now;
 use gm::utils::dates::from_datetimestr_to_option_naivedatetime;
 use crate::rpc::rpc_get_affiliate_profile_by_click_id;
+use crate::rpc;
+use crate::rest::is_worthy_enough;
 
 
 #[serde(rename_all = "camelCase")]
@@ -94,9 +105,50 @@ pub async fn read_payout_split(
 pub struct WritePayoutSplitBody {
     store_or_user_id: String,
     deal_type: PayoutDealType,
+    /// Schedules the PayoutSplit to start at a later date.
+    /// Scheduled PayoutSplits take precedence over the regular one while they last.
+    starts_at: Option<chrono::NaiveDateTime>,
     expires_at: Option<chrono::NaiveDateTime>,
     rate: Option<f64>,
     referrer_id: Option<String>,
+    /// Why the change was made. Who made it is taken from their auth info.
+    reason: Option<String>,
+    /// Writes the PayoutSplit even if the rate is outside the limits.
+    /// Admins only, requires a reason.
+    override_rate_limits: Option<bool>,
+    /// Scopes a BUYER_AFFILIATE deal to a single store's orderItems
+    store_id: Option<String>,
+}
+
+/// Only BUYER_AFFILIATE deals can be scoped to a store
+fn check_store_scope(payout_split: &PayoutSplit) -> Result<(), AffiliateError> {
+    match (&payout_split.store_id, &payout_split.deal_type) {
+        (None, _) => Ok(()),
+        (Some(_), PayoutDealType::BUYER_AFFILIATE) => Ok(()),
+        (Some(store_id), deal_type) => Err(AffiliateError::StoreScopedDealType(errJson!(
+            format!("{:?} PayoutSplit cannot be scoped to store: {}", deal_type, store_id)
+        ))),
+    }
+}
+
+/// Returns the changed_by to record for a PayoutSplit change: the caller's
+/// own userId from their auth info, never one from the request.
+/// Overriding the rate limits is for admins only.
+async fn authorize_payout_split_change(
+    req: &HttpRequest,
+    override_rate_limits: bool,
+) -> Result<Option<String>, Error> {
+
+    let auth_info: AuthInfo = rpc::rpc_get_auth_info(
+        &AppState::from(req).http_client,
+        req
+    ).await?;
+
+    if override_rate_limits {
+        is_worthy_enough(&auth_info.user_role).map_err(Error::from)?;
+    }
+
+    Ok(Some(auth_info.user_id))
 }
 
 pub async fn write_payout_split(
@@ -106,15 +158,28 @@ pub async fn write_payout_split(
 
     let params = json.into_inner();
     debug!("json: {:?}", &params);
+
+    let override_rate_limits = params.override_rate_limits.unwrap_or(false);
+    let changed_by = authorize_payout_split_change(&req, override_rate_limits).await?;
+
     let payout_split = PayoutSplit::new(
         params.store_or_user_id,
         params.deal_type,
+        params.starts_at,
         params.expires_at,
         params.rate.unwrap_or(BUYER_AFFILIATE_FEE_PERCENTAGE),
         params.referrer_id,
-    );
+    ).update_changed_by(changed_by, params.reason)
+    .update_store_id(params.store_id);
     debug!("payout_split: {:?}", &payout_split);
 
+    check_store_scope(&payout_split)?;
+
+    let payout_split = check_payout_split_rate(
+        payout_split,
+        override_rate_limits,
+    ).map_err(PayoutSplitRateError::InvalidRates)?;
+
     let conn = AppState::databaseActor(&req)
                 .send(GetPool::Postgres)
                 .await??;
@@ -197,6 +262,7 @@ pub async fn write_seller_seller_aff_payout_split(
     let seller_aff_payout_split = PayoutSplit::new(
         seller_affiliate.user_id,
         PayoutDealType::SELLER_AFFILIATE,
+        None,
         expiry_date,
         params.seller_affiliate.rate
             .unwrap_or(SELLER_AFFILIATE_FEE_PERCENTAGE), // 5% default if not provided
@@ -206,11 +272,18 @@ pub async fn write_seller_seller_aff_payout_split(
     let referred_seller_payout_split = PayoutSplit::new(
         params.referred_seller_id,
         PayoutDealType::REFERRED_SELLER,
+        None,
         None, // no expiry on the seller's 85% default rate
         1.0 - PLATFORM_FEE_PERCENTAGE, // 85% default rate for sellers
         Some(seller_aff_payout_split.id.clone()), // payoutSplitId of the referrer
     );
 
+    // No overrides on signup, rates outside the limits are rejected
+    let seller_aff_payout_split = check_payout_split_rate(seller_aff_payout_split, false)
+        .map_err(PayoutSplitRateError::InvalidRates)?;
+    let referred_seller_payout_split = check_payout_split_rate(referred_seller_payout_split, false)
+        .map_err(PayoutSplitRateError::InvalidRates)?;
+
     debug!("Writing: ");
     debug!("referred seller's payout split: {:?}", &referred_seller_payout_split);
     debug!("seller affiliate's payout split: {:?}", &seller_aff_payout_split);
@@ -242,6 +315,10 @@ pub async fn write_seller_seller_aff_payout_split(
 pub struct ReadCurrentPayoutSplitsByStoreIdBody {
     store_or_user_ids: Vec<String>,
     payout_deal_types: Option<Vec<PayoutDealType>>,
+    /// Read the PayoutSplits in effect at this date, defaults to now
+    #[serde(default)]
+    #[serde(deserialize_with = "from_datetimestr_to_option_naivedatetime")]
+    valid_at: Option<chrono::NaiveDateTime>,
 }
 pub async fn read_current_payout_splits_by_ids(
     req: HttpRequest,
@@ -253,6 +330,7 @@ pub async fn read_current_payout_splits_by_ids(
     let payout_deal_types: Option<Vec<PayoutDealType>> = json.payout_deal_types;
     debug!("store_or_user_ids: {:?}", &store_or_user_ids);
     debug!("payout_deal_types: {:?}", &payout_deal_types);
+    debug!("valid_at: {:?}", &json.valid_at);
 
     let conn = AppState::databaseActor(&req)
                 .send(GetPool::Postgres)
@@ -262,6 +340,7 @@ pub async fn read_current_payout_splits_by_ids(
         &conn,
         &store_or_user_ids,
         payout_deal_types,
+        json.valid_at,
     )?;
     // Returns a PayoutMethod (camelcased)
     Ok(HttpResponse::Ok()
@@ -307,8 +386,11 @@ pub async fn read_payout_splits_of_user(
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DeletePayoutSplit {
     payout_split_id: String,
+    reason: Option<String>,
 }
 
+/// PayoutSplits are not removed, only ended by setting effective_to,
+/// so past orders can still be traced to the rates they used.
 pub async fn delete_payout_split(
     req: HttpRequest,
     query: Query<DeletePayoutSplit>,
@@ -317,13 +399,17 @@ pub async fn delete_payout_split(
     let params = query.into_inner();
     debug!("json: {:?}", &params);
 
+    let changed_by = authorize_payout_split_change(&req, false).await?;
+
     let conn = AppState::databaseActor(&req)
                 .send(GetPool::Postgres)
                 .await??;
 
-    let payout_split = db::delete_payout_split(
+    let payout_split = db::retire_payout_split(
         &conn,
-        &params.payout_split_id
+        &params.payout_split_id,
+        changed_by,
+        params.reason,
     )?;
 
     // Returns a PayoutMethod (camelcased)
@@ -332,12 +418,34 @@ pub async fn delete_payout_split(
     .json(payout_split))
 }
 
+
+pub async fn read_payout_split_history(
+    req: HttpRequest,
+    query: Query<ReadPayoutSplitBody>,
+) -> Result<HttpResponse, Error> {
+
+    let payout_split_id = query.into_inner().payout_split_id;
+    debug!("payout_split_id: {:?}", &payout_split_id);
+
+    let conn = AppState::databaseActor(&req)
+                .send(GetPool::Postgres)
+                .await??;
+
+    let payout_splits = db::read_payout_split_history(&conn, payout_split_id)?;
+    // Returns every version of the PayoutSplit, oldest first
+    Ok(HttpResponse::Ok()
+    .content_type("application/json")
+    .json(payout_splits))
+}
+
 #[serde(rename_all = "camelCase")]
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DeletePayoutSplitUserId {
     user_id: String,
+    reason: Option<String>,
 }
 
+/// Retires every PayoutSplit of a user, like delete_payout_split
 pub async fn delete_all_payout_splits_for_user(
     req: HttpRequest,
     query: Query<DeletePayoutSplitUserId>,
@@ -346,19 +454,23 @@ pub async fn delete_all_payout_splits_for_user(
     let params = query.into_inner();
     debug!("json: {:?}", &params);
 
+    let changed_by = authorize_payout_split_change(&req, false).await?;
+
     let conn = AppState::databaseActor(&req)
                 .send(GetPool::Postgres)
                 .await??;
 
-    let delete_response = db::delete_all_payout_splits_for_user_id(
+    let payout_splits = db::retire_all_payout_splits_for_user_id(
         &conn,
-        &params.user_id
+        &params.user_id,
+        changed_by,
+        params.reason,
     )?;
 
-    // Returns a PayoutMethod (camelcased)
+    // Returns the retired PayoutSplits (camelcased)
     Ok(HttpResponse::Ok()
     .content_type("application/json")
-    .json(delete_response))
+    .json(payout_splits))
 }
 
 
@@ -376,13 +488,25 @@ pub async fn update_payout_split(
             )).map_err(Error::from)
     }
 
+    let override_rate_limits = params.override_rate_limits.unwrap_or(false);
+    let changed_by = authorize_payout_split_change(&req, override_rate_limits).await?;
+
     let payout_split = PayoutSplit::new(
         params.store_or_user_id,
         params.deal_type.clone(),
+        params.starts_at,
         params.expires_at,
         params.rate.unwrap_or(BUYER_AFFILIATE_FEE_PERCENTAGE),
         params.referrer_id,
-    );
+    ).update_changed_by(changed_by, params.reason)
+    .update_store_id(params.store_id);
+
+    check_store_scope(&payout_split)?;
+
+    let payout_split = check_payout_split_rate(
+        payout_split,
+        override_rate_limits,
+    ).map_err(PayoutSplitRateError::InvalidRates)?;
 
     let conn = AppState::databaseActor(&req)
                 .send(GetPool::Postgres)
@@ -409,7 +533,8 @@ pub async fn update_payout_split(
         let vec_existing_psplit = db::read_current_payout_splits_by_store_or_user_ids(
             &conn,
             &arg_store_or_user_ids,
-            Some(arg_payout_deal_types)
+            Some(arg_payout_deal_types),
+            None,
         ).ok();
 
         if let Some(vpsplit) = vec_existing_psplit {
@@ -458,6 +583,120 @@ pub async fn update_payout_split(
 
 
 
+#[serde(rename_all = "camelCase")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImportPayoutSplitsQuery {
+    /// Why the import was run. Who ran it is taken from their auth info.
+    reason: Option<String>,
+    /// Imports rows even if their rates are outside the limits.
+    /// Admins only, requires a reason.
+    override_rate_limits: Option<bool>,
+}
+
+/// Imports PayoutSplits from CSV rows:
+/// store_or_user_id,deal_type,rate,starts_at,expires_at,referrer,store_id
+///
+/// All rows are written or none are. If any row is invalid, nothing is
+/// written and the report lists the error for each invalid row.
+pub async fn import_payout_splits_csv(
+    req: HttpRequest,
+    query: Query<ImportPayoutSplitsQuery>,
+    body: String,
+) -> Result<HttpResponse, Error> {
+
+    let params = query.into_inner();
+    debug!("query: {:?}", &params);
+
+    let override_rate_limits = params.override_rate_limits.unwrap_or(false);
+    let changed_by = authorize_payout_split_change(&req, override_rate_limits).await?;
+
+    let (rows, mut errors) = parse_payout_split_csv(
+        &body,
+        changed_by,
+        params.reason,
+    );
+
+    let mut checked_rows: Vec<PayoutSplitCsvRow> = vec![];
+    for row in rows.into_iter() {
+        let line = row.line;
+        match check_payout_split_rate(row.payout_split, override_rate_limits) {
+            Ok(payout_split) => checked_rows.push(PayoutSplitCsvRow {
+                line: line,
+                payout_split: payout_split,
+            }),
+            Err(violations) => errors.extend(violations.into_iter().map(|v| CsvRowError {
+                line: line,
+                store_or_user_id: Some(v.store_or_user_id),
+                message: v.message,
+            })),
+        }
+    }
+    let rows = checked_rows;
+
+    let conn = AppState::databaseActor(&req)
+                .send(GetPool::Postgres)
+                .await??;
+
+    let referrer_ids = rows.iter()
+        .filter_map(|r| r.payout_split.referrer_id.clone())
+        .collect::<Vec<String>>();
+    let store_or_user_ids = rows.iter()
+        .map(|r| r.payout_split.store_or_user_id.clone())
+        .collect::<Vec<String>>();
+
+    let referrer_payout_splits = db::read_payout_splits_by_ids(&conn, referrer_ids)?;
+    let open_payout_splits = db::read_open_payout_splits(&conn, Some(store_or_user_ids))?;
+
+    errors.extend(check_payout_split_rows(
+        &rows,
+        &referrer_payout_splits,
+        &open_payout_splits,
+    ));
+    errors.sort_by_key(|e| e.line);
+
+    if errors.len() > 0 {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("application/json")
+            .json(PayoutSplitImportReport {
+                imported: vec![],
+                errors: errors,
+            }))
+    }
+
+    let imported = db::write_many_payout_splits(
+        &conn,
+        rows.into_iter().map(|r| r.payout_split).collect(),
+    )?;
+
+    Ok(HttpResponse::Ok()
+    .content_type("application/json")
+    .json(PayoutSplitImportReport {
+        imported: imported,
+        errors: vec![],
+    }))
+}
+
+
+/// Exports all current PayoutSplits (including scheduled ones)
+/// as CSV, in the same format import_payout_splits_csv reads
+pub async fn export_payout_splits_csv(
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+
+    let conn = AppState::databaseActor(&req)
+                .send(GetPool::Postgres)
+                .await??;
+
+    let payout_splits = db::read_open_payout_splits(&conn, None)?;
+
+    Ok(HttpResponse::Ok()
+    .content_type("text/csv")
+    .header("Content-Disposition", "attachment; filename=\"payout_splits.csv\"")
+    .body(payout_splits_to_csv(&payout_splits)))
+}
+
+
+
 #[test]
 fn deserializes_seller_affiliate_payout_split_body_null_expiry() {
 
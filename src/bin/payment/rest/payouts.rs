@@ -32,6 +32,7 @@ use crate::models::{
     PayoutStatus,
     PayoutPeriod,
     Payout,
+    PayoutStatement,
     UserPublic,
     PayeeId, // String
     PayoutEmail, // String
@@ -128,6 +129,17 @@ pub async fn create_payout(
         None,
     )?;
 
+    // 3a(ii). payees who still owe a clawback from an earlier period have
+    // their unpaid items carried forward, to be netted against this period
+    let payout_items = payout_items
+        .into_iter()
+        .chain(db::read_payout_items_carried_forward(
+            &conn,
+            payout_period.start_period,
+        )?.into_iter())
+        .unique_by(|p: &PayoutItem| p.id.clone())
+        .collect::<Vec<PayoutItem>>();
+
     // 3b. split UNPAID and REFUNDING items
     let payout_items_refunding: Vec<_> = payout_items.clone()
         .into_iter()
@@ -178,6 +190,14 @@ pub async fn create_payout(
             auth_info.user_id,
         );
 
+    // 5a(ii). Clawbacks bigger than a payee's earnings can't be paid out.
+    // Their items are left as they are and carried forward to the next period.
+    let (
+        payout_hashmap,
+        carried_forward_payee_ids
+    ): (HashMap<PayeeId, Payout>, Vec<PayeeId>) =
+        payouts::partition_carried_forward_payouts(payout_hashmap);
+
     // 5b. Partition Payouts by whether has payout email or not.
     let (
         payouts_missing_payout_method_vec,
@@ -204,10 +224,10 @@ pub async fn create_payout(
         .flat_map(|p: &Payout| p.payout_item_ids.clone())
         .collect::<Vec<String>>();
 
-    let refund_item_ids = payout_items_refunding
-        .iter()
-        .map(|pitem: &PayoutItem| pitem.id.clone())
-        .collect::<Vec<String>>();
+    let refund_item_ids = payouts::refund_item_ids_to_settle(
+        &payout_items_refunding,
+        &carried_forward_payee_ids,
+    );
 
     // If no payoutItems are found in this month, return
     if payouts_vec.len() < 1 {
@@ -485,6 +505,53 @@ pub async fn read_payouts_by_ids(
         .json(payouts))
 }
 
+#[serde(rename_all = "camelCase")]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GetPayoutStatementBody {
+    payout_id: String,
+}
+
+/// A Payout's items, with manual adjustments listed separately
+/// from order earnings and refunds
+pub async fn read_payout_statement(
+    req: HttpRequest,
+    query: Query<GetPayoutStatementBody>,
+) -> Result<HttpResponse, Error> {
+
+    let payout_id = query.into_inner().payout_id;
+    debug!("retrieving statement for payout: {:?}", &payout_id);
+
+    let conn = AppState::databaseActor(&req)
+                .send(GetPool::Postgres)
+                .await??;
+
+    let payout = match db::read_many_payouts(&conn, &vec![payout_id.clone()])?.pop() {
+        Some(p) => p,
+        None => return Ok(HttpResponse::NotFound()
+            .content_type("application/json")
+            .json(json!({ "message": format!("Payout not found: {}", payout_id) }))),
+    };
+
+    let payout_items = db::read_payout_items_by_ids(&conn, &payout.payout_item_ids)?;
+    let manual_adjustments = db::read_manual_adjustments_by_payout_item_ids(
+        &conn,
+        &payout.payout_item_ids,
+    )?;
+
+    let statement = PayoutStatement::new(
+        payout.id,
+        payout.payee_id,
+        payout.payee_type,
+        payout.currency.as_string(),
+        payout_items,
+        manual_adjustments,
+    );
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .json(statement))
+}
+
 #[serde(rename_all = "camelCase")]
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct GetPayoutsByStoreIdBody {
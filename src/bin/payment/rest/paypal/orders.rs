@@ -190,20 +190,21 @@ pub async fn paypal_confirm_order(
     tx.update_with_paypal_response(paypal_response);
 
     // 3. create payout_items
-    let payout_items: Vec<PayoutItem> = to_payout_items(
+    let (payout_items, payout_calculations) = to_payout_items(
         &conn,
         confirm_order.payout_items.clone().expect("missing payout_items on OrderDb in rpc_confirm_order()"),
         &tx.id,
         &tx.created_at,
         None
-    );
+    )?;
 
     // 4. write both transaction and payout_items to DB in single transaction
     // for double-entry accounting.
     let (tx_result, pitems_result) = db::write_transaction_and_payout_items(
         &conn,
         &tx,
-        &payout_items
+        &payout_items,
+        &payout_calculations,
     ).map_err(Error::from)?;
 
     debug!("tx: {:?}", &tx_result);
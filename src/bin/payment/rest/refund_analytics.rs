@@ -0,0 +1,58 @@
+use actix_web::{
+    HttpRequest,
+    HttpResponse,
+    web::Query,
+    Error,
+};
+
+use crate::db;
+use crate::db::GetPool;
+use crate::models::{
+    AuthInfo,
+    RefundAnalyticsGroupBy,
+    RefundPeriod,
+};
+use crate::{AppState};
+use crate::rpc;
+use crate::rest::is_worthy_enough;
+
+
+#[serde(rename_all = "camelCase")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReadRefundAnalyticsQuery {
+    start: chrono::NaiveDateTime,
+    end: chrono::NaiveDateTime,
+    group_by: RefundAnalyticsGroupBy,
+    /// For PERIOD, MONTH if not provided
+    period: Option<RefundPeriod>,
+}
+
+/// Refund counts, amounts and rates by store, reason or period,
+/// e.g. to find stores with unusually many refunds.
+pub async fn read_refund_analytics(
+    req: HttpRequest,
+    query: Query<ReadRefundAnalyticsQuery>,
+) -> Result<HttpResponse, Error> {
+
+    let auth_info: AuthInfo = rpc::rpc_get_auth_info(
+        &AppState::from(&req).http_client,
+        &req
+    ).await?;
+    is_worthy_enough(&auth_info.user_role).map_err(Error::from)?;
+
+    let conn = AppState::databaseActor(&req)
+                .send(GetPool::Postgres)
+                .await??;
+
+    let refund_aggregates = db::read_refund_aggregates(
+        &conn,
+        query.start,
+        query.end,
+        &query.group_by,
+        &query.period.clone().unwrap_or_default(),
+    )?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .json(refund_aggregates))
+}
@@ -0,0 +1,190 @@
+use actix_web::{
+    HttpRequest,
+    HttpResponse,
+    web::Query,
+    web::Json,
+    Error,
+};
+
+use crate::db;
+use crate::db::GetPool;
+use crate::models::{
+    ErrJson,
+    AuthInfo,
+    RefundError,
+    RefundRequest,
+    RefundRequestStatus,
+};
+use crate::{AppState};
+use crate::rpc;
+use crate::rest::{
+    is_worthy_enough,
+    execute_refund,
+    RefundOrderBody,
+};
+
+
+#[serde(rename_all = "camelCase")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReadRefundRequestsQuery {
+    /// PENDING_APPROVAL if not provided
+    status: Option<RefundRequestStatus>,
+}
+
+pub async fn read_refund_requests(
+    req: HttpRequest,
+    query: Query<ReadRefundRequestsQuery>,
+) -> Result<HttpResponse, Error> {
+
+    let auth_info: AuthInfo = rpc::rpc_get_auth_info(
+        &AppState::from(&req).http_client,
+        &req
+    ).await?;
+    is_worthy_enough(&auth_info.user_role).map_err(Error::from)?;
+
+    let conn = AppState::databaseActor(&req)
+                .send(GetPool::Postgres)
+                .await??;
+
+    let refund_requests = db::read_refund_requests_by_status(
+        &conn,
+        query.status.clone().unwrap_or(RefundRequestStatus::PENDING_APPROVAL),
+    )?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .json(refund_requests))
+}
+
+
+#[serde(rename_all = "camelCase")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReviewRefundRequestBody {
+    refund_request_id: String,
+    review_notes: Option<String>,
+}
+
+/// Approves a refund request, and makes the refund with Stripe or Paypal.
+/// If the refund fails, the request goes back to PENDING_APPROVAL
+/// with the error, and can be approved again.
+pub async fn approve_refund_request(
+    req: HttpRequest,
+    json: Json<ReviewRefundRequestBody>,
+) -> Result<HttpResponse, Error> {
+
+    let body = json.into_inner();
+
+    let auth_info: AuthInfo = rpc::rpc_get_auth_info(
+        &AppState::from(&req).http_client,
+        &req
+    ).await?;
+    is_worthy_enough(&auth_info.user_role).map_err(Error::from)?;
+
+    let conn = AppState::databaseActor(&req)
+                .send(GetPool::Postgres)
+                .await??;
+
+    let refund_request = review_refund_request(
+        &conn,
+        &body,
+        RefundRequestStatus::APPROVED,
+        &auth_info,
+    )?;
+
+    let refund_order = serde_json::from_value::<RefundOrderBody>(
+        refund_request.refund_order.clone()
+    ).map_err(Error::from)?;
+
+    info!("{} approved refund request {}", auth_info.user_id, refund_request.id);
+
+    match execute_refund(&req, refund_order).await {
+        Ok((refund, tx)) => {
+            let refund_request = db::update_refund_request_outcome(
+                &conn,
+                &RefundRequest {
+                    status: RefundRequestStatus::REFUNDED,
+                    refund_id: Some(refund.id.clone()),
+                    last_error: None,
+                    ..refund_request
+                },
+            )?;
+            Ok(HttpResponse::Ok()
+                .content_type("application/json")
+                .json(json!({
+                    "refundRequest": refund_request,
+                    "refund": refund,
+                    "transaction": tx,
+                })))
+        },
+        Err(e) => {
+            warn!("approved refund request {} failed: {}", refund_request.id, e);
+            db::update_refund_request_outcome(
+                &conn,
+                &RefundRequest {
+                    status: RefundRequestStatus::PENDING_APPROVAL,
+                    last_error: Some(e.to_string()),
+                    ..refund_request
+                },
+            )?;
+            Err(e)
+        },
+    }
+}
+
+
+/// Rejects a refund request, nothing is refunded
+pub async fn reject_refund_request(
+    req: HttpRequest,
+    json: Json<ReviewRefundRequestBody>,
+) -> Result<HttpResponse, Error> {
+
+    let body = json.into_inner();
+
+    let auth_info: AuthInfo = rpc::rpc_get_auth_info(
+        &AppState::from(&req).http_client,
+        &req
+    ).await?;
+    is_worthy_enough(&auth_info.user_role).map_err(Error::from)?;
+
+    let conn = AppState::databaseActor(&req)
+                .send(GetPool::Postgres)
+                .await??;
+
+    let refund_request = review_refund_request(
+        &conn,
+        &body,
+        RefundRequestStatus::REJECTED,
+        &auth_info,
+    )?;
+
+    info!("{} rejected refund request {}", auth_info.user_id, refund_request.id);
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .json(refund_request))
+}
+
+
+/// Takes a refund request out of PENDING_APPROVAL, once
+fn review_refund_request(
+    conn: &diesel::PgConnection,
+    body: &ReviewRefundRequestBody,
+    status: RefundRequestStatus,
+    auth_info: &AuthInfo,
+) -> Result<RefundRequest, Error> {
+
+    let now = chrono::NaiveDateTime::from_timestamp(chrono::Utc::now().timestamp(), 0);
+
+    db::review_refund_request(
+        conn,
+        &body.refund_request_id,
+        status,
+        &auth_info.user_id,
+        now,
+        body.review_notes.clone(),
+    )?
+    .ok_or(Error::from(RefundError::InvalidRefundRequest(errJson!(format!(
+        "refund request {} does not exist, or is not pending approval",
+        body.refund_request_id
+    )))))
+}
@@ -12,6 +12,7 @@ use futures::{
     Stream,
 };
 use std::str::FromStr;
+use itertools::Itertools;
 use serde::Serialize;
 use std::marker::Send;
 
@@ -25,6 +26,7 @@ use crate::models::{
     Transaction,
     RefundReason,
     Refund,
+    RefundStatus,
     PaypalRefundResponse,
     PaypalRefundDetails,
     PaypalErrorResponse,
@@ -32,10 +34,25 @@ use crate::models::{
     PayoutItem,
     PayeeType,
     PayoutStatus,
+    RefundError,
+    ClawbackPolicy,
+    AuthInfo,
+    RefundRequest,
+    refund_approval_reasons,
+    refund_approval_threshold,
+    refund_window_days,
+    create_partial_refund_payout_items,
+    create_clawback_payout_items,
+    create_refund_reversal_transaction,
+    reverse_refund_payout_items,
+    refund_reversal_id,
+    refundable_total,
 };
 use crate::rest::PaymentProcessor;
 use crate::payment_clients::PaypalRequest;
+use crate::rpc;
 use crate::rpc::rpc_update_orders_for_refunds;
+use crate::rest::is_worthy_enough;
 // import stripe traits to enable Requests
 use gm::models::stripe;
 use gm::models::stripe::{
@@ -57,9 +74,16 @@ use crate::payment_clients::{
 pub struct RefundOrderBody {
     order_id: String,
     refund_order_item_ids: Vec<String>,
+    /// Partial refund in cents (excluding taxes), pro-rated across the
+    /// orderItems' payout items. Refunds whatever is left if not provided.
+    refund_amount: Option<i32>,
+    /// Who pays back shares which were already paid out, admins only.
+    /// Defaults to clawing back each share from its payee.
+    clawback_policy: Option<ClawbackPolicy>,
     charge_id: String,
     taxes: i32,
-    reason: Option<String>,
+    /// Unknown reasons are rejected. Other needs reason_details.
+    reason: Option<RefundReason>,
     reason_details: Option<String>,
     payment_intent_id: Option<String>, // only for Stripe
     paypal_invoice_number: Option<String>, // only for Paypal
@@ -86,14 +110,114 @@ pub struct RefundOrderBody {
 
 
 
+/// Refunds an order's items straight away, unless the refund needs an
+/// admin's approval: then a PENDING_APPROVAL refund request is created,
+/// and nothing is refunded until an admin approves it.
 pub async fn refund_endpoint(
     req: HttpRequest,
     json: Json<RefundOrderBody>
 ) -> Result<HttpResponse, Error> {
-    match json.payment_processor {
-        PaymentProcessor::Stripe => refund_stripe(req, json).await,
-        PaymentProcessor::Paypal => refund_paypal(req, json).await,
+
+    let body = json.into_inner();
+    validate_refund_reason(&body)?;
+
+    let conn = AppState::databaseActor(&req)
+                .send(GetPool::Postgres)
+                .await??;
+
+    if let Some(refund_request) = request_refund_approval(&conn, &body)? {
+        return Ok(HttpResponse::Accepted()
+        .content_type("application/json")
+        .json(json!({
+            "refundRequest": refund_request,
+        })))
     }
+
+    let (refund, tx) = execute_refund(&req, body).await?;
+
+    Ok(HttpResponse::Ok()
+    .content_type("application/json")
+    .json(json!({
+        "refund": refund,
+        "transaction": tx,
+    })))
+}
+
+fn validate_refund_reason(body: &RefundOrderBody) -> Result<(), Error> {
+    let has_details = body.reason_details
+        .as_ref()
+        .map(|d| !d.trim().is_empty())
+        .unwrap_or(false);
+    match (&body.reason, has_details) {
+        (Some(RefundReason::Other), false) => Err(Error::from(RefundError::InvalidReason(
+            errJson!("reasonDetails are needed for refunds with reason: other")
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Makes the refund with Stripe or Paypal, then records it
+pub async fn execute_refund(
+    req: &HttpRequest,
+    body: RefundOrderBody,
+) -> Result<(Refund, Option<Transaction>), Error> {
+    match body.payment_processor {
+        PaymentProcessor::Stripe => refund_stripe(req, body).await,
+        PaymentProcessor::Paypal => refund_paypal(req, body).await,
+    }
+}
+
+/// Creates a refund request if the refund is over the approval threshold,
+/// or outside the refund window. None if it can be made straight away.
+fn request_refund_approval(
+    conn: &PgConnection,
+    body: &RefundOrderBody,
+) -> Result<Option<RefundRequest>, Error> {
+
+    let payout_items = read_refundable_payout_items(conn, body)?;
+    let refund_total = refund_subtotal(&payout_items, body.refund_amount)?
+        + body.taxes;
+
+    let paid_at = db::read_payment_transaction_of_order(conn, &body.order_id)?
+        .map(|tx| tx.created_at);
+
+    // partial refunds count towards the threshold together,
+    // including amounts held by requests still waiting on approval
+    let refunded_total = db::read_refunded_total_of_order(conn, &body.order_id)?
+        + db::read_open_refund_requests_of_order(conn, &body.order_id)?
+            .iter()
+            .map(|r| r.refund_total)
+            .sum::<i32>();
+
+    let now = chrono::NaiveDateTime::from_timestamp(chrono::Utc::now().timestamp(), 0);
+
+    let approval_reasons = refund_approval_reasons(
+        refund_total,
+        refunded_total,
+        paid_at,
+        now,
+        refund_approval_threshold(),
+        refund_window_days(),
+    );
+    if approval_reasons.is_empty() {
+        return Ok(None)
+    }
+
+    let refund_request = RefundRequest::new(
+        body.order_id.clone(),
+        body.payment_processor.as_str().to_string(),
+        refund_total,
+        serde_json::to_value(body).map_err(Error::from)?,
+        approval_reasons,
+        now,
+    );
+
+    info!("refund of {} for order {} needs approval: {:?}",
+        refund_total, body.order_id, refund_request.approval_reasons);
+
+    db::write_refund_request(conn, &refund_request)
+        .map(Some)
+        .map_err(Error::from)
 }
 
 
@@ -102,12 +226,10 @@ pub async fn refund_endpoint(
 /////////////////////////////////
 
 
-pub async fn refund_stripe(
-    req: HttpRequest,
-    json: Json<RefundOrderBody>,
-) -> Result<HttpResponse, Error> {
-
-    let body = json.into_inner();
+async fn refund_stripe(
+    req: &HttpRequest,
+    body: RefundOrderBody,
+) -> Result<(Refund, Option<Transaction>), Error> {
 
     let payment_intent_id = match body.payment_intent_id.clone() {
         None => return Err(Error::from(
@@ -116,129 +238,246 @@ pub async fn refund_stripe(
         Some(id) => id,
     };
 
-    let conn = AppState::databaseActor(&req)
+    let clawback_policy = check_clawback_policy(req, &body).await?;
+
+    let conn = AppState::databaseActor(req)
                 .send(GetPool::Postgres)
                 .await??;
 
-    // read all payoutItems associated with orderItemIds
-    let payout_items = db::read_payout_items_by_order_item_ids(
-        &conn,
-        &body.refund_order_item_ids,
-    ).map_err(Error::from)?;
-    // Guard against refunding an already paid-out payoutItem
-    // accept only UNPAID payoutitems
-    if payout_items.clone().into_iter().any(|p: PayoutItem| {
-        p.payout_status != PayoutStatus::UNPAID
-    }) {
-        return Err(StripeError::Refund(errJson!(
-            format!("PayoutItems already refunded: {:?}", &payout_items)
-        ))).map_err(Error::from)
-    }
-
-    // sum payout amounts for each entity to refund
-    let summed_payouts_for_entities = sum_payouts_for_all_payees(
-        &payout_items,
-    );
-    let spfe = summed_payouts_for_entities;
+    // read all payoutItems (and earlier refund items) of the orderItemIds
+    let payout_items = read_refundable_payout_items(&conn, &body)?;
 
     // Total amount to refund
-    let total_amount = spfe.total_seller_payment
-        + spfe.seller_payment_processing_fees
-        + spfe.total_platform_fee
-        + spfe.total_buyer_affiliate_fee
-        + spfe.total_seller_affiliate_fee
+    let total_amount = refund_subtotal(&payout_items, body.refund_amount)?
         + body.taxes;
 
-    // 1. dispatch a Stripe refund
-    let stripe_refund_response: stripe::Refund = AppState::stripeActor(&req)
+    // 1. Lookup Stripe payment intent details, before refunding anything
+    // so nothing after the refund can fail on it
+    let stripe_payment_intent: PaymentIntent = AppState::stripeActor(req)
+        .send(PaymentIntentMsg::Retrieve(payment_intent_id.clone()))
+        .await??;
+
+    // 1b. dispatch a Stripe refund
+    let stripe_refund_response: stripe::Refund = AppState::stripeActor(req)
         .send(RefundMsg::Create(
             RefundCreateParams {
                 amount: Some(total_amount as i64),
                 charge: None, // deprecated for stripe. for paypal only
                 payment_intent: Some(payment_intent_id.clone()),
-                reason: body.reason.clone(),
-                metadata: Default::default(),
+                reason: body.reason.map(|r| r.stripe_reason().to_string()),
+                // the refund webhook records the refund from these,
+                // if it isn't recorded here
+                metadata: Some(stripe_refund_metadata(
+                    &body.order_id,
+                    &body.refund_order_item_ids,
+                    body.taxes,
+                )),
                 refund_application_fee: Default::default(),
                 reverse_transfer: Default::default(),
             }
         ))
         .await??;
 
-    // 1b. Lookup Stripe payment intent details
-    let stripe_payment_intent: PaymentIntent = AppState::stripeActor(&req)
-        .send(PaymentIntentMsg::Retrieve(payment_intent_id.clone()))
-        .await??;
+    debug!("stripe_refund_response: {:#?}", &stripe_refund_response);
+
+    // nothing was refunded, so there is nothing to record
+    let refund_status = RefundStatus::from_stripe(
+        stripe_refund_response.status.as_ref().map(|s| s.as_str())
+    );
+    if refund_status.is_reversed() {
+        return Err(Error::from(StripeError::Refund(errJson!(format!(
+            "refund {} {:?}: {:?}",
+            stripe_refund_response.id,
+            refund_status,
+            stripe_refund_response.failure_reason,
+        )))))
+    }
 
+    // a refund webhook can arrive before we get here, and record the refund
+    let existing = db::read_many_refunds(&conn, vec![stripe_refund_response.id.to_string()])?;
+    if let Some(refund) = existing.into_iter().next() {
+        warn!("Stripe refund {} was already recorded by its webhook", refund.id);
+        let refund = retry_order_update_for_refund(
+            AppState::httpClient(req),
+            &conn,
+            refund,
+        ).await?;
+        return Ok((refund, None))
+    }
 
-    debug!("stripe_refund_response: {:#?}", &stripe_refund_response);
-    let r = stripe_refund_response.clone();
+    // 2. pro-rate the refund across payees, then create refund, transaction structs
+    let (tx, refund, refund_items) = create_stripe_refund_records(
+        &stripe_refund_response,
+        &payout_items,
+        body.refund_amount,
+        &clawback_policy,
+        RefundRecordDetails {
+            order_id: body.order_id,
+            order_item_ids: body.refund_order_item_ids,
+            taxes: body.taxes,
+            reason: body.reason.map(|r| r.as_str().to_string()),
+            reason_details: body.reason_details,
+            charge_id: stripe_payment_intent.id.to_string(),
+            payment_intent_id: Some(stripe_payment_intent.id.to_string()),
+            customer_id: stripe_payment_intent.customer,
+            payment_method_id: stripe_payment_intent.payment_method,
+        },
+    )?;
+
+    // 3. write a refund_items and transaction
+    let (tx, refund, _ritems) = db::write_transaction_and_refund_and_refund_items(
+        &conn,
+        &tx,
+        &refund,
+        &refund_items,
+    ).map_err(Error::from)?;
+
+    // 4. Update Order, OrderSnapshots, OrderItem statuses
+    let refund = update_order_for_refund(
+        AppState::httpClient(req),
+        &conn,
+        tx.clone(),
+        refund,
+    ).await?;
+
+    Ok((refund, Some(tx)))
+}
+
+
+/// Metadata keys on Stripe refunds made by refund_stripe
+pub const STRIPE_REFUND_ORDER_ID_KEY: &str = "orderId";
+pub const STRIPE_REFUND_ORDER_ITEM_IDS_KEY: &str = "orderItemIds";
+pub const STRIPE_REFUND_TAXES_KEY: &str = "taxes";
+
+fn stripe_refund_metadata(
+    order_id: &str,
+    order_item_ids: &Vec<String>,
+    taxes: i32,
+) -> stripe::Metadata {
+    let mut metadata = stripe::Metadata::new();
+    metadata.insert(STRIPE_REFUND_ORDER_ID_KEY.to_string(), order_id.to_string());
+    // Stripe metadata values are at most 500 characters
+    let order_item_ids = order_item_ids.join(",");
+    if order_item_ids.len() <= 500 {
+        metadata.insert(STRIPE_REFUND_ORDER_ITEM_IDS_KEY.to_string(), order_item_ids);
+        metadata.insert(STRIPE_REFUND_TAXES_KEY.to_string(), taxes.to_string());
+    }
+    metadata
+}
+
+/// Whether refund_stripe made the refund, rather than the Stripe dashboard
+pub fn is_refund_initiated_by_us(stripe_refund: &stripe::Refund) -> bool {
+    stripe_refund.metadata.contains_key(STRIPE_REFUND_ORDER_ID_KEY)
+}
+
+/// The orderItemIds and taxes refund_stripe refunded, from the refund's metadata.
+/// None for refunds made from the Stripe dashboard.
+pub fn stripe_refund_order_items(stripe_refund: &stripe::Refund) -> Option<(Vec<String>, i32)> {
+    let order_item_ids = stripe_refund.metadata
+        .get(STRIPE_REFUND_ORDER_ITEM_IDS_KEY)?
+        .split(',')
+        .filter(|id| !id.is_empty())
+        .map(String::from)
+        .collect::<Vec<String>>();
+    let taxes = stripe_refund.metadata
+        .get(STRIPE_REFUND_TAXES_KEY)?
+        .parse::<i32>()
+        .ok()?;
+    match order_item_ids.is_empty() {
+        true => None,
+        false => Some((order_item_ids, taxes)),
+    }
+}
+
+
+/// Everything a refund's records need, besides the Stripe or Paypal refund
+#[derive(Clone, Debug)]
+pub struct RefundRecordDetails {
+    pub order_id: String,
+    pub order_item_ids: Vec<String>,
+    pub taxes: i32,
+    pub reason: Option<String>,
+    pub reason_details: Option<String>,
+    /// PaymentIntent id for Stripe, the Paypal refund id for Paypal
+    pub charge_id: String,
+    pub payment_intent_id: Option<String>,
+    pub customer_id: Option<String>,
+    pub payment_method_id: Option<String>,
+}
+
+/// Creates the Refund, negative Transaction and refund payout items for a
+/// Stripe refund, pro-rating refund_amount across the payout items.
+/// Used for refunds we make, and refunds made from the Stripe dashboard.
+pub fn create_stripe_refund_records(
+    stripe_refund: &stripe::Refund,
+    payout_items: &Vec<PayoutItem>,
+    refund_amount: Option<i32>,
+    clawback_policy: &ClawbackPolicy,
+    details: RefundRecordDetails,
+) -> Result<(Transaction, Refund, Vec<PayoutItem>), Error> {
+
+    let r = stripe_refund.clone();
 
     let created_at = chrono::NaiveDateTime::from_timestamp(r.created, 0);
     let refund_currency = r.currency.to_string();
 
-    // 2. create refund, transaction structs
+    let refund_items: Vec<PayoutItem> = create_partial_refund_payout_items(
+        payout_items,
+        refund_amount,
+        &created_at,
+        &r.id.to_string(),
+    ).map_err(|e| Error::from(RefundError::InvalidAmount(errJson!(e))))?;
+    // shares which were already paid out are clawed back from future payouts
+    let refund_items = create_clawback_payout_items(
+        payout_items,
+        refund_items,
+        clawback_policy,
+    );
+    // refund items are negative
+    let spfe = sum_payouts_for_all_payees(&refund_items);
+
     let refund = Refund {
-        id: stripe_refund_response.id.to_string(),
-        transaction_id: stripe_refund_response
-            .balance_transaction.clone()
-            .expect("balance_transaction missing in refund response")
-            .id().to_string(),
-        order_id: body.order_id,
-        order_item_ids: Some(body.refund_order_item_ids.clone()),
+        id: r.id.to_string(),
+        // pending refunds may not have a balance transaction yet,
+        // it's filled in when the refund is reconciled
+        transaction_id: match &r.balance_transaction {
+            Some(bt) => bt.id().to_string(),
+            None => r.id.to_string(),
+        },
+        order_id: details.order_id,
+        order_item_ids: Some(details.order_item_ids),
         created_at: created_at,
-        reason: body.reason.clone(),
-        reason_details: body.reason_details,
+        reason: details.reason,
+        reason_details: details.reason_details,
+        status: RefundStatus::from_stripe(r.status.as_ref().map(|s| s.as_str())),
+        failure_reason: r.failure_reason.clone(),
+        updated_at: None,
+        order_updated_at: None,
     };
 
     let tx = Transaction {
         id: refund.id.clone(), // txn_xxxxxx
-        subtotal: -sum_subtotal(
+        subtotal: sum_subtotal(
             spfe.total_seller_payment + spfe.seller_payment_processing_fees,
             spfe.total_platform_fee,
             spfe.total_buyer_affiliate_fee,
             spfe.total_seller_affiliate_fee,
         ),
-        taxes: -body.taxes,
-        payment_processing_fee: -spfe.seller_payment_processing_fees,
+        taxes: -details.taxes,
+        payment_processing_fee: spfe.seller_payment_processing_fees,
         created_at: created_at,
         currency: Currency::from_str(&refund_currency).ok(),
-        customer_id: stripe_payment_intent.customer,
+        customer_id: details.customer_id,
         order_id: Some(refund.order_id.clone()),
-        charge_id: Some(payment_intent_id),
+        charge_id: Some(details.charge_id),
         payment_processor: Some(String::from("Stripe")),
-        payment_method_id: stripe_payment_intent.payment_method,
-        payment_intent_id: Some(stripe_payment_intent.id.to_string()),
+        payment_method_id: details.payment_method_id,
+        payment_intent_id: details.payment_intent_id,
         refund_id: Some(r.id.to_string()), // ref_xxxxxxx
         details: None,
     };
 
-    let refund_items: Vec<PayoutItem> = create_refund_payout_items(
-        &payout_items,
-        &created_at,
-        &tx.id,
-    );
-
-    // 3. write a refund_items and transaction
-    let (tx, refund, _ritems) = db::write_transaction_and_refund_and_refund_items(
-        &conn,
-        &tx,
-        &refund,
-        &refund_items,
-    ).map_err(Error::from)?;
-
-    // 4. Update Order, OrderSnapshots, OrderItem statuses
-    let _order_db = rpc_update_orders_for_refunds(
-        AppState::httpClient(&req),
-        tx.clone(),
-        refund.clone(),
-    ).await?;
-
-    Ok(HttpResponse::Ok()
-    .content_type("application/json")
-    .json(json!({
-        "refund": refund,
-        "transaction": tx,
-    })))
+    Ok((tx, refund, refund_items))
 }
 
 
@@ -247,48 +486,26 @@ pub async fn refund_stripe(
 /////////////////////////////////
 
 
-pub async fn refund_paypal(
-    req: HttpRequest,
-    json: Json<RefundOrderBody>,
-) -> Result<HttpResponse, Error> {
+async fn refund_paypal(
+    req: &HttpRequest,
+    body: RefundOrderBody,
+) -> Result<(Refund, Option<Transaction>), Error> {
 
-    let body = json.into_inner();
+    let clawback_policy = check_clawback_policy(req, &body).await?;
 
-    let conn = AppState::databaseActor(&req)
+    let conn = AppState::databaseActor(req)
                 .send(GetPool::Postgres)
                 .await??;
 
-    // read all payoutItems associated with orderItemIds
-    let payout_items = db::read_payout_items_by_order_item_ids(
-        &conn,
-        &body.refund_order_item_ids,
-    ).map_err(Error::from)?;
-    // Guard against refunding an already paid-out payoutItem
-    // accept only UNPAID payoutitems
-    if payout_items.clone().into_iter().any(|p: PayoutItem| {
-        p.payout_status != PayoutStatus::UNPAID
-    }) {
-        return Err(StripeError::Refund(errJson!(
-            format!("PayoutItems already refunded: {:?}", &payout_items)
-        ))).map_err(Error::from)
-    }
-
-    // sum payout amounts for each entity to refund
-    let summed_payouts_for_entities = sum_payouts_for_all_payees(
-        &payout_items,
-    );
-    let spfe = summed_payouts_for_entities;
+    // read all payoutItems (and earlier refund items) of the orderItemIds
+    let payout_items = read_refundable_payout_items(&conn, &body)?;
 
     // Total amount to refund
-    let total_amount = spfe.total_seller_payment
-        + spfe.seller_payment_processing_fees
-        + spfe.total_platform_fee
-        + spfe.total_buyer_affiliate_fee
-        + spfe.total_seller_affiliate_fee
+    let total_amount = refund_subtotal(&payout_items, body.refund_amount)?
         + body.taxes;
 
     // 1. dispatch a Paypal refund
-    let paypal_refund_response = AppState::paypalActor(&req)
+    let paypal_refund_response = AppState::paypalActor(req)
         .send(PaypalRequest::PostBody(
             format!("/v1/payments/sale/{}/refund", body.charge_id),
             json!({
@@ -328,15 +545,15 @@ pub async fn refund_paypal(
     };
 
     // 2. get Paypal refund details
-    let refund_details_response = AppState::paypalActor(&req)
+    let refund_details_response = AppState::paypalActor(req)
         .send(PaypalRequest::Get::<serde_json::Value>(
             format!("/v2/payments/refunds/{}", paypal_refund.id)
         ))
         .await
-        .map_err(|e| Error::from(PaypalError::InternalError(errJson!(e))))?;
+        .map_err(|e| Error::from(PaypalError::InternalError(errJson!(e))))??;
 
     let refund_details = serde_json::from_str::<PaypalRefundDetails>(
-            &refund_details_response.expect("refund_details_response")
+            &refund_details_response
         ).map_err(Error::from)?;
 
     debug!("refund_details: {:#?}", &refund_details);
@@ -345,10 +562,7 @@ pub async fn refund_paypal(
         true => refund_details.invoice_id.clone(),
         false => format!("txn_{}", refund_details.invoice_id),
     };
-    let refund_id = match refund_details.id.starts_with("re_") {
-        true => refund_details.id.clone(),
-        false => format!("re_{}", refund_details.id),
-    };
+    let refund_id = paypal_refund_id(&refund_details.id);
 
     let created_at = refund_details.create_time.unwrap_or(
         chrono::NaiveDateTime::from_timestamp(
@@ -360,81 +574,491 @@ pub async fn refund_paypal(
     let refund_currency = refund_details.amount.currency_code.clone()
         .unwrap_or(String::from("USD"));
 
-    // 3. create refund + transaction structs to write to DB
-    let refund = Refund {
-        id: refund_id.clone(),
-        transaction_id: tx_id,
-        order_id: body.order_id,
-        order_item_ids: Some(body.refund_order_item_ids),
-        created_at: created_at,
-        reason: body.reason.map(|r| r.as_str().to_string()),
-        reason_details: body.reason_details,
-    };
+    let refund_status = RefundStatus::from_paypal(
+        refund_details.status.as_ref().map(|s| s.as_str())
+    );
+    if refund_status.is_reversed() {
+        return Err(Error::from(PaypalError::InternalError(errJson!(format!(
+            "refund {} {:?}", refund_id, refund_status
+        )))))
+    }
+
+    // a refund webhook can arrive before we get here, and record the refund
+    let existing = db::read_many_refunds(&conn, vec![refund_id.clone()])?;
+    if let Some(refund) = existing.into_iter().next() {
+        warn!("Paypal refund {} was already recorded by its webhook", refund.id);
+        let refund = retry_order_update_for_refund(
+            AppState::httpClient(req),
+            &conn,
+            refund,
+        ).await?;
+        return Ok((refund, None))
+    }
 
     let (
         payment_method_id,
         customer_id,
     ) = db::read_payment_method_id_for_order(
         &conn,
-        &refund.order_id
+        &body.order_id
+    );
+
+    // 3. pro-rate the refund across payees, then create refund + transaction
+    // structs to write to DB
+    let (tx, refund, refund_payout_items) = create_paypal_refund_records(
+        &refund_id,
+        tx_id,
+        created_at,
+        &refund_currency,
+        &payout_items,
+        body.refund_amount,
+        &clawback_policy,
+        refund_status,
+        RefundRecordDetails {
+            order_id: body.order_id,
+            order_item_ids: body.refund_order_item_ids,
+            taxes: body.taxes,
+            reason: body.reason.map(|r| r.as_str().to_string()),
+            reason_details: body.reason_details,
+            charge_id: refund_details.id.clone(),
+            payment_intent_id: None,
+            customer_id: customer_id,
+            payment_method_id: payment_method_id,
+        },
+    )?;
+
+    debug!("refund: {:?}", refund);
+    debug!("transaction: {:?}", tx);
+
+    // 4. write a refund and transaction
+    let (tx, refund, _pitems) = db::write_transaction_and_refund_and_refund_items(
+        &conn,
+        &tx,
+        &refund,
+        &refund_payout_items,
+    ).map_err(Error::from)?;
+
+    // 5. Update Order, OrderSnapshots, OrderItem statuses
+    let refund = update_order_for_refund(
+        AppState::httpClient(req),
+        &conn,
+        tx.clone(),
+        refund,
+    ).await?;
+
+    Ok((refund, Some(tx)))
+}
+
+/// Creates the Refund, negative Transaction and refund payout items for a
+/// Paypal refund, pro-rating refund_amount across the payout items.
+/// Used for refunds we make, and refunds made from Paypal (sale refund webhooks).
+pub fn create_paypal_refund_records(
+    refund_id: &str,
+    transaction_id: String,
+    created_at: chrono::NaiveDateTime,
+    refund_currency: &str,
+    payout_items: &Vec<PayoutItem>,
+    refund_amount: Option<i32>,
+    clawback_policy: &ClawbackPolicy,
+    status: RefundStatus,
+    details: RefundRecordDetails,
+) -> Result<(Transaction, Refund, Vec<PayoutItem>), Error> {
+
+    let refund_items: Vec<PayoutItem> = create_partial_refund_payout_items(
+        payout_items,
+        refund_amount,
+        &created_at,
+        refund_id,
+    ).map_err(|e| Error::from(RefundError::InvalidAmount(errJson!(e))))?;
+    // shares which were already paid out are clawed back from future payouts
+    let refund_items = create_clawback_payout_items(
+        payout_items,
+        refund_items,
+        clawback_policy,
     );
+    // refund items are negative
+    let spfe = sum_payouts_for_all_payees(&refund_items);
+
+    let refund = Refund {
+        id: refund_id.to_string(),
+        transaction_id: transaction_id,
+        order_id: details.order_id,
+        order_item_ids: Some(details.order_item_ids),
+        created_at: created_at,
+        reason: details.reason,
+        reason_details: details.reason_details,
+        status: status,
+        failure_reason: None,
+        updated_at: None,
+        order_updated_at: None,
+    };
 
     let tx = Transaction {
         id: refund.id.clone(), // txn_xxxxxx
-        subtotal: -sum_subtotal(
+        subtotal: sum_subtotal(
             spfe.total_seller_payment + spfe.seller_payment_processing_fees,
             spfe.total_platform_fee,
             spfe.total_buyer_affiliate_fee,
             spfe.total_seller_affiliate_fee,
         ),
-        taxes: -body.taxes,
-        payment_processing_fee: -spfe.seller_payment_processing_fees,
+        taxes: -details.taxes,
+        payment_processing_fee: spfe.seller_payment_processing_fees,
         created_at: created_at,
-        currency: Currency::from_str(&refund_currency).ok(),
-        customer_id: customer_id,
+        currency: Currency::from_str(refund_currency).ok(),
+        customer_id: details.customer_id,
         order_id: Some(refund.order_id.clone()),
-        charge_id: Some(refund_details.id.clone()),
+        charge_id: Some(details.charge_id),
         payment_processor: Some(String::from("Paypal")),
-        payment_method_id: payment_method_id,
-        payment_intent_id: None,
+        payment_method_id: details.payment_method_id,
+        payment_intent_id: details.payment_intent_id,
         refund_id: Some(refund.id.clone()), // re_xxxxxxx
         details: None,
     };
 
-    let refund_payout_items: Vec<PayoutItem> = create_refund_payout_items(
-        &payout_items,
-        &created_at,
-        &tx.id,
+    Ok((tx, refund, refund_items))
+}
+
+/// Paypal refund ids are recorded with a re_ prefix
+pub fn paypal_refund_id(paypal_refund_id: &str) -> String {
+    match paypal_refund_id.starts_with("re_") {
+        true => paypal_refund_id.to_string(),
+        false => format!("re_{}", paypal_refund_id),
+    }
+}
+
+
+/////////////////////////////////
+/// Refund Status
+/////////////////////////////////
+
+
+/// How many pending refunds one reconcile request checks
+const REFUND_RECONCILE_BATCH: i64 = 50;
+
+/// Takes a recorded refund's latest status from Stripe or Paypal.
+/// Refunds which failed or were canceled get their refund payout items and
+/// transaction reversed, once: failed refunds are final.
+/// Returns None for refunds we haven't recorded.
+pub fn reconcile_refund_status(
+    conn: &PgConnection,
+    refund_id: &str,
+    status: RefundStatus,
+    failure_reason: Option<String>,
+    balance_transaction_id: Option<String>,
+) -> Result<Option<Refund>, Error> {
+
+    let refund = match db::read_many_refunds(conn, vec![refund_id.to_string()])?
+        .into_iter()
+        .next() {
+            None => return Ok(None),
+            Some(refund) => refund,
+        };
+
+    if refund.status.is_reversed() {
+        debug!("refund {} was already reversed", refund.id);
+        return Ok(Some(refund))
+    }
+
+    let transaction_id = balance_transaction_id.unwrap_or(refund.transaction_id.clone());
+    if refund.status == status && refund.transaction_id == transaction_id {
+        return Ok(Some(refund))
+    }
+
+    let now = chrono::NaiveDateTime::from_timestamp(chrono::Utc::now().timestamp(), 0);
+    let refund = Refund {
+        transaction_id: transaction_id,
+        ..refund
+    }.update_status(status, failure_reason, now);
+
+    if !refund.status.is_reversed() {
+        info!("refund {} is {:?}", refund.id, refund.status);
+        return db::update_refund_status(conn, &refund)
+            .map(Some)
+            .map_err(Error::from)
+    }
+
+    // the refund's transaction and payout items are keyed by the refund id
+    let reversal_tx = db::read_many_transactions_by_ids(conn, vec![refund.id.clone()])?
+        .into_iter()
+        .next()
+        .map(|tx| create_refund_reversal_transaction(&tx, now));
+
+    let reversal_items = reverse_refund_payout_items(
+        &db::read_payout_items_by_txn_id(conn, &refund.id)?,
+        &refund.id,
+        &now,
     );
 
-    debug!("refund: {:?}", refund);
-    debug!("transaction: {:?}", tx);
+    warn!("refund {} {:?} ({:?}), reversing {} payout items for order {}",
+        refund.id,
+        refund.status,
+        refund.failure_reason,
+        reversal_items.len(),
+        refund.order_id,
+    );
 
-    // 4. write a refund and transaction
-    let (tx, refund, _pitems) = db::write_transaction_and_refund_and_refund_items(
-        &conn,
-        &tx,
-        &refund,
-        &refund_payout_items,
-    ).map_err(Error::from)?;
+    db::write_refund_reversal(conn, &refund, reversal_tx.as_ref(), &reversal_items)
+        .map(Some)
+        .map_err(Error::from)
+}
 
-    // 5. Update Order, OrderSnapshots, OrderItem statuses
-    let _order_db = rpc_update_orders_for_refunds(
-        AppState::httpClient(&req),
-        tx.clone(),
-        refund.clone(),
+
+/// Updates the Order, OrderSnapshots and OrderItem statuses in gm-shopping
+/// for a refund which is already written, then records that it's done.
+/// If gm-shopping can't be reached the refund stays recorded, and the order
+/// update is retried by reconcile_refunds or when the refund is seen again.
+pub async fn update_order_for_refund(
+    client: &actix_web::client::Client,
+    conn: &PgConnection,
+    tx: Transaction,
+    refund: Refund,
+) -> Result<Refund, Error> {
+
+    let refund_id = refund.id.clone();
+    let _order_db = rpc_update_orders_for_refunds(client, tx, refund).await?;
+
+    let now = chrono::NaiveDateTime::from_timestamp(chrono::Utc::now().timestamp(), 0);
+    db::update_refund_order_updated_at(conn, &refund_id, Some(now))
+        .map_err(Error::from)
+}
+
+/// Retries the order update of a refund recorded earlier, if it failed.
+/// Reversed refunds send their reversal, so gm-shopping undoes the refund.
+pub async fn retry_order_update_for_refund(
+    client: &actix_web::client::Client,
+    conn: &PgConnection,
+    refund: Refund,
+) -> Result<Refund, Error> {
+
+    if refund.order_updated_at.is_some() {
+        return Ok(refund)
+    }
+
+    // the refund's transaction is keyed by the refund id
+    let tx_ids = match refund.status.is_reversed() {
+        true => vec![refund_reversal_id(&refund.id), refund.id.clone()],
+        false => vec![refund.id.clone()],
+    };
+    let txs = db::read_many_transactions_by_ids(conn, tx_ids.clone())?;
+    let tx = tx_ids
+        .iter()
+        .find_map(|id| txs.iter().find(|tx| &tx.id == id))
+        .cloned()
+        .ok_or(Error::from(DbError::TransactionReadError(errJson!(
+            format!("no transaction for refund: {}", refund.id)
+        ))))?;
+
+    info!("retrying order update for refund {}, order {}", refund.id, refund.order_id);
+    update_order_for_refund(client, conn, tx, refund).await
+}
+
+
+#[serde(rename_all = "camelCase")]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ReconcileRefundsBody {
+    /// Every pending refund if not provided
+    refund_id: Option<String>,
+}
+
+/// Checks refunds with Stripe or Paypal, for refunds whose webhooks
+/// were missed. Called periodically for pending refunds.
+/// Also retries order updates in gm-shopping which failed.
+pub async fn reconcile_refunds(
+    req: HttpRequest,
+    json: Json<ReconcileRefundsBody>,
+) -> Result<HttpResponse, Error> {
+
+    let body = json.into_inner();
+
+    let auth_info: AuthInfo = rpc::rpc_get_auth_info(
+        &AppState::from(&req).http_client,
+        &req
     ).await?;
+    is_worthy_enough(&auth_info.user_role).map_err(Error::from)?;
+
+    let conn = AppState::databaseActor(&req)
+                .send(GetPool::Postgres)
+                .await??;
+
+    let refunds = match body.refund_id.clone() {
+        Some(refund_id) => db::read_many_refunds(&conn, vec![refund_id])?,
+        None => db::read_refunds_by_status(
+            &conn,
+            RefundStatus::PENDING,
+            REFUND_RECONCILE_BATCH,
+        )?,
+    };
+
+    let mut reconciled: Vec<Refund> = vec![];
+    for refund in refunds.into_iter() {
+
+        let payment_processor = db::read_many_transactions_by_ids(&conn, vec![refund.id.clone()])?
+            .into_iter()
+            .next()
+            .and_then(|tx| tx.payment_processor);
+
+        let (status, failure_reason, balance_transaction_id) = match payment_processor
+            .as_ref()
+            .map(|p| p.as_str()) {
+                Some("Paypal") => retrieve_paypal_refund_status(&req, &refund.id).await?,
+                _ => retrieve_stripe_refund_status(&req, &refund.id).await?,
+            };
+
+        if let Some(refund) = reconcile_refund_status(
+            &conn,
+            &refund.id,
+            status,
+            failure_reason,
+            balance_transaction_id,
+        )? {
+            reconciled.push(refund);
+        }
+    }
+
+    let pending_order_updates = match body.refund_id {
+        Some(_) => reconciled.iter()
+            .filter(|r| r.order_updated_at.is_none())
+            .cloned()
+            .collect::<Vec<Refund>>(),
+        None => db::read_refunds_pending_order_update(&conn, REFUND_RECONCILE_BATCH)?,
+    };
+
+    for refund in pending_order_updates.into_iter() {
+        let refund_id = refund.id.clone();
+        match retry_order_update_for_refund(
+            AppState::httpClient(&req),
+            &conn,
+            refund,
+        ).await {
+            Ok(refund) => {
+                reconciled.retain(|r| r.id != refund_id);
+                reconciled.push(refund);
+            },
+            // left for the next reconcile
+            Err(e) => warn!("order update for refund {} failed again: {}", refund_id, e),
+        }
+    }
 
     Ok(HttpResponse::Ok()
     .content_type("application/json")
-    .json(json!({
-        "refund": refund,
-        "transaction": tx,
-    })))
+    .json(reconciled))
 }
 
+async fn retrieve_stripe_refund_status(
+    req: &HttpRequest,
+    refund_id: &str,
+) -> Result<(RefundStatus, Option<String>, Option<String>), Error> {
+
+    let stripe_refund: stripe::Refund = AppState::stripeActor(req)
+        .send(RefundMsg::Retrieve(refund_id.to_string()))
+        .await??;
+
+    Ok((
+        RefundStatus::from_stripe(stripe_refund.status.as_ref().map(|s| s.as_str())),
+        stripe_refund.failure_reason.clone(),
+        stripe_refund.balance_transaction.as_ref().map(|bt| bt.id().to_string()),
+    ))
+}
+
+async fn retrieve_paypal_refund_status(
+    req: &HttpRequest,
+    refund_id: &str,
+) -> Result<(RefundStatus, Option<String>, Option<String>), Error> {
+
+    // Paypal refund ids are recorded with a re_ prefix
+    let paypal_id = refund_id.trim_start_matches("re_");
+
+    let refund_details_response = AppState::paypalActor(req)
+        .send(PaypalRequest::Get::<serde_json::Value>(
+            format!("/v2/payments/refunds/{}", paypal_id)
+        ))
+        .await
+        .map_err(|e| Error::from(PaypalError::InternalError(errJson!(e))))??;
+
+    let refund_details = serde_json::from_str::<PaypalRefundDetails>(
+            &refund_details_response
+        ).map_err(Error::from)?;
+
+    Ok((
+        RefundStatus::from_paypal(refund_details.status.as_ref().map(|s| s.as_str())),
+        None,
+        None,
+    ))
+}
+
+
 ///////////// Helpers ///////////
 
+/// Reads the payout items of the orderItems to refund, including refund
+/// items of earlier partial refunds, which reduce what is left to refund.
+/// Paid out payout items can be refunded too, their shares are clawed back.
+fn read_refundable_payout_items(
+    conn: &PgConnection,
+    body: &RefundOrderBody,
+) -> Result<Vec<PayoutItem>, Error> {
+    db::read_payout_items_by_order_item_ids(
+        conn,
+        &body.refund_order_item_ids,
+    ).map_err(Error::from)
+}
+
+/// Payout items of every orderItem paid for in a transaction,
+/// with refund items of earlier refunds on those orderItems.
+pub fn read_refundable_payout_items_of_transaction(
+    conn: &PgConnection,
+    txn_id: &str,
+) -> Result<Vec<PayoutItem>, Error> {
+
+    let order_item_ids = db::read_payout_items_by_txn_id(conn, txn_id)?
+        .into_iter()
+        .filter_map(|p: PayoutItem| p.order_item_id)
+        .unique()
+        .collect::<Vec<String>>();
+
+    db::read_payout_items_by_order_item_ids(conn, &order_item_ids)
+        .map_err(Error::from)
+}
+
+/// Only admins may decide someone else absorbs a payee's clawback
+async fn check_clawback_policy(
+    req: &HttpRequest,
+    body: &RefundOrderBody,
+) -> Result<ClawbackPolicy, Error> {
+    match &body.clawback_policy {
+        None => Ok(ClawbackPolicy::default()),
+        Some(policy) if policy.is_default() => Ok(policy.clone()),
+        Some(policy) => {
+            let auth_info: AuthInfo = rpc::rpc_get_auth_info(
+                &AppState::from(req).http_client,
+                req
+            ).await?;
+            is_worthy_enough(&auth_info.user_role).map_err(Error::from)?;
+            info!("clawback policy {:?} set by {}", policy, auth_info.user_id);
+            Ok(policy.clone())
+        }
+    }
+}
+
+/// Amount to refund, excluding taxes.
+/// Fails if the orderItems don't have refund_amount left to refund.
+fn refund_subtotal(
+    payout_items: &Vec<PayoutItem>,
+    refund_amount: Option<i32>,
+) -> Result<i32, Error> {
+
+    let created_at = chrono::NaiveDateTime::from_timestamp(chrono::Utc::now().timestamp(), 0);
+
+    let refund_items = create_partial_refund_payout_items(
+        payout_items,
+        refund_amount,
+        &created_at,
+        "",
+    ).map_err(|e| Error::from(RefundError::InvalidAmount(errJson!(e))))?;
+
+    Ok(-refundable_total(&refund_items))
+}
+
+
 #[serde(rename_all = "camelCase")]
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct ReadRefundsByIdsBody {
@@ -0,0 +1,117 @@
+use actix_web::{
+    HttpRequest,
+    HttpResponse,
+    web::Query, web::Json,
+    Error,
+};
+
+use crate::db;
+use crate::db::GetPool;
+use crate::models::{
+    ErrJson,
+    RevenueShare,
+    RevenueShareError,
+    validate_revenue_shares,
+};
+use crate::{AppState};
+
+
+#[serde(rename_all = "camelCase")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReadRevenueSharesBody {
+    store_id: String,
+}
+
+pub async fn read_revenue_shares(
+    req: HttpRequest,
+    query: Query<ReadRevenueSharesBody>,
+) -> Result<HttpResponse, Error> {
+
+    let store_id = query.into_inner().store_id;
+    debug!("store_id: {:?}", &store_id);
+
+    let conn = AppState::databaseActor(&req)
+                .send(GetPool::Postgres)
+                .await??;
+
+    let revenue_shares = db::read_revenue_shares_by_store_id(&conn, &store_id)?;
+
+    Ok(HttpResponse::Ok()
+    .content_type("application/json")
+    .json(revenue_shares))
+}
+
+
+#[serde(rename_all = "camelCase")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RevenueShareRecipient {
+    payee_id: String,
+    percentage: Option<f64>,
+    fixed_amount: Option<i32>,
+}
+
+#[serde(rename_all = "camelCase")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WriteRevenueSharesBody {
+    store_id: String,
+    recipients: Vec<RevenueShareRecipient>,
+}
+
+/// Replaces the store's revenue-share table with these recipients.
+/// Recipients are paid with the PayoutMethod of their payeeId.
+pub async fn write_revenue_shares(
+    req: HttpRequest,
+    json: Json<WriteRevenueSharesBody>,
+) -> Result<HttpResponse, Error> {
+
+    let params = json.into_inner();
+    debug!("json: {:?}", &params);
+
+    let store_id = params.store_id;
+    let revenue_shares = params.recipients
+        .into_iter()
+        .map(|r| RevenueShare::new(
+            store_id.clone(),
+            r.payee_id,
+            r.percentage,
+            r.fixed_amount,
+        ))
+        .collect::<Vec<RevenueShare>>();
+
+    validate_revenue_shares(&revenue_shares)
+        .map_err(|e| RevenueShareError::InvalidRevenueShares(errJson!(e)))?;
+
+    let conn = AppState::databaseActor(&req)
+                .send(GetPool::Postgres)
+                .await??;
+
+    let revenue_shares = db::write_revenue_shares_for_store(
+        &conn,
+        &store_id,
+        revenue_shares,
+    )?;
+
+    Ok(HttpResponse::Ok()
+    .content_type("application/json")
+    .json(revenue_shares))
+}
+
+
+pub async fn delete_revenue_shares(
+    req: HttpRequest,
+    query: Query<ReadRevenueSharesBody>,
+) -> Result<HttpResponse, Error> {
+
+    let store_id = query.into_inner().store_id;
+    debug!("store_id: {:?}", &store_id);
+
+    let conn = AppState::databaseActor(&req)
+                .send(GetPool::Postgres)
+                .await??;
+
+    let revenue_shares = db::delete_revenue_shares_for_store(&conn, &store_id)?;
+
+    Ok(HttpResponse::Ok()
+    .content_type("application/json")
+    .json(revenue_shares))
+}
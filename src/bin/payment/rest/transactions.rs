@@ -250,13 +250,13 @@ pub async fn record_frontend_tx(
                 .await??;
 
     // Create payout_items first, before transaction
-    let payout_items: Vec<PayoutItem> = to_payout_items(
+    let (payout_items, payout_calculations) = to_payout_items(
         &conn,
         params.order_items_rpc.clone(),
         &tx_id,
         &created_at,
         buyer_affiliate_user_id
-    );
+    )?;
 
     // seller pays payment_processing_fee for each orderItem:
     // this figure is for tx reference only, not payoutItems
@@ -293,7 +293,8 @@ pub async fn record_frontend_tx(
     let (_tx_result, _pitems_result) = db::write_transaction_and_payout_items(
         &conn,
         &tx,
-        &payout_items
+        &payout_items,
+        &payout_calculations,
     ).map_err(Error::from)?;
 
     // Return http response with Transaction object
@@ -0,0 +1,150 @@
+use actix_web::{
+    HttpRequest,
+    HttpResponse,
+    web::Query,
+    web::Json,
+    Error,
+};
+
+use crate::db;
+use crate::db::GetPool;
+use crate::models::{
+    ErrJson,
+    WebhookError,
+    AuthInfo,
+    WebhookEvent,
+};
+use crate::{AppState};
+use crate::rpc;
+use crate::rest::is_worthy_enough;
+use crate::webhooks::{
+    process_webhook_event,
+    retry_due_webhook_events,
+    WEBHOOK_EVENT_RETRY_BATCH,
+};
+
+
+#[serde(rename_all = "camelCase")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReadWebhookEventsQuery {
+    start: chrono::NaiveDateTime,
+    end: chrono::NaiveDateTime,
+}
+
+pub async fn read_webhook_events(
+    req: HttpRequest,
+    query: Query<ReadWebhookEventsQuery>,
+) -> Result<HttpResponse, Error> {
+
+    let auth_info: AuthInfo = rpc::rpc_get_auth_info(
+        &AppState::from(&req).http_client,
+        &req
+    ).await?;
+    is_worthy_enough(&auth_info.user_role).map_err(Error::from)?;
+
+    let conn = AppState::databaseActor(&req)
+                .send(GetPool::Postgres)
+                .await??;
+
+    let webhook_events = db::read_webhook_events_by_received_at(
+        &conn,
+        query.start,
+        query.end,
+    )?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .json(webhook_events))
+}
+
+
+/// Either a single event, or every event received from start to end
+#[serde(rename_all = "camelCase")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplayWebhookEventsBody {
+    webhook_event_id: Option<String>,
+    start: Option<chrono::NaiveDateTime>,
+    end: Option<chrono::NaiveDateTime>,
+}
+
+/// Processes stored webhook events again, whatever their status,
+/// e.g. after fixing a bug which made them fail.
+/// Refunds which were already recorded are skipped, so events which
+/// were processed before are not applied twice.
+pub async fn replay_webhook_events(
+    req: HttpRequest,
+    json: Json<ReplayWebhookEventsBody>,
+) -> Result<HttpResponse, Error> {
+
+    let body = json.into_inner();
+
+    let auth_info: AuthInfo = rpc::rpc_get_auth_info(
+        &AppState::from(&req).http_client,
+        &req
+    ).await?;
+    is_worthy_enough(&auth_info.user_role).map_err(Error::from)?;
+
+    let conn = AppState::databaseActor(&req)
+                .send(GetPool::Postgres)
+                .await??;
+
+    let webhook_events = match body {
+        ReplayWebhookEventsBody { webhook_event_id: Some(id), .. } => {
+            vec![db::read_webhook_event(&conn, &id)?]
+        },
+        ReplayWebhookEventsBody { start: Some(start), end: Some(end), .. } => {
+            db::read_webhook_events_by_received_at(&conn, start, end)?
+        },
+        _ => return Err(Error::from(WebhookError::InvalidPayload(errJson!(
+            "replay needs a webhookEventId, or a start and end"
+        )))),
+    };
+
+    info!("{} replaying {} webhook events", auth_info.user_id, webhook_events.len());
+
+    let mut replayed: Vec<WebhookEvent> = vec![];
+    for webhook_event in webhook_events.into_iter() {
+        let event_id = webhook_event.event_id.clone();
+        match process_webhook_event(
+            AppState::httpClient(&req),
+            &conn,
+            webhook_event,
+        ).await? {
+            Some(webhook_event) => replayed.push(webhook_event),
+            None => info!("skipping webhook event {}, it is already being processed", event_id),
+        }
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .json(replayed))
+}
+
+
+/// Retries failed webhook events whose backoff has passed, and stale events
+/// which were never processed. WebhookRetryScheduler does this periodically,
+/// events are abandoned after WEBHOOK_EVENT_MAX_ATTEMPTS.
+pub async fn retry_webhook_events(
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+
+    let auth_info: AuthInfo = rpc::rpc_get_auth_info(
+        &AppState::from(&req).http_client,
+        &req
+    ).await?;
+    is_worthy_enough(&auth_info.user_role).map_err(Error::from)?;
+
+    let conn = AppState::databaseActor(&req)
+                .send(GetPool::Postgres)
+                .await??;
+
+    let retried = retry_due_webhook_events(
+        AppState::httpClient(&req),
+        &conn,
+        WEBHOOK_EVENT_RETRY_BATCH,
+    ).await?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .json(retried))
+}
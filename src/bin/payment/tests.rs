@@ -49,7 +49,7 @@ mod tests {
             confirm: Some(false), // Do not try auto-confirm. Do in 2nd step
             confirmation_method: Some("automatic".to_string()),
             description: None,
-            // metadata: None,
+            metadata: None,
             // on_behalf_of: None,
             // receipt_email: None,
             // return_url: None,
@@ -104,7 +104,7 @@ mod tests {
                     confirm: Some(false), // Do not try auto-confirm. Do in 2nd step
                     confirmation_method: Some("automatic".to_string()),
                     description: None,
-                    // metadata: None,
+                    metadata: None,
                     // on_behalf_of: None,
                     // receipt_email: None,
                     // return_url: None,
@@ -1,16 +1,47 @@
 pub mod stripe_refunds;
 pub mod paypal_refunds;
+pub mod stripe_disputes;
+pub mod verification;
+pub mod retry_scheduler;
 
 pub use stripe_refunds::*;
 pub use paypal_refunds::*;
+pub use stripe_disputes::*;
+pub use verification::*;
+pub use retry_scheduler::*;
 
-use actix_web::{HttpResponse, HttpRequest, Error, web::Json};
+use actix_web::{HttpResponse, HttpRequest, Error, web::Bytes};
 use futures::future::Future;
 // diesel
 use diesel::prelude::*; // need for table_name proc macro
 use gm::db::schema::refunds;
 use std::f64;
-use crate::models::Refund;
+use itertools::Itertools;
+use crate::models::{
+    Refund,
+    RefundStatus,
+    RefundReason,
+    ClawbackPolicy,
+    WebhookEvent,
+    WebhookProvider,
+    WEBHOOK_EVENT_STALE_SECS,
+    remaining_refundable_items,
+    refundable_total,
+    split_refund_taxes,
+};
+use crate::rest::{
+    RefundRecordDetails,
+    create_stripe_refund_records,
+    create_paypal_refund_records,
+    paypal_refund_id,
+    is_refund_initiated_by_us,
+    stripe_refund_order_items,
+    read_refundable_payout_items_of_transaction,
+    reconcile_refund_status,
+    update_order_for_refund,
+    retry_order_update_for_refund,
+};
+use crate::models::errors::{ErrJson, WebhookError};
 
 use crate::AppState;
 use crate::db;
@@ -19,40 +50,513 @@ use crate::db::GetPool;
 
 
 
-pub fn handle_stripe_refund_webhook(
+/// Receives Stripe events. Events are verified, then written to
+/// webhook_events before they are processed, so failed events are retried
+/// and duplicate deliveries of the same event are ignored.
+pub async fn handle_stripe_refund_webhook(
     req: HttpRequest,
-    json: Json<StripeRefundResponse>,
-) -> HttpResponse {
+    body: Bytes,
+) -> Result<HttpResponse, Error> {
 
     debug!("req: {:?}", req);
     debug!("headers: {:?}", req.headers());
     debug!("uri: {:?}", req.uri());
-    let stripe_refund = json.into_inner();
-    debug!("stripe_refund: {:?}", &stripe_refund);
+    // the signature is over the raw body, so verify before deserializing
+    verify_stripe_webhook(&req, &body)?;
 
-    HttpResponse::Ok()
-        .content_type("application/json")
-        .json(json!({
-            "refund_result": stripe_refund,
-        }))
+    receive_webhook_event(&req, WebhookProvider::STRIPE, &body).await
 }
 
 
-pub fn handle_paypal_refund_webhook(
+/// Stripe sends refund, refund status and dispute events to the same endpoint
+pub async fn process_stripe_event(
+    client: &actix_web::client::Client,
+    conn: &PgConnection,
+    payload: &str,
+) -> Result<serde_json::Value, Error> {
+
+    let event_type = serde_json::from_str::<serde_json::Value>(payload)
+        .map_err(|e| Error::from(WebhookError::InvalidPayload(errJson!(e))))?
+        ["type"]
+        .as_str()
+        .unwrap_or("")
+        .to_string();
+
+    match event_type.as_str() {
+        "charge.refunded" => process_stripe_refund_event(client, conn, payload).await,
+        "charge.refund.updated" => {
+            let stripe_event = serde_json::from_str::<StripeRefundUpdatedEvent>(payload)
+                .map_err(|e| Error::from(WebhookError::InvalidPayload(errJson!(e))))?;
+            process_stripe_refund_updated_event(client, conn, stripe_event).await
+        },
+        t if t.starts_with("charge.dispute.") => {
+            let stripe_event = serde_json::from_str::<StripeDisputeEvent>(payload)
+                .map_err(|e| Error::from(WebhookError::InvalidPayload(errJson!(e))))?;
+            process_stripe_dispute_event(conn, stripe_event)
+        },
+        _ => Ok(json!({ "ignored": event_type })),
+    }
+}
+
+
+/// Records refunds made from the Stripe dashboard, from `charge.refunded` events,
+/// and refunds made through refund_stripe which it didn't get to record.
+///
+/// Refunds which are already written are skipped, so replayed or duplicate
+/// events never refund payout items twice, and refund_stripe skips refunds
+/// this webhook has recorded first.
+/// Refunds we have already recorded get their status reconciled instead.
+async fn process_stripe_refund_event(
+    client: &actix_web::client::Client,
+    conn: &PgConnection,
+    payload: &str,
+) -> Result<serde_json::Value, Error> {
+
+    let stripe_event = serde_json::from_str::<StripeRefundResponse>(payload)
+        .map_err(|e| Error::from(WebhookError::InvalidPayload(errJson!(e))))?;
+    debug!("stripe_event: {:?}", &stripe_event);
+
+    let charge = stripe_event.data.object;
+
+    // 1. the payment being refunded
+    let payment_tx = match db::read_stripe_payment_transaction(
+        conn,
+        charge.payment_intent.clone(),
+        &charge.id.to_string(),
+    )? {
+        Some(tx) => tx,
+        None => {
+            warn!("No transaction found for refunded charge: {}", charge.id);
+            return Ok(json!({ "refunds": [] }))
+        }
+    };
+    let order_id = match payment_tx.order_id.clone() {
+        Some(order_id) => order_id,
+        None => {
+            warn!("Transaction {} has no order to refund", payment_tx.id);
+            return Ok(json!({ "refunds": [] }))
+        }
+    };
+
+    // 2. only refunds we haven't recorded
+    let mut recorded: Vec<Refund> = vec![];
+
+    for stripe_refund in charge.refunds.data.iter() {
+
+        let status = RefundStatus::from_stripe(stripe_refund.status.as_ref().map(|s| s.as_str()));
+
+        let existing = reconcile_refund_status(
+            conn,
+            &stripe_refund.id.to_string(),
+            status.clone(),
+            stripe_refund.failure_reason.clone(),
+            stripe_refund.balance_transaction.as_ref().map(|bt| bt.id().to_string()),
+        )?;
+        if let Some(refund) = existing {
+            debug!("skipping refund already recorded: {}", stripe_refund.id);
+            // its order update may have failed the first time around
+            retry_order_update_for_refund(client, conn, refund).await?;
+            continue
+        }
+        if status.is_reversed() {
+            continue
+        }
+
+        // 3. pro-rate the refund across the payout items and taxes it refunded.
+        // Refunds made by refund_stripe, which it failed to record, refunded the
+        // orderItems in their metadata. Dashboard refunds refund the whole payment.
+        let (payout_items, order_item_ids, payout_refund, taxes_refund) =
+            match stripe_refund_order_items(stripe_refund) {
+                Some((order_item_ids, taxes)) => {
+                    warn!("recording refund {} made by refund_stripe", stripe_refund.id);
+                    (
+                        db::read_payout_items_by_order_item_ids(conn, &order_item_ids)?,
+                        order_item_ids,
+                        stripe_refund.amount as i32 - taxes,
+                        taxes,
+                    )
+                },
+                None => {
+                    let payout_items = read_refundable_payout_items_of_transaction(
+                        conn,
+                        &payment_tx.id,
+                    )?;
+                    let order_item_ids = payout_items
+                        .iter()
+                        .filter_map(|p| p.order_item_id.clone())
+                        .unique()
+                        .collect::<Vec<String>>();
+                    let (payout_refund, taxes_refund) = split_refund_taxes(
+                        stripe_refund.amount as i32,
+                        payment_tx.subtotal,
+                        payment_tx.taxes,
+                    );
+                    (payout_items, order_item_ids, payout_refund, taxes_refund)
+                },
+            };
+
+        let remaining_total = refundable_total(&remaining_refundable_items(&payout_items));
+        let refund_amount = match payout_refund >= remaining_total {
+            true => None,
+            false => Some(payout_refund),
+        };
+
+        let (tx, refund, refund_items) = create_stripe_refund_records(
+            stripe_refund,
+            &payout_items,
+            refund_amount,
+            &ClawbackPolicy::default(),
+            RefundRecordDetails {
+                order_id: order_id.clone(),
+                order_item_ids: order_item_ids,
+                taxes: taxes_refund,
+                reason: stripe_refund.reason
+                    .as_ref()
+                    .map(|r| RefundReason::from_str(r).as_str().to_string()),
+                reason_details: match is_refund_initiated_by_us(stripe_refund) {
+                    true => None,
+                    false => Some(String::from("Refunded from the Stripe dashboard")),
+                },
+                charge_id: charge.payment_intent.clone()
+                    .unwrap_or(charge.id.to_string()),
+                payment_intent_id: Some(charge.payment_intent.clone()
+                    .unwrap_or(charge.id.to_string())),
+                customer_id: charge.customer.clone(),
+                payment_method_id: charge.payment_method.clone(),
+            },
+        )?;
+
+        // 4. write a refund_items and transaction
+        let (tx, refund, _ritems) = db::write_transaction_and_refund_and_refund_items(
+            conn,
+            &tx,
+            &refund,
+            &refund_items,
+        ).map_err(Error::from)?;
+
+        // 5. Update Order, OrderSnapshots, OrderItem statuses
+        let refund = update_order_for_refund(client, conn, tx, refund).await?;
+
+        info!("recorded Stripe refund: {}", refund.id);
+        recorded.push(refund);
+    }
+
+    Ok(json!({ "refunds": recorded }))
+}
+
+
+/// Reconciles the status of refunds we've recorded, from
+/// `charge.refund.updated` events, e.g. when a pending refund fails.
+/// Failed refunds are reversed once, so replayed events are harmless.
+/// Reversed refunds are undone on their order in gm-shopping.
+pub async fn process_stripe_refund_updated_event(
+    client: &actix_web::client::Client,
+    conn: &PgConnection,
+    stripe_event: StripeRefundUpdatedEvent,
+) -> Result<serde_json::Value, Error> {
+
+    let stripe_refund = stripe_event.data.object;
+    debug!("refund updated: {:?}", &stripe_refund);
+
+    let refund = reconcile_refund_status(
+        conn,
+        &stripe_refund.id.to_string(),
+        RefundStatus::from_stripe(stripe_refund.status.as_ref().map(|s| s.as_str())),
+        stripe_refund.failure_reason.clone(),
+        stripe_refund.balance_transaction.as_ref().map(|bt| bt.id().to_string()),
+    )?;
+
+    let refund = match refund {
+        Some(refund) => Some(retry_order_update_for_refund(client, conn, refund).await?),
+        None => {
+            // recorded by its charge.refunded event, if it was made from the dashboard
+            debug!("refund {} hasn't been recorded", stripe_refund.id);
+            None
+        },
+    };
+
+    Ok(json!({ "refund": refund }))
+}
+
+
+/// Receives Paypal events. Events are verified, then written to
+/// webhook_events before they are processed, like Stripe events.
+pub async fn handle_paypal_refund_webhook(
     req: HttpRequest,
-    json: Json<PaypalRefundResponse>,
-) -> HttpResponse {
+    body: Bytes,
+) -> Result<HttpResponse, Error> {
 
     debug!("req: {:?}", req);
     debug!("headers: {:?}", req.headers());
     debug!("uri: {:?}", req.uri());
-    let paypal_refund = json.into_inner();
+    // the signature is over the raw body, so verify before deserializing
+    verify_paypal_webhook(&req, &body).await?;
+
+    receive_webhook_event(&req, WebhookProvider::PAYPAL, &body).await
+}
+
+
+/// Records refunds made from Paypal (and buyer-initiated reversals),
+/// from `PAYMENT.SALE.REFUNDED` events.
+///
+/// Refunds made through refund_paypal are skipped if already recorded,
+/// and refund_paypal skips refunds this webhook has recorded first.
+pub async fn process_paypal_event(
+    client: &actix_web::client::Client,
+    conn: &PgConnection,
+    payload: &str,
+) -> Result<serde_json::Value, Error> {
+
+    let paypal_refund = serde_json::from_str::<PaypalRefundResponse>(payload)
+        .map_err(|e| Error::from(WebhookError::InvalidPayload(errJson!(e))))?;
     debug!("paypal_refund: {:?}", &paypal_refund);
 
-    HttpResponse::Ok()
-        .content_type("application/json")
-        .json(json!({
-            "paypal_refund": paypal_refund,
-        }))
+    if paypal_refund.event_type != "PAYMENT.SALE.REFUNDED" {
+        return Ok(json!({ "ignored": paypal_refund.event_type }))
+    }
+
+    let resource = paypal_refund.resource;
+    let refund_id = paypal_refund_id(&resource.id);
+
+    let existing = db::read_many_refunds(conn, vec![refund_id.clone()])?;
+    if let Some(refund) = existing.into_iter().next() {
+        debug!("skipping refund already recorded: {}", refund_id);
+        // its order update may have failed the first time around
+        retry_order_update_for_refund(client, conn, refund).await?;
+        return Ok(json!({ "refunds": [] }))
+    }
+
+    // 1. the sale being refunded
+    let payment_tx = match db::read_paypal_payment_transaction(conn, &resource.sale_id)? {
+        Some(tx) => tx,
+        None => {
+            warn!("No transaction found for refunded Paypal sale: {}", resource.sale_id);
+            return Ok(json!({ "refunds": [] }))
+        }
+    };
+    let order_id = match payment_tx.order_id.clone() {
+        Some(order_id) => order_id,
+        None => {
+            warn!("Transaction {} has no order to refund", payment_tx.id);
+            return Ok(json!({ "refunds": [] }))
+        }
+    };
+
+    // 2. pro-rate the refund across the sale's payout items and taxes.
+    // Paypal sends refund totals as dollars, sometimes negative
+    let payout_items = read_refundable_payout_items_of_transaction(
+        conn,
+        &payment_tx.id,
+    )?;
+    let remaining_total = refundable_total(&remaining_refundable_items(&payout_items));
+
+    let (payout_refund, taxes_refund) = split_refund_taxes(
+        (resource.amount.total.abs() * 100.0).round() as i32,
+        payment_tx.subtotal,
+        payment_tx.taxes,
+    );
+    let refund_amount = match payout_refund >= remaining_total {
+        true => None,
+        false => Some(payout_refund),
+    };
+
+    let order_item_ids = payout_items
+        .iter()
+        .filter_map(|p| p.order_item_id.clone())
+        .unique()
+        .collect::<Vec<String>>();
+
+    let (tx, refund, refund_items) = create_paypal_refund_records(
+        &refund_id,
+        payment_tx.id.clone(),
+        resource.create_time,
+        &resource.amount.currency,
+        &payout_items,
+        refund_amount,
+        &ClawbackPolicy::default(),
+        RefundStatus::SUCCEEDED,
+        RefundRecordDetails {
+            order_id: order_id,
+            order_item_ids: order_item_ids,
+            taxes: taxes_refund,
+            reason: None,
+            reason_details: Some(paypal_refund.summary),
+            charge_id: resource.id.clone(),
+            payment_intent_id: None,
+            customer_id: payment_tx.customer_id.clone(),
+            payment_method_id: payment_tx.payment_method_id.clone(),
+        },
+    )?;
+
+    // 3. write a refund_items and transaction
+    let (tx, refund, _ritems) = db::write_transaction_and_refund_and_refund_items(
+        conn,
+        &tx,
+        &refund,
+        &refund_items,
+    ).map_err(Error::from)?;
+
+    // 4. Update Order, OrderSnapshots, OrderItem statuses
+    let refund = update_order_for_refund(client, conn, tx, refund).await?;
+
+    info!("recorded Paypal refund: {}", refund.id);
+
+    Ok(json!({ "refunds": vec![refund] }))
+}
+
+
+/////////////////////////////////
+/// Webhook Events
+/////////////////////////////////
+
+/// The provider's event id and type, without deserializing the whole event
+fn read_event_id_and_type(
+    provider: &WebhookProvider,
+    payload: &serde_json::Value,
+) -> Option<(String, String)> {
+    let type_key = match provider {
+        WebhookProvider::STRIPE => "type",
+        WebhookProvider::PAYPAL => "event_type",
+    };
+    match (payload["id"].as_str(), payload[type_key].as_str()) {
+        (Some(id), Some(event_type)) => Some((id.to_string(), event_type.to_string())),
+        _ => None,
+    }
+}
+
+/// Writes a verified webhook event, then processes it.
+/// The event is stored either way, so we respond 200 to the provider even if
+/// processing fails: failed events are retried with backoff from webhook_events.
+async fn receive_webhook_event(
+    req: &HttpRequest,
+    provider: WebhookProvider,
+    body: &Bytes,
+) -> Result<HttpResponse, Error> {
+
+    let payload = String::from_utf8(body.to_vec())
+        .map_err(|e| Error::from(WebhookError::InvalidPayload(errJson!(e))))?;
+    let json_payload = serde_json::from_str::<serde_json::Value>(&payload)
+        .map_err(|e| Error::from(WebhookError::InvalidPayload(errJson!(e))))?;
+
+    let (event_id, event_type) = read_event_id_and_type(&provider, &json_payload)
+        .ok_or(Error::from(WebhookError::InvalidPayload(errJson!(
+            format!("{:?} event without an id or type", provider)
+        ))))?;
+
+    let conn = AppState::databaseActor(req)
+                .send(GetPool::Postgres)
+                .await??;
+
+    let received_at = chrono::NaiveDateTime::from_timestamp(chrono::Utc::now().timestamp(), 0);
+
+    let webhook_event = match db::write_webhook_event(
+        &conn,
+        &WebhookEvent::new(provider, event_id.clone(), event_type, payload, received_at),
+    )? {
+        Some(webhook_event) => webhook_event,
+        None => {
+            info!("ignoring duplicate webhook event: {}", event_id);
+            return Ok(HttpResponse::Ok()
+                .content_type("application/json")
+                .json(json!({ "duplicate": event_id })))
+        }
+    };
+
+    match process_webhook_event(
+        AppState::httpClient(req),
+        &conn,
+        webhook_event,
+    ).await? {
+        Some(webhook_event) => Ok(HttpResponse::Ok()
+            .content_type("application/json")
+            .json(webhook_event)),
+        None => Ok(HttpResponse::Ok()
+            .content_type("application/json")
+            .json(json!({ "processing": event_id }))),
+    }
 }
 
+/// Retries failed events whose backoff has passed, and events which were
+/// received but never processed. Run periodically by WebhookRetryScheduler,
+/// and on demand by admins.
+pub async fn retry_due_webhook_events(
+    client: &actix_web::client::Client,
+    conn: &PgConnection,
+    limit_count: i64,
+) -> Result<Vec<WebhookEvent>, Error> {
+
+    let now = chrono::NaiveDateTime::from_timestamp(chrono::Utc::now().timestamp(), 0);
+    let stale_before = now - chrono::Duration::seconds(WEBHOOK_EVENT_STALE_SECS);
+
+    let webhook_events = db::read_webhook_events_due_for_retry(
+        conn,
+        now,
+        stale_before,
+        limit_count,
+    )?;
+
+    // keep going if one event can't be recorded, it is picked up again
+    // once its claim goes stale
+    let mut retried: Vec<WebhookEvent> = vec![];
+    for webhook_event in webhook_events.into_iter() {
+        let event_id = webhook_event.event_id.clone();
+        match process_webhook_event(client, conn, webhook_event).await {
+            Ok(Some(webhook_event)) => retried.push(webhook_event),
+            Ok(None) => debug!("webhook event {} is already being processed", event_id),
+            Err(e) => warn!("could not retry webhook event {}: {}", event_id, e),
+        }
+    }
+    Ok(retried)
+}
+
+/// Claims a stored webhook event, processes it, and records whether it succeeded.
+/// Returns None if the event is already being processed elsewhere.
+/// Also used to retry failed events and replay events, which is safe
+/// because refunds already recorded are skipped.
+pub async fn process_webhook_event(
+    client: &actix_web::client::Client,
+    conn: &PgConnection,
+    webhook_event: WebhookEvent,
+) -> Result<Option<WebhookEvent>, Error> {
+
+    let claimed_at = chrono::NaiveDateTime::from_timestamp(chrono::Utc::now().timestamp(), 0);
+    let webhook_event = match db::claim_webhook_event(
+        conn,
+        &webhook_event,
+        claimed_at,
+        claimed_at + chrono::Duration::seconds(WEBHOOK_EVENT_STALE_SECS),
+    )? {
+        Some(webhook_event) => webhook_event,
+        None => return Ok(None),
+    };
+
+    let result = match webhook_event.provider {
+        WebhookProvider::STRIPE => process_stripe_event(
+            client,
+            conn,
+            &webhook_event.payload,
+        ).await,
+        WebhookProvider::PAYPAL => process_paypal_event(
+            client,
+            conn,
+            &webhook_event.payload,
+        ).await,
+    };
+
+    let now = chrono::NaiveDateTime::from_timestamp(chrono::Utc::now().timestamp(), 0);
+
+    let webhook_event = match result {
+        Ok(outcome) => {
+            debug!("processed webhook event {}: {}", webhook_event.event_id, outcome);
+            webhook_event.set_processed(now)
+        },
+        Err(e) => {
+            warn!("webhook event {} failed: {}", webhook_event.event_id, e);
+            webhook_event.set_failed(e.to_string(), now)
+        },
+    };
+
+    db::update_webhook_event_status(conn, &webhook_event)
+        .map(Some)
+        .map_err(Error::from)
+}
@@ -0,0 +1,77 @@
+use actix::{Actor, Addr, AsyncContext, Context};
+use std::cell::Cell;
+use std::rc::Rc;
+use std::time::Duration;
+
+use crate::db::{DatabaseActor, GetPool};
+use crate::webhooks::retry_due_webhook_events;
+use crate::AppState;
+
+
+/// How many due events one retry processes
+pub const WEBHOOK_EVENT_RETRY_BATCH: i64 = 50;
+/// How often the scheduler retries due events
+const WEBHOOK_EVENT_RETRY_INTERVAL_SECS: u64 = 60;
+
+
+/// Retries failed and stale webhook events in the background, so missed
+/// refunds and disputes are recorded without an admin asking for it.
+pub struct WebhookRetryScheduler {
+    database_actor: Addr<DatabaseActor>,
+    http_client: actix_web::client::Client,
+    /// A slow batch finishes before the next one starts
+    running: Rc<Cell<bool>>,
+}
+
+impl WebhookRetryScheduler {
+    pub fn new(database_actor: Addr<DatabaseActor>) -> Self {
+        Self {
+            database_actor: database_actor,
+            http_client: AppState::create_client(),
+            running: Rc::new(Cell::new(false)),
+        }
+    }
+
+    fn retry(&mut self) {
+
+        if self.running.get() {
+            debug!("webhook event retries still running, skipping");
+            return
+        }
+        self.running.set(true);
+
+        let database_actor = self.database_actor.clone();
+        let http_client = self.http_client.clone();
+        let running = self.running.clone();
+
+        actix_rt::spawn(async move {
+            match database_actor.send(GetPool::Postgres).await {
+                Ok(Ok(conn)) => match retry_due_webhook_events(
+                    &http_client,
+                    &conn,
+                    WEBHOOK_EVENT_RETRY_BATCH,
+                ).await {
+                    Ok(retried) if retried.len() > 0 => {
+                        info!("retried {} webhook events", retried.len());
+                    },
+                    Ok(_) => {},
+                    Err(e) => warn!("webhook event retries failed: {}", e),
+                },
+                Ok(Err(e)) => warn!("webhook event retries failed: {:?}", e),
+                Err(e) => warn!("webhook event retries failed: {}", e),
+            };
+            running.set(false);
+        });
+    }
+}
+
+impl Actor for WebhookRetryScheduler {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(
+            Duration::from_secs(WEBHOOK_EVENT_RETRY_INTERVAL_SECS),
+            |act, _ctx| act.retry(),
+        );
+    }
+}
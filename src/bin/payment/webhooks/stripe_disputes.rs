@@ -0,0 +1,204 @@
+use actix_web::Error;
+use diesel::PgConnection;
+use gm::models::stripe;
+use std::str::FromStr;
+
+use crate::db;
+use crate::models::{
+    Dispute,
+    DisputeOutcome,
+    PayoutItem,
+    Transaction,
+    Currency,
+    dispute_outcome,
+    dispute_hold_item_ids,
+    stripe_dispute_fee,
+    create_dispute_clawback_items,
+    reverse_dispute_clawback_items,
+    create_dispute_loss_items,
+    create_dispute_fee_items,
+    split_refund_taxes,
+};
+use crate::rest::read_refundable_payout_items_of_transaction;
+
+
+/// `charge.dispute.*` events
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct StripeDisputeEvent {
+    pub id: String,
+    pub created: i64,
+    #[serde(rename = "type")]
+    pub type_: String,
+    pub data: StripeDisputeData,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct StripeDisputeData {
+    pub object: stripe::Dispute,
+}
+
+
+/// Tracks a dispute through its lifecycle, from `charge.dispute.*` events.
+///
+/// 1. When a dispute is opened, its payment's unpaid payout items are held
+///    (DISPUTED) and shares which were paid out are clawed back.
+/// 2. While it's open, its status and evidence due date are kept up to date.
+/// 3. When it's won, held items are released and clawbacks given back.
+///    When it's lost, held items are refunded, and the dispute fee is
+///    booked against the sellers.
+///
+/// Closed disputes are never settled twice, so replayed events are harmless.
+pub fn process_stripe_dispute_event(
+    conn: &PgConnection,
+    stripe_event: StripeDisputeEvent,
+) -> Result<serde_json::Value, Error> {
+
+    let stripe_dispute = stripe_event.data.object;
+    debug!("{}: {:?}", stripe_event.type_, &stripe_dispute);
+
+    let now = chrono::NaiveDateTime::from_timestamp(chrono::Utc::now().timestamp(), 0);
+
+    // 1. the disputed payment
+    let payment_tx = match db::read_stripe_payment_transaction(
+        conn,
+        stripe_dispute.payment_intent.clone(),
+        &stripe_dispute.charge.id().to_string(),
+    )? {
+        Some(tx) => tx,
+        None => {
+            warn!("No transaction found for disputed charge: {}", stripe_dispute.charge.id());
+            return Ok(json!({ "dispute": null }))
+        }
+    };
+
+    let payout_items = read_refundable_payout_items_of_transaction(conn, &payment_tx.id)?;
+
+    // 2. open, or update the dispute
+    let dispute = match db::read_dispute(conn, &stripe_dispute.id)? {
+        None => {
+            let dispute = Dispute::new(&stripe_dispute, &payment_tx, now);
+            let hold_ids = dispute_hold_item_ids(&payout_items);
+            let clawback_items = create_dispute_clawback_items(&payout_items, &dispute.id, &now);
+            info!("dispute {} opened, holding {} payout items, clawing back {}",
+                dispute.id, hold_ids.len(), clawback_items.len());
+            db::write_dispute_and_hold_payout_items(
+                conn,
+                &dispute,
+                &hold_ids,
+                &clawback_items,
+            )?
+        },
+        Some(dispute) if dispute.is_closed() => {
+            debug!("dispute {} is already closed", dispute.id);
+            return Ok(json!({ "dispute": dispute }))
+        },
+        Some(dispute) => db::update_dispute(
+            conn,
+            &dispute.update_from_stripe(&stripe_dispute, now),
+        )?,
+    };
+
+    // 3. settle the dispute's payout items, once it's closed
+    let outcome = match dispute_outcome(&stripe_dispute.status) {
+        None => return Ok(json!({ "dispute": dispute })),
+        Some(outcome) => outcome,
+    };
+
+    // read again, to include the clawbacks written when it was opened
+    let payout_items = read_refundable_payout_items_of_transaction(conn, &payment_tx.id)?;
+    let order_item_ids = payout_items
+        .iter()
+        .filter_map(|p| p.order_item_id.clone())
+        .collect::<Vec<String>>();
+
+    let dispute = Dispute {
+        status: stripe_dispute.status.as_str().to_string(),
+        updated_at: now,
+        closed_at: Some(now),
+        dispute_fee: stripe_dispute_fee(&stripe_dispute),
+        ..dispute
+    };
+
+    let (outcome_items, lost_tx): (Vec<PayoutItem>, Option<Transaction>) = match outcome {
+        DisputeOutcome::WON => (
+            reverse_dispute_clawback_items(&payout_items, &dispute.id, &now),
+            None,
+        ),
+        DisputeOutcome::LOST => {
+            // the payout share of what was lost, as booked by lost_tx
+            let (dispute_subtotal, _taxes) = split_refund_taxes(
+                dispute.amount,
+                payment_tx.subtotal,
+                payment_tx.taxes,
+            );
+            let loss_items = create_dispute_loss_items(
+                &payout_items,
+                dispute_subtotal,
+                &dispute.id,
+                &now,
+            );
+            let fee_items = create_dispute_fee_items(
+                &payout_items,
+                dispute.dispute_fee,
+                &dispute.id,
+                &now,
+            );
+            let lost_tx = create_dispute_lost_transaction(&dispute, &payment_tx, now);
+            (
+                loss_items.into_iter().chain(fee_items.into_iter()).collect(),
+                Some(lost_tx),
+            )
+        },
+    };
+
+    info!("dispute {} closed as {:?}, writing {} payout items",
+        dispute.id, outcome, outcome_items.len());
+
+    let dispute = db::write_dispute_outcome(
+        conn,
+        &dispute,
+        &order_item_ids,
+        &outcome_items,
+        lost_tx.as_ref(),
+    )?;
+
+    Ok(json!({
+        "dispute": dispute,
+        "outcome": outcome,
+        "payoutItems": outcome_items,
+    }))
+}
+
+
+/// Books the funds Stripe took for a lost dispute, and its dispute fee
+fn create_dispute_lost_transaction(
+    dispute: &Dispute,
+    payment_tx: &Transaction,
+    created_at: chrono::NaiveDateTime,
+) -> Transaction {
+
+    let (subtotal, taxes) = split_refund_taxes(
+        dispute.amount,
+        payment_tx.subtotal,
+        payment_tx.taxes,
+    );
+
+    Transaction {
+        id: dispute.id.clone(),
+        subtotal: -subtotal,
+        taxes: -taxes,
+        payment_processing_fee: dispute.dispute_fee,
+        created_at: created_at,
+        currency: Currency::from_str(&dispute.currency).ok(),
+        customer_id: payment_tx.customer_id.clone(),
+        order_id: payment_tx.order_id.clone(),
+        charge_id: Some(dispute.charge_id.clone()),
+        payment_processor: Some(String::from("Stripe")),
+        payment_method_id: payment_tx.payment_method_id.clone(),
+        payment_intent_id: payment_tx.payment_intent_id.clone(),
+        // like refunds, lost funds are reversals of the payment, so they are
+        // never mistaken for the payment itself
+        refund_id: Some(dispute.id.clone()),
+        details: Some(format!("Dispute {} lost: {}", dispute.id, dispute.reason)),
+    }
+}
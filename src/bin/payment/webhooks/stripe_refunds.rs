@@ -1,5 +1,5 @@
 use actix_web::{HttpResponse, HttpRequest, Error, web::Query};
-use gm::models::stripe::{ Charge };
+use gm::models::stripe::{ Charge, Refund };
 // use gm::models::stripe::ids::{ChargeId};
 
 
@@ -28,6 +28,21 @@ pub struct StripeRefundData {
     pub object: Charge,
 }
 
+/// `charge.refund.updated` events
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct StripeRefundUpdatedEvent {
+    pub id: String,
+    pub created: i64,
+    #[serde(rename = "type")]
+    pub type_: String,
+    pub data: StripeRefundUpdatedData,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct StripeRefundUpdatedData {
+    pub object: Refund,
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct StripeRequest {
     pub id: Option<String>,
@@ -0,0 +1,343 @@
+use actix_web::{HttpRequest, Error};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::models::errors::{ErrJson, WebhookError};
+use crate::AppState;
+
+
+/// Stripe signing secrets (whsec_...), comma separated. During a secret
+/// rotation both the old and new secrets are set, until Stripe stops
+/// signing with the old one.
+const STRIPE_WEBHOOK_SECRETS: &str = "STRIPE_WEBHOOK_SECRETS";
+/// How old a Stripe event's signature timestamp may be, to stop replays
+const STRIPE_WEBHOOK_TOLERANCE_SECS: &str = "STRIPE_WEBHOOK_TOLERANCE_SECS";
+const DEFAULT_STRIPE_WEBHOOK_TOLERANCE_SECS: i64 = 300;
+/// Id of the webhook registered in the Paypal developer dashboard
+const PAYPAL_WEBHOOK_ID: &str = "PAYPAL_WEBHOOK_ID";
+
+
+lazy_static! {
+    /// Paypal signing certificates (DER), by cert url.
+    /// Paypal rarely rotates them, so they are fetched once per url.
+    static ref PAYPAL_CERT_CACHE: Mutex<HashMap<String, Vec<u8>>> = Mutex::new(HashMap::new());
+}
+
+
+/////////////////////////////////
+/// Stripe
+/////////////////////////////////
+
+pub fn stripe_webhook_secrets() -> Vec<String> {
+    dotenv::dotenv().ok();
+    std::env::var(STRIPE_WEBHOOK_SECRETS)
+        .unwrap_or(String::from(""))
+        .split(",")
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+pub fn stripe_webhook_tolerance_secs() -> i64 {
+    dotenv::dotenv().ok();
+    std::env::var(STRIPE_WEBHOOK_TOLERANCE_SECS)
+        .ok()
+        .and_then(|s| s.parse::<i64>().ok())
+        .unwrap_or(DEFAULT_STRIPE_WEBHOOK_TOLERANCE_SECS)
+}
+
+/// Rejects webhook requests which were not signed by Stripe.
+pub fn verify_stripe_webhook(
+    req: &HttpRequest,
+    payload: &[u8],
+) -> Result<(), Error> {
+
+    let secrets = stripe_webhook_secrets();
+    if secrets.is_empty() {
+        return Err(Error::from(WebhookError::NotConfigured(errJson!(
+            format!("{} not set in .env", STRIPE_WEBHOOK_SECRETS)
+        ))))
+    }
+
+    let signature_header = req.headers()
+        .get("Stripe-Signature")
+        .and_then(|h| h.to_str().ok())
+        .unwrap_or("");
+
+    verify_stripe_signature(
+        payload,
+        signature_header,
+        &secrets,
+        stripe_webhook_tolerance_secs(),
+        chrono::Utc::now().timestamp(),
+    ).map_err(|e| {
+        warn!("rejected Stripe webhook to {} from {:?}: {}",
+            req.uri(),
+            req.peer_addr(),
+            e
+        );
+        Error::from(WebhookError::InvalidSignature(errJson!(e)))
+    })
+}
+
+/// Checks a `Stripe-Signature` header: `t=<timestamp>,v1=<hex hmac>,...`.
+/// The HMAC-SHA256 of `<timestamp>.<payload>` must match a v1 signature
+/// for any of the secrets, and the timestamp must be within tolerance_secs.
+pub fn verify_stripe_signature(
+    payload: &[u8],
+    signature_header: &str,
+    secrets: &Vec<String>,
+    tolerance_secs: i64,
+    now: i64,
+) -> Result<(), String> {
+
+    let mut timestamp: Option<i64> = None;
+    let mut signatures: Vec<Vec<u8>> = vec![];
+
+    for pair in signature_header.split(",") {
+        let mut kv = pair.trim().splitn(2, "=");
+        match (kv.next(), kv.next()) {
+            (Some("t"), Some(t)) => timestamp = t.parse::<i64>().ok(),
+            (Some("v1"), Some(sig)) => if let Some(s) = decode_hex(sig) {
+                signatures.push(s)
+            },
+            _ => {},
+        }
+    }
+
+    let timestamp = timestamp
+        .ok_or(String::from("no timestamp in Stripe-Signature header"))?;
+    if signatures.is_empty() {
+        return Err(String::from("no v1 signatures in Stripe-Signature header"))
+    }
+    if (now - timestamp).abs() > tolerance_secs {
+        return Err(format!(
+            "Stripe-Signature timestamp {} is outside the {}s tolerance", timestamp, tolerance_secs
+        ))
+    }
+
+    let mut signed_payload = format!("{}.", timestamp).into_bytes();
+    signed_payload.extend_from_slice(payload);
+
+    let verified = secrets.iter().any(|secret| {
+        let key = ring::hmac::Key::new(ring::hmac::HMAC_SHA256, secret.as_bytes());
+        signatures.iter().any(|sig| {
+            ring::hmac::verify(&key, &signed_payload, sig).is_ok()
+        })
+    });
+
+    match verified {
+        true => Ok(()),
+        false => Err(String::from("no Stripe-Signature matches the signing secrets")),
+    }
+}
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    if s.len() % 2 != 0 {
+        return None
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| s.get(i..i + 2).and_then(|b| u8::from_str_radix(b, 16).ok()))
+        .collect()
+}
+
+
+/////////////////////////////////
+/// Paypal
+/////////////////////////////////
+
+pub fn paypal_webhook_id() -> Option<String> {
+    dotenv::dotenv().ok();
+    std::env::var(PAYPAL_WEBHOOK_ID).ok()
+        .filter(|s| !s.trim().is_empty())
+}
+
+/// Rejects webhook requests which were not signed by Paypal,
+/// for the webhook registered as PAYPAL_WEBHOOK_ID.
+pub async fn verify_paypal_webhook(
+    req: &HttpRequest,
+    payload: &[u8],
+) -> Result<(), Error> {
+
+    let webhook_id = paypal_webhook_id()
+        .ok_or(Error::from(WebhookError::NotConfigured(errJson!(
+            format!("{} not set in .env", PAYPAL_WEBHOOK_ID)
+        ))))?;
+
+    let reject = |message: String| {
+        warn!("rejected Paypal webhook to {} from {:?}: {}",
+            req.uri(),
+            req.peer_addr(),
+            message
+        );
+        Error::from(WebhookError::InvalidSignature(errJson!(message)))
+    };
+
+    let header = |name: &str| req.headers()
+        .get(name)
+        .and_then(|h| h.to_str().ok())
+        .map(|h| h.to_string())
+        .ok_or(format!("missing {} header", name));
+
+    let transmission_id = header("PAYPAL-TRANSMISSION-ID").map_err(reject)?;
+    let transmission_time = header("PAYPAL-TRANSMISSION-TIME").map_err(reject)?;
+    let transmission_sig = header("PAYPAL-TRANSMISSION-SIG").map_err(reject)?;
+    let cert_url = header("PAYPAL-CERT-URL").map_err(reject)?;
+    let auth_algo = header("PAYPAL-AUTH-ALGO").map_err(reject)?;
+
+    if auth_algo != "SHA256withRSA" {
+        return Err(reject(format!("unsupported PAYPAL-AUTH-ALGO: {}", auth_algo)))
+    }
+    is_paypal_cert_url(&cert_url).map_err(reject)?;
+
+    let cert = read_paypal_cert(req, &cert_url).await?;
+
+    let signature = base64::decode(&transmission_sig)
+        .map_err(|e| reject(format!("PAYPAL-TRANSMISSION-SIG is not base64: {}", e)))?;
+
+    let message = paypal_transmission_message(
+        &transmission_id,
+        &transmission_time,
+        &webhook_id,
+        payload,
+    );
+
+    webpki::EndEntityCert::from(&cert)
+        .and_then(|c| c.verify_signature(
+            &webpki::RSA_PKCS1_2048_8192_SHA256,
+            message.as_bytes(),
+            &signature,
+        ))
+        .map_err(|e| reject(format!("PAYPAL-TRANSMISSION-SIG does not verify: {:?}", e)))
+}
+
+/// What Paypal signs: `<transmission id>|<transmission time>|<webhook id>|<crc32 of the body>`
+pub fn paypal_transmission_message(
+    transmission_id: &str,
+    transmission_time: &str,
+    webhook_id: &str,
+    payload: &[u8],
+) -> String {
+    format!("{}|{}|{}|{}",
+        transmission_id,
+        transmission_time,
+        webhook_id,
+        crc32fast::hash(payload),
+    )
+}
+
+/// Only certificates served by Paypal over https are trusted,
+/// anyone can send a PAYPAL-CERT-URL header pointing at their own certificate.
+pub fn is_paypal_cert_url(cert_url: &str) -> Result<(), String> {
+    let uri = cert_url.parse::<actix_web::http::Uri>()
+        .map_err(|e| format!("invalid PAYPAL-CERT-URL {}: {}", cert_url, e))?;
+    let host = uri.host().unwrap_or("");
+    match uri.scheme_str() == Some("https")
+        && (host == "paypal.com" || host.ends_with(".paypal.com")) {
+        true => Ok(()),
+        false => Err(format!("PAYPAL-CERT-URL is not a Paypal url: {}", cert_url)),
+    }
+}
+
+async fn read_paypal_cert(
+    req: &HttpRequest,
+    cert_url: &str,
+) -> Result<Vec<u8>, Error> {
+
+    if let Some(cert) = PAYPAL_CERT_CACHE.lock()
+        .expect("PAYPAL_CERT_CACHE lock")
+        .get(cert_url) {
+        return Ok(cert.clone())
+    }
+
+    let mut response = AppState::httpClient(req)
+        .get(cert_url)
+        .send()
+        .await
+        .map_err(|e| Error::from(WebhookError::Certificate(errJson!(e))))?;
+
+    let bytes = response.body()
+        .await
+        .map_err(|e| Error::from(WebhookError::Certificate(errJson!(e))))?;
+
+    let cert = pem_to_der(&String::from_utf8_lossy(&bytes))
+        .map_err(|e| Error::from(WebhookError::Certificate(errJson!(e))))?;
+
+    debug!("caching Paypal cert: {}", cert_url);
+    PAYPAL_CERT_CACHE.lock()
+        .expect("PAYPAL_CERT_CACHE lock")
+        .insert(cert_url.to_string(), cert.clone());
+
+    Ok(cert)
+}
+
+/// The first certificate in a PEM file, e.g. Paypal's signing cert chain
+fn pem_to_der(pem: &str) -> Result<Vec<u8>, String> {
+    let b64 = pem
+        .lines()
+        .skip_while(|l| !l.starts_with("-----BEGIN CERTIFICATE-----"))
+        .skip(1)
+        .take_while(|l| !l.starts_with("-----END CERTIFICATE-----"))
+        .map(|l| l.trim())
+        .collect::<String>();
+    if b64.is_empty() {
+        return Err(String::from("no certificate in PEM"))
+    }
+    base64::decode(&b64).map_err(|e| format!("invalid PEM certificate: {}", e))
+}
+
+
+
+#[test]
+fn verifies_stripe_signatures_with_rotated_secrets() {
+
+    let payload = br#"{"id":"evt_123","type":"charge.refunded"}"#;
+    let now = 1_500_000_000;
+
+    let sign = |secret: &str, t: i64| {
+        let key = ring::hmac::Key::new(ring::hmac::HMAC_SHA256, secret.as_bytes());
+        let mut signed = format!("{}.", t).into_bytes();
+        signed.extend_from_slice(payload);
+        ring::hmac::sign(&key, &signed)
+            .as_ref()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>()
+    };
+
+    let secrets = vec![String::from("whsec_old"), String::from("whsec_new")];
+
+    // signed with the new secret, alongside a v0 test signature
+    let header = format!("t={},v1={},v0=abcd", now, sign("whsec_new", now));
+    assert_eq!(verify_stripe_signature(payload, &header, &secrets, 300, now + 10), Ok(()));
+
+    // unknown secret
+    let header = format!("t={},v1={}", now, sign("whsec_other", now));
+    assert_eq!(verify_stripe_signature(payload, &header, &secrets, 300, now).is_err(), true);
+
+    // tampered payload
+    let header = format!("t={},v1={}", now, sign("whsec_old", now));
+    assert_eq!(
+        verify_stripe_signature(br#"{"id":"evt_456"}"#, &header, &secrets, 300, now).is_err(),
+        true
+    );
+
+    // replayed after the tolerance
+    assert_eq!(verify_stripe_signature(payload, &header, &secrets, 300, now + 301).is_err(), true);
+
+    // no signature
+    assert_eq!(verify_stripe_signature(payload, "", &secrets, 300, now).is_err(), true);
+}
+
+#[test]
+fn builds_paypal_transmission_messages_for_paypal_certs_only() {
+    assert_eq!(
+        paypal_transmission_message("id_123", "2020-06-01T00:00:00Z", "WH_123", b"hello"),
+        format!("id_123|2020-06-01T00:00:00Z|WH_123|{}", 907060870u32)
+    );
+    assert_eq!(is_paypal_cert_url("https://api.paypal.com/v1/notifications/certs/CERT-360caa42"), Ok(()));
+    assert_eq!(is_paypal_cert_url("https://api.sandbox.paypal.com/v1/notifications/certs/CERT"), Ok(()));
+    assert_eq!(is_paypal_cert_url("http://api.paypal.com/v1/notifications/certs/CERT").is_err(), true);
+    assert_eq!(is_paypal_cert_url("https://paypal.com.evil.io/cert").is_err(), true);
+}
@@ -1,3 +1,59 @@
+table! {
+    audit_logs (id) {
+        id -> Text,
+        created_at -> Timestamp,
+        action -> Text,
+        actor_id -> Text,
+        target_id -> Text,
+        reason -> Nullable<Text>,
+        details -> Nullable<Text>,
+    }
+}
+
+table! {
+    disputes (id) {
+        id -> Text,
+        transaction_id -> Text,
+        order_id -> Nullable<Text>,
+        charge_id -> Text,
+        amount -> Int4,
+        currency -> Text,
+        reason -> Text,
+        status -> Text,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+        evidence_due_by -> Nullable<Timestamp>,
+        evidence_submitted_at -> Nullable<Timestamp>,
+        closed_at -> Nullable<Timestamp>,
+        dispute_fee -> Int4,
+    }
+}
+
+table! {
+    fee_overrides (id) {
+        id -> Text,
+        created_at -> Timestamp,
+        product_id -> Nullable<Text>,
+        category_id -> Nullable<Text>,
+        store_id -> Nullable<Text>,
+        platform_fee_rate -> Nullable<Float8>,
+        max_platform_fee -> Nullable<Int4>,
+        expires_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    manual_adjustments (id) {
+        id -> Text,
+        payout_item_id -> Text,
+        created_at -> Timestamp,
+        category -> Text,
+        reason -> Text,
+        approved_by -> Text,
+        requested_by -> Nullable<Text>,
+    }
+}
+
 table! {
     payment_method_addresses (payment_method_id) {
         payment_method_id -> Text,
@@ -29,6 +85,35 @@ table! {
     }
 }
 
+table! {
+    payout_calculations (id) {
+        id -> Text,
+        created_at -> Timestamp,
+        order_item_id -> Text,
+        txn_id -> Text,
+        currency -> Text,
+        subtotal -> Int4,
+        payment_processing_fee -> Int4,
+        seller_payout_split_id -> Nullable<Text>,
+        buyer_affiliate_payout_split_id -> Nullable<Text>,
+        seller_affiliate_payout_split_id -> Nullable<Text>,
+        upline_affiliate_payout_split_ids -> Array<Text>,
+        fee_override_id -> Nullable<Text>,
+        seller_rate -> Float8,
+        buyer_affiliate_rate -> Float8,
+        seller_affiliate_rate -> Float8,
+        upline_affiliate_rates -> Array<Float8>,
+        payment_fee_percentage -> Float8,
+        payment_fee_fixed -> Int4,
+        platform_fee_percentage -> Float8,
+        platform_funded_discount -> Int4,
+        seller_funded_discount -> Int4,
+        store_id -> Nullable<Text>,
+        product_id -> Nullable<Text>,
+        category_id -> Nullable<Text>,
+    }
+}
+
 table! {
     payout_items (id) {
         id -> Text,
@@ -39,8 +124,8 @@ table! {
         created_at -> Timestamp,
         payout_status -> Text,
         currency -> Text,
-        order_item_id -> Text,
-        txn_id -> Text,
+        order_item_id -> Nullable<Text>,
+        txn_id -> Nullable<Text>,
         payout_id -> Nullable<Text>,
     }
 }
@@ -67,6 +152,13 @@ table! {
         expires_at -> Nullable<Timestamp>,
         rate -> Float8,
         referrer_id -> Nullable<Text>,
+        effective_from -> Timestamp,
+        effective_to -> Nullable<Timestamp>,
+        changed_by -> Nullable<Text>,
+        reason -> Nullable<Text>,
+        starts_at -> Nullable<Timestamp>,
+        rate_override -> Bool,
+        store_id -> Nullable<Text>,
     }
 }
 
@@ -91,6 +183,24 @@ table! {
     }
 }
 
+table! {
+    refund_requests (id) {
+        id -> Text,
+        order_id -> Text,
+        payment_processor -> Text,
+        refund_total -> Int4,
+        refund_order -> Jsonb,
+        approval_reasons -> Array<Text>,
+        status -> Text,
+        created_at -> Timestamp,
+        reviewed_by -> Nullable<Text>,
+        reviewed_at -> Nullable<Timestamp>,
+        review_notes -> Nullable<Text>,
+        refund_id -> Nullable<Text>,
+        last_error -> Nullable<Text>,
+    }
+}
+
 table! {
     refunds (id) {
         id -> Text,
@@ -100,6 +210,21 @@ table! {
         created_at -> Timestamp,
         reason -> Nullable<Text>,
         reason_details -> Nullable<Text>,
+        status -> Text,
+        failure_reason -> Nullable<Text>,
+        updated_at -> Nullable<Timestamp>,
+        order_updated_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    revenue_shares (id) {
+        id -> Text,
+        created_at -> Timestamp,
+        store_id -> Text,
+        payee_id -> Text,
+        percentage -> Nullable<Float8>,
+        fixed_amount -> Nullable<Int4>,
     }
 }
 
@@ -122,13 +247,37 @@ table! {
     }
 }
 
+table! {
+    webhook_events (id) {
+        id -> Text,
+        provider -> Text,
+        event_id -> Text,
+        event_type -> Text,
+        payload -> Text,
+        received_at -> Timestamp,
+        processing_status -> Text,
+        attempts -> Int4,
+        last_error -> Nullable<Text>,
+        next_attempt_at -> Nullable<Timestamp>,
+        processed_at -> Nullable<Timestamp>,
+    }
+}
+
 allow_tables_to_appear_in_same_query!(
+    audit_logs,
+    disputes,
+    fee_overrides,
+    manual_adjustments,
     payment_method_addresses,
     payment_methods,
+    payout_calculations,
     payout_items,
     payout_methods,
     payout_splits,
     payouts,
+    refund_requests,
     refunds,
+    revenue_shares,
     transactions,
+    webhook_events,
 );
@@ -49,6 +49,10 @@ pub struct Dispute {
     /// This can be useful for storing additional information about the object in a structured format.
     pub metadata: Metadata,
 
+    /// ID of the PaymentIntent that was disputed.
+    #[serde(default)]
+    pub payment_intent: Option<String>,
+
     /// Reason given by cardholder for dispute.
     ///
     /// Possible values are `bank_cannot_process`, `check_returned`, `credit_not_processed`, `customer_initiated`, `debit_not_authorized`, `duplicate`, `fraudulent`, `general`, `incorrect_account_details`, `insufficient_funds`, `product_not_received`, `product_unacceptable`, `subscription_canceled`, or `unrecognized`.
@@ -222,6 +226,27 @@ pub struct DisputeEvidenceDetails {
     pub submission_count: u64,
 }
 
+/// The parameters for `Dispute::update`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DisputeUpdateParams {
+    /// Evidence to upload, to respond to a dispute.
+    ///
+    /// Updating any field in the hash will submit all fields in the hash for review.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub evidence: Option<DisputeEvidence>,
+
+    /// Set of key-value pairs that you can attach to an object.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<Metadata>,
+
+    /// Whether to immediately submit evidence to the bank.
+    ///
+    /// If `false`, evidence is staged on the dispute.
+    /// Staged evidence is visible in the API and Dashboard, and can be submitted to the bank by making another request with this attribute set to `true` (the default).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub submit: Option<bool>,
+}
+
 /// The parameters for `Dispute::list`.
 #[derive(Clone, Debug, Serialize)]
 pub struct ListDisputes {
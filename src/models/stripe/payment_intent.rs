@@ -368,8 +368,8 @@ pub struct PaymentIntentCreateParams {
     pub confirmation_method: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub description: Option<String>,
-    // #[serde(skip_serializing_if = "Option::is_none")]
-    // pub metadata: Option<Metadata>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<Metadata>,
     // #[serde(skip_serializing_if = "Option::is_none")]
     // pub on_behalf_of: Option<String>,
     // #[serde(skip_serializing_if = "Option::is_none")]